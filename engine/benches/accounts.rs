@@ -0,0 +1,107 @@
+//! Account store contention
+//!
+//! Settles the same seeded fills from one thread per symbol, as books matched on threads of their own would, through a
+//! `ShardedAccountStore` and through the same store behind one lock. Criterion writes the comparison report to
+//! `target/criterion/<workload>/report`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use engine::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const ACCOUNTS: usize = 1_000;
+const SYMBOLS: usize = 8;
+const FILLS: usize = 10_000;
+
+/// How the symbol threads share the accounts
+#[derive(Debug, Clone, Copy)]
+enum Locking {
+  /// Every settlement takes one lock over the whole store
+  Global,
+  /// Settlements only lock the accounts they touch
+  Sharded,
+}
+
+/// A store shared by the symbol threads
+enum Shared {
+  Global(Mutex<ShardedAccountStore>),
+  Sharded(ShardedAccountStore),
+}
+
+impl Shared {
+  fn new(locking: Locking) -> Arc<Self> {
+    Arc::new(match locking {
+      Locking::Global => Shared::Global(Mutex::new(store())),
+      Locking::Sharded => Shared::Sharded(store()),
+    })
+  }
+
+  fn settle(&self, fill: Transfer) -> Result<(), Error> {
+    match self {
+      Shared::Global(store) => store.lock().unwrap().settle(fill),
+      Shared::Sharded(store) => store.settle(fill),
+    }
+  }
+}
+
+fn symbol(index: usize) -> Symbol {
+  ['S', 'Y', 'M', (b'A' + index as u8) as char].into()
+}
+
+/// A store where every account has the cash and holdings to settle any of the fills
+fn store() -> ShardedAccountStore {
+  let store = ShardedAccountStore::default();
+  for id in 0..ACCOUNTS {
+    store.insert(id.into(), Ledger {
+      cash: u64::MAX / 2,
+      holdings: (0..SYMBOLS).map(|index| (symbol(index), (u64::MAX / 2).into())).collect(),
+      ..Ledger::default()
+    });
+  }
+  store
+}
+
+/// Fills of one symbol between random accounts
+fn fills(rng: &mut StdRng, index: usize) -> Vec<Transfer> {
+  (0..FILLS)
+    .map(|_| Transfer {
+      buyer: rng.gen_range(0, ACCOUNTS).into(),
+      seller: rng.gen_range(0, ACCOUNTS).into(),
+      symbol: symbol(index),
+      quantity: rng.gen_range(1, 100).into(),
+      value: rng.gen_range(1, 10_000),
+    })
+    .collect()
+}
+
+fn settle_in_parallel(store: Arc<Shared>, fills: &[Arc<Vec<Transfer>>]) {
+  let threads: Vec<_> = fills
+    .iter()
+    .cloned()
+    .map(|fills| {
+      let store = store.clone();
+      thread::spawn(move || {
+        for &fill in fills.iter() {
+          let _ = store.settle(fill);
+        }
+      })
+    })
+    .collect();
+  threads.into_iter().for_each(|thread| thread.join().unwrap());
+}
+
+fn multi_symbol(c: &mut Criterion) {
+  let mut rng = StdRng::seed_from_u64(0);
+  let fills: Vec<_> = (0..SYMBOLS).map(|index| Arc::new(fills(&mut rng, index))).collect();
+  c.bench_function_over_inputs(
+    "settle 10k fills on each of 8 symbol threads",
+    move |b, &&locking| {
+      b.iter_with_setup(|| Shared::new(locking), |store| settle_in_parallel(store, &fills))
+    },
+    &[Locking::Global, Locking::Sharded],
+  );
+}
+
+criterion_group!(benches, multi_symbol);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! Level store comparison
+//!
+//! Runs the same seeded workloads through an engine whose books use each `LevelStoreKind`. Criterion writes the
+//! comparison report for each workload to `target/criterion/<workload>/report`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use engine::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const KINDS: [LevelStoreKind; 3] = [LevelStoreKind::Tree, LevelStoreKind::Array, LevelStoreKind::List];
+const SYMBOL: [char; 4] = ['A', 'D', 'B', 'E'];
+const MID: u32 = 10_000;
+
+/// An engine with one account and one symbol
+fn engine(kind: LevelStoreKind) -> (MatchEngine, AccountId) {
+  let mut engine = MatchEngine::default();
+  engine.set_level_store(kind);
+  let account_id = engine.create_account();
+  engine.insert_new_symbol(SYMBOL.into()).unwrap();
+  (engine, account_id)
+}
+
+fn run(engine: &mut MatchEngine, account_id: AccountId, commands: &[CommandKind]) {
+  for &kind in commands {
+    let _ = engine.try_process(Command { account_id, kind });
+  }
+  engine.drain_market_data();
+  engine.drain_audit_trail();
+  engine.drain_journal();
+}
+
+/// A resting order, with prices clustered near the touch
+fn passive(rng: &mut StdRng) -> CommandKind {
+  let side = if rng.gen() { Side::Bid } else { Side::Ask };
+  let offset = rng.gen_range(1, 100).min(rng.gen_range(1, 100));
+  let price = match side {
+    Side::Bid => MID - offset,
+    Side::Ask => MID + offset,
+  };
+  CommandKind::PlaceOrder(side, SYMBOL.into(), Order::new(price.into(), rng.gen_range(1, 100).into()))
+}
+
+/// Orders that are placed, cancelled and occasionally cross, as on a busy book
+fn mixed(rng: &mut StdRng, count: usize) -> Vec<CommandKind> {
+  let mut placed = 0;
+  (0..count)
+    .map(|_| match rng.gen_range(0, 10) {
+      0..=2 if placed > 0 => CommandKind::CancelOrder(rng.gen_range(0, placed).into()),
+      3 => {
+        placed += 1;
+        let side = if rng.gen() { Side::Bid } else { Side::Ask };
+        let price = match side {
+          Side::Bid => MID + rng.gen_range(0, 5),
+          Side::Ask => MID - rng.gen_range(0, 5),
+        };
+        CommandKind::PlaceOrder(side, SYMBOL.into(), Order::new(price.into(), rng.gen_range(1, 200).into()))
+      }
+      _ => {
+        placed += 1;
+        passive(rng)
+      }
+    })
+    .collect()
+}
+
+fn insert(c: &mut Criterion) {
+  let mut rng = StdRng::seed_from_u64(0);
+  let commands: Vec<_> = (0..10_000).map(|_| passive(&mut rng)).collect();
+  c.bench_function_over_inputs(
+    "insert 10k resting orders",
+    move |b, &&kind| {
+      b.iter_with_setup(|| engine(kind), |(mut engine, account_id)| run(&mut engine, account_id, &commands))
+    },
+    &KINDS,
+  );
+}
+
+fn place_cancel_cross(c: &mut Criterion) {
+  let mut rng = StdRng::seed_from_u64(1);
+  let commands = mixed(&mut rng, 10_000);
+  c.bench_function_over_inputs(
+    "10k mixed places, cancels and crosses",
+    move |b, &&kind| {
+      b.iter_with_setup(|| engine(kind), |(mut engine, account_id)| run(&mut engine, account_id, &commands))
+    },
+    &KINDS,
+  );
+}
+
+fn sweep(c: &mut Criterion) {
+  let mut rng = StdRng::seed_from_u64(2);
+  let resting: Vec<_> = (0..10_000).map(|_| passive(&mut rng)).collect();
+  let sweeps = vec![
+    CommandKind::PlaceOrder(Side::Bid, SYMBOL.into(), Order::new((MID + 50).into(), 50_000.into())),
+    CommandKind::PlaceOrder(Side::Ask, SYMBOL.into(), Order::new((MID - 50).into(), 50_000.into())),
+  ];
+  c.bench_function_over_inputs(
+    "sweep 50 levels of each side",
+    move |b, &&kind| {
+      let (mut built, account_id) = engine(kind);
+      run(&mut built, account_id, &resting);
+      b.iter_with_setup(|| built.clone(), |mut engine| run(&mut engine, account_id, &sweeps))
+    },
+    &KINDS,
+  );
+}
+
+criterion_group!(benches, insert, place_cancel_cross, sweep);
+criterion_main!(benches);
@@ -0,0 +1,316 @@
+//! Concurrent account store
+//!
+//! Books of different symbols can be matched on threads of their own, but their fills all settle against the same
+//! accounts. A `ShardedAccountStore` spreads accounts over shards, each behind its own lock, and puts each account's
+//! `Ledger` behind a lock of its own, so threads settling fills only contend when they touch the same account.
+//!
+//! A fill moves cash from the buyer to the seller and holdings the other way, and is settled in two phases. `reserve`
+//! holds the buyer's cash and then the seller's holdings, locking one account at a time, and fails without holding
+//! anything if either account is short. The `Reservation` it returns then moves what it holds with `commit`, or gives
+//! it back when dropped. No thread ever holds two account locks at once, so fills between the same accounts in
+//! opposite directions cannot deadlock, and what is held cannot be spent twice by fills settling concurrently.
+
+use crate::engine::{Account, AccountStore, Error};
+use crate::types::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// The shards of a default store
+pub const DEFAULT_ACCOUNT_SHARDS: usize = 64;
+
+/// An account's cash and holdings, and how much of each is held for fills being settled
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+  pub cash: u64,
+  pub holdings: HashMap<Symbol, Quantity>,
+  /// Cash held to pay for fills reserved but not committed
+  pub held_cash: u64,
+  /// Holdings held to deliver for fills reserved but not committed
+  pub held: HashMap<Symbol, Quantity>,
+}
+
+impl Ledger {
+  /// Get the cash not held for any fill
+  pub fn available_cash(&self) -> u64 {
+    self.cash - self.held_cash
+  }
+
+  /// Get the holding of a symbol not held for any fill
+  pub fn available(&self, symbol: Symbol) -> Quantity {
+    let holding = self.holdings.get(&symbol).copied().unwrap_or_default();
+    holding - self.held.get(&symbol).copied().unwrap_or_default()
+  }
+}
+
+impl From<&Account> for Ledger {
+  fn from(account: &Account) -> Self {
+    Self {
+      cash: u32::from(account.balance).into(),
+      holdings: account.portfolio.clone(),
+      ..Self::default()
+    }
+  }
+}
+
+/// A fill to settle between two accounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Transfer {
+  pub buyer: AccountId,
+  pub seller: AccountId,
+  pub symbol: Symbol,
+  pub quantity: Quantity,
+  /// The cash paid for the quantity, e.g. by `Quantity::value_at`
+  pub value: u64,
+}
+
+/// Accounts spread over shards, with a lock for each shard and each account
+#[derive(Debug)]
+pub struct ShardedAccountStore {
+  shards: Vec<RwLock<HashMap<AccountId, Arc<Mutex<Ledger>>>>>,
+}
+
+impl Default for ShardedAccountStore {
+  fn default() -> Self {
+    Self::new(DEFAULT_ACCOUNT_SHARDS)
+  }
+}
+
+impl ShardedAccountStore {
+  /// Create a store with `shards` shards, at least one
+  pub fn new(shards: usize) -> Self {
+    Self {
+      shards: (0..shards.max(1)).map(|_| RwLock::default()).collect(),
+    }
+  }
+
+  /// Create a store with the cash and holdings of every account in `store`
+  pub fn from_store(store: &AccountStore, shards: usize) -> Self {
+    let sharded = Self::new(shards);
+    for (id, account) in &store.accounts {
+      sharded.insert(*id, account.into());
+    }
+    sharded
+  }
+
+  /// Insert an account, replacing any ledger it had
+  pub fn insert(&self, id: AccountId, ledger: Ledger) {
+    self.shard(id).write().unwrap().insert(id, Arc::new(Mutex::new(ledger)));
+  }
+
+  /// Get a copy of an account's ledger
+  pub fn ledger(&self, id: AccountId) -> Option<Ledger> {
+    self.get(id).ok().map(|ledger| lock(&ledger).clone())
+  }
+
+  pub fn len(&self) -> usize {
+    self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Hold the buyer's cash and the seller's holding for a fill
+  ///
+  /// # Errors
+  /// `Error::InsufficientBuyingPower` if the buyer's available cash does not cover the value, or
+  /// `Error::InsufficientHoldings` if the seller's available holding does not cover the quantity, in which case
+  /// nothing is held
+  pub fn reserve(&self, transfer: Transfer) -> Result<Reservation, Error> {
+    let (buyer, seller) = (self.get(transfer.buyer)?, self.get(transfer.seller)?);
+
+    {
+      let mut ledger = lock(&buyer);
+      let buying_power = ledger.available_cash();
+      if buying_power < transfer.value {
+        return Err(Error::InsufficientBuyingPower {
+          id: transfer.buyer,
+          buying_power,
+        });
+      }
+      ledger.held_cash += transfer.value;
+    }
+
+    {
+      let mut ledger = lock(&seller);
+      let holding = ledger.available(transfer.symbol);
+      if holding < transfer.quantity {
+        drop(ledger);
+        lock(&buyer).held_cash -= transfer.value;
+        return Err(Error::InsufficientHoldings {
+          id: transfer.seller,
+          symbol: transfer.symbol,
+          holding,
+        });
+      }
+      *ledger.held.entry(transfer.symbol).or_default() += transfer.quantity;
+    }
+
+    Ok(Reservation {
+      transfer,
+      buyer,
+      seller,
+      is_settled: false,
+    })
+  }
+
+  /// Reserve and commit a fill
+  pub fn settle(&self, transfer: Transfer) -> Result<(), Error> {
+    self.reserve(transfer).map(Reservation::commit)
+  }
+
+  fn shard(&self, id: AccountId) -> &RwLock<HashMap<AccountId, Arc<Mutex<Ledger>>>> {
+    &self.shards[usize::from(id) % self.shards.len()]
+  }
+
+  fn get(&self, id: AccountId) -> Result<Arc<Mutex<Ledger>>, Error> {
+    let shard = self.shard(id).read().unwrap();
+    shard.get(&id).cloned().ok_or(Error::AccountDoesNotExist { id })
+  }
+}
+
+/// The cash and holding held for a fill, given back if dropped before it is committed
+#[derive(Debug)]
+#[must_use = "a reservation is given back when dropped"]
+pub struct Reservation {
+  transfer: Transfer,
+  buyer: Arc<Mutex<Ledger>>,
+  seller: Arc<Mutex<Ledger>>,
+  is_settled: bool,
+}
+
+impl Reservation {
+  pub fn transfer(&self) -> Transfer {
+    self.transfer
+  }
+
+  /// Pay the seller with the held cash and deliver the held holding to the buyer
+  pub fn commit(mut self) {
+    let Transfer {
+      symbol,
+      quantity,
+      value,
+      ..
+    } = self.transfer;
+
+    {
+      let mut seller = lock(&self.seller);
+      take(&mut seller.holdings, symbol, quantity);
+      take(&mut seller.held, symbol, quantity);
+      seller.cash += value;
+    }
+    {
+      let mut buyer = lock(&self.buyer);
+      buyer.held_cash -= value;
+      buyer.cash -= value;
+      *buyer.holdings.entry(symbol).or_default() += quantity;
+    }
+    self.is_settled = true;
+  }
+}
+
+impl Drop for Reservation {
+  fn drop(&mut self) {
+    if self.is_settled {
+      return;
+    }
+
+    let Transfer {
+      symbol,
+      quantity,
+      value,
+      ..
+    } = self.transfer;
+    lock(&self.buyer).held_cash -= value;
+    take(&mut lock(&self.seller).held, symbol, quantity);
+  }
+}
+
+/// Take a quantity of a symbol out of holdings known to have it
+fn take(holdings: &mut HashMap<Symbol, Quantity>, symbol: Symbol, quantity: Quantity) {
+  let holding = holdings.entry(symbol).or_default();
+  *holding = *holding - quantity;
+}
+
+/// Lock a ledger, which is never left inconsistent by a panic as every update to it is made under one lock
+fn lock(ledger: &Mutex<Ledger>) -> MutexGuard<'_, Ledger> {
+  ledger.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::thread;
+
+  const ADBE: [char; 4] = ['A', 'D', 'B', 'E'];
+
+  fn ledger(cash: u64, holding: u64) -> Ledger {
+    Ledger {
+      cash,
+      holdings: vec![(ADBE.into(), holding.into())].into_iter().collect(),
+      ..Ledger::default()
+    }
+  }
+
+  fn transfer(buyer: usize, seller: usize, quantity: u64, value: u64) -> Transfer {
+    Transfer {
+      buyer: buyer.into(),
+      seller: seller.into(),
+      symbol: ADBE.into(),
+      quantity: quantity.into(),
+      value,
+    }
+  }
+
+  #[test]
+  fn reservations_hold_until_committed_or_dropped() {
+    let store = ShardedAccountStore::new(2);
+    store.insert(0.into(), ledger(1_000, 0));
+    store.insert(1.into(), ledger(0, 10));
+
+    let reservation = store.reserve(transfer(0, 1, 6, 600)).unwrap();
+    assert_eq!(store.reserve(transfer(0, 1, 4, 500)).unwrap_err(), Error::InsufficientBuyingPower {
+      id: 0.into(),
+      buying_power: 400,
+    });
+    assert_eq!(store.reserve(transfer(0, 1, 5, 400)).unwrap_err(), Error::InsufficientHoldings {
+      id: 1.into(),
+      symbol: ADBE.into(),
+      holding: 4.into(),
+    });
+    // the failed reservation of the seller's holding gave back the buyer's cash
+    assert_eq!(store.ledger(0.into()).unwrap().available_cash(), 400);
+
+    drop(reservation);
+    assert_eq!(store.ledger(0.into()), Some(ledger(1_000, 0)));
+    store.reserve(transfer(0, 1, 6, 600)).unwrap().commit();
+    let (buyer, seller) = (store.ledger(0.into()).unwrap(), store.ledger(1.into()).unwrap());
+    assert_eq!((buyer.cash, buyer.available(ADBE.into())), (400, 6.into()));
+    assert_eq!((seller.cash, seller.available(ADBE.into())), (600, 4.into()));
+    assert_eq!(store.settle(transfer(0, 2, 1, 1)), Err(Error::AccountDoesNotExist { id: 2.into() }));
+  }
+
+  #[test]
+  fn concurrent_fills_between_the_same_accounts_conserve_cash_and_holdings() {
+    let store = Arc::new(ShardedAccountStore::new(4));
+    store.insert(0.into(), ledger(10_000, 100));
+    store.insert(1.into(), ledger(10_000, 100));
+
+    let threads: Vec<_> = (0..4)
+      .map(|thread| {
+        let store = store.clone();
+        thread::spawn(move || {
+          for _ in 0..1_000 {
+            let _ = store.settle(if thread % 2 == 0 { transfer(0, 1, 1, 100) } else { transfer(1, 0, 1, 100) });
+          }
+        })
+      })
+      .collect();
+    threads.into_iter().for_each(|thread| thread.join().unwrap());
+
+    let (a, b) = (store.ledger(0.into()).unwrap(), store.ledger(1.into()).unwrap());
+    assert_eq!(a.cash + b.cash, 20_000);
+    assert_eq!(a.available(ADBE.into()) + b.available(ADBE.into()), 200.into());
+    assert_eq!((a.held_cash, b.held_cash), (0, 0));
+  }
+}
@@ -0,0 +1,208 @@
+//! Account activity
+//!
+//! The engine keeps an append-only log of what happens to each account: its orders accepted, rejected, modified and
+//! cancelled, its fills, the fees it is charged, and the settlement obligations each trading day closes with. Entries
+//! are numbered from 1 in their account's log, and `CommandKind::GetActivity` pages through the entries after a
+//! number, so a client that remembers the last number it saw can catch up on everything it missed while it was away.
+//!
+//! Only the latest `ACTIVITY_KEPT` entries of an account are kept. Asking for entries from before them fails with
+//! `Error::ActivityExpired`, after which a client rebuilds its view of the account from `CommandKind::GetAccount` and
+//! `CommandKind::GetOpenOrders`, and follows the log from the `latest` entry of any page.
+
+use crate::audit::CancelReason;
+use crate::clock::Timestamp;
+use crate::engine::{Error, Id, MAX_PAGE_SIZE, TradeId};
+use crate::events::EngineEvent;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Entries kept in each account's log
+pub const ACTIVITY_KEPT: usize = 10_000;
+
+/// Something that happened to an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Activity {
+  /// An order was given an id, before any of it traded
+  OrderAccepted {
+    order: Id,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+  },
+  /// An order entry command failed, with the order it named if any
+  OrderRejected { order: Option<Id> },
+  /// A resting order's price or quantity changed, or it was suspended or resumed
+  OrderModified { order: Id },
+  /// An order left its book before it filled
+  OrderCancelled { order: Id, reason: CancelReason },
+  /// One of the account's orders traded
+  Fill {
+    order: Id,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+    /// The order's fills so far, including this one
+    filled: Quantity,
+  },
+  /// A fee was charged, or a rebate paid if negative, for a fill or for an order beyond the order-to-trade threshold
+  Fee {
+    amount: i64,
+    /// The trade the fee was charged on, if it was for a fill
+    trade: Option<TradeId>,
+    /// The account's balance after it
+    balance: Price,
+  },
+  /// The trading day closed with the account owing cash for what it bought of a symbol and delivery of what it sold
+  Settlement {
+    symbol: Symbol,
+    bought: Quantity,
+    sold: Quantity,
+    paid: u64,
+    received: u64,
+  },
+}
+
+impl Activity {
+  /// Get the account an order event happened to, and the activity it stands for
+  pub(crate) fn from_event(event: EngineEvent) -> Option<(AccountId, Self)> {
+    match event {
+      EngineEvent::OrderAccepted {
+        order,
+        account,
+        symbol,
+        side,
+        price,
+        quantity,
+      } => Some((account, Activity::OrderAccepted {
+        order,
+        symbol,
+        side,
+        price,
+        quantity,
+      })),
+      EngineEvent::OrderRejected { account, order } => Some((account, Activity::OrderRejected { order })),
+      EngineEvent::OrderModified { order, account } => Some((account, Activity::OrderModified { order })),
+      EngineEvent::OrderCancelled { order, account, reason } => {
+        Some((account, Activity::OrderCancelled { order, reason }))
+      }
+      EngineEvent::Fill {
+        order,
+        account,
+        symbol,
+        side,
+        price,
+        quantity,
+        trade,
+        filled,
+      } => Some((account, Activity::Fill {
+        order,
+        symbol,
+        side,
+        price,
+        quantity,
+        trade,
+        filled,
+      })),
+      EngineEvent::Trade { .. } | EngineEvent::Halt { .. } | EngineEvent::AccountUpdated { .. } => None,
+    }
+  }
+}
+
+/// An entry of an account's activity log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityEntry {
+  /// The entry's number in its account's log, from 1
+  pub cursor: u64,
+  pub timestamp: Timestamp,
+  pub activity: Activity,
+}
+
+/// A page of an account's activity, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityPage {
+  pub entries: Vec<ActivityEntry>,
+  /// The cursor of the next page, or `None` if this page reaches the latest entry
+  pub next: Option<u64>,
+  /// The number of the account's latest entry, or 0 if it has none
+  pub latest: u64,
+}
+
+/// The latest entries of an account's activity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ActivityLog {
+  entries: VecDeque<ActivityEntry>,
+  latest: u64,
+}
+
+impl ActivityLog {
+  /// Append an entry, dropping the oldest beyond `ACTIVITY_KEPT`
+  pub fn push(&mut self, timestamp: Timestamp, activity: Activity) {
+    self.latest += 1;
+    if self.entries.len() == ACTIVITY_KEPT {
+      self.entries.pop_front();
+    }
+    self.entries.push_back(ActivityEntry {
+      cursor: self.latest,
+      timestamp,
+      activity,
+    });
+  }
+
+  /// Get up to `limit`, at most `MAX_PAGE_SIZE`, of the entries after `after`, or from the oldest if it is 0
+  ///
+  /// # Errors
+  /// `Error::ActivityExpired` if entries after `after` were dropped
+  pub fn page(&self, id: AccountId, after: u64, limit: usize) -> Result<ActivityPage, Error> {
+    let oldest = self.latest + 1 - self.entries.len() as u64;
+    if after != 0 && after + 1 < oldest {
+      return Err(Error::ActivityExpired { id, oldest });
+    }
+
+    let start = (after + 1).saturating_sub(oldest) as usize;
+    let entries: Vec<_> = self.entries.iter().skip(start).take(limit.min(MAX_PAGE_SIZE)).copied().collect();
+    let next = match entries.last() {
+      Some(last) if last.cursor < self.latest => Some(last.cursor),
+      _ => None,
+    };
+    Ok(ActivityPage {
+      entries,
+      next,
+      latest: self.latest,
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn pages_follow_the_cursor_until_entries_expire() {
+    let mut log = ActivityLog::default();
+    let id = AccountId::from(1);
+    assert_eq!(log.page(id, 0, 10), Ok(ActivityPage::default()));
+
+    for order in 0..ACTIVITY_KEPT + 3 {
+      let activity = Activity::OrderCancelled {
+        order: order.into(),
+        reason: CancelReason::Requested,
+      };
+      log.push(Timestamp::default(), activity);
+    }
+    let latest = ACTIVITY_KEPT as u64 + 3;
+    let cursors = |page: &ActivityPage| page.entries.iter().map(|entry| entry.cursor).collect::<Vec<_>>();
+
+    let page = log.page(id, 0, 2).unwrap();
+    assert_eq!((cursors(&page), page.next, page.latest), (vec![4, 5], Some(5), latest));
+    assert_eq!(log.page(id, 3, 1).unwrap().entries[0].cursor, 4);
+    assert_eq!(log.page(id, 2, 1), Err(Error::ActivityExpired { id, oldest: 4 }));
+
+    let page = log.page(id, latest - 2, 10).unwrap();
+    assert_eq!((cursors(&page), page.next), (vec![latest - 1, latest], None));
+    assert!(log.page(id, latest, 10).unwrap().entries.is_empty());
+  }
+}
@@ -0,0 +1,86 @@
+//! Call auctions
+
+use crate::clock::{self, Timestamp};
+use crate::config::AuctionSchedule;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+
+/// A marketable order held back to give other participants a chance to improve on the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct ImprovementAuction {
+  pub symbol: Symbol,
+  pub side: Side,
+  pub order: Order,
+  pub ends_at: Timestamp,
+}
+
+/// The next of a symbol's scheduled auctions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct ScheduledAuction {
+  /// When the auction is announced and the symbol stops matching orders on arrival
+  pub calls_at: Timestamp,
+  /// The time of day the auction is scheduled for, which is announced
+  pub scheduled_at: Timestamp,
+  /// The scheduled time plus the auction's random jitter
+  pub uncrosses_at: Timestamp,
+  pub is_called: bool,
+}
+
+impl ScheduledAuction {
+  /// Schedule the first auction of `schedule` that uncrosses after `now`
+  pub fn after(now: Timestamp, schedule: AuctionSchedule) -> Self {
+    let every = schedule.every.as_nanos() as u64;
+    // the schedule was validated to call for less than its interval, so the call starts after the epoch
+    let scheduled_at = (u64::from(now) / every + 1) * every;
+    let scheduled_at = Timestamp::from(scheduled_at);
+    ScheduledAuction {
+      calls_at: (u64::from(scheduled_at) - schedule.call.as_nanos() as u64).into(),
+      scheduled_at,
+      uncrosses_at: scheduled_at + clock::jitter(schedule.seed, scheduled_at, schedule.jitter),
+      is_called: false,
+    }
+  }
+}
+
+/// Find the price at which crossed interest uncrosses
+///
+/// `bids` and `asks` are aggregated `(price, quantity)` levels. The clearing price is the price that maximizes executable
+/// volume, then minimizes the surplus left on either side. Remaining ties are broken by the lowest price.
+///
+/// # Returns
+/// The clearing price and executable volume, or `None` if the book is not crossed
+pub fn clearing_price(bids: &[(Price, Quantity)], asks: &[(Price, Quantity)]) -> Option<(Price, Quantity)> {
+  let cumulative = |levels: &[(Price, Quantity)], includes: &dyn Fn(Price) -> bool| {
+    levels
+      .iter()
+      .filter(|(price, _)| includes(*price))
+      .fold(Quantity::default(), |total, &(_, quantity)| total + quantity)
+  };
+
+  let mut candidates: Vec<Price> = bids.iter().chain(asks.iter()).map(|&(price, _)| price).collect();
+  candidates.sort();
+  candidates.dedup();
+
+  let mut best: Option<(Price, Quantity, Quantity)> = None;
+  for price in candidates {
+    let demand = cumulative(bids, &|bid| bid >= price);
+    let supply = cumulative(asks, &|ask| ask <= price);
+    let volume = demand.min(supply);
+    let surplus = demand.max(supply) - volume;
+
+    if volume == Quantity::default() {
+      continue;
+    }
+
+    let is_better = match best {
+      None => true,
+      Some((_, best_volume, best_surplus)) => volume > best_volume || (volume == best_volume && surplus < best_surplus),
+    };
+
+    if is_better {
+      best = Some((price, volume, surplus));
+    }
+  }
+
+  best.map(|(price, volume, _)| (price, volume))
+}
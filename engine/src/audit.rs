@@ -0,0 +1,283 @@
+//! Regulatory audit trail
+//!
+//! Every order event is recorded as an `AuditRecord`. The flat export format is one record per line, fields separated
+//! by `|` in the order of `AUDIT_HEADER`, with empty fields where a value does not apply:
+//!
+//! | field           | contents                                                                          |
+//! |-----------------|-----------------------------------------------------------------------------------|
+//! | `sequence`      | the engine-wide event id, shared with the journal                                 |
+//! | `timestamp`     | nanoseconds since the unix epoch                                                  |
+//! | `session`       | the session the order event arrived on, or that placed the order                  |
+//! | `account`       | the account that owns the order                                                   |
+//! | `event`         | one of the `AuditEvent` codes, e.g. `ACCEPT`, `EXECUTE` or `SELF_MATCH_PREVENTED` |
+//! | `order`         | the engine order id, once assigned                                                |
+//! | `symbol`        | the order's symbol                                                                |
+//! | `side`          | `BID` or `ASK`                                                                    |
+//! | `price`         | the order's limit price, or the execution price for `EXECUTE`                     |
+//! | `quantity`      | the order's quantity, or the executed quantity for `EXECUTE`                      |
+//! | `trade`         | the trade id, for `EXECUTE`                                                       |
+//! | `liquidity`     | `MAKER`, `TAKER` or `CROSSED`, for `EXECUTE`                                      |
+//! | `filled`        | the order's quantity filled so far, for `EXECUTE`                                 |
+//! | `average_price` | the average price of the order's fills so far, for `EXECUTE`                      |
+//! | `source`        | the address refused, for `DENY`                                                   |
+//! | `tag`           | the order's tag, with separators and line breaks written as spaces                |
+//! | `check`         | the `RiskCheck` code bypassed, e.g. `BUYING_POWER`, for `BYPASS`                  |
+//! | `reason`        | the reason given for the bypass, written like `tag`, for `BYPASS`                 |
+//! | `cancel_reason` | the `CancelReason` code, e.g. `REQUESTED` or `END_OF_DAY`, for `CANCEL`           |
+//!
+//! A `DENY` refusing a connection before it named any account has the default account. A `SELF_MATCH_PREVENTED` has
+//! the price and quantity an order would have executed at had it not matched an order of its own account.
+
+use crate::clock::Timestamp;
+use crate::engine::{EventId, Id, Liquidity, TradeId};
+use crate::label::Label;
+use crate::risk::RiskCheck;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+/// The column names of the flat export format
+pub const AUDIT_HEADER: &str =
+  "sequence|timestamp|session|account|event|order|symbol|side|price|quantity|trade|liquidity|filled|average_price|\
+   source|tag|check|reason|cancel_reason";
+
+/// A kind of order event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuditEvent {
+  /// An order entry command arrived
+  Receive,
+  /// An order was assigned an id
+  Accept,
+  /// An order entry command failed
+  Reject,
+  Modify,
+  Cancel,
+  Execute,
+  /// A connection or command was refused for the address it came from
+  Deny,
+  /// Quantity was taken off an order instead of trading it with another order of the same account
+  SelfMatchPrevented,
+  /// An account was found under-margined and its liquidation started
+  Liquidate,
+  /// A pre-trade check failed but was passed for an admin session bypassing it
+  Bypass,
+}
+
+impl AuditEvent {
+  fn code(self) -> &'static str {
+    use AuditEvent::*;
+    match self {
+      Receive => "RECEIVE",
+      Accept => "ACCEPT",
+      Reject => "REJECT",
+      Modify => "MODIFY",
+      Cancel => "CANCEL",
+      Execute => "EXECUTE",
+      Deny => "DENY",
+      SelfMatchPrevented => "SELF_MATCH_PREVENTED",
+      Liquidate => "LIQUIDATE",
+      Bypass => "BYPASS",
+    }
+  }
+}
+
+/// Why an order was cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CancelReason {
+  /// The account, or an admin on its behalf, asked for it to be cancelled
+  Requested,
+  /// It was a day order still resting when the trading day closed
+  EndOfDay,
+  /// It was an auction-only order left unfilled when its auction uncrossed
+  AuctionUnfilled,
+  /// Resting would have crossed the book with orders of its own account it was kept from matching
+  SelfMatch,
+  /// Its account was closed
+  AccountClosed,
+  /// Its account was being liquidated
+  Liquidation,
+  /// Its symbol's book was replaced, e.g. for a corporate action
+  SymbolReplaced,
+  /// Every resting order was cancelled at once, as by a kill switch or a server shutting down
+  KillSwitch,
+}
+
+impl CancelReason {
+  fn code(self) -> &'static str {
+    use CancelReason::*;
+    match self {
+      Requested => "REQUESTED",
+      EndOfDay => "END_OF_DAY",
+      AuctionUnfilled => "AUCTION_UNFILLED",
+      SelfMatch => "SELF_MATCH",
+      AccountClosed => "ACCOUNT_CLOSED",
+      Liquidation => "LIQUIDATION",
+      SymbolReplaced => "SYMBOL_REPLACED",
+      KillSwitch => "KILL_SWITCH",
+    }
+  }
+
+  /// Returns true if the engine cancelled the order rather than a command asking for it
+  pub fn is_unsolicited(self) -> bool {
+    self != CancelReason::Requested
+  }
+}
+
+/// A normalized, timestamped order event
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+  pub sequence: EventId,
+  pub timestamp: Timestamp,
+  pub session: SessionId,
+  pub account: AccountId,
+  pub event: AuditEvent,
+  pub order: Option<Id>,
+  pub symbol: Option<Symbol>,
+  pub side: Option<Side>,
+  pub price: Option<Price>,
+  pub quantity: Option<Quantity>,
+  /// The trade an execution was part of and which side of it the order was on
+  #[serde(default)]
+  pub trade: Option<TradeId>,
+  #[serde(default)]
+  pub liquidity: Option<Liquidity>,
+  /// The order's fills so far, including this one
+  #[serde(default)]
+  pub filled: Option<Quantity>,
+  /// Weighted by quantity
+  #[serde(default)]
+  pub average_price: Option<f64>,
+  #[serde(default)]
+  pub source: Option<IpAddr>,
+  /// The tag the order was placed with, if any
+  #[serde(default)]
+  pub tag: Option<Label>,
+  /// The check a `Bypass` passed and the reason it was bypassed
+  #[serde(default)]
+  pub check: Option<RiskCheck>,
+  #[serde(default)]
+  pub reason: Option<Label>,
+  #[serde(default)]
+  pub cancel_reason: Option<CancelReason>,
+}
+
+impl AuditRecord {
+  /// Format the record as a line of the flat export format, without the trailing newline
+  pub fn to_flat(&self) -> String {
+    fn field<T: Display>(value: Option<T>) -> String {
+      value.map(|value| value.to_string()).unwrap_or_default()
+    }
+    // the flat format has no quoting, so separators in a label are written as spaces
+    fn label(value: Option<Label>) -> String {
+      field(value.map(|label| label.as_str().replace(['|', '\r', '\n'], " ")))
+    }
+
+    let side = self.side.map(|side| match side {
+      Side::Bid => "BID",
+      Side::Ask => "ASK",
+    });
+    let liquidity = self.liquidity.map(|liquidity| match liquidity {
+      Liquidity::Maker => "MAKER",
+      Liquidity::Taker => "TAKER",
+      Liquidity::Crossed => "CROSSED",
+    });
+
+    [
+      self.sequence.to_string(),
+      self.timestamp.to_string(),
+      self.session.to_string(),
+      self.account.to_string(),
+      self.event.code().to_string(),
+      field(self.order),
+      field(self.symbol),
+      field(side),
+      field(self.price),
+      field(self.quantity),
+      field(self.trade),
+      field(liquidity),
+      field(self.filled),
+      field(self.average_price),
+      field(self.source),
+      label(self.tag),
+      field(self.check.map(RiskCheck::code)),
+      label(self.reason),
+      field(self.cancel_reason.map(CancelReason::code)),
+    ]
+    .join("|")
+  }
+}
+
+/// Writes records in the flat export format
+#[derive(Debug)]
+pub struct AuditExporter<W: Write> {
+  writer: W,
+}
+
+impl<W: Write> AuditExporter<W> {
+  /// Create an exporter, writing the header line
+  pub fn new(writer: W) -> io::Result<Self> {
+    Self::resume(writer, 0)
+  }
+
+  /// Create an exporter appending to a log already `written` bytes long, writing the header line only if it is empty
+  pub fn resume(mut writer: W, written: u64) -> io::Result<Self> {
+    if written == 0 {
+      writeln!(writer, "{}", AUDIT_HEADER)?;
+    }
+    Ok(Self { writer })
+  }
+
+  /// Write records and flush them
+  pub fn export(&mut self, records: &[AuditRecord]) -> io::Result<()> {
+    for record in records {
+      writeln!(self.writer, "{}", record.to_flat())?;
+    }
+
+    self.writer.flush()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs::{self, OpenOptions};
+
+  #[test]
+  fn a_resumed_log_has_one_header() {
+    let path = std::env::temp_dir().join(format!("matchbook-audit-test-{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let record = AuditRecord {
+      sequence: 1.into(),
+      timestamp: Timestamp::from(1_000),
+      session: 0.into(),
+      account: 0.into(),
+      event: AuditEvent::Deny,
+      order: None,
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    };
+    for _ in 0..2 {
+      let file = OpenOptions::new().create(true).append(true).open(&path).unwrap();
+      let written = file.metadata().unwrap().len();
+      AuditExporter::resume(file, written).unwrap().export(&[record]).unwrap();
+    }
+
+    let log = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    let line = record.to_flat();
+    assert_eq!(log.lines().collect::<Vec<_>>(), vec![AUDIT_HEADER, &line, &line]);
+  }
+}
@@ -1,19 +1,79 @@
 //! Central limit order book (CLOB)
 
+use crate::levels::{LevelStore, LevelStoreKind};
 use crate::types::*;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use alloc::vec;
 use if_chain::if_chain;
+#[cfg(feature = "std")]
+use serde_derive::{Deserialize, Serialize};
 use std::cmp::Reverse;
-use std::collections::{BTreeMap, VecDeque};
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-pub struct OrderBook {
-  bids: LimitLevels<Reverse<Price>>,
-  asks: LimitLevels<Price>,
+/// A broken book invariant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation<P = Price> {
+  /// A level holds no orders
+  EmptyLevel { side: Side, price: P },
+  /// A level holds an order the book does not have
+  MissingOrder { side: Side, id: OrderId },
+  /// A level holds an order priced elsewhere
+  MispricedOrder { side: Side, id: OrderId, level: P },
+  /// A level holds an order that is filled or cancelled
+  InactiveOrder { side: Side, id: OrderId },
+  /// The best bid is at or through the best ask
+  Crossed { bid: P, ask: P },
+  /// The running count of a side's queued orders or their remaining quantity disagrees with its levels
+  MiscountedTotals { side: Side },
 }
 
-impl OrderBook {
+/// An order a book was asked about that it does not have on that side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownOrder {
+  pub side: Side,
+  pub id: OrderId,
+}
+
+/// What an incoming order does with a resting order it reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contra {
+  /// Trade with it
+  Match,
+  /// Pass over it, leaving it its place in the queue
+  PassOver,
+  /// Take the quantity they would have traded off both without trading, cancelling either left with none
+  DecrementBoth,
+}
+
+/// The best bid and ask price of a book, with the aggregate remaining quantity at each
+pub type TopOfBook<P = Price, Q = Quantity> = (Option<(P, Q)>, Option<(P, Q)>);
+
+/// A resting order an order executed against, the quantity traded, and whether the resting order is now filled
+pub type Executed<Q = Quantity> = (OrderId, Q, bool);
+
+/// A book of orders priced in `P` and filled in `Q`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct OrderBook<P = Price, Q = Quantity>
+where
+  P: BookPrice,
+  Q: BookQuantity,
+{
+  bids: LimitLevels<Reverse<P>, P, Q>,
+  asks: LimitLevels<P, P, Q>,
+}
+
+impl<P: BookPrice, Q: BookQuantity> OrderBook<P, Q> {
+  /// Create an empty book storing its levels in `kind`
+  pub fn new(kind: LevelStoreKind) -> Self {
+    Self {
+      bids: LimitLevels::new(kind),
+      asks: LimitLevels::new(kind),
+    }
+  }
+
   /// Return the current spread
-  pub fn spread(&self) -> Price {
+  pub fn spread(&self) -> P {
     let ask = self.asks.best_price();
     let bid = self.bids.best_price();
 
@@ -24,15 +84,19 @@ impl OrderBook {
     }
   }
 
-  /// Update an order
+  /// Change a resting order's price or total quantity, leaving whichever is `None` as it is
+  ///
+  /// The order keeps its place in the queue only if its price is unchanged and its quantity does not grow; otherwise
+  /// it moves to the back of the level at its new price. Nothing is matched, even if the new price crosses.
   ///
-  /// TODO: is this a real thing that can be done?
+  /// # Returns
+  /// false if the order is not resting, or the new quantity is not above what has already filled
   pub fn update(
     &mut self,
     side: Side,
     id: OrderId,
-    maybe_price: Option<Price>,
-    maybe_quantity: Option<Quantity>,
+    maybe_price: Option<P>,
+    maybe_quantity: Option<Q>,
   ) -> bool {
     use Side::*;
     match side {
@@ -41,8 +105,35 @@ impl OrderBook {
     }
   }
 
+  /// Return the midpoint of the best bid and ask, rounded down, if both sides have orders
+  pub fn midpoint(&self) -> Option<P> {
+    if self.bids.is_empty() || self.asks.is_empty() {
+      return None;
+    }
+
+    Some(self.bids.best_price().midpoint(self.asks.best_price()))
+  }
+
+  /// Returns true if an order at `price` on `side` would execute immediately
+  pub fn is_marketable(&self, side: Side, price: P) -> bool {
+    use Side::*;
+    match side {
+      Bid => !self.asks.is_empty() && price >= self.asks.best_price(),
+      Ask => !self.bids.is_empty() && price <= self.bids.best_price(),
+    }
+  }
+
+  /// Get the best bid and ask, if either side has orders
+  pub fn best_prices(&self) -> (Option<P>, Option<P>) {
+    let best = |is_empty: bool, price: P| if is_empty { None } else { Some(price) };
+    (
+      best(self.bids.is_empty(), self.bids.best_price()),
+      best(self.asks.is_empty(), self.asks.best_price()),
+    )
+  }
+
   /// Get the best price for the given side
-  pub fn best_price(&self, side: Side) -> Price {
+  pub fn best_price(&self, side: Side) -> P {
     use Side::*;
     match side {
       Ask => self.asks.best_price(),
@@ -59,8 +150,32 @@ impl OrderBook {
     }
   }
 
+  /// Take a resting order out of matching and depth, keeping it to resume later
+  ///
+  /// # Returns
+  /// false if the order is not queued at its level
+  pub fn suspend(&mut self, side: Side, id: OrderId) -> bool {
+    use Side::*;
+    match side {
+      Bid => self.bids.suspend(id),
+      Ask => self.asks.suspend(id),
+    }
+  }
+
+  /// Return a suspended order to the back of its level
+  ///
+  /// # Returns
+  /// false if the order is not suspended
+  pub fn resume(&mut self, side: Side, id: OrderId) -> bool {
+    use Side::*;
+    match side {
+      Bid => self.bids.resume(id),
+      Ask => self.asks.resume(id),
+    }
+  }
+
   /// Insert an order
-  pub fn insert(&mut self, side: Side, order: Order) -> OrderId {
+  pub fn insert(&mut self, side: Side, order: Order<P, Q>) -> OrderId {
     use Side::*;
     match side {
       Ask => self.asks.insert(order),
@@ -68,8 +183,17 @@ impl OrderBook {
     }
   }
 
+  /// Fill part of a resting order outside of the normal level walk
+  pub fn fill(&mut self, side: Side, id: OrderId, quantity: Q) -> bool {
+    use Side::*;
+    match side {
+      Ask => self.asks.fill(id, quantity),
+      Bid => self.bids.fill(id, quantity),
+    }
+  }
+
   /// Get an order
-  pub fn get(&self, side: Side, id: OrderId) -> Option<&Order> {
+  pub fn get(&self, side: Side, id: OrderId) -> Option<&Order<P, Q>> {
     use Side::*;
     match side {
       Ask => self.asks.get(id),
@@ -78,27 +202,163 @@ impl OrderBook {
   }
 
   /// Execute an order
-  pub fn execute(&mut self, side: Side, id: OrderId) -> (bool, Vec<(OrderId, Quantity, bool)>) {
+  pub fn execute(&mut self, side: Side, id: OrderId) -> Result<(bool, Vec<Executed<Q>>), UnknownOrder> {
+    let mut executions = vec![];
+    Ok((self.execute_into(side, id, &mut executions)?, executions))
+  }
+
+  /// Execute an order, appending each `(resting order, quantity, resting order is filled)` to `executions` so the
+  /// caller can reuse one buffer across orders
+  ///
+  /// # Returns
+  /// true if the order was filled, or an error if the book does not have it
+  pub fn execute_into(
+    &mut self,
+    side: Side,
+    id: OrderId,
+    executions: &mut Vec<(OrderId, Q, bool)>,
+  ) -> Result<bool, UnknownOrder> {
     use Side::*;
     match side {
       Bid => {
         if let Some(order) = self.bids.get_mut(id) {
-          self.asks.execute(order)
+          let before = order.remaining();
+          let is_filled = self.asks.execute(order, executions);
+          let filled = before - order.remaining();
+          self.bids.take_filled(filled);
+          if is_filled {
+            self.bids.remove_from_level(id);
+          }
+          Ok(is_filled)
         } else {
-          unimplemented!()
+          Err(UnknownOrder { side, id })
         }
       }
       Ask => {
         if let Some(order) = self.asks.get_mut(id) {
-          self.bids.execute(order)
+          let before = order.remaining();
+          let is_filled = self.bids.execute(order, executions);
+          let filled = before - order.remaining();
+          self.asks.take_filled(filled);
+          if is_filled {
+            self.asks.remove_from_level(id);
+          }
+          Ok(is_filled)
         } else {
-          unimplemented!()
+          Err(UnknownOrder { side, id })
         }
       }
     }
   }
 
-  pub fn level(&self, side: Side, price: Price) -> Option<Vec<OrderId>> {
+  /// Execute an order as `execute_into` does, doing with each resting order it reaches what `contra` returns for it,
+  /// and appending each `(resting order, quantity, resting order is cancelled)` decremented to `decrements`
+  ///
+  /// # Returns
+  /// true if the order has none left, whether filled or decremented, or an error if the book does not have it
+  pub fn execute_into_except<S: FnMut(OrderId, &Order<P, Q>) -> Contra>(
+    &mut self,
+    side: Side,
+    id: OrderId,
+    executions: &mut Vec<(OrderId, Q, bool)>,
+    decrements: &mut Vec<(OrderId, Q, bool)>,
+    contra: S,
+  ) -> Result<bool, UnknownOrder> {
+    use Side::*;
+    match side {
+      Bid => {
+        if let Some(order) = self.bids.get_mut(id) {
+          let before = order.remaining();
+          let is_filled = self.asks.execute_except(order, executions, decrements, contra);
+          let filled = before - order.remaining();
+          self.bids.take_filled(filled);
+          if is_filled {
+            self.bids.remove_from_level(id);
+          }
+          Ok(is_filled)
+        } else {
+          Err(UnknownOrder { side, id })
+        }
+      }
+      Ask => {
+        if let Some(order) = self.asks.get_mut(id) {
+          let before = order.remaining();
+          let is_filled = self.bids.execute_except(order, executions, decrements, contra);
+          let filled = before - order.remaining();
+          self.asks.take_filled(filled);
+          if is_filled {
+            self.asks.remove_from_level(id);
+          }
+          Ok(is_filled)
+        } else {
+          Err(UnknownOrder { side, id })
+        }
+      }
+    }
+  }
+
+  /// Get the aggregate remaining quantity at each price level, best price first
+  pub fn depth(&self, side: Side) -> Vec<(P, Q)> {
+    use Side::*;
+    match side {
+      Bid => self.bids.depth(),
+      Ask => self.asks.depth(),
+    }
+  }
+
+  /// Get the aggregate remaining quantity at up to the `levels` best price levels, best price first
+  pub fn top_levels(&self, side: Side, levels: usize) -> Vec<(P, Q)> {
+    use Side::*;
+    match side {
+      Bid => self.bids.aggregate_levels().take(levels).collect(),
+      Ask => self.asks.aggregate_levels().take(levels).collect(),
+    }
+  }
+
+  /// Get how many orders are queued on a side and their total remaining quantity, kept as the book changes
+  pub fn totals(&self, side: Side) -> (usize, Q) {
+    use Side::*;
+    match side {
+      Bid => (self.bids.queued, self.bids.resting),
+      Ask => (self.asks.queued, self.asks.resting),
+    }
+  }
+
+  /// Get the aggregate remaining quantity at the best bid and ask, if either side has orders
+  pub fn top(&self) -> TopOfBook<P, Q> {
+    (self.bids.top(), self.asks.top())
+  }
+
+  /// Fill `quantity` of crossed interest against each side at a single price
+  ///
+  /// # Returns
+  /// The `(bid, ask, quantity)` of each resulting trade
+  pub fn uncross(&mut self, price: P, quantity: Q) -> Vec<(OrderId, OrderId, Q)> {
+    let mut bid_fills = self.bids.fill_through(price, quantity).into_iter();
+    let mut ask_fills = self.asks.fill_through(price, quantity).into_iter();
+
+    let mut trades = vec![];
+    let (mut bid, mut ask) = (bid_fills.next(), ask_fills.next());
+    while let (Some((bid_id, bid_quantity)), Some((ask_id, ask_quantity))) = (bid, ask) {
+      let traded = bid_quantity.min(ask_quantity);
+      trades.push((bid_id, ask_id, traded));
+
+      bid = if bid_quantity > traded {
+        Some((bid_id, bid_quantity - traded))
+      } else {
+        bid_fills.next()
+      };
+      ask = if ask_quantity > traded {
+        Some((ask_id, ask_quantity - traded))
+      } else {
+        ask_fills.next()
+      };
+    }
+
+    trades
+  }
+
+  pub fn level(&self, side: Side, price: P) -> Option<Vec<OrderId>> {
     use Side::*;
     match side {
       Bid => self.bids.level(price),
@@ -106,6 +366,15 @@ impl OrderBook {
     }
   }
 
+  /// Check that every level holds only live orders at its price
+  ///
+  /// A crossed book is not checked, as books are crossed between auctions.
+  pub fn violations(&self) -> Vec<Violation<P>> {
+    let mut violations = self.bids.violations(Side::Bid);
+    violations.extend(self.asks.violations(Side::Ask));
+    violations
+  }
+
   pub fn first(&self) -> Option<(Side, OrderId)> {
     use Side::*;
     match (self.asks.first(), self.bids.first()) {
@@ -118,65 +387,199 @@ impl OrderBook {
   }
 }
 
+/// The priority of a price on one side of the book, with better prices ordered first
+trait Priority<P>: Ord + Clone {
+  fn from_price(price: P) -> Self;
+  fn price(&self) -> P;
+}
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-struct LimitLevels<P>
+impl<P: BookPrice> Priority<P> for P {
+  fn from_price(price: P) -> Self {
+    price
+  }
+
+  fn price(&self) -> P {
+    *self
+  }
+}
+
+impl<P: BookPrice> Priority<P> for Reverse<P> {
+  fn from_price(price: P) -> Self {
+    Reverse(price)
+  }
+
+  fn price(&self) -> P {
+    self.0
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(
+  feature = "std",
+  serde(bound(deserialize = "K: Ord + serde::Deserialize<'de>, P: serde::Deserialize<'de>, Q: Default + serde::Deserialize<'de>"))
+)]
+struct LimitLevels<K, P, Q>
 where
-  P: Ord + From<Price> + Into<Price>,
+  K: Ord,
 {
-  limit_levels: BTreeMap<P, VecDeque<OrderId>>,
-  orders: Vec<Order>,
+  limit_levels: LevelStore<K>,
+  orders: Vec<Order<P, Q>>,
+  /// Resting orders taken out of their levels until resumed
+  suspended: BTreeSet<OrderId>,
+  /// How many orders are queued in the levels
+  queued: usize,
+  /// The remaining quantity of the orders queued in the levels
+  resting: Q,
   // TODO: add id -> limit level index map for fast access and deletion
 }
 
-impl<P> LimitLevels<P>
+impl<K, P, Q> LimitLevels<K, P, Q>
 where
-  P: Ord + From<Price> + Into<Price> + Clone,
+  K: Priority<P>,
+  P: BookPrice,
+  Q: BookQuantity,
 {
+  pub fn new(kind: LevelStoreKind) -> Self {
+    Self {
+      limit_levels: LevelStore::new(kind),
+      orders: vec![],
+      suspended: BTreeSet::new(),
+      queued: 0,
+      resting: Q::default(),
+    }
+  }
+
+  /// Count a queued order's remaining quantity down by a fill made outside of `fill_each`
+  fn take_filled(&mut self, quantity: Q) {
+    self.resting = self.resting - quantity;
+  }
+
   pub fn first(&self) -> Option<OrderId> {
     self
       .limit_levels
-      .values()
+      .iter()
       .next()
-      .and_then(|x| VecDeque::front(x).map(|x| *x))
+      .and_then(|(_, x)| VecDeque::front(x).map(|x| *x))
   }
 
+  /// Remove an order from its limit level without cancelling it
   pub fn remove_from_level(&mut self, id: OrderId) -> bool {
-    unimplemented!()
+    if_chain! {
+      if let Some(order) = self.orders.get::<usize>(id.into());
+      let key = K::from_price(order.price);
+      if let Some(limit_level) = self.limit_levels.get_mut(&key);
+      if let Some(removal_index) = limit_level.iter().position(|&other_id| other_id == id);
+      then {
+        limit_level.remove(removal_index);
+        if limit_level.is_empty() {
+          self.limit_levels.remove(&key);
+        }
+        self.queued -= 1;
+        self.resting = self.resting - order.remaining();
+
+        true
+      } else {
+        false
+      }
+    }
+  }
+
+  pub fn suspend(&mut self, id: OrderId) -> bool {
+    if !self.remove_from_level(id) {
+      return false;
+    }
+    self.suspended.insert(id);
+    true
+  }
+
+  pub fn resume(&mut self, id: OrderId) -> bool {
+    if !self.suspended.remove(&id) {
+      return false;
+    }
+    let order = self.orders[usize::from(id)];
+    self.limit_levels.entry(K::from_price(order.price)).push_back(id);
+    self.queued += 1;
+    self.resting += order.remaining();
+    true
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.limit_levels.is_empty()
   }
 
-  pub fn best_price(&self) -> Price {
+  pub fn best_price(&self) -> P {
     self
       .limit_levels
-      .keys()
+      .iter()
       .next()
-      .cloned()
-      .map(Into::into)
+      .map(|(price, _)| price.price())
       .unwrap_or_default()
   }
 
   /// Insert an order into the book
-  pub fn insert(&mut self, order: Order) -> OrderId {
+  pub fn insert(&mut self, order: Order<P, Q>) -> OrderId {
     assert_eq!(order.is_cancelled, false);
     let id = self.orders.len().into();
     let price = order.price;
 
     self.orders.push(order);
-    self.limit_levels.entry(P::from(price)).or_default().push_back(id);
+    if !order.is_filled() {
+      self.limit_levels.entry(K::from_price(price)).push_back(id);
+      self.queued += 1;
+      self.resting += order.remaining();
+    }
 
     id
   }
 
 
-  pub fn update(&mut self, id: OrderId, maybe_price: Option<Price>, maybe_quantity: Option<Quantity>) -> bool {
+  /// Change a resting order's price or total quantity, re-queueing it if it loses priority
+  pub fn update(&mut self, id: OrderId, maybe_price: Option<P>, maybe_quantity: Option<Q>) -> bool {
+    let order = match self.orders.get::<usize>(id.into()) {
+      Some(order) if !order.is_cancelled && !order.is_filled() => *order,
+      _ => return false,
+    };
+    let price = maybe_price.unwrap_or(order.price);
+    let quantity = maybe_quantity.unwrap_or(order.quantity);
+    if quantity <= order.filled {
+      return false;
+    }
+
+    // a suspended order has no place to lose, and takes its new price when resumed
+    let is_queued = !self.suspended.contains(&id);
+    let loses_priority = price != order.price || quantity > order.quantity;
+    if loses_priority && is_queued && !self.remove_from_level(id) {
+      return false;
+    }
+
+    let order = &mut self.orders[usize::from(id)];
+    let before = order.remaining();
+    order.price = price;
+    order.quantity = quantity;
+    let after = order.remaining();
+    if loses_priority && is_queued {
+      self.limit_levels.entry(K::from_price(price)).push_back(id);
+      self.queued += 1;
+      self.resting += after;
+    } else if is_queued {
+      self.resting = self.resting - before + after;
+    }
+
+    true
+  }
+
+  /// Fill part of an order, removing it from its level once filled
+  pub fn fill(&mut self, id: OrderId, quantity: Q) -> bool {
+    let is_queued = !self.suspended.contains(&id);
     if let Some(order) = self.orders.get_mut::<usize>(id.into()) {
-      if let Some(price) = maybe_price {
-        // TODO: this needs to update the index...
-        order.price = price;
+      let was_live = !order.is_cancelled && !order.is_filled();
+      order.filled += quantity;
+      if was_live && is_queued {
+        self.resting = self.resting - quantity;
       }
-
-      if let Some(quantity) = maybe_quantity {
-        order.quantity = quantity;
+      if order.is_filled() {
+        self.remove_from_level(id);
       }
 
       true
@@ -185,49 +588,81 @@ where
     }
   }
 
-  pub fn get_mut(&mut self, id: OrderId) -> Option<&mut Order> {
+  pub fn get_mut(&mut self, id: OrderId) -> Option<&mut Order<P, Q>> {
     self.orders.get_mut::<usize>(id.into())
   }
 
   /// Get an order from the book
-  pub fn get(&self, id: OrderId) -> Option<&Order> {
+  pub fn get(&self, id: OrderId) -> Option<&Order<P, Q>> {
     self.orders.get::<usize>(id.into())
   }
 
-  pub fn execute(&mut self, order: &mut Order) -> (bool, Vec<(OrderId, Quantity, bool)>) {
-    if order.remaining() == 0.into() {
-      return (true, vec![]);
-    }
+  /// Execute an order against every level it crosses, in priority order, appending each execution to `executions`
+  pub fn execute(&mut self, order: &mut Order<P, Q>, executions: &mut Vec<(OrderId, Q, bool)>) -> bool {
+    self.fill_each(order.price, order.remaining(), |id, quantity, is_filled| {
+      order.filled += quantity;
+      executions.push((id, quantity, is_filled));
+    });
 
-    let mut should_remove_level = false; // FIXME: I don't like using this
-    let mut executions = vec![]; // FIXME: I don't like using this
-    if let Some(limit_level) = self.limit_levels.get_mut(&P::from(order.price)) {
-      while let Some(id) = limit_level.pop_front() {
-        let against = self.orders.get_mut::<usize>(id.into()).unwrap();
-        let to_fill = against.remaining().min(order.remaining()); // number of fills are bounded by the least remaining
-        order.filled += to_fill;
-        against.filled += to_fill;
+    order.is_filled()
+  }
 
-        // push order back to front if it's not filled
-        if against.is_filled() {
-          limit_level.push_front(id);
-        } else if limit_level.is_empty() {
-          should_remove_level = true;
+  /// Execute an order as `execute` does, doing with each resting order it reaches what `contra` returns for it
+  pub fn execute_except<S: FnMut(OrderId, &Order<P, Q>) -> Contra>(
+    &mut self,
+    order: &mut Order<P, Q>,
+    executions: &mut Vec<(OrderId, Q, bool)>,
+    decrements: &mut Vec<(OrderId, Q, bool)>,
+    mut contra: S,
+  ) -> bool {
+    let limit = K::from_price(order.price);
+    let prices: Vec<K> = self
+      .limit_levels
+      .iter()
+      .map(|(price, _)| price.clone())
+      .take_while(|price| *price <= limit)
+      .collect();
+
+    for price in prices {
+      let level = self.limit_levels.get_mut(&price).unwrap();
+      let mut index = 0;
+      while index < level.len() && !order.is_filled() {
+        let id = level[index];
+        let resting = &mut self.orders[usize::from(id)];
+        let action = contra(id, resting);
+        if action == Contra::PassOver {
+          index += 1;
+          continue;
         }
 
-        executions.push((id, to_fill, against.is_filled()));
-
-        if order.filled == order.quantity {
-          break;
+        let quantity = resting.remaining().min(order.remaining());
+        self.resting = self.resting - quantity;
+        if action == Contra::DecrementBoth {
+          resting.quantity = resting.quantity - quantity;
+          order.quantity = order.quantity - quantity;
+          resting.is_cancelled = resting.is_filled();
+          order.is_cancelled = order.is_filled();
+          decrements.push((id, quantity, resting.is_cancelled));
+        } else {
+          resting.filled += quantity;
+          order.filled += quantity;
+          executions.push((id, quantity, resting.is_filled()));
+        }
+        if resting.is_filled() {
+          level.remove(index);
+          self.queued -= 1;
         }
       }
-    }
 
-    if should_remove_level {
-      self.limit_levels.remove(&order.price.into());
+      if level.is_empty() {
+        self.limit_levels.remove(&price);
+      }
+      if order.is_filled() {
+        break;
+      }
     }
 
-    (order.is_filled(), executions)
+    order.is_filled()
   }
 
   pub fn cancel(&mut self, id: OrderId) -> bool {
@@ -239,19 +674,26 @@ where
         .map(|(i, _)| i)
     };
 
+    if self.suspended.remove(&id) {
+      self.orders[usize::from(id)].is_cancelled = true;
+      return true;
+    }
+
     if_chain! {
       if let Some(order) = self.orders.get_mut::<usize>(id.into()); // order exists
       // price level exists
-      if let Some(limit_level) = self.limit_levels.get_mut(&P::from(order.price));
+      if let Some(limit_level) = self.limit_levels.get_mut(&K::from_price(order.price));
       // id is in the limit level
       if let Some(removal_index) = find_index_of_id(limit_level);
       then {
         order.is_cancelled = true;
         limit_level.remove(removal_index);
+        self.queued -= 1;
+        self.resting = self.resting - order.remaining();
 
         // if no other prices at this limit level exist, remove it
         if limit_level.is_empty() {
-          self.limit_levels.remove(&order.price.into());
+          self.limit_levels.remove(&K::from_price(order.price));
         }
 
         true
@@ -261,15 +703,174 @@ where
     }
   }
 
+  /// Return the aggregate remaining quantity of each limit level, best price first
+  pub fn depth(&self) -> Vec<(P, Q)> {
+    self.aggregate_levels().collect()
+  }
+
+  /// Return the aggregate remaining quantity of the best limit level, if there is one
+  pub fn top(&self) -> Option<(P, Q)> {
+    self.aggregate_levels().next()
+  }
+
+  fn aggregate_levels(&self) -> impl Iterator<Item = (P, Q)> + '_ {
+    self.limit_levels.iter().map(move |(price, level)| {
+      let quantity = level
+        .iter()
+        .map(|&id| self.orders[usize::from(id)].remaining())
+        .fold(Q::default(), |total, remaining| total + remaining);
+      (price.price(), quantity)
+    })
+  }
+
+  /// Fill up to `quantity` from resting orders priced at or better than `limit`, in priority order
+  ///
+  /// # Returns
+  /// The id and filled quantity of each order filled
+  pub fn fill_through(&mut self, limit: P, quantity: Q) -> Vec<(OrderId, Q)> {
+    let mut fills = vec![];
+    self.fill_each(limit, quantity, |id, quantity, _| fills.push((id, quantity)));
+    fills
+  }
+
+  /// Fill as `fill_through` does, calling `fill` with each order, the quantity filled and whether it is now filled
+  fn fill_each<F: FnMut(OrderId, Q, bool)>(&mut self, limit: P, quantity: Q, mut fill: F) {
+    let limit = K::from_price(limit);
+    let mut remaining = quantity;
+
+    // a level is only left with orders once the fill is complete, so the next level is always the best
+    while remaining > Q::default() {
+      let (price, level) = match self.limit_levels.first_mut() {
+        Some((price, level)) if *price <= limit => (price.clone(), level),
+        _ => break,
+      };
+
+      while remaining > Q::default() {
+        let id = match level.pop_front() {
+          Some(id) => id,
+          None => break,
+        };
+        let order = &mut self.orders[usize::from(id)];
+        let to_fill = order.remaining().min(remaining);
+        order.filled += to_fill;
+        remaining = remaining - to_fill;
+        self.resting = self.resting - to_fill;
+        fill(id, to_fill, order.is_filled());
+
+        if order.is_filled() {
+          self.queued -= 1;
+        } else {
+          level.push_front(id);
+        }
+      }
+
+      if level.is_empty() {
+        self.limit_levels.remove(&price);
+      }
+    }
+  }
+
+
+  fn violations(&self, side: Side) -> Vec<Violation<P>> {
+    let mut violations = vec![];
+    let queued = self.limit_levels.iter().map(|(_, level)| level.len()).sum::<usize>();
+    let resting = self.aggregate_levels().fold(Q::default(), |total, (_, quantity)| total + quantity);
+    if (queued, resting) != (self.queued, self.resting) {
+      violations.push(Violation::MiscountedTotals { side });
+    }
+
+    for (key, level) in self.limit_levels.iter() {
+      let price = key.price();
+      if level.is_empty() {
+        violations.push(Violation::EmptyLevel { side, price });
+      }
+
+      for &id in level {
+        match self.orders.get(usize::from(id)) {
+          None => violations.push(Violation::MissingOrder { side, id }),
+          Some(order) if order.price != price => violations.push(Violation::MispricedOrder { side, id, level: price }),
+          Some(order) if order.is_filled() || order.is_cancelled => {
+            violations.push(Violation::InactiveOrder { side, id })
+          }
+          _ => (),
+        }
+      }
+    }
+
+    violations
+  }
+
   /// Return all orders id at a limit
-  pub fn level(&self, price: Price) -> Option<Vec<OrderId>> {
+  pub fn level(&self, price: P) -> Option<Vec<OrderId>> {
     self
       .limit_levels
-      .get(&price.into())
+      .get(&K::from_price(price))
       .map(|level| level.iter().cloned().collect())
   }
 }
 
+#[cfg(test)]
+mod test {
+  use super::*;
+  use derive_more::{Add, AddAssign, Sub};
+
+  /// Lots of a thousandth each
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Add, AddAssign, Sub)]
+  struct Lots(u64);
+
+  #[test]
+  fn book_matches_in_any_numeric_types() {
+    let mut book: OrderBook<u64, Lots> = OrderBook::default();
+    book.insert(Side::Ask, Order::limit(10_050, Lots(1_500)));
+    book.insert(Side::Ask, Order::limit(10_000, Lots(250)));
+    let bid = book.insert(Side::Bid, Order::limit(10_050, Lots(1_000)));
+
+    let (is_filled, executions) = book.execute(Side::Bid, bid).unwrap();
+    assert!(is_filled);
+    assert_eq!(executions, vec![(1.into(), Lots(250), true), (0.into(), Lots(750), false)]);
+    assert_eq!(book.depth(Side::Ask), vec![(10_050, Lots(750))]);
+    assert_eq!(book.violations(), vec![]);
+  }
+
+  #[test]
+  fn updates_keep_priority_only_when_shrinking() {
+    let mut book: OrderBook<u64, Lots> = OrderBook::default();
+    let first = book.insert(Side::Bid, Order::limit(100, Lots(10)));
+    let second = book.insert(Side::Bid, Order::limit(100, Lots(10)));
+
+    assert!(book.update(Side::Bid, first, None, Some(Lots(5))));
+    assert_eq!(book.level(Side::Bid, 100), Some(vec![first, second]));
+    assert!(book.update(Side::Bid, first, None, Some(Lots(20))));
+    assert_eq!(book.level(Side::Bid, 100), Some(vec![second, first]));
+    assert!(book.update(Side::Bid, second, Some(101), None));
+    assert_eq!(book.level(Side::Bid, 100), Some(vec![first]));
+    assert_eq!(book.level(Side::Bid, 101), Some(vec![second]));
+
+    book.fill(Side::Bid, second, Lots(4));
+    assert!(!book.update(Side::Bid, second, None, Some(Lots(4))));
+    assert_eq!(book.violations(), vec![]);
+  }
+
+  #[test]
+  fn suspended_orders_resume_at_the_back() {
+    let mut book: OrderBook<u64, Lots> = OrderBook::default();
+    let first = book.insert(Side::Ask, Order::limit(100, Lots(10)));
+    let second = book.insert(Side::Ask, Order::limit(100, Lots(10)));
+
+    assert!(book.suspend(Side::Ask, first));
+    assert!(!book.suspend(Side::Ask, first));
+    assert_eq!(book.depth(Side::Ask), vec![(100, Lots(10))]);
+    let bid = book.insert(Side::Bid, Order::limit(100, Lots(15)));
+    assert_eq!(book.execute(Side::Bid, bid), Ok((false, vec![(second, Lots(10), true)])));
+
+    let third = book.insert(Side::Ask, Order::limit(100, Lots(10)));
+    assert!(book.resume(Side::Ask, first));
+    assert!(!book.resume(Side::Ask, first));
+    assert_eq!(book.level(Side::Ask, 100), Some(vec![third, first]));
+    assert_eq!(book.violations(), vec![]);
+  }
+}
+
 // #[cfg(test)]
 // mod test {
 //   use super::*;
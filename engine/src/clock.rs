@@ -0,0 +1,157 @@
+//! Injectable time source
+
+use derivative::Derivative;
+use derive_more::{Display, From, Into};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Nanoseconds since the unix epoch
+#[derive(
+  Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Derivative, From, Into, Serialize, Deserialize, Display,
+)]
+#[derivative(Debug = "transparent")]
+pub struct Timestamp(u64);
+
+impl Add<Duration> for Timestamp {
+  type Output = Timestamp;
+
+  /// Get the time `duration` later, or the last time there is if that is beyond it
+  fn add(self, duration: Duration) -> Self::Output {
+    Timestamp(self.0.saturating_add(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)))
+  }
+}
+
+/// Nanoseconds in a day
+pub const NANOS_PER_DAY: u64 = 86_400_000_000_000;
+
+/// A day of the UTC calendar, written as `YYYY-MM-DD`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Date {
+  pub year: u32,
+  /// From 1
+  pub month: u32,
+  /// From 1
+  pub day: u32,
+}
+
+impl Date {
+  /// Get the first day of the date's month
+  pub fn first_of_month(self) -> Self {
+    Self { day: 1, ..self }
+  }
+
+  /// Get the first day of the month before the date's
+  pub fn previous_month(self) -> Self {
+    match self.month {
+      1 => Self {
+        year: self.year - 1,
+        month: 12,
+        day: 1,
+      },
+      month => Self {
+        month: month - 1,
+        day: 1,
+        ..self
+      },
+    }
+  }
+}
+
+impl From<Timestamp> for Date {
+  fn from(time: Timestamp) -> Self {
+    // days since 0000-03-01, so leap days fall at the end of each year
+    let days = time.0 / NANOS_PER_DAY + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    Self {
+      year: year as u32,
+      month: month as u32,
+      day: day as u32,
+    }
+  }
+}
+
+impl fmt::Display for Date {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+  }
+}
+
+/// Draw a pseudo-random duration below `max`, the same for the same seed and time so a replay draws what the original run
+/// did
+pub fn jitter(seed: u64, at: Timestamp, max: Duration) -> Duration {
+  let max = max.as_nanos() as u64;
+  if max == 0 {
+    return Duration::default();
+  }
+
+  // splitmix64
+  let mut z = (seed ^ at.0).wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  Duration::from_nanos((z ^ (z >> 31)) % max)
+}
+
+/// A source of time for the engine
+pub trait Clock: Send + Sync {
+  /// Return the current time
+  fn now(&self) -> Timestamp;
+}
+
+/// The system wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Timestamp {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timestamp(since_epoch.as_nanos() as u64)
+  }
+}
+
+/// A clock that only moves when told to, for tests and simulations
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+  pub fn new(now: Timestamp) -> Self {
+    ManualClock(AtomicU64::new(now.into()))
+  }
+
+  /// Set the current time
+  pub fn set(&self, now: Timestamp) {
+    self.0.store(now.into(), Ordering::SeqCst);
+  }
+
+  /// Move the current time forward
+  pub fn advance(&self, duration: Duration) {
+    self.0.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Timestamp {
+    Timestamp(self.0.load(Ordering::SeqCst))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn adding_past_the_last_time_saturates() {
+    assert_eq!(Timestamp::from(u64::MAX - 1) + Duration::from_secs(1), Timestamp::from(u64::MAX));
+    assert_eq!(Timestamp::from(1) + Duration::MAX, Timestamp::from(u64::MAX));
+    assert_eq!(Timestamp::from(1) + Duration::from_nanos(2), Timestamp::from(3));
+  }
+}
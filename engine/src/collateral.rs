@@ -0,0 +1,73 @@
+//! Collateral
+//!
+//! Holdings count towards an account's buying power, alongside its cash balance, at the price the symbol last printed
+//! less a haircut configured for it. Marks are taken from the market data the engine publishes, so collateral is
+//! revalued as trades and closes print. A symbol without a haircut, or that has not printed, counts for nothing.
+
+use crate::engine::Account;
+use crate::feed::MarketData;
+use crate::liquidation::LiquidationRules;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The most a haircut may be, in basis points, discounting a holding entirely
+pub const FULL_HAIRCUT: u32 = 10_000;
+
+/// How much each symbol counts for as collateral, and whether buying power is enforced
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollateralRules {
+  /// The share of a symbol's marked value that does not count, in basis points up to `FULL_HAIRCUT`
+  pub haircuts: Vec<(Symbol, u32)>,
+  /// Reject bids that, with the account's other resting bids, would cost more than its buying power
+  pub enforce: bool,
+  /// Liquidate accounts whose buying power falls below their maintenance margin, or none to never liquidate
+  #[serde(default)]
+  pub liquidation: Option<LiquidationRules>,
+}
+
+/// Values accounts' holdings at the latest marks
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Collateral {
+  pub rules: CollateralRules,
+  #[serde(with = "crate::types::pairs")]
+  marks: HashMap<Symbol, Price>,
+}
+
+impl Collateral {
+  /// Mark a symbol to the price of a lit trade, auction or close
+  pub fn on_market_data(&mut self, data: &MarketData) {
+    match *data {
+      MarketData::Trade { symbol, price, .. }
+      | MarketData::AuctionUncross { symbol, price, .. }
+      | MarketData::ClosingPrice { symbol, price, .. } => {
+        self.marks.insert(symbol, price);
+      }
+      _ => (),
+    }
+  }
+
+  /// Get the value of a holding after its haircut, for a symbol quoting quantities to `precision` places
+  pub fn value(&self, symbol: Symbol, quantity: Quantity, precision: u8) -> u64 {
+    let haircut = self.rules.haircuts.iter().find(|&&(other, _)| other == symbol);
+    match (self.marks.get(&symbol), haircut) {
+      (Some(&mark), Some(&(_, haircut))) => {
+        let value = quantity.value_at(mark, precision);
+        value * u64::from(FULL_HAIRCUT - haircut.min(FULL_HAIRCUT)) / u64::from(FULL_HAIRCUT)
+      }
+      _ => 0,
+    }
+  }
+
+  /// Get what an account can spend, its balance and the value of its holdings after haircuts
+  ///
+  /// `precision` gives the decimal places each symbol quotes quantities to.
+  pub fn buying_power<F: Fn(Symbol) -> u8>(&self, account: &Account, precision: F) -> u64 {
+    let holdings: u64 = account
+      .portfolio
+      .iter()
+      .map(|(&symbol, &quantity)| self.value(symbol, quantity, precision(symbol)))
+      .sum();
+    u64::from(u32::from(account.balance)) + holdings
+  }
+}
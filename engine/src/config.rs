@@ -0,0 +1,157 @@
+//! Per-symbol configuration
+
+use crate::collateral::{CollateralRules, FULL_HAIRCUT};
+use crate::fees::{FeeModel, FeeSchedule};
+use crate::order_to_trade::OrderToTradeRules;
+use crate::surveillance::SurveillanceRules;
+use crate::types::*;
+use failure::Fail;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How a symbol matches orders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum TradingMode {
+  /// Orders match on arrival
+  #[default]
+  Continuous,
+  /// Orders accumulate and only match when the book is uncrossed, once every `interval`
+  PeriodicAuction { interval: Duration },
+}
+
+/// Auctions held at fixed times of day in a continuously trading symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuctionSchedule {
+  /// How often an auction is held, counted from midnight UTC, e.g. every hour on the hour
+  pub every: Duration,
+  /// How long before each uncross the auction is announced, during which orders rest without matching
+  pub call: Duration,
+  /// Uncross each auction at a random moment up to this long after its scheduled time, so its end cannot be gamed
+  #[serde(default)]
+  pub jitter: Duration,
+  /// Seeds the random uncross times
+  #[serde(default)]
+  pub seed: u64,
+}
+
+/// What becomes of a cancel arriving before its order has rested for the minimum quote life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EarlyCancel {
+  Reject,
+  /// Accept the cancel but only take the order off the book once it has rested long enough
+  Delay,
+}
+
+/// How long a symbol's lit orders must rest before they may be cancelled, against quote stuffing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinimumQuoteLife {
+  pub duration: Duration,
+  pub early_cancel: EarlyCancel,
+}
+
+/// The configuration of a symbol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SymbolConfig {
+  pub trading_mode: TradingMode,
+  /// Accept `OrderFlags::DARK` orders into a non-displayed book matching at the lit midpoint
+  pub dark_pool: bool,
+  /// Cross incoming orders against resting orders from other accounts of the same firm, at or within the BBO, before
+  /// they reach the public book
+  pub internalization: bool,
+  /// Hold marketable orders for this long so others can respond with price-improving interest before they sweep the book
+  pub price_improvement: Option<Duration>,
+  /// Hold marketable orders for this long before they match, without announcing them, instead of any price improvement
+  #[serde(default)]
+  pub speed_bump: Option<Duration>,
+  #[serde(default)]
+  pub scheduled_auctions: Option<AuctionSchedule>,
+  #[serde(default)]
+  pub minimum_quote_life: Option<MinimumQuoteLife>,
+  /// Which of the fee schedule's rates the symbol's fills charge each side
+  #[serde(default)]
+  pub fee_model: FeeModel,
+  /// How many decimal places quantities are quoted to, up to `MAX_QUANTITY_PRECISION`, each `Quantity` counting units
+  /// of the last place
+  #[serde(default)]
+  pub quantity_precision: u8,
+  /// The increment, in units of the last place, that order quantities must be a multiple of
+  #[serde(default)]
+  pub lot_size: Option<Quantity>,
+}
+
+/// Configuration that can be swapped while the engine runs, e.g. loaded from a file on `SIGHUP`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeConfig {
+  /// The configuration of each listed symbol, which must already exist; symbols not listed are left as they are
+  #[serde(default)]
+  pub symbols: Vec<(Symbol, SymbolConfig)>,
+  #[serde(default)]
+  pub surveillance: SurveillanceRules,
+  #[serde(default)]
+  pub order_to_trade: OrderToTradeRules,
+  #[serde(default)]
+  pub fees: FeeSchedule,
+  #[serde(default)]
+  pub collateral: CollateralRules,
+}
+
+/// Why a configuration was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Fail, Serialize, Deserialize)]
+pub enum ConfigError {
+  #[fail(display = "symbol '{}' must auction at a non-zero interval", symbol)]
+  ZeroAuctionInterval { symbol: Symbol },
+  #[fail(display = "symbol '{}' must schedule auctions at an interval longer than their call and jitter", symbol)]
+  InvalidAuctionSchedule { symbol: Symbol },
+  #[fail(display = "the order-to-trade window must be non-zero")]
+  ZeroOrderToTradeWindow,
+  #[fail(display = "order-to-trade ratios must not decrease from warning to fee to throttle")]
+  UnorderedOrderToTradeRatios,
+  #[fail(display = "fee tiers must start from no volume and rise")]
+  UnorderedFeeTiers,
+  #[fail(display = "the haircut of symbol '{}' is more than the whole of its value", symbol)]
+  HaircutOutOfRange { symbol: Symbol },
+  #[fail(display = "symbol '{}' quotes quantities to more places than the engine can hold", symbol)]
+  QuantityPrecisionOutOfRange { symbol: Symbol },
+  #[fail(display = "symbol '{}' must have a non-zero lot size", symbol)]
+  ZeroLotSize { symbol: Symbol },
+  #[fail(display = "an index divisor must be positive and finite")]
+  InvalidIndexDivisor,
+}
+
+impl RuntimeConfig {
+  /// Check the configuration is consistent, apart from whether its symbols exist
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    for &(symbol, ref config) in self.symbols.iter() {
+      if config.trading_mode == (TradingMode::PeriodicAuction { interval: Duration::default() }) {
+        return Err(ConfigError::ZeroAuctionInterval { symbol });
+      }
+      if let Some(schedule) = config.scheduled_auctions {
+        if schedule.call + schedule.jitter >= schedule.every {
+          return Err(ConfigError::InvalidAuctionSchedule { symbol });
+        }
+      }
+      if config.quantity_precision > MAX_QUANTITY_PRECISION {
+        return Err(ConfigError::QuantityPrecisionOutOfRange { symbol });
+      }
+      if config.lot_size == Some(Quantity::default()) {
+        return Err(ConfigError::ZeroLotSize { symbol });
+      }
+    }
+
+    let rules = &self.order_to_trade;
+    if rules.window == Duration::default() {
+      return Err(ConfigError::ZeroOrderToTradeWindow);
+    }
+    if !(rules.warning_ratio <= rules.fee_ratio && rules.fee_ratio <= rules.throttle_ratio) {
+      return Err(ConfigError::UnorderedOrderToTradeRatios);
+    }
+    if !self.fees.is_ordered() {
+      return Err(ConfigError::UnorderedFeeTiers);
+    }
+    if let Some(&(symbol, _)) = self.collateral.haircuts.iter().find(|&&(_, haircut)| haircut > FULL_HAIRCUT) {
+      return Err(ConfigError::HaircutOutOfRange { symbol });
+    }
+
+    Ok(())
+  }
+}
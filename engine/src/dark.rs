@@ -0,0 +1,108 @@
+//! Non-displayed midpoint book
+
+use crate::engine::Id;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Resting orders that match only at the lit book's midpoint
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DarkPool {
+  #[serde(with = "crate::types::pairs")]
+  orders: HashMap<Id, (Side, Order)>,
+  bids: VecDeque<Id>,
+  asks: VecDeque<Id>,
+}
+
+impl DarkPool {
+  /// Insert an order
+  pub fn insert(&mut self, side: Side, id: Id, order: Order) {
+    self.orders.insert(id, (side, order));
+    match side {
+      Side::Bid => self.bids.push_back(id),
+      Side::Ask => self.asks.push_back(id),
+    }
+  }
+
+  /// Get an order
+  pub fn get(&self, id: Id) -> Option<&Order> {
+    self.orders.get(&id).map(|(_, order)| order)
+  }
+
+  /// Get the side an order rests on
+  pub fn side(&self, id: Id) -> Option<Side> {
+    self.orders.get(&id).map(|&(side, _)| side)
+  }
+
+  /// Cancel an order
+  pub fn cancel(&mut self, id: Id) -> bool {
+    if let Some((side, order)) = self.orders.get_mut(&id) {
+      let queue = match side {
+        Side::Bid => &mut self.bids,
+        Side::Ask => &mut self.asks,
+      };
+
+      if let Some(index) = queue.iter().position(|&other| other == id) {
+        queue.remove(index);
+        order.is_cancelled = true;
+        return true;
+      }
+    }
+
+    false
+  }
+
+  /// Match resting orders at `midpoint` in time priority
+  ///
+  /// Both orders must accept the midpoint and each fill must be at least the minimum quantity of both orders. An order
+  /// whose remaining quantity is below its own minimum may still complete.
+  ///
+  /// # Returns
+  /// The `(bid, ask, quantity)` of each execution
+  pub fn match_at(&mut self, midpoint: Price) -> Vec<(Id, Id, Quantity)> {
+    let minimum = |order: &Order| order.minimum_quantity.min(order.remaining());
+
+    let mut executions = vec![];
+    for &bid_id in self.bids.iter() {
+      for &ask_id in self.asks.iter() {
+        let (bid, ask) = (self.orders[&bid_id].1, self.orders[&ask_id].1);
+        if bid.is_filled() || bid.price < midpoint {
+          break;
+        }
+        if ask.is_filled() || ask.price > midpoint {
+          continue;
+        }
+
+        let quantity = bid.remaining().min(ask.remaining());
+        if quantity < minimum(&bid) || quantity < minimum(&ask) {
+          continue;
+        }
+
+        for id in &[bid_id, ask_id] {
+          self.orders.get_mut(id).unwrap().1.filled += quantity;
+        }
+        executions.push((bid_id, ask_id, quantity));
+      }
+    }
+
+    let orders = &self.orders;
+    self.bids.retain(|id| !orders[id].1.is_filled());
+    self.asks.retain(|id| !orders[id].1.is_filled());
+
+    executions
+  }
+}
+
+impl Hash for DarkPool {
+  // hash in queue order, since the order map has none
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    for queue in &[&self.bids, &self.asks] {
+      queue.len().hash(state);
+      for id in queue.iter() {
+        id.hash(state);
+        self.orders[id].hash(state);
+      }
+    }
+  }
+}
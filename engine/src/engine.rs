@@ -1,10 +1,36 @@
-use crate::book::OrderBook;
+use crate::activity::{Activity, ActivityLog, ActivityPage};
+use crate::auction::{self, ImprovementAuction, ScheduledAuction};
+use crate::audit::{AuditEvent, AuditRecord, CancelReason};
+use crate::book::{Contra, OrderBook, TopOfBook, Violation};
+use crate::clock::{Clock, Date, ManualClock, SystemClock, Timestamp};
+use crate::config::{ConfigError, EarlyCancel, RuntimeConfig, SymbolConfig, TradingMode};
+use crate::dark::DarkPool;
+use crate::eod::{DailyStats, EndOfDay, Settlement};
+use crate::events::{EngineEvent, Subscribers};
+use crate::feed::{Candle, Entitlement, Feed, MarketData, QuoteTick, TradeTick};
+use crate::hash::StateHasher;
+use crate::index::Index;
+use crate::journal::{JournalEntry, JournalEvent, JournalPoint};
+use crate::label::Label;
+use crate::levels::LevelStoreKind;
+use crate::liquidation::{Liquidation, LiquidationRules, LIQUIDATION_TAG};
+use crate::collateral::{Collateral, CollateralRules};
+use crate::fees::{FeeLedger, FeeModel, FeeMonitor, FeePeriod, FeeReport, FeeSchedule, FeeTierStatus};
+use crate::risk::{RiskBypass, RiskCheck};
+use crate::order_to_trade::{Consequence, OrderToTradeMonitor, OrderToTradeRules, OrderToTradeStatus};
+use crate::surveillance::{Alert, Party, Surveillance, SurveillanceRules};
 use crate::types::*;
 use derivative::Derivative;
 use derive_more::{Add, AddAssign, Display, From, Into};
 use failure::Fail;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::IpAddr;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::Duration;
 
 
 // TODO: do not leak out newtypes for this API
@@ -16,8 +42,35 @@ use std::collections::HashMap;
 #[derivative(Debug = "transparent")]
 pub struct Id(usize);
 
+/// The position of a state-changing event in the engine-wide sequence shared by every output stream
+#[derive(
+  Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Display, AddAssign, From, Into, Derivative,
+  Default,
+)]
+#[derivative(Debug = "transparent")]
+pub struct EventId(u64);
+
+/// A trade, numbered in the order trades print, so replaying a journal numbers them the same
+#[derive(
+  Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Display, AddAssign, From, Into, Derivative,
+  Default,
+)]
+#[derivative(Debug = "transparent")]
+pub struct TradeId(u64);
+
+/// Which side of a trade a fill was on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Liquidity {
+  /// The order was resting and added the liquidity
+  Maker,
+  /// The order arrived and took the liquidity
+  Taker,
+  /// Neither order arrived first, as in an auction uncross or the dark pool
+  Crossed,
+}
+
 /// An error
-#[derive(Debug, Clone, Copy, Fail, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Fail, Serialize, Deserialize)]
 pub enum Error {
   #[fail(display = "account number '{}' does not exist", id)]
   AccountDoesNotExist { id: AccountId },
@@ -25,16 +78,70 @@ pub enum Error {
   SymbolDoesNotExist { symbol: Symbol },
   #[fail(display = "order with id '{}' does not exist", id)]
   IdDoesNotExist { id: Id },
+  #[fail(display = "index '{}' does not exist", symbol)]
+  IndexDoesNotExist { symbol: Symbol },
+  #[fail(display = "symbol '{}' only trades in auctions", symbol)]
+  ContinuousTradingDisabled { symbol: Symbol },
+  #[fail(display = "symbol '{}' does not accept dark orders", symbol)]
+  DarkPoolDisabled { symbol: Symbol },
+  #[fail(display = "account '{}' is not permitted to do that", id)]
+  PermissionDenied { id: AccountId },
+  #[fail(display = "account '{}' is throttled for its order-to-trade ratio", id)]
+  OrderToTradeRatioExceeded { id: AccountId },
+  #[fail(display = "state hash {} does not match the journaled {}", actual, expected)]
+  StateHashMismatch { expected: u64, actual: u64 },
+  #[fail(display = "invalid configuration: {}", reason)]
+  InvalidConfig { reason: ConfigError },
+  #[fail(display = "the server is already serving its limit of {} connections", limit)]
+  TooManyConnections { limit: usize },
+  #[fail(display = "journal entry {} arrived after entry {}", sequence, last)]
+  JournalOutOfOrder { last: EventId, sequence: EventId },
+  #[fail(display = "account '{}' is only entitled to {:?} market data", id, entitlement)]
+  NotEntitled { id: AccountId, entitlement: Entitlement },
+  #[fail(display = "account '{}' is {:?}, which does not allow that", id, state)]
+  AccountRestricted { id: AccountId, state: AccountState },
+  #[fail(display = "account '{}' has only {} of buying power left", id, buying_power)]
+  InsufficientBuyingPower { id: AccountId, buying_power: u64 },
+  #[fail(display = "order entry is rejected on every symbol; account '{}' may only cancel and query", id)]
+  RejectingAllOrders { id: AccountId },
+  #[fail(display = "input could not be decoded as a command at line {} column {}", line, column)]
+  MalformedCommand { line: usize, column: usize },
+  #[fail(display = "order '{}' may not be cancelled before it has rested until {}", id, rests_until)]
+  QuoteLifeNotReached { id: Id, rests_until: Timestamp },
+  #[fail(display = "the server could not write a dump of symbol '{}'", symbol)]
+  DumpFailed { symbol: Symbol },
+  #[fail(display = "that command is not served on {:?} endpoints", role)]
+  NotServedOnEndpoint { role: EndpointRole },
+  #[fail(display = "account '{}' may not be used from {}", id, source)]
+  SourceNotAllowed { id: AccountId, source: IpAddr },
+  #[fail(display = "account '{}' is not permitted to enter orders on symbol '{}'", id, symbol)]
+  SymbolNotPermitted { id: AccountId, symbol: Symbol },
+  #[fail(display = "order '{}' may not be replaced at a price marketable beyond {}", id, limit)]
+  ReplaceOutsideBand { id: Id, limit: Price },
+  #[fail(display = "symbol '{}' only takes quantities in multiples of {}", symbol, lot_size)]
+  InvalidLotSize { symbol: Symbol, lot_size: Quantity },
+  #[fail(display = "a risk bypass must give its reason")]
+  MissingBypassReason,
+  #[fail(display = "account '{}' has only {} of '{}' left to deliver", id, holding, symbol)]
+  InsufficientHoldings { id: AccountId, symbol: Symbol, holding: Quantity },
+  #[fail(display = "symbol '{}' already exists", symbol)]
+  SymbolAlreadyExists { symbol: Symbol },
+  #[fail(display = "the book of symbol '{}' does not match the engine's index of its orders", symbol)]
+  InconsistentBook { symbol: Symbol },
+  #[fail(display = "order entry is halted on symbol '{}'; only cancels and queries are allowed", symbol)]
+  SymbolHalted { symbol: Symbol },
+  #[fail(display = "account '{}' only has activity from entry {} on", id, oldest)]
+  ActivityExpired { id: AccountId, oldest: u64 },
 }
 
 /// A match engine command
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Command {
   pub account_id: AccountId,
   pub kind: CommandKind,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum CommandKind {
   // FIXME: these should take in an account id
   CancelOrder(Id),
@@ -43,165 +150,5553 @@ pub enum CommandKind {
   ExecuteOrder(Id),
   GetQuote(Symbol, Side),
   GetAccount(AccountId),
+  /// Get an account without its orders and holdings, which is cheaper to poll
+  GetAccountSummary(AccountId),
+  GetIndex(Symbol),
+  /// Admin only
+  GetOrderToTradeRatio(AccountId),
+  /// Get a page of an account's open orders, oldest first
+  GetOpenOrders(AccountId, Page),
+  /// Get the queue of orders resting at a price, without their accounts
+  GetLevel(Symbol, Side, Price),
+  /// Change a resting lit order's price or total quantity, leaving whichever is `None` as it is
+  ///
+  /// The order keeps its queue position only if its price is unchanged and its quantity shrinks. Otherwise it moves to
+  /// the back of its new level, and is matched as if newly placed if the new price crosses.
+  UpdateOrder(Id, Option<Price>, Option<Quantity>),
+  /// Take a resting lit order out of matching and market data, keeping it to resume later
+  SuspendOrder(Id),
+  /// Return a suspended order to the back of its level, matching it if it now crosses
+  ResumeOrder(Id),
+  /// Be pushed a `Push::ExecutionReport` for each of an account's order events from now on, until the session ends
+  ///
+  /// Only the account itself or an admin may subscribe to it.
+  SubscribeExecutions(AccountId),
+  /// Be pushed a symbol's market data of one feed from now on, until unsubscribed or the session ends
+  Subscribe(Symbol, Feed),
+  /// Stop being pushed a symbol's market data of one feed
+  Unsubscribe(Symbol, Feed),
+  /// Be pushed market data once every interval, only the latest update of each feed in it, or as it happens if `None`
+  Conflate(Option<Duration>),
+  /// Choose how the session's placed orders are acknowledged from now on, until the session ends
+  ///
+  /// Acknowledgements are sent by the server rather than the engine, so the engine always answers with full results.
+  SetAckMode(AckMode),
+  /// Admin only: limit the market data an account may be sent
+  SetEntitlement(AccountId, Entitlement),
+  /// Admin only: protect an account's replaces from crossing the book too far, or stop protecting them
+  SetReplaceProtection(AccountId, Option<ReplaceProtection>),
+  /// Get up to `MAX_PAGE_SIZE` of a symbol's trades from the first time until before the second, oldest first
+  ///
+  /// History is kept by the server rather than the engine, so a server without a tick store always answers with none.
+  GetTradesHistory(Symbol, Timestamp, Timestamp),
+  /// Get up to `MAX_PAGE_SIZE` of a symbol's changes of best bid and ask from the first time until before the second,
+  /// oldest first
+  GetQuotesHistory(Symbol, Timestamp, Timestamp),
+  /// Admin only: create an account, or get the one already created with the same reference
+  CreateAccount(NewAccount),
+  /// Get an account's volume-based fee tier
+  ///
+  /// Only the account itself or an admin may get it.
+  GetFeeTier(AccountId),
+  /// Get an account's volume-based fee tier, with the rates a symbol's fee model charges it
+  ///
+  /// Only the account itself or an admin may get it.
+  GetSymbolFeeTier(AccountId, Symbol),
+  /// Get what an account is worth, its holdings marked to their symbols' reference prices
+  ///
+  /// Only the account itself or an admin may get it.
+  GetEquity(AccountId),
+  /// Get an account's liquidation, if it is being liquidated
+  ///
+  /// Only the account itself or an admin may get it.
+  GetLiquidation(AccountId),
+  /// Admin only: move an account to another state, cancelling its resting orders if it closes
+  ///
+  /// A closed account stays closed.
+  SetAccountState(AccountId, AccountState),
+  /// Get where a resting lit order stands in the queue at its price
+  ///
+  /// Only the order's account or an admin may get it.
+  GetQueuePosition(Id),
+  /// Get the totals of a symbol's lit book
+  GetBookStats(Symbol),
+  /// Admin only: reject every order entry command on every symbol from every account, or stop rejecting them
+  ///
+  /// Cancels, suspensions, queries and admin commands are still allowed.
+  SetRejectAll(bool),
+  /// Admin only: have the session this is sent on bypass some pre-trade checks for admin accounts, or none again
+  ///
+  /// The bypass lasts until the session replaces or clears it.
+  SetRiskBypass(Option<RiskBypass>),
+  /// Admin only: get the input each connected session sent that could not be decoded as commands
+  ///
+  /// Dead letters are kept by the server rather than the engine, so the engine always answers with none.
+  GetDeadLetters,
+  /// Admin only: write a symbol's whole lit book to a file on the server, answering with the file's path
+  ///
+  /// Files are written by the server rather than the engine, so the engine always answers with an empty path.
+  DumpBook(Symbol, DumpFormat),
+  /// Admin only: let only a symbol's grantees enter orders on it, or any account again
+  ///
+  /// Orders already resting on the symbol are left as they are, and may still be cancelled.
+  RestrictSymbol(Symbol, bool),
+  /// Admin only: grant or revoke an account's or firm's permission to enter orders on a symbol, which only limits
+  /// anyone while the symbol is restricted
+  SetSymbolPermission(Symbol, Grantee, bool),
+  /// Admin only: get whether a symbol is restricted and who it is granted to
+  GetSymbolPermissions(Symbol),
+  /// Cancel every resting order of an account placed with a tag, e.g. to flatten one strategy without touching others
+  ///
+  /// Only the account itself or an admin may cancel its orders. Orders without a tag are never cancelled this way.
+  CancelTagged(AccountId, Label),
+  /// Admin only: list a new symbol with an empty book
+  ListSymbol(Symbol),
+  /// Admin only: cancel every order resting on a symbol and give it an empty book, discarding its old one
+  ///
+  /// Each order is cancelled as if by its account, so its account is sent an execution report for it.
+  ReplaceSymbol(Symbol),
+  /// Admin only: reject every order entry command on a symbol from every account, or stop rejecting them
+  ///
+  /// Cancels, suspensions and queries are still allowed.
+  HaltSymbol(Symbol, bool),
+  /// Admin only: get how the matching of each symbol is progressing
+  ///
+  /// Progress is watched by the server rather than the engine, so the engine always answers with none.
+  GetWatchdog,
+  /// Get up to the given number, at most `MAX_PAGE_SIZE`, of an account's activity entries after the given cursor, or
+  /// from its oldest if it is 0, oldest first
+  ///
+  /// Only the account itself or an admin may get it.
+  GetActivity(AccountId, u64, usize),
+  /// Get the fees one account, or every account if `None`, accrued over a period ending on a day, by symbol
+  ///
+  /// Only the account itself or an admin may get one account's fees, and only an admin every account's.
+  GetFees(Option<AccountId>, Date, FeePeriod),
+}
+
+/// How a placed order is acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum AckMode {
+  /// With a `Success::PlaceOrder` of everything placing it did, including its fills
+  #[default]
+  Full,
+  /// With a `Success::Accepted` of only its id, its fills following as execution reports
+  ///
+  /// Placing an order subscribes the session to its account's execution reports, as `CommandKind::SubscribeExecutions`
+  /// does, so each of the order's events is pushed after the acknowledgement.
+  Fast,
+}
+
+/// How a book dump is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DumpFormat {
+  /// A `BookDump`
+  Json,
+  /// One row per queued order, without the book's stats
+  Csv,
+}
+
+/// The details of an account to create
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct NewAccount {
+  /// The creating system's own reference for the account, or empty for an account created every time it is asked for
+  pub reference: Label,
+  #[serde(default)]
+  pub name: Label,
+  #[serde(default)]
+  pub firm: Option<FirmId>,
+}
+
+/// An account or firm a restricted symbol can be granted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Grantee {
+  Account(AccountId),
+  /// Every account of the firm
+  Firm(FirmId),
+}
+
+/// Who may enter orders on a symbol
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct SymbolPermissions {
+  /// Whether only the grantees may enter orders on the symbol, rather than any account
+  pub is_restricted: bool,
+  /// In the order they were granted
+  pub grantees: Vec<Grantee>,
+}
+
+impl SymbolPermissions {
+  /// Returns true if an account may enter orders on the symbol
+  pub fn permits(&self, id: AccountId, account: &Account) -> bool {
+    !self.is_restricted
+      || self.grantees.contains(&Grantee::Account(id))
+      || account.firm.is_some_and(|firm| self.grantees.contains(&Grantee::Firm(firm)))
+  }
+}
+
+/// An order in a price level's queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueEntry {
+  pub id: Id,
+  pub remaining: Quantity,
+  /// Orders ahead of this one at its price
+  pub position: usize,
+}
+
+/// Where a resting order stands in the queue at its price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePosition {
+  pub price: Price,
+  /// Orders ahead of this one at its price
+  pub position: usize,
+  /// The quantity left to fill of the orders ahead
+  pub ahead: Quantity,
+  /// Orders resting at the price, this one included
+  pub orders: usize,
+  /// The quantity resting at the price, this one included
+  pub quantity: Quantity,
+}
+
+/// What an account is worth, with its holdings marked to their symbols' reference prices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Equity {
+  pub account: AccountId,
+  pub cash: u64,
+  /// The value of the account's holdings, counting nothing for a symbol without a reference price
+  pub position_value: u64,
+  /// The cost of the account's resting bids, which its cash is committed to
+  pub reserved: u64,
+  /// Cash and position value together
+  pub equity: u64,
+}
+
+/// The resting liquidity of a symbol's lit book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookStats {
+  pub symbol: Symbol,
+  /// Orders queued on either side, not counting suspended ones
+  pub orders: usize,
+  pub bid_quantity: Quantity,
+  pub ask_quantity: Quantity,
+  /// The value of the bids at the `STATS_LEVELS` best prices
+  pub bid_notional: u64,
+  /// The value of the asks at the `STATS_LEVELS` best prices
+  pub ask_notional: u64,
+}
+
+/// A price level of a book dump, with its queue in priority order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DumpedLevel {
+  pub price: Price,
+  pub quantity: Quantity,
+  pub orders: Vec<QueueEntry>,
+}
+
+/// The whole of a symbol's lit book, for offline inspection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookDump {
+  pub symbol: Symbol,
+  pub taken_at: Timestamp,
+  pub stats: BookStats,
+  /// Best price first
+  pub bids: Vec<DumpedLevel>,
+  /// Best price first
+  pub asks: Vec<DumpedLevel>,
+}
+
+impl BookDump {
+  /// Rebuild a book holding the dumped orders in their queue order, with fresh ids and nothing filled
+  pub fn to_book(&self, kind: LevelStoreKind) -> OrderBook {
+    let mut book = OrderBook::new(kind);
+    for &(side, levels) in &[(Side::Bid, &self.bids), (Side::Ask, &self.asks)] {
+      for level in levels {
+        for entry in &level.orders {
+          book.insert(side, Order::new(level.price, entry.remaining));
+        }
+      }
+    }
+    book
+  }
+}
+
+/// What a session sent that could not be decoded as commands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetters {
+  pub session: SessionId,
+  /// Inputs dropped since the session connected
+  pub count: usize,
+  /// The latest inputs dropped, oldest first, as text with anything not UTF-8 replaced
+  pub inputs: Vec<String>,
+}
+
+/// How the matching of a symbol is progressing, as watched by the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolProgress {
+  pub symbol: Symbol,
+  /// The engine's last event when the last command on the symbol finished, if one has
+  pub sequence: Option<EventId>,
+  /// When the last command on the symbol finished
+  pub processed_at: Timestamp,
+  /// When the command being processed on the symbol started, if one is
+  pub busy_since: Option<Timestamp>,
+  /// Commands on the symbol processed so far
+  pub processed: u64,
+  /// Commands on the symbol received and waiting behind the one being processed
+  #[serde(default)]
+  pub pending: usize,
+  /// The longest any of them took to process, in nanoseconds
+  pub max_latency: u64,
+  /// Times the symbol was found stalled
+  pub stalls: usize,
+  /// Whether the command being processed, or the last one, took longer than a stall is allowed to
+  pub is_stalled: bool,
+  /// Whether order entry is halted on the symbol
+  pub is_halted: bool,
+}
+
+/// Where a paginated query starts and how much it returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct Page {
+  /// Start after this id, the `next` of the previous page, or from the beginning if `None`
+  pub after: Option<Id>,
+  /// Most items to return, up to `MAX_PAGE_SIZE`
+  pub limit: usize,
+}
+
+/// Most items returned in one page, however many are asked for
+pub const MAX_PAGE_SIZE: usize = 1_000;
+
+/// Price levels of each side counted towards a book's notional in `BookStats`
+pub const STATS_LEVELS: usize = 5;
+
+/// A page of open orders
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct OrderPage {
+  pub orders: Vec<(Id, Order)>,
+  /// The cursor of the next page, or `None` if this is the last
+  pub next: Option<Id>,
+}
+
+impl CommandKind {
+  /// Returns true if the command only reads engine state
+  pub fn is_query(&self) -> bool {
+    use CommandKind::*;
+    match self {
+      GetOrder(_)
+      | GetQuote(..)
+      | GetAccount(_)
+      | GetAccountSummary(_)
+      | GetIndex(_)
+      | GetOrderToTradeRatio(_)
+      | GetOpenOrders(..)
+      | GetLevel(..)
+      | SubscribeExecutions(_)
+      | Subscribe(..)
+      | Unsubscribe(..)
+      | Conflate(_)
+      | SetAckMode(_)
+      | GetTradesHistory(..)
+      | GetQuotesHistory(..)
+      | GetFeeTier(_)
+      | GetSymbolFeeTier(..)
+      | GetEquity(_)
+      | GetLiquidation(_)
+      | GetQueuePosition(_)
+      | GetBookStats(_)
+      | GetDeadLetters
+      | DumpBook(..)
+      | GetSymbolPermissions(_)
+      | GetWatchdog
+      | GetActivity(..)
+      | GetFees(..) => true,
+      CancelOrder(_)
+      | PlaceOrder(..)
+      | ExecuteOrder(_)
+      | UpdateOrder(..)
+      | SuspendOrder(_)
+      | ResumeOrder(_)
+      | SetEntitlement(..)
+      | SetReplaceProtection(..)
+      | CreateAccount(_)
+      | SetAccountState(..)
+      | SetRejectAll(_)
+      | SetRiskBypass(_)
+      | RestrictSymbol(..)
+      | SetSymbolPermission(..)
+      | CancelTagged(..)
+      | ListSymbol(_)
+      | ReplaceSymbol(_)
+      | HaltSymbol(..) => false,
+    }
+  }
+
+  /// Returns true if the command enters or adds to an order that may trade
+  pub fn is_order_entry(&self) -> bool {
+    use CommandKind::*;
+    matches!(self, PlaceOrder(..) | UpdateOrder(..) | ResumeOrder(_) | ExecuteOrder(_))
+  }
+}
+
+/// What a listener of the server is for, limiting the commands its sessions may send
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum EndpointRole {
+  /// Every command, from any account
+  #[default]
+  All,
+  /// Orders, cancels and queries about an account's own orders
+  OrderEntry,
+  /// Market data subscriptions and queries
+  MarketData,
+  /// Execution reports of accounts' orders, without entering any
+  DropCopy,
+  /// Every command, from admin accounts only
+  Admin,
+}
+
+impl EndpointRole {
+  /// Returns true if sessions on an endpoint of this role may send the command
+  pub fn permits(&self, kind: &CommandKind) -> bool {
+    use CommandKind::*;
+    match self {
+      EndpointRole::All | EndpointRole::Admin => true,
+      EndpointRole::OrderEntry => matches!(
+        kind,
+        CancelOrder(_)
+          | PlaceOrder(..)
+          | GetOrder(_)
+          | ExecuteOrder(_)
+          | GetAccount(_)
+          | GetAccountSummary(_)
+          | GetOpenOrders(..)
+          | UpdateOrder(..)
+          | SuspendOrder(_)
+          | ResumeOrder(_)
+          | SubscribeExecutions(_)
+          | SetAckMode(_)
+          | GetFeeTier(_)
+          | GetSymbolFeeTier(..)
+          | GetEquity(_)
+          | GetLiquidation(_)
+          | GetQueuePosition(_)
+          | CancelTagged(..)
+          | GetActivity(..)
+          | GetFees(..)
+      ),
+      EndpointRole::MarketData => matches!(
+        kind,
+        GetQuote(..)
+          | GetIndex(_)
+          | GetLevel(..)
+          | Subscribe(..)
+          | Unsubscribe(..)
+          | Conflate(_)
+          | GetTradesHistory(..)
+          | GetQuotesHistory(..)
+          | GetBookStats(_)
+      ),
+      EndpointRole::DropCopy => {
+        matches!(kind, SubscribeExecutions(_) | GetOrder(_) | GetOpenOrders(..) | GetActivity(..))
+      }
+    }
+  }
+
+  /// Returns true if only admin accounts may send commands on an endpoint of this role
+  pub fn requires_admin(&self) -> bool {
+    *self == EndpointRole::Admin
+  }
+}
+
+/// A fill of a resting order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Execution {
+  /// The resting order
+  pub id: Id,
+  pub trade: TradeId,
+  pub quantity: Quantity,
+  /// The resting order is now filled
+  pub is_filled: bool,
+  /// When the command that caused the fill was received
+  pub received_at: Timestamp,
+  /// When the fill was matched
+  pub matched_at: Timestamp,
+}
+
+/// An order with the average price of its fills so far
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OrderStatus {
+  pub order: Order,
+  /// Weighted by quantity, or `None` if nothing has filled
+  pub average_price: Option<f64>,
+}
+
+/// An order as it was placed, with the fills it matched on arrival
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+  pub id: Id,
+  /// In the order they matched
+  pub fills: Vec<Fill>,
+  /// The quantity left resting, or held in a price improvement auction
+  pub remaining: Quantity,
+  pub state: OrderState,
+  /// How many decimal places the symbol quotes quantities to, so each quantity counts units of `10^-precision`
+  #[serde(default)]
+  pub quantity_precision: u8,
+}
+
+/// One of an order's fills, without the order it matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fill {
+  /// The fill's place in the engine-wide event sequence, as in the audit trail
+  pub execution: EventId,
+  pub trade: TradeId,
+  pub liquidity: Liquidity,
+  pub price: Price,
+  pub quantity: Quantity,
+}
+
+/// Where an order stands after being placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OrderState {
+  /// Resting with none of it filled
+  New,
+  /// Resting with some of it filled
+  PartiallyFilled,
+  Filled,
+  /// Held in a price improvement auction or behind a speed bump until it ends
+  Held,
+  /// Taken off the book before it filled, as when it could only have matched orders of its own firm
+  Cancelled,
 }
 
 /// Result of a successful match engine processing
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Success {
-  GetOrder(Order),
-  PlaceOrder(Id),
+  GetOrder(OrderStatus),
+  PlaceOrder(Placement),
+  /// The id of an order placed by a session acknowledged with `AckMode::Fast`
+  Accepted(Id),
   CancelOrder(bool),
-  ExecuteOrder(bool, Vec<(Id, Quantity, bool)>),
+  /// A cancel asked for before the order's minimum quote life under `EarlyCancel::Delay`, taking effect when the life
+  /// ends unless the order fills first
+  CancelPending(Timestamp),
+  /// Whether the order was resting and could be updated
+  UpdateOrder(bool),
+  /// Whether the order was resting and is now suspended
+  SuspendOrder(bool),
+  /// Whether the order was suspended and is now resting
+  ResumeOrder(bool),
+  ExecuteOrder(bool, Vec<Execution>),
   GetQuote(Price),
   GetAccount(Account),
+  GetAccountSummary(AccountSummary),
+  /// The current index value, or `None` if not every constituent has traded
+  GetIndex(Option<f64>),
+  GetOrderToTradeRatio(OrderToTradeStatus),
+  GetOpenOrders(OrderPage),
+  /// The queue at the price, first to fill first
+  GetLevel(Vec<QueueEntry>),
+  SubscribeExecutions,
+  Subscribe,
+  Unsubscribe,
+  Conflate,
+  SetAckMode,
+  SetEntitlement,
+  SetReplaceProtection,
+  GetTradesHistory(Vec<TradeTick>),
+  GetQuotesHistory(Vec<QuoteTick>),
+  CreateAccount(AccountId),
+  SetAccountState,
+  SetRejectAll,
+  SetRiskBypass,
+  GetFeeTier(FeeTierStatus),
+  GetEquity(Equity),
+  GetLiquidation(Option<Liquidation>),
+  GetQueuePosition(QueuePosition),
+  GetBookStats(BookStats),
+  GetDeadLetters(Vec<DeadLetters>),
+  /// The path of the file written
+  DumpBook(String),
+  RestrictSymbol,
+  SetSymbolPermission,
+  GetSymbolPermissions(SymbolPermissions),
+  /// The orders cancelled, oldest first
+  CancelTagged(Vec<Id>),
+  ListSymbol,
+  /// The orders cancelled, oldest first
+  ReplaceSymbol(Vec<Id>),
+  HaltSymbol,
+  GetWatchdog(Vec<SymbolProgress>),
+  GetActivity(ActivityPage),
+  GetFees(FeeReport),
+}
+
+/// A message a session is sent without asking for it, on its own line between replies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Push {
+  /// An order event of an account the session subscribed to, other than receiving a command for it
+  ///
+  /// Reports are numbered from 1 in the order they are sent, so a gap means the session missed one.
+  ExecutionReport {
+    sequence: u64,
+    record: AuditRecord,
+    /// How many decimal places the record's symbol quotes quantities to, or `None` if the record has no symbol
+    #[serde(default)]
+    quantity_precision: Option<u8>,
+  },
+  /// A symbol's best bid and ask, with the aggregate quantity at each, whenever either changes
+  Bbo {
+    symbol: Symbol,
+    bid: Option<(Price, Quantity)>,
+    ask: Option<(Price, Quantity)>,
+    /// How many decimal places the symbol quotes quantities to
+    #[serde(default)]
+    quantity_precision: u8,
+  },
+  /// The price levels of a symbol's book the session subscribed to, best first, whenever any of them changes
+  Depth {
+    symbol: Symbol,
+    bids: Vec<(Price, Quantity)>,
+    asks: Vec<(Price, Quantity)>,
+    #[serde(default)]
+    quantity_precision: u8,
+  },
+  Trade {
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+    #[serde(default)]
+    quantity_precision: u8,
+  },
+  /// A symbol's trades over an interval, once it has ended
+  Candle(Candle),
+  /// Quantity taken off an order the session placed instead of trading it with another of the same account
+  SelfMatchPrevented(AuditRecord),
+  /// An order the session placed was cancelled by the engine rather than by a command, e.g. as a day order when the
+  /// trading day closed, with the `CancelReason` saying why
+  Cancelled(AuditRecord),
+  /// When the command answered by the previous reply reached each stage, sent only to sessions of a server tracing
+  /// latency
+  Latency(LatencyTrace),
+}
+
+/// When a command reached each stage of being answered, so its latency can be split between them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LatencyTrace {
+  /// When the network read the command, or for a command forwarded by a gateway, when the gateway did
+  pub received_at: Timestamp,
+  /// When the matching thread took the command off the network
+  pub dequeued_at: Timestamp,
+  /// When the engine finished processing the command
+  pub matched_at: Timestamp,
+  /// When the reply was handed to the network, after the journal was written
+  pub sent_at: Timestamp,
 }
 
 /// A match engine user account
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Account {
+  pub firm: Option<FirmId>,
+  /// The account that beneficially owns this one, if not itself
+  pub beneficial_owner: Option<AccountId>,
+  pub is_admin: bool,
   pub balance: Price,
   pub orders: Vec<Id>,
+  #[serde(with = "portfolio")]
   pub portfolio: HashMap<Symbol, Quantity>,
+  #[serde(default)]
+  pub entitlement: Entitlement,
+  /// The reference the account was created with by `CommandKind::CreateAccount`, if any
+  #[serde(default)]
+  pub reference: Option<Label>,
+  #[serde(default)]
+  pub name: Label,
+  #[serde(default)]
+  pub state: AccountState,
+  #[serde(default)]
+  pub replace_protection: Option<ReplaceProtection>,
+}
+
+/// How far past the best opposite price an account may replace its orders to, as stale prices amended in a fast market
+/// would otherwise sweep the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ReplaceProtection {
+  /// How far past the best opposite price a replaced order may be priced
+  pub band: Price,
+  /// Whether a replace priced past the band is rejected or capped at its edge
+  pub action: ProtectionAction,
+}
+
+/// What is done with a replace priced past its account's protection band
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ProtectionAction {
+  /// Reject it with `Error::ReplaceOutsideBand`, leaving the order as it was
+  Reject,
+  /// Replace the order at the edge of the band instead
+  Cap,
+}
+
+/// What an account may do, each state other than `Active` only letting it query and what is listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum AccountState {
+  /// Anything its permissions allow
+  #[default]
+  Active,
+  /// Cancel its orders
+  Suspended,
+  /// Cancel its orders and place asks for no more of a symbol than it holds
+  LiquidationOnly,
+  /// Nothing more, its orders having been cancelled as it closed
+  Closed,
+}
+
+/// An account's details without its order and holding lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct AccountSummary {
+  pub firm: Option<FirmId>,
+  pub beneficial_owner: Option<AccountId>,
+  pub is_admin: bool,
+  pub balance: Price,
+  /// Orders the account has placed
+  pub orders: usize,
+  /// Symbols the account holds
+  pub holdings: usize,
+  pub state: AccountState,
+}
+
+impl From<&Account> for AccountSummary {
+  fn from(account: &Account) -> Self {
+    Self {
+      firm: account.firm,
+      beneficial_owner: account.beneficial_owner,
+      is_admin: account.is_admin,
+      balance: account.balance,
+      orders: account.orders.len(),
+      holdings: account.portfolio.len(),
+      state: account.state,
+    }
+  }
+}
+
+/// The accounts of an engine and the counters of the ids it gives out, as kept across restarts
+///
+/// Orders do not outlive the engine that accepted them, so stored accounts have none.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct AccountStore {
+  pub accounts: Vec<(AccountId, Account)>,
+  /// The counters may be missing, if the store was written by hand, and then only the account ids are carried over
+  #[serde(default)]
+  pub next_account_id: AccountId,
+  #[serde(default)]
+  pub next_order_id: Id,
+  #[serde(default)]
+  pub next_event_id: EventId,
+  #[serde(default)]
+  pub next_trade_id: TradeId,
+}
+
+/// A successful result borrowing from the engine, encoded exactly as the `Success` it stands in for
+#[derive(Serialize)]
+enum SuccessRef<'a> {
+  GetAccount(&'a Account),
+}
+
+/// Holdings encoded as `[symbol, quantity]` pairs ordered by symbol, as JSON object keys can only be strings
+mod portfolio {
+  use crate::types::{Quantity, Symbol};
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+  use std::collections::HashMap;
+
+  pub fn serialize<S: Serializer>(portfolio: &HashMap<Symbol, Quantity>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut holdings: Vec<_> = portfolio.iter().collect();
+    holdings.sort_by_key(|(symbol, _)| symbol.to_string());
+    holdings.serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Symbol, Quantity>, D::Error> {
+    Ok(Vec::<(Symbol, Quantity)>::deserialize(deserializer)?.into_iter().collect())
+  }
+}
+
+/// How many items are waiting in each of the engine's outboxes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct QueueDepths {
+  pub market_data: usize,
+  pub audit_trail: usize,
+  pub alerts: usize,
+  pub journal: usize,
 }
 
 type OrderPath = (Symbol, Side, OrderId);
+type Executions = (bool, Vec<Execution>);
 
 /// A central limit order book matching engine
-#[derive(Debug, Clone, Default)]
+///
+/// An engine serializes to a snapshot of its state, without the clock or any outputs not yet drained.
+#[derive(Derivative, Clone, Serialize, Deserialize)]
+#[derivative(Debug, Default)]
 pub struct MatchEngine {
+  #[serde(with = "crate::types::pairs")]
   books: HashMap<Symbol, OrderBook>,
+  /// How books inserted from now on store their levels
+  level_store: LevelStoreKind,
   // NOTE: since id's are given out sequentially and nothing is ever deleted, this can be a Vec
+  #[serde(with = "crate::types::pairs")]
   id_to_order_path_index: HashMap<Id, OrderPath>,
+  #[serde(with = "crate::types::pairs")]
   order_path_to_id_index: HashMap<OrderPath, Id>,
+  #[serde(with = "crate::types::pairs")]
   accounts: HashMap<AccountId, Account>,
+  /// The account created with each reference
+  #[serde(with = "crate::types::pairs")]
+  account_references: HashMap<Label, AccountId>,
+  #[serde(with = "crate::types::pairs")]
+  indices: HashMap<Symbol, Index>,
+  #[serde(with = "crate::types::pairs")]
+  last_trade_prices: HashMap<Symbol, Price>,
+  /// Each symbol's lit trading since the last end of day
+  #[serde(with = "crate::types::pairs")]
+  daily_stats: HashMap<Symbol, DailyStats>,
+  /// What each account owes and is owed for each symbol it traded since the last end of day
+  #[serde(with = "crate::types::pairs")]
+  settlements: HashMap<(AccountId, Symbol), Settlement>,
+  #[serde(skip)]
+  market_data: Vec<MarketData>,
+  #[serde(with = "crate::types::pairs")]
+  configs: HashMap<Symbol, SymbolConfig>,
+  #[serde(with = "crate::types::pairs")]
+  next_auctions: HashMap<Symbol, Timestamp>,
+  #[serde(with = "crate::types::pairs")]
+  scheduled_auctions: HashMap<Symbol, ScheduledAuction>,
+  #[serde(with = "crate::types::pairs")]
+  dark_pools: HashMap<Symbol, DarkPool>,
+  #[serde(with = "crate::types::pairs")]
+  dark_order_symbols: HashMap<Id, Symbol>,
+  /// Orders held in a price improvement auction or behind a speed bump, which are placed when it ends
+  #[serde(with = "crate::types::pairs")]
+  improvement_auctions: HashMap<Id, ImprovementAuction>,
+  /// The symbol of each `OrderFlags::AUCTION_ONLY` order, which is held suspended in its book until the next uncross
+  #[serde(with = "crate::types::pairs")]
+  auction_orders: HashMap<Id, Symbol>,
+  #[serde(with = "crate::types::pairs")]
+  order_accounts: HashMap<Id, AccountId>,
+  #[serde(with = "crate::types::pairs")]
+  order_sessions: HashMap<Id, SessionId>,
+  /// The tag of each order placed with one
+  #[serde(with = "crate::types::pairs")]
+  order_tags: HashMap<Id, Label>,
+  /// The quantity each order has filled and the value of those fills at their prices
+  #[serde(with = "crate::types::pairs")]
+  order_fills: HashMap<Id, (Quantity, u64)>,
+  /// The latest activity of each account that had any
+  #[serde(with = "crate::types::pairs")]
+  activity_logs: HashMap<AccountId, ActivityLog>,
+  /// When each order of a symbol with a minimum quote life may first be cancelled
+  #[serde(with = "crate::types::pairs")]
+  quote_lives: HashMap<Id, Timestamp>,
+  /// Cancels accepted early under `EarlyCancel::Delay`, by when they take effect
+  #[serde(with = "crate::types::pairs")]
+  delayed_cancels: HashMap<Id, Timestamp>,
+  /// Whether every order entry command is rejected
+  rejecting_all: bool,
+  /// The symbols on which every order entry command is rejected
+  halted_symbols: HashSet<Symbol>,
+  /// Who may enter orders on each symbol that was ever restricted or granted
+  #[serde(with = "crate::types::pairs")]
+  symbol_permissions: HashMap<Symbol, SymbolPermissions>,
+  /// The accounts being liquidated, until an admin moves them out of `AccountState::LiquidationOnly`
+  #[serde(with = "crate::types::pairs")]
+  liquidations: HashMap<AccountId, Liquidation>,
+  /// The pre-trade checks each session bypasses for admin accounts
+  #[serde(with = "crate::types::pairs")]
+  risk_bypasses: HashMap<SessionId, RiskBypass>,
+  /// The session of the command being processed
+  session: SessionId,
+  #[serde(skip)]
+  audit_trail: Vec<AuditRecord>,
+  /// When the command or tick being processed was received
+  received_at: Timestamp,
+  #[serde(skip)]
+  journal: Vec<JournalEntry>,
+  surveillance: Surveillance,
+  order_to_trade: OrderToTradeMonitor,
+  fees: FeeMonitor,
+  fee_ledger: FeeLedger,
+  collateral: Collateral,
+  #[serde(skip)]
+  alerts: Vec<Alert>,
+  #[derivative(Debug = "ignore")]
+  #[serde(skip)]
+  subscribers: Subscribers,
   next_order_id: Id,
+  next_event_id: EventId,
+  next_trade_id: TradeId,
   next_account_id: AccountId,
+  /// Scratch space for the fills of the order being matched
+  #[derivative(Debug = "ignore")]
+  #[serde(skip)]
+  fills: Vec<(OrderId, Quantity, bool)>,
+  #[derivative(Debug = "ignore", Default(value = "Arc::new(SystemClock)"))]
+  #[serde(skip, default = "system_clock")]
+  clock: Arc<dyn Clock>,
+}
+
+fn system_clock() -> Arc<dyn Clock> {
+  Arc::new(SystemClock)
 }
 
 impl MatchEngine {
-  /// Try to process a command
-  pub fn try_process(&mut self, command: Command) -> Result<Success, Error> {
-    use CommandKind::*;
+  /// Create a match engine that reads time from `clock`
+  pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+    Self {
+      clock,
+      ..Self::default()
+    }
+  }
 
-    if let Some(account) = self.accounts.get_mut(&command.account_id) {
-      // Self::validate_command_against_account(account, &command.kind)?;
-      match command.kind {
-        ExecuteOrder(id) => {
-          let (symbol, side, book_id) = self.try_get_order_path(id)?;
-          let book = self.try_get_book_mut(symbol)?;
-          let (is_filled, executions) = book.execute(side, book_id);
-
-          let executions = executions
-            .iter()
-            .cloned()
-            // FIXME: this is no good
-            .map(|(id, quantity, is_filled)| {
-              (
-                self.order_path_to_id_index.get(&(symbol, side, id)).cloned().unwrap(),
-                quantity,
-                is_filled
-              )
-            })
-            .collect();
+  /// Read time from `clock` from now on, e.g. once a replica replaying journaled times takes over
+  pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
 
-          Ok(Success::ExecuteOrder(is_filled, executions))
-        }
-        GetOrder(id) => {
-          let (symbol, side, book_id) = self.try_get_order_path(id)?;
-          let book = self.try_get_book_mut(symbol)?;
-          Ok(Success::GetOrder(*book.get(side, book_id).unwrap()))
-        }
+  /// Set how books inserted from now on store their levels
+  pub fn set_level_store(&mut self, kind: LevelStoreKind) {
+    self.level_store = kind;
+  }
 
-        PlaceOrder(side, symbol, order) => {
-          let book = self.try_get_book_mut(symbol)?;
-          let book_id = book.insert(side, order);
-          let id = self.next_order_id;
-          self.next_order_id += 1.into();
-          self.id_to_order_path_index.insert(id, (symbol, side, book_id));
-          self.order_path_to_id_index.insert((symbol, side, book_id), id);
-          self.books.get_mut(&symbol).map(|book| book.execute(Side::Ask, book_id));
+  /// Rebuild an engine by applying journal entries in order, with the clock set to when each was received
+  ///
+  /// # Returns
+  /// an error if the rebuilt state diverges from a journaled state hash
+  pub fn replay<I: IntoIterator<Item = JournalEntry>>(clock: Arc<ManualClock>, entries: I) -> Result<Self, Error> {
+    let mut engine = Self::with_clock(clock.clone());
+    for entry in entries {
+      clock.set(entry.received_at);
+      engine.apply(entry.event)?;
+    }
 
-          Ok(Success::PlaceOrder(id))
-        }
+    Ok(engine)
+  }
 
-        CancelOrder(id) => {
-          let (symbol, side, book_id) = self.try_get_order_path(id)?;
-          let book = self.try_get_book_mut(symbol)?;
-          Ok(Success::CancelOrder(book.cancel(side, book_id)))
-        }
+  /// Rebuild an engine as it was at a point in a journal, applying entries in order until the first past `point`
+  ///
+  /// # Returns
+  /// an error if the rebuilt state diverges from a journaled state hash before the point
+  pub fn replay_until<I: IntoIterator<Item = JournalEntry>>(
+    clock: Arc<ManualClock>,
+    entries: I,
+    point: JournalPoint,
+  ) -> Result<Self, Error> {
+    Self::replay(clock, entries.into_iter().take_while(|entry| point.includes(entry)))
+  }
 
-        GetQuote(symbol, side) => {
-          if let Some(book) = self.books.get(&symbol) {
-            Ok(Success::GetQuote(book.best_price(side)))
-          } else {
-            Err(Error::SymbolDoesNotExist { symbol })
-          }
-        }
+  /// Apply a journaled event
+  ///
+  /// # Returns
+  /// an error only if the event is a state hash that does not match this engine's
+  pub fn apply(&mut self, event: JournalEvent) -> Result<(), Error> {
+    use JournalEvent::*;
 
-        GetAccount(id) => {
-          if let Some(account) = self.accounts.get(&id) {
-            Ok(Success::GetAccount(account.clone()))
-          } else {
-            Err(Error::AccountDoesNotExist { id })
-          }
+    // results were journaled along with the events that produced them, so there is nothing to do with them here
+    match event {
+      Command { session, command } => {
+        let _ = self.try_process_from(session, command);
+      }
+      Tick => self.tick(),
+      EndOfDay => {
+        self.end_of_day();
+      }
+      CreateAccount { firm, is_admin } => {
+        self.insert_account(firm, is_admin);
+      }
+      SetBeneficialOwner { account, owner } => {
+        let _ = self.set_beneficial_owner(account, owner);
+      }
+      InsertSymbol(symbol) => {
+        let _ = self.insert_new_symbol(symbol);
+      }
+      LoadAccounts(store) => self.load_accounts(store),
+      ConfigureSymbol(symbol, config) => {
+        let _ = self.configure_symbol(symbol, config);
+      }
+      InsertIndex(symbol, index) => {
+        self.insert_index(symbol, index);
+      }
+      SetSurveillanceRules(rules) => self.set_surveillance_rules(rules),
+      SetOrderToTradeRules(rules) => self.set_order_to_trade_rules(rules),
+      SetFeeSchedule(schedule) => self.set_fee_schedule(schedule),
+      SetCollateralRules(rules) => self.set_collateral_rules(rules),
+      StateHash(expected) => {
+        let actual = self.checkpoint();
+        if actual != expected {
+          return Err(Error::StateHashMismatch { expected, actual });
         }
       }
-    } else {
-      Err(Error::AccountDoesNotExist { id: command.account_id })
+      Deny { session, account, source } => self.deny(session, account, source),
+      HaltSymbol(symbol) => {
+        let _ = self.halt_symbol(symbol);
+      }
+      CancelAll => {
+        self.cancel_all();
+      }
     }
+
+    Ok(())
   }
 
-  pub fn insert_new_symbol(&mut self, symbol: Symbol) -> bool {
-    // TODO: we probably don't want to overwrite the order book
-    self.books.insert(symbol, OrderBook::default()).is_none()
+  /// Return a digest of the books, accounts and id counters that is stable across processes and platforms
+  pub fn state_hash(&self) -> u64 {
+    let state = &mut StateHasher::default();
+    let symbol_key = |symbol: &Symbol| symbol.to_string();
+    let mut symbols: Vec<_> = self.books.keys().collect();
+    symbols.sort_by_key(|symbol| symbol_key(symbol));
+    for symbol in symbols {
+      symbol.hash(state);
+      self.books[symbol].hash(state);
+      self.dark_pools.get(symbol).hash(state);
+      self.last_trade_prices.get(symbol).hash(state);
+    }
+
+    let mut held: Vec<_> = self.improvement_auctions.iter().collect();
+    held.sort_by_key(|&(&id, _)| usize::from(id));
+    held.hash(state);
+
+    let mut accounts: Vec<_> = self.accounts.iter().collect();
+    accounts.sort_by_key(|&(&id, _)| usize::from(id));
+    for (id, account) in accounts {
+      id.hash(state);
+      account.firm.hash(state);
+      account.beneficial_owner.hash(state);
+      account.entitlement.hash(state);
+      account.reference.hash(state);
+      account.name.hash(state);
+      account.state.hash(state);
+      account.replace_protection.hash(state);
+      account.is_admin.hash(state);
+      account.balance.hash(state);
+      account.orders.hash(state);
+      let mut portfolio: Vec<_> = account.portfolio.iter().collect();
+      portfolio.sort_by_key(|&(symbol, _)| symbol_key(symbol));
+      portfolio.hash(state);
+    }
+
+    self.rejecting_all.hash(state);
+    let mut halted: Vec<_> = self.halted_symbols.iter().collect();
+    halted.sort_by_key(|&symbol| symbol_key(symbol));
+    halted.hash(state);
+    let mut permissions: Vec<_> = self.symbol_permissions.iter().collect();
+    permissions.sort_by_key(|&(symbol, _)| symbol_key(symbol));
+    permissions.hash(state);
+    let mut liquidations: Vec<_> = self.liquidations.iter().collect();
+    liquidations.sort_by_key(|&(&id, _)| usize::from(id));
+    liquidations.hash(state);
+    let mut bypasses: Vec<_> = self.risk_bypasses.iter().collect();
+    bypasses.sort_by_key(|&(&session, _)| usize::from(session));
+    bypasses.hash(state);
+    self.next_order_id.hash(state);
+    self.next_account_id.hash(state);
+    self.next_event_id.hash(state);
+    self.next_trade_id.hash(state);
+    state.finish()
   }
 
-  /// Create a new account
+  /// Record the current state hash in the journal
   ///
   /// # Returns
-  /// the id of the created account
-  pub fn create_account(&mut self) -> AccountId {
-    let id = self.next_account_id;
-    self.next_account_id += 1.into();
-    self.accounts.insert(id, Account::default());
-    id
+  /// the recorded hash
+  pub fn checkpoint(&mut self) -> u64 {
+    let hash = self.state_hash();
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::StateHash(hash));
+    hash
   }
 
-  fn validate_command_against_account(_account: &Account, _command: &CommandKind) -> Result<(), Error> {
-    match _command {
-      _ => unimplemented!(),
-    }
+  /// Take all journal entries since the last call
+  pub fn drain_journal(&mut self) -> Vec<JournalEntry> {
+    // like the other outboxes, this keeps its capacity rather than regrowing after every drain
+    self.journal.drain(..).collect()
   }
 
-  fn try_get_account_mut(&mut self, id: AccountId) -> Result<&mut Account, Error> {
-    if let Some(account) = self.accounts.get_mut(&id) {
-      Ok(account)
-    } else {
-      Err(Error::AccountDoesNotExist { id })
+  /// Try to process a command
+  pub fn try_process(&mut self, command: Command) -> Result<Success, Error> {
+    self.try_process_from(SessionId::default(), command)
+  }
+
+  /// Try to process a command received on a session
+  pub fn try_process_from(&mut self, session: SessionId, command: Command) -> Result<Success, Error> {
+    self.receive(session, command);
+    let result = self.process_audited(session, command);
+    if !command.kind.is_query() {
+      self.liquidate_under_margined();
     }
+    result
   }
 
-  fn try_get_book_mut(&mut self, symbol: Symbol) -> Result<&mut OrderBook, Error> {
-    if let Some(book) = self.books.get_mut(&symbol) {
-      Ok(book)
-    } else {
-      Err(Error::SymbolDoesNotExist { symbol })
+  /// Process a received command, auditing it if it enters or changes an order
+  fn process_audited(&mut self, session: SessionId, command: Command) -> Result<Success, Error> {
+    use CommandKind::*;
+
+    let (order, symbol, side, price, quantity) = match command.kind {
+      PlaceOrder(side, symbol, order) => (None, Some(symbol), Some(side), Some(order.price), Some(order.quantity)),
+      CancelOrder(id) | ExecuteOrder(id) | SuspendOrder(id) | ResumeOrder(id) => (Some(id), None, None, None, None),
+      UpdateOrder(id, price, quantity) => (Some(id), None, None, price, quantity),
+      _ => return self.process(command),
+    };
+    let tag = match (command.kind, order) {
+      (PlaceOrder(_, _, order), _) => Some(order.tag).filter(|tag| !tag.is_empty()),
+      (_, id) => id.and_then(|id| self.order_tag(id)),
+    };
+    let mut record = AuditRecord {
+      sequence: self.next_event_id(),
+      timestamp: self.clock.now(),
+      session,
+      account: command.account_id,
+      event: AuditEvent::Receive,
+      order,
+      symbol,
+      side,
+      price,
+      quantity,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    };
+    self.audit(record);
+
+    let result = self.process(command);
+    match result {
+      Ok(Success::CancelOrder(true)) => {
+        record.event = AuditEvent::Cancel;
+        record.cancel_reason = Some(CancelReason::Requested);
+      }
+      Ok(Success::UpdateOrder(true)) | Ok(Success::SuspendOrder(true)) | Ok(Success::ResumeOrder(true)) => {
+        record.event = AuditEvent::Modify
+      }
+      Ok(Success::CancelOrder(false))
+      | Ok(Success::UpdateOrder(false))
+      | Ok(Success::SuspendOrder(false))
+      | Ok(Success::ResumeOrder(false))
+      | Err(_) => record.event = AuditEvent::Reject,
+      _ => return result,
     }
+    record.sequence = self.next_event_id();
+    record.timestamp = self.clock.now();
+    self.audit(record);
+
+    result
   }
 
-  fn try_get_order_path(&self, id: Id) -> Result<OrderPath, Error> {
-    if let Some(path) = self.id_to_order_path_index.get(&id) {
-      Ok(*path)
-    } else {
-      Err(Error::IdDoesNotExist { id })
+  /// Process a command received on a session, writing its JSON encoded result to `writer`
+  ///
+  /// Results that would copy large parts of the engine, like `Success::GetAccount`, are encoded straight from the
+  /// engine's state instead.
+  pub fn write_result<W: io::Write>(
+    &mut self,
+    session: SessionId,
+    command: Command,
+    writer: W,
+  ) -> serde_json::Result<()> {
+    match command.kind {
+      CommandKind::GetAccount(id) => {
+        self.receive(session, command);
+        let result = self
+          .account(command.account_id)
+          .and_then(|_| self.account(id))
+          .map(SuccessRef::GetAccount);
+        serde_json::to_writer(writer, &result)
+      }
+      _ => serde_json::to_writer(writer, &self.try_process_from(session, command)),
     }
   }
 
-  fn try_get_path_from_id(&self) -> OrderPath {
-    unimplemented!()
+  /// Start processing a command, running anything due before it and journaling it if it changes state
+  fn receive(&mut self, session: SessionId, command: Command) {
+    self.tick();
+    self.session = session;
+    self.received_at = self.clock.now();
+    if !command.kind.is_query() {
+      self.record_journal(JournalEvent::Command { session, command });
+    }
   }
-}
 
+  fn process(&mut self, command: Command) -> Result<Success, Error> {
+    use CommandKind::*;
 
-#[cfg(test)]
-mod test {}
+    if let Some(account) = self.accounts.get(&command.account_id) {
+      let state = Self::validate_command_against_account(command.account_id, account, &command.kind);
+      let symbol = self.order_entry_symbol(&command.kind);
+      let reject_all = match symbol {
+        _ if self.rejecting_all && command.kind.is_order_entry() => {
+          Err(Error::RejectingAllOrders { id: command.account_id })
+        }
+        Some(symbol) if self.halted_symbols.contains(&symbol) => Err(Error::SymbolHalted { symbol }),
+        _ => Ok(()),
+      };
+      let permission = match symbol.map(|symbol| (symbol, self.symbol_permissions.get(&symbol))) {
+        Some((symbol, Some(permissions))) if !permissions.permits(command.account_id, account) => {
+          Err(Error::SymbolNotPermitted {
+            id: command.account_id,
+            symbol,
+          })
+        }
+        _ => Ok(()),
+      };
+      self.check_risk(command, RiskCheck::AccountState, state)?;
+      self.check_risk(command, RiskCheck::RejectAll, reject_all)?;
+      self.check_risk(command, RiskCheck::SymbolPermission, permission)?;
+      if let Some(symbol) = symbol {
+        let lot_size = self.enforce_lot_size(symbol, &command.kind);
+        self.check_risk(command, RiskCheck::LotSize, lot_size)?;
+      }
+      match command.kind {
+        PlaceOrder(side, symbol, order) => {
+          let order_to_trade = self.enforce_order_to_trade(command.account_id, symbol);
+          self.check_risk(command, RiskCheck::OrderToTrade, order_to_trade)?;
+          if side == Side::Bid {
+            let buying_power = self.enforce_buying_power(command.account_id, symbol, order);
+            self.check_risk(command, RiskCheck::BuyingPower, buying_power)?;
+          }
+          self.record_order_message(command.account_id);
+        }
+        CancelOrder(_) | UpdateOrder(..) | SuspendOrder(_) | ResumeOrder(_) => {
+          self.record_order_message(command.account_id)
+        }
+        _ => (),
+      }
+
+      match command.kind {
+        ExecuteOrder(id) => {
+          let (symbol, side, book_id) = self.try_get_order_path(id)?;
+          if !self.is_continuous(symbol) {
+            return Err(Error::ContinuousTradingDisabled { symbol });
+          }
+
+          let (is_filled, executions) = self.execute(symbol, side, book_id)?;
+          Ok(Success::ExecuteOrder(is_filled, executions))
+        }
+        GetOrder(id) => {
+          let order = match (self.try_get_dark_order(id), self.improvement_auctions.get(&id)) {
+            (Some(order), _) => *order,
+            (None, Some(auction)) => auction.order,
+            (None, None) => {
+              *self.try_get_book_order(self.try_get_order_path(id)?)?
+            }
+          };
+          let (_, average_price) = self.cumulative_fills(id);
+          Ok(Success::GetOrder(OrderStatus { order, average_price }))
+        }
+
+        PlaceOrder(side, symbol, order) if order.flags.contains(OrderFlags::DARK) => {
+          self.try_get_dark_pool_mut(symbol)?;
+          let audited = self.audit_trail.len();
+          let id = self.next_order_id;
+          self.try_get_dark_pool_mut(symbol)?.insert(side, id, order);
+          self.next_order_id += 1.into();
+          self.accept(command.account_id, id, symbol, side, order);
+          self.dark_order_symbols.insert(id, symbol);
+          self.match_dark(symbol);
+
+          Ok(Success::PlaceOrder(self.placement(id, symbol, audited)))
+        }
+
+        PlaceOrder(side, symbol, order) if order.flags.contains(OrderFlags::AUCTION_ONLY) => {
+          self.try_get_book_mut(symbol)?;
+          let audited = self.audit_trail.len();
+          let id = self.next_order_id;
+          self.next_order_id += 1.into();
+          self.accept(command.account_id, id, symbol, side, order);
+
+          // kept out of the levels so nothing but an uncross can match it
+          let book = self.try_get_book_mut(symbol)?;
+          let book_id = book.insert(side, order);
+          book.suspend(side, book_id);
+          self.id_to_order_path_index.insert(id, (symbol, side, book_id));
+          self.order_path_to_id_index.insert((symbol, side, book_id), id);
+          self.auction_orders.insert(id, symbol);
+
+          Ok(Success::PlaceOrder(self.placement(id, symbol, audited)))
+        }
+
+        PlaceOrder(side, symbol, mut order) => {
+          self.try_get_book_mut(symbol)?;
+          let audited = self.audit_trail.len();
+          let id = self.next_order_id;
+          self.next_order_id += 1.into();
+          self.accept(command.account_id, id, symbol, side, order);
+
+          let config = self.configs.get(&symbol).cloned().unwrap_or_default();
+          let is_anti_internalized = order.flags.contains(OrderFlags::ANTI_INTERNALIZATION);
+          if config.internalization && !is_anti_internalized && self.is_continuous(symbol) {
+            self.internalize(command.account_id, symbol, side, id, &mut order);
+          }
+          let is_marketable = self.books[&symbol].is_marketable(side, order.price);
+          let distance = match (side, self.books[&symbol].best_prices()) {
+            (Side::Bid, (Some(touch), _)) if touch > order.price => touch - order.price,
+            (Side::Ask, (_, Some(touch))) if order.price > touch => order.price - touch,
+            _ => Price::default(),
+          };
+          let now = self.clock.now();
+          self.surveillance.on_place(now, symbol, command.account_id, side, id, distance);
+
+          match (config.speed_bump, config.price_improvement) {
+            (Some(delay), _) if is_marketable && self.is_continuous(symbol) => {
+              self.improvement_auctions.insert(id, ImprovementAuction {
+                symbol,
+                side,
+                order,
+                ends_at: now + delay,
+              });
+            }
+            (None, Some(duration)) if is_marketable && self.is_continuous(symbol) => {
+              let ends_at = self.clock.now() + duration;
+              self.improvement_auctions.insert(id, ImprovementAuction {
+                symbol,
+                side,
+                order,
+                ends_at,
+              });
+              self.publish(MarketData::PriceImprovementAuction {
+                symbol,
+                side,
+                price: order.price,
+                quantity: order.remaining(),
+                ends_at,
+              });
+            }
+            _ => self.place(id, symbol, side, order)?,
+          }
+
+          Ok(Success::PlaceOrder(self.placement(id, symbol, audited)))
+        }
+
+        CancelOrder(id) if self.improvement_auctions.contains_key(&id) => {
+          self.improvement_auctions.remove(&id);
+          Ok(Success::CancelOrder(true))
+        }
+
+        CancelOrder(id) if self.dark_order_symbols.contains_key(&id) => {
+          let symbol = self.dark_order_symbols[&id];
+          Ok(Success::CancelOrder(self.try_get_dark_pool_mut(symbol)?.cancel(id)))
+        }
+
+        CancelOrder(id) => {
+          let (symbol, side, book_id) = self.try_get_order_path(id)?;
+          if let Some(&rests_until) = self.quote_lives.get(&id) {
+            let is_resting = self.resting_order(id).is_some();
+            let life = self.configs.get(&symbol).and_then(|config| config.minimum_quote_life);
+            match life {
+              Some(life) if is_resting && rests_until > self.clock.now() => {
+                if life.early_cancel == EarlyCancel::Reject {
+                  return Err(Error::QuoteLifeNotReached { id, rests_until });
+                }
+                self.delayed_cancels.insert(id, rests_until);
+                return Ok(Success::CancelPending(rests_until));
+              }
+              _ => {
+                self.quote_lives.remove(&id);
+              }
+            }
+          }
+          self.auction_orders.remove(&id);
+          let book = self.try_get_book_mut(symbol)?;
+          let is_cancelled = book.cancel(side, book_id);
+          if is_cancelled {
+            let alerts = self.surveillance.on_cancel(self.clock.now(), id);
+            self.alerts.extend(alerts);
+          }
+
+          Ok(Success::CancelOrder(is_cancelled))
+        }
+
+        UpdateOrder(id, price, quantity) => {
+          let (symbol, side, book_id) = self.try_get_order_path(id)?;
+          self.check_order_owner(command.account_id, id)?;
+          // the band is the order's account's, whoever sent the replace
+          let owner = *self.order_accounts.get(&id).ok_or(Error::IdDoesNotExist { id })?;
+          let protection = self.account(owner)?.replace_protection;
+          let price = match (price, protection) {
+            (Some(price), Some(protection)) => Some(self.protect_replace(id, symbol, side, price, protection)?),
+            (price, _) => price,
+          };
+          let is_updated = self.try_get_book_mut(symbol)?.update(side, book_id, price, quantity);
+          if is_updated && price.is_some() {
+            if self.is_continuous(symbol) && !self.auction_orders.contains_key(&id) {
+              self.match_order(symbol, side, book_id, None)?;
+            }
+            // the midpoint may have moved
+            self.match_dark(symbol);
+          }
+
+          Ok(Success::UpdateOrder(is_updated))
+        }
+
+        SuspendOrder(id) if self.auction_orders.contains_key(&id) => {
+          self.check_order_owner(command.account_id, id)?;
+          Ok(Success::SuspendOrder(false))
+        }
+
+        SuspendOrder(id) => {
+          let (symbol, side, book_id) = self.try_get_order_path(id)?;
+          self.check_order_owner(command.account_id, id)?;
+          let is_suspended = self.try_get_book_mut(symbol)?.suspend(side, book_id);
+          if is_suspended {
+            // the midpoint may have moved
+            self.match_dark(symbol);
+          }
+
+          Ok(Success::SuspendOrder(is_suspended))
+        }
+
+        ResumeOrder(id) if self.auction_orders.contains_key(&id) => {
+          self.check_order_owner(command.account_id, id)?;
+          Ok(Success::ResumeOrder(false))
+        }
+
+        ResumeOrder(id) => {
+          let (symbol, side, book_id) = self.try_get_order_path(id)?;
+          self.check_order_owner(command.account_id, id)?;
+          let is_resumed = self.try_get_book_mut(symbol)?.resume(side, book_id);
+          if is_resumed {
+            if self.is_continuous(symbol) {
+              self.match_order(symbol, side, book_id, None)?;
+            }
+            self.match_dark(symbol);
+          }
+
+          Ok(Success::ResumeOrder(is_resumed))
+        }
+
+        GetBookStats(symbol) => Ok(Success::GetBookStats(self.book_stats(symbol)?)),
+
+        GetQuote(symbol, side) => {
+          if let Some(book) = self.books.get(&symbol) {
+            Ok(Success::GetQuote(book.best_price(side)))
+          } else {
+            Err(Error::SymbolDoesNotExist { symbol })
+          }
+        }
+
+        GetAccount(id) => Ok(Success::GetAccount(self.account(id)?.clone())),
+
+        GetAccountSummary(id) => Ok(Success::GetAccountSummary(self.account(id)?.into())),
+
+        GetOpenOrders(id, page) => Ok(Success::GetOpenOrders(self.open_orders(id, page)?)),
+
+        GetLevel(symbol, side, price) => {
+          let entitlement = self.accounts[&command.account_id].entitlement;
+          if entitlement < Entitlement::OrderByOrder {
+            return Err(Error::NotEntitled { id: command.account_id, entitlement });
+          }
+          Ok(Success::GetLevel(self.level(symbol, side, price)?))
+        }
+
+        SubscribeExecutions(id) => {
+          self.account(id)?;
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          Ok(Success::SubscribeExecutions)
+        }
+
+        Subscribe(symbol, feed) | Unsubscribe(symbol, feed) => {
+          if !self.books.contains_key(&symbol) {
+            return Err(Error::SymbolDoesNotExist { symbol });
+          }
+          let entitlement = self.accounts[&command.account_id].entitlement;
+          if matches!(command.kind, Subscribe(..)) && !entitlement.allows(feed) {
+            return Err(Error::NotEntitled { id: command.account_id, entitlement });
+          }
+          match command.kind {
+            Subscribe(..) => Ok(Success::Subscribe),
+            _ => Ok(Success::Unsubscribe),
+          }
+        }
+
+        Conflate(_) => Ok(Success::Conflate),
+
+        SetAckMode(_) => Ok(Success::SetAckMode),
+
+        GetDeadLetters => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          Ok(Success::GetDeadLetters(vec![]))
+        }
+
+        DumpBook(symbol, _) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          self.book_stats(symbol)?;
+          Ok(Success::DumpBook(String::new()))
+        }
+
+        GetTradesHistory(symbol, ..) | GetQuotesHistory(symbol, ..) => {
+          if !self.books.contains_key(&symbol) {
+            return Err(Error::SymbolDoesNotExist { symbol });
+          }
+          match command.kind {
+            GetTradesHistory(..) => Ok(Success::GetTradesHistory(vec![])),
+            _ => Ok(Success::GetQuotesHistory(vec![])),
+          }
+        }
+
+        CreateAccount(details) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          let reference = Some(details.reference).filter(|reference| !reference.is_empty());
+          if let Some(&id) = reference.and_then(|reference| self.account_references.get(&reference)) {
+            return Ok(Success::CreateAccount(id));
+          }
+
+          let id = self.add_account(details.firm, false);
+          let account = self.try_get_account_mut(id)?;
+          account.reference = reference;
+          account.name = details.name;
+          if let Some(reference) = reference {
+            self.account_references.insert(reference, id);
+          }
+          Ok(Success::CreateAccount(id))
+        }
+
+        SetRejectAll(is_rejecting) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          if self.rejecting_all != is_rejecting {
+            self.emit(EngineEvent::Halt {
+              is_halted: is_rejecting,
+            });
+          }
+          self.rejecting_all = is_rejecting;
+          Ok(Success::SetRejectAll)
+        }
+
+        SetRiskBypass(bypass) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          match bypass {
+            Some(bypass) if bypass.reason.is_empty() => return Err(Error::MissingBypassReason),
+            Some(bypass) => self.risk_bypasses.insert(self.session, bypass),
+            None => self.risk_bypasses.remove(&self.session),
+          };
+          Ok(Success::SetRiskBypass)
+        }
+
+        RestrictSymbol(symbol, is_restricted) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_book_mut(symbol)?;
+          self.symbol_permissions.entry(symbol).or_default().is_restricted = is_restricted;
+          Ok(Success::RestrictSymbol)
+        }
+
+        HaltSymbol(symbol, is_halted) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_book_mut(symbol)?;
+          self.set_halted(symbol, is_halted);
+          Ok(Success::HaltSymbol)
+        }
+
+        GetWatchdog => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          Ok(Success::GetWatchdog(vec![]))
+        }
+
+        SetSymbolPermission(symbol, grantee, is_granted) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          self.try_get_book_mut(symbol)?;
+          if let Grantee::Account(id) = grantee {
+            self.try_get_account_mut(id)?;
+          }
+
+          let grantees = &mut self.symbol_permissions.entry(symbol).or_default().grantees;
+          grantees.retain(|&granted| granted != grantee);
+          if is_granted {
+            grantees.push(grantee);
+          }
+          Ok(Success::SetSymbolPermission)
+        }
+
+        GetSymbolPermissions(symbol) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_book_mut(symbol)?;
+          Ok(Success::GetSymbolPermissions(self.symbol_permissions.get(&symbol).cloned().unwrap_or_default()))
+        }
+
+        CancelTagged(id, tag) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          let account = self.accounts.get(&id).ok_or(Error::AccountDoesNotExist { id })?;
+
+          let tagged: Vec<_> =
+            account.orders.iter().copied().filter(|order| self.order_tags.get(order) == Some(&tag)).collect();
+          let cancelled = tagged.into_iter().filter(|&order| self.expire(order, CancelReason::Requested)).collect();
+          Ok(Success::CancelTagged(cancelled))
+        }
+
+        ListSymbol(symbol) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          self.add_book(symbol)?;
+          Ok(Success::ListSymbol)
+        }
+
+        ReplaceSymbol(symbol) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          self.try_get_book_mut(symbol)?;
+          Ok(Success::ReplaceSymbol(self.replace_book(symbol)))
+        }
+
+        SetAccountState(id, state) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          let current = self.try_get_account_mut(id)?.state;
+          if current == AccountState::Closed && state != AccountState::Closed {
+            return Err(Error::AccountRestricted { id, state: current });
+          }
+
+          if state == AccountState::Closed {
+            let orders = self.accounts[&id].orders.clone();
+            for order in orders {
+              self.expire(order, CancelReason::AccountClosed);
+            }
+          }
+          self.try_get_account_mut(id)?.state = state;
+          self.account_updated(id);
+          if state != AccountState::LiquidationOnly {
+            self.liquidations.remove(&id);
+          }
+          Ok(Success::SetAccountState)
+        }
+
+        SetReplaceProtection(id, protection) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_account_mut(id)?.replace_protection = protection;
+          Ok(Success::SetReplaceProtection)
+        }
+
+        SetEntitlement(id, entitlement) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_account_mut(id)?.entitlement = entitlement;
+          Ok(Success::SetEntitlement)
+        }
+
+        GetOrderToTradeRatio(id) => {
+          if !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_account_mut(id)?;
+          Ok(Success::GetOrderToTradeRatio(self.order_to_trade.status(self.clock.now(), id)))
+        }
+
+        GetActivity(id, after, limit) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          Ok(Success::GetActivity(self.activity(id, after, limit)?))
+        }
+
+        GetFees(id, date, period) => {
+          let is_admin = self.accounts[&command.account_id].is_admin;
+          if !is_admin && id != Some(command.account_id) {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          if let Some(id) = id {
+            self.account(id)?;
+          }
+          Ok(Success::GetFees(self.fee_report(id, date, period)))
+        }
+
+        GetFeeTier(id) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_account_mut(id)?;
+          Ok(Success::GetFeeTier(self.fees.status(self.clock.now(), id)))
+        }
+
+        GetSymbolFeeTier(id, symbol) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.try_get_account_mut(id)?;
+          self.try_get_book_mut(symbol)?;
+          Ok(Success::GetFeeTier(self.fee_model(symbol).apply(self.fees.status(self.clock.now(), id))))
+        }
+
+        GetEquity(id) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          Ok(Success::GetEquity(self.equity(id)?))
+        }
+
+        GetLiquidation(id) => {
+          if id != command.account_id && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+
+          self.account(id)?;
+          Ok(Success::GetLiquidation(self.liquidations.get(&id).cloned()))
+        }
+
+        GetQueuePosition(id) => {
+          let owner = self.order_accounts.get(&id).copied();
+          if owner != Some(command.account_id) && !self.accounts[&command.account_id].is_admin {
+            return Err(Error::PermissionDenied { id: command.account_id });
+          }
+          Ok(Success::GetQueuePosition(self.queue_position(id)?))
+        }
+
+        GetIndex(symbol) => {
+          if let Some(index) = self.indices.get(&symbol) {
+            Ok(Success::GetIndex(index.value(&self.last_trade_prices)))
+          } else {
+            Err(Error::IndexDoesNotExist { symbol })
+          }
+        }
+      }
+    } else {
+      Err(Error::AccountDoesNotExist { id: command.account_id })
+    }
+  }
+
+  /// Insert an empty book for a new symbol
+  ///
+  /// # Errors
+  /// `Error::SymbolAlreadyExists` if the symbol already has a book, which only `CommandKind::ReplaceSymbol` replaces
+  pub fn insert_new_symbol(&mut self, symbol: Symbol) -> Result<(), Error> {
+    self.add_book(symbol)?;
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::InsertSymbol(symbol));
+    Ok(())
+  }
+
+  /// Insert an empty book for a new symbol without journaling it, for commands journaled themselves
+  fn add_book(&mut self, symbol: Symbol) -> Result<(), Error> {
+    if self.books.contains_key(&symbol) {
+      return Err(Error::SymbolAlreadyExists { symbol });
+    }
+    self.books.insert(symbol, OrderBook::new(self.level_store));
+    Ok(())
+  }
+
+  /// Cancel every order resting on a symbol, in its book, dark pool or an improvement auction, then give it an empty
+  /// book
+  ///
+  /// # Returns
+  /// the orders cancelled, oldest first
+  fn replace_book(&mut self, symbol: Symbol) -> Vec<Id> {
+    let mut resting: Vec<Id> = self
+      .id_to_order_path_index
+      .iter()
+      .filter(|&(_, &(other, ..))| other == symbol)
+      .map(|(&id, _)| id)
+      .chain(self.dark_order_symbols.iter().filter(|&(_, &other)| other == symbol).map(|(&id, _)| id))
+      .chain(self.improvement_auctions.iter().filter(|(_, auction)| auction.symbol == symbol).map(|(&id, _)| id))
+      .collect();
+    resting.sort_by_key(|&id| usize::from(id));
+    let cancelled = resting.into_iter().filter(|&id| self.expire(id, CancelReason::SymbolReplaced)).collect();
+
+    // the old book's order ids would name orders of the new one
+    self.id_to_order_path_index.retain(|_, &mut (other, ..)| other != symbol);
+    self.order_path_to_id_index.retain(|&(other, ..), _| other != symbol);
+    self.auction_orders.retain(|_, &mut other| other != symbol);
+    self.books.insert(symbol, OrderBook::new(self.level_store));
+    cancelled
+  }
+
+  /// Halt order entry on a symbol, as the server does when its matching stalls
+  ///
+  /// # Errors
+  /// `Error::SymbolDoesNotExist` if the symbol has no book
+  pub fn halt_symbol(&mut self, symbol: Symbol) -> Result<(), Error> {
+    self.try_get_book_mut(symbol)?;
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::HaltSymbol(symbol));
+    self.set_halted(symbol, true);
+    Ok(())
+  }
+
+  /// Returns true if order entry is halted on a symbol
+  pub fn is_halted(&self, symbol: Symbol) -> bool {
+    self.halted_symbols.contains(&symbol)
+  }
+
+  fn set_halted(&mut self, symbol: Symbol, is_halted: bool) {
+    if is_halted {
+      self.halted_symbols.insert(symbol);
+    } else {
+      self.halted_symbols.remove(&symbol);
+    }
+  }
+
+  /// Configure an existing symbol
+  pub fn configure_symbol(&mut self, symbol: Symbol, config: SymbolConfig) -> Result<(), Error> {
+    if !self.books.contains_key(&symbol) {
+      return Err(Error::SymbolDoesNotExist { symbol });
+    }
+
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::ConfigureSymbol(symbol, config.clone()));
+    if config.dark_pool {
+      self.dark_pools.entry(symbol).or_default();
+    }
+
+    self.next_auctions.remove(&symbol);
+    if let TradingMode::PeriodicAuction { interval } = config.trading_mode {
+      self.next_auctions.insert(symbol, self.clock.now() + interval);
+    }
+    self.scheduled_auctions.remove(&symbol);
+    if let Some(schedule) = config.scheduled_auctions {
+      self.scheduled_auctions.insert(symbol, ScheduledAuction::after(self.clock.now(), schedule));
+    }
+    self.configs.insert(symbol, config);
+
+    Ok(())
+  }
+
+  /// Run any auctions that are due, and announce the calls of scheduled auctions
+  pub fn tick(&mut self) {
+    self.tick_watched(|_| ());
+  }
+
+  /// Tick as `tick` does, holding what `watch` returns for a symbol while an auction of the symbol uncrosses or ends,
+  /// or one of its orders is cancelled at the end of its quote life, so each symbol's share of a tick can be timed
+  pub fn tick_watched<W>(&mut self, mut watch: impl FnMut(Symbol) -> W) {
+    let now = self.clock.now();
+    let mut due: Vec<Symbol> = self
+      .next_auctions
+      .iter()
+      .filter(|(_, &at)| at <= now)
+      .map(|(&symbol, _)| symbol)
+      .collect();
+    due.sort_by_key(|symbol| symbol.to_string());
+
+    let mut calling: Vec<Symbol> = self
+      .scheduled_auctions
+      .iter()
+      .filter(|(_, auction)| !auction.is_called && auction.calls_at <= now)
+      .map(|(&symbol, _)| symbol)
+      .collect();
+    calling.sort_by_key(|symbol| symbol.to_string());
+    let mut uncrossing: Vec<Symbol> = self
+      .scheduled_auctions
+      .iter()
+      .filter(|(_, auction)| auction.uncrosses_at <= now)
+      .map(|(&symbol, _)| symbol)
+      .collect();
+    uncrossing.sort_by_key(|symbol| symbol.to_string());
+
+    let mut ended: Vec<(Timestamp, usize)> = self
+      .improvement_auctions
+      .iter()
+      .filter(|(_, auction)| auction.ends_at <= now)
+      .map(|(&id, auction)| (auction.ends_at, id.into()))
+      .collect();
+    ended.sort();
+
+    let mut cancelling: Vec<Id> = self
+      .delayed_cancels
+      .iter()
+      .filter(|(_, &at)| at <= now)
+      .map(|(&id, _)| id)
+      .collect();
+    cancelling.sort_by_key(|&id| usize::from(id));
+
+    if due.is_empty() && calling.is_empty() && uncrossing.is_empty() && ended.is_empty() && cancelling.is_empty() {
+      return;
+    }
+
+    self.received_at = now;
+    self.record_journal(JournalEvent::Tick);
+    for symbol in calling {
+      let uncrosses_at = match self.scheduled_auctions.get_mut(&symbol) {
+        Some(auction) => {
+          auction.is_called = true;
+          auction.scheduled_at
+        }
+        None => continue,
+      };
+      self.publish(MarketData::AuctionCall { symbol, uncrosses_at });
+    }
+
+    for symbol in due {
+      let _watch = watch(symbol);
+      if let TradingMode::PeriodicAuction { interval } = self.configs[&symbol].trading_mode {
+        let mut next = self.next_auctions[&symbol];
+        while next <= now {
+          next = next + interval;
+        }
+        self.next_auctions.insert(symbol, next);
+      }
+
+      // the symbol was checked when the auction was scheduled
+      let _ = self.uncross(symbol);
+    }
+
+    for symbol in uncrossing {
+      let _watch = watch(symbol);
+      if let Some(schedule) = self.configs[&symbol].scheduled_auctions {
+        self.scheduled_auctions.insert(symbol, ScheduledAuction::after(now, schedule));
+      }
+      // the symbol was checked when the auction was scheduled
+      let _ = self.uncross(symbol);
+    }
+
+    for (_, id) in ended {
+      let id = id.into();
+      let auction = match self.improvement_auctions.remove(&id) {
+        Some(auction) => auction,
+        None => continue,
+      };
+      let _watch = watch(auction.symbol);
+      // the symbol was checked when the order was held
+      let _ = self.place(id, auction.symbol, auction.side, auction.order);
+    }
+
+    for id in cancelling {
+      let _watch = self.book_symbol(&CommandKind::CancelOrder(id)).map(&mut watch);
+      self.delayed_cancels.remove(&id);
+      self.quote_lives.remove(&id);
+      self.expire(id, CancelReason::Requested);
+    }
+    self.liquidate_under_margined();
+  }
+
+  /// Close the trading day: expire day orders, run a closing auction in every book, publish each symbol's official
+  /// closing price, and start the next day's statistics and settlement obligations afresh
+  ///
+  /// # Returns
+  /// what the day closed with
+  pub fn end_of_day(&mut self) -> EndOfDay {
+    let now = self.clock.now();
+    self.received_at = now;
+    self.record_journal(JournalEvent::EndOfDay);
+
+    let mut day_orders: Vec<Id> = self
+      .order_accounts
+      .keys()
+      .copied()
+      .filter(|&id| self.resting_order(id).is_some_and(|order| order.flags.contains(OrderFlags::DAY)))
+      .collect();
+    day_orders.sort_by_key(|&id| usize::from(id));
+    let expired: Vec<Id> = day_orders.into_iter().filter(|&id| self.expire(id, CancelReason::EndOfDay)).collect();
+
+    let symbols = self.symbols();
+    for &symbol in &symbols {
+      // the closing auction's trades are the day's last, so they set its closing price
+      let _ = self.uncross(symbol);
+    }
+    let closes: Vec<DailyStats> = symbols.iter().filter_map(|symbol| self.daily_stats.remove(symbol)).collect();
+    for close in &closes {
+      self.publish(MarketData::ClosingPrice {
+        symbol: close.symbol,
+        price: close.close,
+        volume: close.volume,
+      });
+    }
+
+    self.fee_ledger.prune(Date::from(now).previous_month());
+
+    let mut settlements: Vec<Settlement> = self.settlements.drain().map(|(_, settlement)| settlement).collect();
+    settlements.sort_by_key(|settlement| (usize::from(settlement.account), settlement.symbol.to_string()));
+    for settlement in &settlements {
+      self.log_activity(settlement.account, now, Activity::Settlement {
+        symbol: settlement.symbol,
+        bought: settlement.bought,
+        sold: settlement.sold,
+        paid: settlement.paid,
+        received: settlement.received,
+      });
+    }
+    EndOfDay {
+      closed_at: now,
+      expired,
+      closes,
+      settlements,
+    }
+  }
+
+  /// Take a resting order off its book, dark pool or improvement auction, recording it as cancelled for `reason`
+  ///
+  /// # Returns
+  /// true if the order was resting
+  fn expire(&mut self, id: Id, reason: CancelReason) -> bool {
+    let is_expired = if self.improvement_auctions.remove(&id).is_some() {
+      true
+    } else if let Some(&symbol) = self.dark_order_symbols.get(&id) {
+      self.dark_pools.get_mut(&symbol).is_some_and(|dark_pool| dark_pool.cancel(id))
+    } else {
+      match self.id_to_order_path_index.get(&id) {
+        Some(&(symbol, side, book_id)) => self.books.get_mut(&symbol).is_some_and(|book| book.cancel(side, book_id)),
+        None => false,
+      }
+    };
+    if !is_expired {
+      return false;
+    }
+
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session: self.order_sessions[&id],
+      account: self.order_accounts[&id],
+      event: AuditEvent::Cancel,
+      order: Some(id),
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: self.order_tag(id),
+      check: None,
+      reason: None,
+      cancel_reason: Some(reason),
+    });
+    true
+  }
+
+  /// Uncross a symbol's book at a single clearing price, together with its auction-only orders, which are cancelled if
+  /// left unfilled
+  ///
+  /// # Returns
+  /// the clearing price and volume, or `None` if the book was not crossed
+  pub fn uncross(&mut self, symbol: Symbol) -> Result<Option<(Price, Quantity)>, Error> {
+    self.try_get_book_mut(symbol)?;
+    let mut auction_only: Vec<Id> = self
+      .auction_orders
+      .iter()
+      .filter(|&(_, &other)| other == symbol)
+      .map(|(&id, _)| id)
+      .collect();
+    auction_only.sort_by_key(|&id| usize::from(id));
+    for &id in &auction_only {
+      self.auction_orders.remove(&id);
+      let (_, side, book_id) = self.try_get_order_path(id)?;
+      self.try_get_book_mut(symbol)?.resume(side, book_id);
+    }
+
+    let book = self.try_get_book_mut(symbol)?;
+    let clearing = auction::clearing_price(&book.depth(Side::Bid), &book.depth(Side::Ask));
+
+    if let Some((price, volume)) = clearing {
+      for (bid, ask, quantity) in book.uncross(price, volume) {
+        let bid = self.try_get_id((symbol, Side::Bid, bid))?;
+        let ask = self.try_get_id((symbol, Side::Ask, ask))?;
+        self.record_trade(symbol, price, quantity, bid, ask, None);
+      }
+      self.publish(MarketData::AuctionUncross {
+        symbol,
+        price,
+        quantity: volume,
+      });
+    }
+    for id in auction_only {
+      self.expire(id, CancelReason::AuctionUnfilled);
+    }
+
+    Ok(clearing)
+  }
+
+  /// Get a page of an account's open orders, oldest first
+  ///
+  /// Pages are cursored by order id rather than offset, so orders filling between pages do not shift the next one.
+  pub fn open_orders(&self, id: AccountId, page: Page) -> Result<OrderPage, Error> {
+    let account = self.account(id)?;
+    // orders are accepted in id order, so the cursor can be found by search
+    let start = match page.after {
+      Some(after) => account.orders.partition_point(|&order| usize::from(order) <= usize::from(after)),
+      None => 0,
+    };
+
+    let limit = page.limit.min(MAX_PAGE_SIZE);
+    let mut open = account.orders[start..]
+      .iter()
+      .filter_map(|&order| self.resting_order(order).map(|resting| (order, resting)));
+    let orders: Vec<_> = open.by_ref().take(limit).collect();
+    let next = match open.next() {
+      Some(_) => orders.last().map(|&(order, _)| order),
+      None => None,
+    };
+    Ok(OrderPage { orders, next })
+  }
+
+  /// Get up to `limit`, at most `MAX_PAGE_SIZE`, of an account's activity entries after `after`, or from its oldest if
+  /// it is 0, oldest first
+  ///
+  /// # Errors
+  /// `Error::ActivityExpired` if entries after `after` are no longer kept
+  pub fn activity(&self, id: AccountId, after: u64, limit: usize) -> Result<ActivityPage, Error> {
+    self.account(id)?;
+    match self.activity_logs.get(&id) {
+      Some(log) => log.page(id, after, limit),
+      None => Ok(ActivityPage::default()),
+    }
+  }
+
+  /// Get the fees one account, or every account if `None`, accrued over a period ending on `date`
+  ///
+  /// Only the days since the first of the month before the last close are kept.
+  pub fn fee_report(&self, id: Option<AccountId>, date: Date, period: FeePeriod) -> FeeReport {
+    self.fee_ledger.report(id, date, period)
+  }
+
+  /// Cancel every resting order at once, oldest first, as a kill switch does, whatever their minimum quote lives
+  ///
+  /// # Returns
+  /// the number of orders cancelled
+  pub fn cancel_all(&mut self) -> usize {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::CancelAll);
+
+    let mut resting: Vec<Id> = self
+      .accounts
+      .values()
+      .flat_map(|details| details.orders.iter().copied())
+      .filter(|&order| self.resting_order(order).is_some())
+      .collect();
+    resting.sort_by_key(|&order| usize::from(order));
+    resting
+      .into_iter()
+      .filter(|&order| {
+        self.delayed_cancels.remove(&order);
+        self.quote_lives.remove(&order);
+        self.expire(order, CancelReason::KillSwitch)
+      })
+      .count()
+  }
+
+  /// Get an order if it is still resting, in a book, a dark pool or an improvement auction
+  fn resting_order(&self, id: Id) -> Option<Order> {
+    let order = match self.try_get_dark_order(id) {
+      Some(order) => *order,
+      None => match self.improvement_auctions.get(&id) {
+        Some(auction) => auction.order,
+        None => {
+          let (symbol, side, book_id) = self.try_get_order_path(id).ok()?;
+          *self.books.get(&symbol)?.get(side, book_id)?
+        }
+      },
+    };
+
+    if order.is_filled() || order.is_cancelled {
+      None
+    } else {
+      Some(order)
+    }
+  }
+
+  /// Returns true if an order was cancelled off its book
+  fn is_cancelled(&self, id: Id) -> bool {
+    self
+      .try_get_order_path(id)
+      .ok()
+      .and_then(|(symbol, side, book_id)| self.books.get(&symbol)?.get(side, book_id))
+      .is_some_and(|order| order.is_cancelled)
+  }
+
+  /// Describe an order just placed, from the fills audited since the audit trail was `audited` records long
+  fn placement(&self, id: Id, symbol: Symbol, audited: usize) -> Placement {
+    let fills = self.audit_trail[audited..]
+      .iter()
+      .filter(|record| record.event == AuditEvent::Execute && record.order == Some(id))
+      .map(|record| Fill {
+        execution: record.sequence,
+        trade: record.trade.unwrap_or_default(),
+        liquidity: record.liquidity.unwrap_or(Liquidity::Crossed),
+        price: record.price.unwrap_or_default(),
+        quantity: record.quantity.unwrap_or_default(),
+      })
+      .collect();
+    let (remaining, state) = match self.resting_order(id) {
+      Some(order) if self.improvement_auctions.contains_key(&id) => (order.remaining(), OrderState::Held),
+      Some(order) if order.filled == Quantity::default() => (order.remaining(), OrderState::New),
+      Some(order) => (order.remaining(), OrderState::PartiallyFilled),
+      None if self.is_cancelled(id) => (Quantity::default(), OrderState::Cancelled),
+      None => (Quantity::default(), OrderState::Filled),
+    };
+
+    Placement {
+      id,
+      fills,
+      remaining,
+      state,
+      quantity_precision: self.quantity_precision(symbol),
+    }
+  }
+
+  /// Get how a symbol charges its fills
+  fn fee_model(&self, symbol: Symbol) -> FeeModel {
+    self.configs.get(&symbol).map(|config| config.fee_model).unwrap_or_default()
+  }
+
+  /// Get how many decimal places a symbol quotes quantities to, each of its quantities counting units of the last place
+  pub fn quantity_precision(&self, symbol: Symbol) -> u8 {
+    self.configs.get(&symbol).map(|config| config.quantity_precision).unwrap_or_default()
+  }
+
+  /// Reject a command on an order from an account that neither placed it nor is an admin
+  fn check_order_owner(&self, account: AccountId, id: Id) -> Result<(), Error> {
+    let is_admin = self.accounts.get(&account).is_some_and(|account| account.is_admin);
+    if self.order_accounts.get(&id) != Some(&account) && !is_admin {
+      return Err(Error::PermissionDenied { id: account });
+    }
+    Ok(())
+  }
+
+  /// Get the tag an order was placed with, if any
+  fn order_tag(&self, id: Id) -> Option<Label> {
+    self.order_tags.get(&id).copied()
+  }
+
+  /// Get how much of an order has filled and the average price of those fills
+  fn cumulative_fills(&self, id: Id) -> (Quantity, Option<f64>) {
+    match self.order_fills.get(&id) {
+      Some(&(filled, value)) if filled > Quantity::default() => {
+        (filled, Some(value as f64 / u64::from(filled) as f64))
+      }
+      _ => (Quantity::default(), None),
+    }
+  }
+
+  /// Get the side an order rests on, if it is still resting
+  fn resting_side(&self, id: Id) -> Option<Side> {
+    self.resting_order(id)?;
+    if let Some(auction) = self.improvement_auctions.get(&id) {
+      return Some(auction.side);
+    }
+    match self.dark_order_symbols.get(&id) {
+      Some(symbol) => self.dark_pools.get(symbol)?.side(id),
+      None => self.try_get_order_path(id).ok().map(|(_, side, _)| side),
+    }
+  }
+
+  /// Get an account without copying it
+  pub fn account(&self, id: AccountId) -> Result<&Account, Error> {
+    self.accounts.get(&id).ok_or(Error::AccountDoesNotExist { id })
+  }
+
+  /// Get every symbol with a book, ordered by name
+  pub fn symbols(&self) -> Vec<Symbol> {
+    let mut symbols: Vec<_> = self.books.keys().cloned().collect();
+    symbols.sort_by_key(|symbol| symbol.to_string());
+    symbols
+  }
+
+  /// Get how a symbol trades
+  pub fn trading_mode(&self, symbol: Symbol) -> Result<TradingMode, Error> {
+    if !self.books.contains_key(&symbol) {
+      return Err(Error::SymbolDoesNotExist { symbol });
+    }
+
+    Ok(self.configs.get(&symbol).map(|config| config.trading_mode).unwrap_or_default())
+  }
+
+  /// Get the aggregate remaining quantity at each of a side's price levels, best first
+  pub fn depth(&self, symbol: Symbol, side: Side) -> Result<Vec<(Price, Quantity)>, Error> {
+    match self.books.get(&symbol) {
+      Some(book) => Ok(book.depth(side)),
+      None => Err(Error::SymbolDoesNotExist { symbol }),
+    }
+  }
+
+  /// Get the totals of a symbol's lit book, kept as the book changes, and the value at its best prices
+  pub fn book_stats(&self, symbol: Symbol) -> Result<BookStats, Error> {
+    let book = match self.books.get(&symbol) {
+      Some(book) => book,
+      None => return Err(Error::SymbolDoesNotExist { symbol }),
+    };
+    let notional = |side| {
+      book
+        .top_levels(side, STATS_LEVELS)
+        .into_iter()
+        .map(|(price, quantity)| quantity.value_at(price, self.quantity_precision(symbol)))
+        .sum()
+    };
+
+    let (bids, bid_quantity) = book.totals(Side::Bid);
+    let (asks, ask_quantity) = book.totals(Side::Ask);
+    Ok(BookStats {
+      symbol,
+      orders: bids + asks,
+      bid_quantity,
+      ask_quantity,
+      bid_notional: notional(Side::Bid),
+      ask_notional: notional(Side::Ask),
+    })
+  }
+
+  /// Get everything resting in a symbol's lit book, level by level
+  pub fn dump_book(&self, symbol: Symbol) -> Result<BookDump, Error> {
+    let stats = self.book_stats(symbol)?;
+    let levels = |side| -> Result<Vec<DumpedLevel>, Error> {
+      self
+        .depth(symbol, side)?
+        .into_iter()
+        .map(|(price, quantity)| {
+          let orders = self.level(symbol, side, price)?;
+          Ok(DumpedLevel { price, quantity, orders })
+        })
+        .collect()
+    };
+
+    Ok(BookDump {
+      symbol,
+      taken_at: self.clock.now(),
+      stats,
+      bids: levels(Side::Bid)?,
+      asks: levels(Side::Ask)?,
+    })
+  }
+
+  /// Get the best bid and ask of a symbol, with the aggregate remaining quantity at each
+  pub fn bbo(&self, symbol: Symbol) -> Result<TopOfBook, Error> {
+    match self.books.get(&symbol) {
+      Some(book) => Ok(book.top()),
+      None => Err(Error::SymbolDoesNotExist { symbol }),
+    }
+  }
+
+  /// Get the queue of orders resting at a price, first to fill first
+  ///
+  /// # Returns
+  /// an empty queue if nothing rests at the price
+  pub fn level(&self, symbol: Symbol, side: Side, price: Price) -> Result<Vec<QueueEntry>, Error> {
+    let book = match self.books.get(&symbol) {
+      Some(book) => book,
+      None => return Err(Error::SymbolDoesNotExist { symbol }),
+    };
+
+    let queue = book.level(side, price).unwrap_or_default();
+    Ok(queue
+      .into_iter()
+      .enumerate()
+      .filter_map(|(position, book_id)| {
+        let id = *self.order_path_to_id_index.get(&(symbol, side, book_id))?;
+        let remaining = book.get(side, book_id)?.remaining();
+        Some(QueueEntry { id, remaining, position })
+      })
+      .collect())
+  }
+
+  /// Get where a resting lit order stands in the queue at its price
+  ///
+  /// # Returns
+  /// `Error::IdDoesNotExist` if the order is not queued in a lit book, e.g. because it is filled or suspended
+  pub fn queue_position(&self, id: Id) -> Result<QueuePosition, Error> {
+    let (symbol, side, book_id) = self.try_get_order_path(id)?;
+    let price = match self.books.get(&symbol).and_then(|book| book.get(side, book_id)) {
+      Some(order) => order.price,
+      None => return Err(Error::IdDoesNotExist { id }),
+    };
+
+    let queue = self.level(symbol, side, price)?;
+    let position = match queue.iter().position(|entry| entry.id == id) {
+      Some(position) => position,
+      None => return Err(Error::IdDoesNotExist { id }),
+    };
+    let total = |entries: &[QueueEntry]| entries.iter().fold(Quantity::default(), |sum, entry| sum + entry.remaining);
+    Ok(QueuePosition {
+      price,
+      position,
+      ahead: total(&queue[..position]),
+      orders: queue.len(),
+      quantity: total(&queue),
+    })
+  }
+
+  /// Check the books, and that every indexed order is in its book
+  ///
+  /// # Returns
+  /// each broken invariant and the symbol it was found in, ordered by symbol
+  pub fn violations(&self) -> Vec<(Symbol, Violation)> {
+    let mut symbols: Vec<_> = self.books.keys().cloned().collect();
+    symbols.sort_by_key(|symbol| symbol.to_string());
+
+    let mut violations = vec![];
+    for symbol in symbols {
+      let book = &self.books[&symbol];
+      violations.extend(book.violations().into_iter().map(|violation| (symbol, violation)));
+      if let (Some(bid), Some(ask)) = book.best_prices() {
+        if bid >= ask && self.is_continuous(symbol) {
+          violations.push((symbol, Violation::Crossed { bid, ask }));
+        }
+      }
+    }
+
+    let mut paths: Vec<_> = self.id_to_order_path_index.values().cloned().collect();
+    paths.sort_by_key(|&(symbol, _, id)| (symbol.to_string(), usize::from(id)));
+    for (symbol, side, id) in paths {
+      if self.books.get(&symbol).and_then(|book| book.get(side, id)).is_none() {
+        violations.push((symbol, Violation::MissingOrder { side, id }));
+      }
+    }
+
+    violations
+  }
+
+  /// Set which patterns surveillance looks for
+  pub fn set_surveillance_rules(&mut self, rules: SurveillanceRules) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::SetSurveillanceRules(rules.clone()));
+    self.surveillance.rules = rules;
+  }
+
+  /// Get an account's surveillance score
+  pub fn surveillance_score(&self, id: AccountId) -> Result<u32, Error> {
+    if self.accounts.contains_key(&id) {
+      Ok(self.surveillance.score(id))
+    } else {
+      Err(Error::AccountDoesNotExist { id })
+    }
+  }
+
+  /// Set the order-to-trade thresholds and whether they are enforced
+  pub fn set_order_to_trade_rules(&mut self, rules: OrderToTradeRules) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::SetOrderToTradeRules(rules.clone()));
+    self.order_to_trade.rules = rules;
+  }
+
+  /// Set the fee tiers and the window volume is counted over
+  pub fn set_fee_schedule(&mut self, schedule: FeeSchedule) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::SetFeeSchedule(schedule.clone()));
+    self.fees.schedule = schedule;
+  }
+
+  /// Set how much holdings count for as collateral and whether buying power is enforced
+  pub fn set_collateral_rules(&mut self, rules: CollateralRules) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::SetCollateralRules(rules.clone()));
+    self.collateral.rules = rules;
+    self.liquidate_under_margined();
+  }
+
+  /// Get the price a symbol's holdings are marked to, the midpoint of its lit book or, if one side is empty, its last
+  /// trade
+  pub fn reference_price(&self, symbol: Symbol) -> Option<Price> {
+    let midpoint = self.books.get(&symbol).and_then(OrderBook::midpoint);
+    midpoint.or_else(|| self.last_trade_prices.get(&symbol).copied())
+  }
+
+  /// Get what an account is worth, its balance and its holdings marked to their reference prices
+  pub fn equity(&self, id: AccountId) -> Result<Equity, Error> {
+    let account = self.account(id)?;
+    let cash = u64::from(u32::from(account.balance));
+    let position_value = account
+      .portfolio
+      .iter()
+      .filter_map(|(&symbol, &quantity)| {
+        let price = self.reference_price(symbol)?;
+        Some(quantity.value_at(price, self.quantity_precision(symbol)))
+      })
+      .sum();
+    Ok(Equity {
+      account: id,
+      cash,
+      position_value,
+      reserved: self.reserved(account),
+      equity: cash + position_value,
+    })
+  }
+
+  /// Get what an account can spend, its balance and its holdings valued as collateral
+  pub fn buying_power(&self, id: AccountId) -> Result<u64, Error> {
+    Ok(self.collateral.buying_power(self.account(id)?, |symbol| self.quantity_precision(symbol)))
+  }
+
+  /// Swap in a new runtime configuration, leaving the current one in place if any of it is invalid
+  ///
+  /// Only the parts that changed are applied, so reloading an unchanged symbol does not reschedule its auctions.
+  pub fn reload_config(&mut self, config: RuntimeConfig) -> Result<(), Error> {
+    config.validate().map_err(|reason| Error::InvalidConfig { reason })?;
+    if let Some(&(symbol, _)) = config.symbols.iter().find(|(symbol, _)| !self.books.contains_key(symbol)) {
+      return Err(Error::SymbolDoesNotExist { symbol });
+    }
+
+    for (symbol, symbol_config) in config.symbols {
+      if self.configs.get(&symbol) != Some(&symbol_config) {
+        self.configure_symbol(symbol, symbol_config)?;
+      }
+    }
+    if self.surveillance.rules != config.surveillance {
+      self.set_surveillance_rules(config.surveillance);
+    }
+    if self.order_to_trade.rules != config.order_to_trade {
+      self.set_order_to_trade_rules(config.order_to_trade);
+    }
+    if self.fees.schedule != config.fees {
+      self.set_fee_schedule(config.fees);
+    }
+    if self.collateral.rules != config.collateral {
+      self.set_collateral_rules(config.collateral);
+    }
+
+    Ok(())
+  }
+
+  pub fn queue_depths(&self) -> QueueDepths {
+    QueueDepths {
+      market_data: self.market_data.len(),
+      audit_trail: self.audit_trail.len(),
+      alerts: self.alerts.len(),
+      journal: self.journal.len(),
+    }
+  }
+
+  /// Journal and audit a connection or command refused for the address it came from, with the default account if
+  /// none was named
+  pub fn deny(&mut self, session: SessionId, account: AccountId, source: IpAddr) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::Deny { session, account, source });
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session,
+      account,
+      event: AuditEvent::Deny,
+      order: None,
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: Some(source),
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    });
+  }
+
+  /// Take all audit records since the last call
+  pub fn drain_audit_trail(&mut self) -> Vec<AuditRecord> {
+    self.audit_trail.drain(..).collect()
+  }
+
+  /// Take all alerts raised on the ops channel since the last call
+  pub fn drain_alerts(&mut self) -> Vec<Alert> {
+    self.alerts.drain(..).collect()
+  }
+
+  /// Be sent every `EngineEvent` from now on, in the order they happen, until the receiver is dropped
+  pub fn subscribe(&mut self) -> Receiver<EngineEvent> {
+    self.subscribers.subscribe()
+  }
+
+  /// Insert an index, replacing any existing index with the same symbol
+  ///
+  /// # Returns
+  /// true if the index did not already exist
+  pub fn insert_index(&mut self, symbol: Symbol, index: Index) -> bool {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::InsertIndex(symbol, index.clone()));
+    self.indices.insert(symbol, index).is_none()
+  }
+
+  /// Take all market data published since the last call
+  pub fn drain_market_data(&mut self) -> Vec<MarketData> {
+    self.market_data.drain(..).collect()
+  }
+
+  /// Create a new account
+  ///
+  /// # Returns
+  /// the id of the created account
+  pub fn create_account(&mut self) -> AccountId {
+    self.insert_account(None, false)
+  }
+
+  /// Declare the beneficial owner of an account
+  pub fn set_beneficial_owner(&mut self, id: AccountId, owner: AccountId) -> Result<(), Error> {
+    if !self.accounts.contains_key(&owner) {
+      return Err(Error::AccountDoesNotExist { id: owner });
+    }
+
+    self.try_get_account_mut(id)?.beneficial_owner = Some(owner);
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::SetBeneficialOwner { account: id, owner });
+    Ok(())
+  }
+
+  /// Create a new account permitted to run admin commands
+  ///
+  /// # Returns
+  /// the id of the created account
+  pub fn create_admin_account(&mut self) -> AccountId {
+    self.insert_account(None, true)
+  }
+
+  /// Create a new sub-account of a firm
+  ///
+  /// # Returns
+  /// the id of the created account
+  pub fn create_firm_account(&mut self, firm: FirmId) -> AccountId {
+    self.insert_account(Some(firm), false)
+  }
+
+  /// Get every account, without its orders, and the id counters to carry over to the next run
+  pub fn account_store(&self) -> AccountStore {
+    let mut accounts: Vec<_> = self
+      .accounts
+      .iter()
+      .map(|(&id, account)| {
+        let account = Account {
+          orders: vec![],
+          ..account.clone()
+        };
+        (id, account)
+      })
+      .collect();
+    accounts.sort_by_key(|&(id, _)| usize::from(id));
+
+    AccountStore {
+      accounts,
+      next_account_id: self.next_account_id,
+      next_order_id: self.next_order_id,
+      next_event_id: self.next_event_id,
+      next_trade_id: self.next_trade_id,
+    }
+  }
+
+  /// Load the accounts kept from an earlier run, replacing any with the same ids, and carry its id counters over so no
+  /// id it gave out is given out again
+  pub fn load_accounts(&mut self, store: AccountStore) {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::LoadAccounts(store.clone()));
+
+    let after_accounts = store.accounts.iter().map(|&(id, _)| usize::from(id) + 1).max().unwrap_or_default();
+    let next_account_id = usize::from(self.next_account_id).max(store.next_account_id.into()).max(after_accounts);
+    self.next_account_id = next_account_id.into();
+    self.next_order_id = usize::from(self.next_order_id).max(store.next_order_id.into()).into();
+    self.next_event_id = self.next_event_id.max(store.next_event_id);
+    self.next_trade_id = self.next_trade_id.max(store.next_trade_id);
+    for (id, account) in store.accounts {
+      if let Some(reference) = account.reference {
+        self.account_references.insert(reference, id);
+      }
+      self.accounts.insert(id, account);
+    }
+  }
+
+  /// Create and journal a new account
+  fn insert_account(&mut self, firm: Option<FirmId>, is_admin: bool) -> AccountId {
+    self.received_at = self.clock.now();
+    self.record_journal(JournalEvent::CreateAccount { firm, is_admin });
+    self.add_account(firm, is_admin)
+  }
+
+  /// Create an account without journaling it, for commands journaled themselves
+  fn add_account(&mut self, firm: Option<FirmId>, is_admin: bool) -> AccountId {
+    let id = self.next_account_id;
+    self.next_account_id += 1.into();
+    self.accounts.insert(id, Account {
+      firm,
+      is_admin,
+      ..Account::default()
+    });
+    self.account_updated(id);
+    id
+  }
+
+  /// Take the next id in the engine-wide event sequence
+  fn next_event_id(&mut self) -> EventId {
+    let id = self.next_event_id;
+    self.next_event_id += 1.into();
+    id
+  }
+
+  /// Journal an event received at `received_at` and applied now
+  fn record_journal(&mut self, event: JournalEvent) {
+    let sequence = self.next_event_id();
+    self.journal.push(JournalEntry {
+      sequence,
+      received_at: self.received_at,
+      processed_at: self.clock.now(),
+      event,
+    });
+  }
+
+  /// Insert an order into its book and match it
+  fn place(&mut self, id: Id, symbol: Symbol, side: Side, order: Order) -> Result<(), Error> {
+    let book_id = self.try_get_book_mut(symbol)?.insert(side, order);
+    self.id_to_order_path_index.insert(id, (symbol, side, book_id));
+    self.order_path_to_id_index.insert((symbol, side, book_id), id);
+    if self.is_continuous(symbol) {
+      self.match_order(symbol, side, book_id, None)?;
+    }
+    // the midpoint may have moved
+    self.match_dark(symbol);
+
+    Ok(())
+  }
+
+  /// Execute an order as `match_order` does, collecting its executions to reply with
+  fn execute(&mut self, symbol: Symbol, side: Side, book_id: OrderId) -> Result<Executions, Error> {
+    let mut executions = vec![];
+    let is_filled = self.match_order(symbol, side, book_id, Some(&mut executions))?;
+    Ok((is_filled, executions))
+  }
+
+  /// Execute an order against the opposite side of its book, publishing the resulting trades
+  ///
+  /// # Returns
+  /// true if the order was filled
+  fn match_order(
+    &mut self,
+    symbol: Symbol,
+    side: Side,
+    book_id: OrderId,
+    mut executions: Option<&mut Vec<Execution>>,
+  ) -> Result<bool, Error> {
+    // reuse one buffer for the fills of every order, rather than allocating per order
+    let mut fills = std::mem::take(&mut self.fills);
+    fills.clear();
+    let mut decrements = vec![];
+    let id = self.try_get_id((symbol, side, book_id))?;
+    let account = self.order_accounts.get(&id).copied();
+    let firm = account.and_then(|account| self.accounts.get(&account)).and_then(|account| account.firm);
+    let book = self.books.get_mut(&symbol).ok_or(Error::SymbolDoesNotExist { symbol })?;
+    let flags = book.get(side, book_id).ok_or(Error::InconsistentBook { symbol })?.flags;
+    let decrements_self_matches = flags.contains(OrderFlags::DECREMENT_SELF_MATCH);
+    let mut is_blocked = false;
+    // only orders of a firm can be kept from matching within it, and only the order's own may be decremented instead
+    let is_filled = if firm.is_some() || decrements_self_matches {
+      let is_anti_internalized = flags.contains(OrderFlags::ANTI_INTERNALIZATION);
+      let (paths, order_accounts, accounts) = (&self.order_path_to_id_index, &self.order_accounts, &self.accounts);
+      let is_filled = book.execute_into_except(side, book_id, &mut fills, &mut decrements, |resting, order| {
+        let resting_account = paths.get(&(symbol, side.opposite(), resting)).and_then(|id| order_accounts.get(id));
+        let resting_firm = resting_account.and_then(|account| accounts.get(account)).and_then(|account| account.firm);
+        let is_firm = firm.is_some() && resting_firm == firm;
+        if decrements_self_matches && resting_account.is_some() && resting_account.copied() == account {
+          Contra::DecrementBoth
+        } else if (is_anti_internalized || order.flags.contains(OrderFlags::ANTI_INTERNALIZATION)) && is_firm {
+          Contra::PassOver
+        } else {
+          Contra::Match
+        }
+      });
+      let is_filled = is_filled.map_err(|_| Error::InconsistentBook { symbol })?;
+      is_blocked = !is_filled && book.get(side, book_id).is_some_and(|order| book.is_marketable(side, order.price));
+      is_filled
+    } else {
+      book.execute_into(side, book_id, &mut fills).map_err(|_| Error::InconsistentBook { symbol })?
+    };
+
+    for &(against_book_id, quantity, against_is_filled) in fills.iter() {
+      let price = self.try_get_book_order((symbol, side.opposite(), against_book_id))?.price;
+      let against_id = self.try_get_id((symbol, side.opposite(), against_book_id))?;
+      let trade = match side {
+        Side::Bid => self.record_trade(symbol, price, quantity, id, against_id, Some(id)),
+        Side::Ask => self.record_trade(symbol, price, quantity, against_id, id, Some(id)),
+      };
+      if let Some(executions) = executions.as_mut() {
+        executions.push(Execution {
+          id: against_id,
+          trade,
+          quantity,
+          is_filled: against_is_filled,
+          received_at: self.received_at,
+          matched_at: self.clock.now(),
+        });
+      }
+    }
+    self.fills = fills;
+    for (against_book_id, quantity, _) in decrements {
+      let resting = *self.try_get_book_order((symbol, side.opposite(), against_book_id))?;
+      let against_id = self.try_get_id((symbol, side.opposite(), against_book_id))?;
+      self.prevent_self_match(against_id, symbol, side.opposite(), resting.price, quantity);
+      self.prevent_self_match(id, symbol, side, resting.price, quantity);
+    }
+    // resting against the orders it passed over would leave the book crossed, so the order is cancelled instead
+    if is_blocked {
+      self.expire(id, CancelReason::SelfMatch);
+    }
+
+    Ok(is_filled)
+  }
+
+  /// Audit the quantity taken off an order instead of trading it with another of its own account
+  fn prevent_self_match(&mut self, id: Id, symbol: Symbol, side: Side, price: Price, quantity: Quantity) {
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session: self.order_sessions[&id],
+      account: self.order_accounts[&id],
+      event: AuditEvent::SelfMatchPrevented,
+      order: Some(id),
+      symbol: Some(symbol),
+      side: Some(side),
+      price: Some(price),
+      quantity: Some(quantity),
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: self.order_tag(id),
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    });
+  }
+
+  /// Publish market data, marking collateral to the prices in it
+  fn publish(&mut self, data: MarketData) {
+    self.collateral.on_market_data(&data);
+    match data {
+      MarketData::Trade {
+        symbol,
+        price,
+        quantity,
+        trade,
+      } => self.emit(EngineEvent::Trade {
+        symbol,
+        price,
+        quantity,
+        trade,
+        is_dark: false,
+      }),
+      MarketData::DarkTrade {
+        symbol,
+        price,
+        quantity,
+        trade,
+      } => self.emit(EngineEvent::Trade {
+        symbol,
+        price,
+        quantity,
+        trade,
+        is_dark: true,
+      }),
+      _ => {}
+    }
+    self.market_data.push(data);
+  }
+
+  /// Add a record to the audit trail, logging the order event it stands for as activity of the order's account and
+  /// sending it to subscribers
+  fn audit(&mut self, record: AuditRecord) {
+    self.audit_trail.push(record);
+    let event = self.order_event(&record);
+    if let Some((account, activity)) = event.and_then(Activity::from_event) {
+      self.log_activity(account, record.timestamp, activity);
+    }
+    if self.subscribers.is_empty() {
+      return;
+    }
+
+    if let Some(event) = event {
+      self.emit(event);
+    }
+    if let AuditEvent::Execute | AuditEvent::Liquidate = record.event {
+      self.account_updated(record.account);
+    }
+  }
+
+  /// Get the event an audit record stands for, with the account of the order it names rather than of the command
+  fn order_event(&self, record: &AuditRecord) -> Option<EngineEvent> {
+    if record.event == AuditEvent::Reject {
+      return Some(EngineEvent::OrderRejected {
+        account: record.account,
+        order: record.order,
+      });
+    }
+
+    let order = record.order?;
+    let account = self.order_accounts.get(&order).copied().unwrap_or(record.account);
+    match record.event {
+      AuditEvent::Accept => Some(EngineEvent::OrderAccepted {
+        order,
+        account,
+        symbol: record.symbol?,
+        side: record.side?,
+        price: record.price?,
+        quantity: record.quantity?,
+      }),
+      AuditEvent::Modify => Some(EngineEvent::OrderModified { order, account }),
+      AuditEvent::Cancel => Some(EngineEvent::OrderCancelled {
+        order,
+        account,
+        reason: record.cancel_reason?,
+      }),
+      AuditEvent::Execute => Some(EngineEvent::Fill {
+        order,
+        account,
+        symbol: record.symbol?,
+        side: record.side?,
+        price: record.price?,
+        quantity: record.quantity?,
+        trade: record.trade?,
+        filled: record.filled?,
+      }),
+      _ => None,
+    }
+  }
+
+  /// Send an event to every subscriber
+  fn emit(&mut self, event: EngineEvent) {
+    if !self.subscribers.is_empty() {
+      self.subscribers.send(event);
+    }
+  }
+
+  fn log_activity(&mut self, account: AccountId, timestamp: Timestamp, activity: Activity) {
+    self.activity_logs.entry(account).or_default().push(timestamp, activity);
+  }
+
+  /// Send subscribers an account's details as they are now
+  fn account_updated(&mut self, id: AccountId) {
+    if let Some(account) = self.accounts.get(&id).filter(|_| !self.subscribers.is_empty()) {
+      let summary = account.into();
+      self.emit(EngineEvent::AccountUpdated { account: id, summary });
+    }
+  }
+
+  /// Record a trade, republishing every index the symbol is a constituent of
+  ///
+  /// # Returns
+  /// the trade's id
+  fn record_trade(
+    &mut self,
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    bid: Id,
+    ask: Id,
+    taker: Option<Id>,
+  ) -> TradeId {
+    self.last_trade_prices.insert(symbol, price);
+    let stats = self.daily_stats.entry(symbol).or_insert_with(|| DailyStats::new(symbol, price));
+    stats.add_trade(price, quantity);
+    let trade = self.record_fills(symbol, price, quantity, bid, ask, taker);
+    self.publish(MarketData::Trade {
+      symbol,
+      price,
+      quantity,
+      trade,
+    });
+
+    let values: Vec<_> = self
+      .indices
+      .iter()
+      .filter(|(_, index)| index.contains(symbol))
+      .filter_map(|(&index_symbol, index)| Some((index_symbol, index.value(&self.last_trade_prices)?)))
+      .collect();
+    for (symbol, value) in values {
+      self.publish(MarketData::IndexValue { symbol, value });
+    }
+    trade
+  }
+
+  /// Reject or charge an order from an account breaching its order-to-trade thresholds
+  fn enforce_order_to_trade(&mut self, id: AccountId, symbol: Symbol) -> Result<(), Error> {
+    if !self.order_to_trade.rules.enforce {
+      return Ok(());
+    }
+
+    match self.order_to_trade.consequence(self.clock.now(), id) {
+      Consequence::Throttle => Err(Error::OrderToTradeRatioExceeded { id }),
+      Consequence::Fee => {
+        let fee = self.order_to_trade.rules.excess_order_fee;
+        let account = self.try_get_account_mut(id)?;
+        let before = account.balance;
+        account.balance = if account.balance > fee {
+          account.balance - fee
+        } else {
+          Price::default()
+        };
+        let (amount, balance) = (i64::from(u32::from(before - account.balance)), account.balance);
+        let now = self.clock.now();
+        self.fee_ledger.on_order_fee(now, id, symbol, amount as u64);
+        self.log_activity(id, now, Activity::Fee {
+          amount,
+          trade: None,
+          balance,
+        });
+        self.account_updated(id);
+        Ok(())
+      }
+      Consequence::Warning | Consequence::None => Ok(()),
+    }
+  }
+
+  /// Get the cost of an account's resting bids
+  fn reserved(&self, account: &Account) -> u64 {
+    account
+      .orders
+      .iter()
+      .filter(|&&id| self.resting_side(id) == Some(Side::Bid))
+      .filter_map(|&id| {
+        let (symbol, ..) = *self.id_to_order_path_index.get(&id)?;
+        let order = self.resting_order(id)?;
+        Some(order.remaining().value_at(order.price, self.quantity_precision(symbol)))
+      })
+      .sum()
+  }
+
+  /// Liquidate every active account whose buying power no longer covers its maintenance margin, in id order
+  fn liquidate_under_margined(&mut self) {
+    let rules = match self.collateral.rules.liquidation {
+      Some(rules) => rules,
+      None => return,
+    };
+    let mut breached: Vec<(AccountId, u64, u64)> = self
+      .accounts
+      .iter()
+      .filter(|(_, account)| account.state == AccountState::Active && !account.orders.is_empty())
+      .map(|(&id, account)| {
+        let buying_power = self.collateral.buying_power(account, |symbol| self.quantity_precision(symbol));
+        (id, buying_power, self.reserved(account))
+      })
+      .filter(|&(_, buying_power, reserved)| rules.is_breached(buying_power, reserved))
+      .collect();
+    breached.sort_by_key(|&(id, ..)| usize::from(id));
+
+    for (id, buying_power, reserved) in breached {
+      self.liquidate(id, rules, buying_power, reserved);
+    }
+  }
+
+  /// Move an account to `AccountState::LiquidationOnly`, cancel its resting orders and place an ask for each of its
+  /// holdings at the band below the symbol's reference price, in whole lots
+  fn liquidate(&mut self, id: AccountId, rules: LiquidationRules, buying_power: u64, reserved: u64) {
+    // liquidation orders belong to the engine rather than the session whose command set them off
+    let session = std::mem::take(&mut self.session);
+    // the time the command or tick was received, which a replay sees too
+    let started_at = self.received_at;
+    if let Some(account) = self.accounts.get_mut(&id) {
+      account.state = AccountState::LiquidationOnly;
+    }
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session: self.session,
+      account: id,
+      event: AuditEvent::Liquidate,
+      order: None,
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    });
+    for order in self.accounts[&id].orders.clone() {
+      self.expire(order, CancelReason::Liquidation);
+    }
+
+    let mut holdings: Vec<(Symbol, Quantity)> =
+      self.accounts[&id].portfolio.iter().map(|(&symbol, &held)| (symbol, held)).collect();
+    holdings.sort_by_key(|(symbol, _)| symbol.to_string());
+    let mut orders = vec![];
+    for (symbol, held) in holdings {
+      let lot_size = self.configs.get(&symbol).and_then(|config| config.lot_size).map_or(1, u64::from);
+      let quantity = Quantity::from(u64::from(held) - u64::from(held).checked_rem(lot_size).unwrap_or(0));
+      let reference = self.reference_price(symbol).filter(|_| self.books.contains_key(&symbol));
+      let price = match reference {
+        Some(reference) if quantity > Quantity::default() => rules.ask_price(reference),
+        _ => continue,
+      };
+
+      let order = Order::new(price, quantity).with_tag(Label::new(LIQUIDATION_TAG).unwrap());
+      let order_id = self.next_order_id;
+      self.next_order_id += 1.into();
+      self.accept(id, order_id, symbol, Side::Ask, order);
+      // the book was checked above
+      let _ = self.place(order_id, symbol, Side::Ask, order);
+      orders.push(order_id);
+    }
+
+    self.liquidations.insert(id, Liquidation {
+      account: id,
+      started_at,
+      buying_power,
+      reserved,
+      orders,
+    });
+    self.session = session;
+  }
+
+  /// Reject a bid that, with an account's other resting bids, would cost more than its buying power
+  fn enforce_buying_power(&self, id: AccountId, symbol: Symbol, order: Order) -> Result<(), Error> {
+    if !self.collateral.rules.enforce {
+      return Ok(());
+    }
+
+    let account = self.account(id)?;
+    let buying_power = self
+      .collateral
+      .buying_power(account, |symbol| self.quantity_precision(symbol))
+      .saturating_sub(self.reserved(account));
+    if order.remaining().value_at(order.price, self.quantity_precision(symbol)) > buying_power {
+      Err(Error::InsufficientBuyingPower { id, buying_power })
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Count an order message towards an account's order-to-trade ratio
+  fn record_order_message(&mut self, account: AccountId) {
+    let now = self.clock.now();
+    if let Some(consequence) = self.order_to_trade.on_order(now, account) {
+      let ratio = self.order_to_trade.status(now, account).ratio as u64;
+      self.alerts.push(Alert::OrderToTradeRatio {
+        account,
+        ratio,
+        consequence,
+      });
+    }
+  }
+
+  /// Assign an order to its account and session
+  fn accept(&mut self, account: AccountId, id: Id, symbol: Symbol, side: Side, order: Order) {
+    if let Some(life) = self.configs.get(&symbol).and_then(|config| config.minimum_quote_life) {
+      self.quote_lives.insert(id, self.clock.now() + life.duration);
+    }
+    if let Some(account) = self.accounts.get_mut(&account) {
+      account.orders.push(id);
+    }
+    self.order_accounts.insert(id, account);
+    self.order_sessions.insert(id, self.session);
+    if !order.tag.is_empty() {
+      self.order_tags.insert(id, order.tag);
+    }
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session: self.session,
+      account,
+      event: AuditEvent::Accept,
+      order: Some(id),
+      symbol: Some(symbol),
+      side: Some(side),
+      price: Some(order.price),
+      quantity: Some(order.quantity),
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: self.order_tag(id),
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    });
+  }
+
+  /// Record both sides of an execution in the audit trail, charge their fees and run surveillance over it
+  ///
+  /// `taker` is the side that arrived and traded, if either did.
+  ///
+  /// # Returns
+  /// the id given to the trade
+  fn record_fills(
+    &mut self,
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    bid: Id,
+    ask: Id,
+    taker: Option<Id>,
+  ) -> TradeId {
+    let timestamp = self.clock.now();
+    let trade = self.next_trade_id;
+    self.next_trade_id += 1.into();
+    let precision = self.quantity_precision(symbol);
+    for &(id, side) in &[(bid, Side::Bid), (ask, Side::Ask)] {
+      let account = self.order_accounts[&id];
+      let settlement = self.settlements.entry((account, symbol)).or_insert_with(|| Settlement::new(account, symbol));
+      settlement.add_fill(side, price, quantity, precision);
+
+      let value = quantity.value_at(price, precision);
+      let liquidity = match taker {
+        Some(taker) if taker == id => Liquidity::Taker,
+        Some(_) => Liquidity::Maker,
+        None => Liquidity::Crossed,
+      };
+      let fee = self.fees.on_fill(timestamp, account, self.fee_model(symbol), liquidity, value, quantity);
+      self.fee_ledger.on_fill(timestamp, account, symbol, fee);
+      if let Some(details) = self.accounts.get_mut(&account) {
+        let balance = i64::from(u32::from(details.balance)) - fee;
+        details.balance = (balance.clamp(0, i64::from(u32::MAX)) as u32).into();
+        let (trade, balance) = (Some(trade), details.balance);
+        if fee != 0 {
+          self.log_activity(account, timestamp, Activity::Fee { amount: fee, trade, balance });
+        }
+      }
+
+      let fills = self.order_fills.entry(id).or_default();
+      fills.0 += quantity;
+      fills.1 = fills.1.saturating_add(u64::from(u32::from(price)).saturating_mul(quantity.into()));
+      let (filled, average_price) = self.cumulative_fills(id);
+
+      let sequence = self.next_event_id();
+      self.audit(AuditRecord {
+        sequence,
+        timestamp,
+        session: self.order_sessions[&id],
+        account,
+        event: AuditEvent::Execute,
+        order: Some(id),
+        symbol: Some(symbol),
+        side: Some(side),
+        price: Some(price),
+        quantity: Some(quantity),
+        trade: Some(trade),
+        liquidity: Some(liquidity),
+        filled: Some(filled),
+        average_price,
+        source: None,
+        tag: self.order_tag(id),
+        check: None,
+        reason: None,
+        cancel_reason: None,
+      });
+    }
+
+    self.surveil(symbol, quantity, bid, ask);
+    trade
+  }
+
+  /// Run surveillance over a trade, publishing any alerts on the ops channel
+  fn surveil(&mut self, symbol: Symbol, quantity: Quantity, bid: Id, ask: Id) {
+    let party = |id| {
+      let account = self.order_accounts[&id];
+      let owner = self.accounts[&account].beneficial_owner.unwrap_or(account);
+      Party { account, owner }
+    };
+    let (buyer, seller) = (party(bid), party(ask));
+    let now = self.clock.now();
+    self.order_to_trade.on_trade(now, buyer.account);
+    self.order_to_trade.on_trade(now, seller.account);
+
+    let alerts = self.surveillance.on_trade(now, symbol, buyer, seller, quantity);
+    self.alerts.extend(alerts);
+  }
+
+  /// Cross an incoming order against resting orders from other accounts of the same firm
+  ///
+  /// Each cross is priced at the middle of the range allowed by both limits and the BBO, rounded down. Resting orders are
+  /// crossed in time priority.
+  fn internalize(&mut self, account_id: AccountId, symbol: Symbol, side: Side, id: Id, order: &mut Order) {
+    let firm = match self.accounts[&account_id].firm {
+      Some(firm) => firm,
+      None => return,
+    };
+
+    let mut contra: Vec<(Id, OrderId)> = self
+      .accounts
+      .iter()
+      .filter(|(&id, account)| id != account_id && account.firm == Some(firm))
+      .flat_map(|(_, account)| account.orders.iter())
+      .filter_map(|id| match self.id_to_order_path_index.get(id) {
+        Some(&(other_symbol, other_side, book_id)) if other_symbol == symbol && other_side == side.opposite() => {
+          Some((*id, book_id))
+        }
+        _ => None,
+      })
+      .collect();
+    contra.sort_by_key(|&(id, _)| usize::from(id));
+
+    let book = match self.books.get_mut(&symbol) {
+      Some(book) => book,
+      None => return,
+    };
+    let (best_bid, best_ask) = book.best_prices();
+    let mut trades = vec![];
+    for (contra_id, book_id) in contra {
+      if order.is_filled() {
+        break;
+      }
+
+      let resting = match book.get(side.opposite(), book_id) {
+        Some(&resting) => resting,
+        None => continue,
+      };
+      if resting.is_filled() || resting.is_cancelled || resting.flags.contains(OrderFlags::ANTI_INTERNALIZATION) {
+        continue;
+      }
+
+      let (bid_limit, ask_limit) = match side {
+        Side::Bid => (order.price, resting.price),
+        Side::Ask => (resting.price, order.price),
+      };
+      let low = best_bid.map_or(ask_limit, |bid| bid.max(ask_limit));
+      let high = best_ask.map_or(bid_limit, |ask| ask.min(bid_limit));
+      if low > high {
+        continue;
+      }
+
+      let (low, high) = (u32::from(low), u32::from(high));
+      let price = (low + (high - low) / 2).into();
+      let quantity = order.remaining().min(resting.remaining());
+      book.fill(side.opposite(), book_id, quantity);
+      order.filled += quantity;
+      trades.push(match side {
+        Side::Bid => (price, quantity, id, contra_id),
+        Side::Ask => (price, quantity, contra_id, id),
+      });
+    }
+
+    for (price, quantity, bid, ask) in trades {
+      self.record_trade(symbol, price, quantity, bid, ask, Some(id));
+    }
+  }
+
+  /// Match a symbol's dark orders at the lit book's midpoint
+  fn match_dark(&mut self, symbol: Symbol) {
+    let midpoint = self.books.get(&symbol).and_then(OrderBook::midpoint);
+
+    if let (Some(midpoint), Some(dark_pool)) = (midpoint, self.dark_pools.get_mut(&symbol)) {
+      for (bid, ask, quantity) in dark_pool.match_at(midpoint) {
+        let trade = self.record_fills(symbol, midpoint, quantity, bid, ask, None);
+        self.publish(MarketData::DarkTrade {
+          symbol,
+          price: midpoint,
+          quantity,
+          trade,
+        });
+      }
+    }
+  }
+
+  fn try_get_dark_pool_mut(&mut self, symbol: Symbol) -> Result<&mut DarkPool, Error> {
+    if !self.books.contains_key(&symbol) {
+      Err(Error::SymbolDoesNotExist { symbol })
+    } else if let Some(dark_pool) = self.dark_pools.get_mut(&symbol) {
+      Ok(dark_pool)
+    } else {
+      Err(Error::DarkPoolDisabled { symbol })
+    }
+  }
+
+  fn try_get_dark_order(&self, id: Id) -> Option<&Order> {
+    self
+      .dark_order_symbols
+      .get(&id)
+      .and_then(|symbol| self.dark_pools.get(symbol))
+      .and_then(|dark_pool| dark_pool.get(id))
+  }
+
+  fn is_continuous(&self, symbol: Symbol) -> bool {
+    let is_called = self.scheduled_auctions.get(&symbol).is_some_and(|auction| auction.is_called);
+    !is_called
+      && self
+        .configs
+        .get(&symbol)
+        .is_none_or(|config| config.trading_mode == TradingMode::Continuous)
+  }
+
+  /// Check a replace's price against its account's protection band, around the best price on the opposite side
+  ///
+  /// # Returns
+  /// the price to replace the order at
+  fn protect_replace(
+    &self,
+    id: Id,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    protection: ReplaceProtection,
+  ) -> Result<Price, Error> {
+    let (best_bid, best_ask) = self.books.get(&symbol).ok_or(Error::SymbolDoesNotExist { symbol })?.best_prices();
+    let band = u32::from(protection.band);
+    let (limit, is_outside) = match (side, best_bid, best_ask) {
+      (Side::Bid, _, Some(ask)) => {
+        let limit = Price::from(u32::from(ask).saturating_add(band));
+        (limit, price > limit)
+      }
+      (Side::Ask, Some(bid), _) => {
+        let limit = Price::from(u32::from(bid).saturating_sub(band));
+        (limit, price < limit)
+      }
+      // with nothing to trade against, no price is marketable
+      _ => return Ok(price),
+    };
+
+    match (is_outside, protection.action) {
+      (false, _) => Ok(price),
+      (true, ProtectionAction::Reject) => Err(Error::ReplaceOutsideBand { id, limit }),
+      (true, ProtectionAction::Cap) => Ok(limit),
+    }
+  }
+
+  /// Get the symbol an order entry command enters or adds to an order on
+  fn order_entry_symbol(&self, command: &CommandKind) -> Option<Symbol> {
+    match *command {
+      CommandKind::PlaceOrder(_, symbol, _) => Some(symbol),
+      CommandKind::UpdateOrder(id, ..) | CommandKind::ResumeOrder(id) | CommandKind::ExecuteOrder(id) => {
+        self.id_to_order_path_index.get(&id).map(|&(symbol, ..)| symbol)
+      }
+      _ => None,
+    }
+  }
+
+  /// Get the symbol whose book a command changes, if it changes one
+  pub fn book_symbol(&self, command: &CommandKind) -> Option<Symbol> {
+    match *command {
+      CommandKind::CancelOrder(id) | CommandKind::SuspendOrder(id) => self
+        .id_to_order_path_index
+        .get(&id)
+        .map(|&(symbol, ..)| symbol)
+        .or_else(|| self.dark_order_symbols.get(&id).copied()),
+      CommandKind::ReplaceSymbol(symbol) => Some(symbol),
+      _ => self.order_entry_symbol(command),
+    }
+  }
+
+  /// Reject an order entry command whose quantity is not a multiple of its symbol's lot size
+  fn enforce_lot_size(&self, symbol: Symbol, command: &CommandKind) -> Result<(), Error> {
+    let quantity = match *command {
+      CommandKind::PlaceOrder(_, _, order) => order.quantity,
+      CommandKind::UpdateOrder(_, _, Some(quantity)) => quantity,
+      _ => return Ok(()),
+    };
+    match self.configs.get(&symbol).and_then(|config| config.lot_size) {
+      Some(lot_size) if u64::from(quantity).checked_rem(u64::from(lot_size)).is_some_and(|rest| rest != 0) => {
+        Err(Error::InvalidLotSize { symbol, lot_size })
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Pass a failed pre-trade check if the command's session bypasses it for an admin account, auditing the bypass
+  fn check_risk(&mut self, command: Command, check: RiskCheck, result: Result<(), Error>) -> Result<(), Error> {
+    let bypass = match self.risk_bypasses.get(&self.session) {
+      Some(&bypass) if result.is_err() && bypass.bypasses(check) && self.accounts[&command.account_id].is_admin => {
+        bypass
+      }
+      _ => return result,
+    };
+
+    let sequence = self.next_event_id();
+    self.audit(AuditRecord {
+      sequence,
+      timestamp: self.clock.now(),
+      session: self.session,
+      account: command.account_id,
+      event: AuditEvent::Bypass,
+      order: None,
+      symbol: self.order_entry_symbol(&command.kind),
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: Some(check),
+      reason: Some(bypass.reason),
+      cancel_reason: None,
+    });
+    Ok(())
+  }
+
+  /// Check that an account's state allows it to send a command
+  fn validate_command_against_account(id: AccountId, account: &Account, command: &CommandKind) -> Result<(), Error> {
+    let is_allowed = command.is_query()
+      || match (account.state, command) {
+        (AccountState::Active, _) => true,
+        (AccountState::Suspended, CommandKind::CancelOrder(_) | CommandKind::CancelTagged(..))
+        | (AccountState::LiquidationOnly, CommandKind::CancelOrder(_) | CommandKind::CancelTagged(..)) => true,
+        (AccountState::LiquidationOnly, &CommandKind::PlaceOrder(Side::Ask, symbol, order)) => {
+          account.portfolio.get(&symbol).is_some_and(|&held| order.quantity <= held)
+        }
+        _ => false,
+      };
+
+    if is_allowed {
+      Ok(())
+    } else {
+      Err(Error::AccountRestricted { id, state: account.state })
+    }
+  }
+
+  fn try_get_account_mut(&mut self, id: AccountId) -> Result<&mut Account, Error> {
+    if let Some(account) = self.accounts.get_mut(&id) {
+      Ok(account)
+    } else {
+      Err(Error::AccountDoesNotExist { id })
+    }
+  }
+
+  fn try_get_book_mut(&mut self, symbol: Symbol) -> Result<&mut OrderBook, Error> {
+    if let Some(book) = self.books.get_mut(&symbol) {
+      Ok(book)
+    } else {
+      Err(Error::SymbolDoesNotExist { symbol })
+    }
+  }
+
+  fn try_get_order_path(&self, id: Id) -> Result<OrderPath, Error> {
+    if let Some(path) = self.id_to_order_path_index.get(&id) {
+      Ok(*path)
+    } else {
+      Err(Error::IdDoesNotExist { id })
+    }
+  }
+
+  /// Get the id of the order at a path in its book
+  fn try_get_id(&self, path: OrderPath) -> Result<Id, Error> {
+    let (symbol, ..) = path;
+    self.order_path_to_id_index.get(&path).copied().ok_or(Error::InconsistentBook { symbol })
+  }
+
+  /// Get the order at a path the engine gave out, which its book has
+  fn try_get_book_order(&self, (symbol, side, book_id): OrderPath) -> Result<&Order, Error> {
+    let book = self.books.get(&symbol).ok_or(Error::SymbolDoesNotExist { symbol })?;
+    book.get(side, book_id).ok_or(Error::InconsistentBook { symbol })
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn index_is_updated_on_execution() {
+    let mut engine = MatchEngine::default();
+    let account_id = engine.create_account();
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+    let index_symbol = ['I', 'N', 'D', 'X'].into();
+    engine.insert_new_symbol(adbe).unwrap();
+    engine.insert_new_symbol(msft).unwrap();
+    engine.insert_index(index_symbol, Index::new(vec![(adbe, 1.0), (msft, 2.0)], 2.0).unwrap());
+
+    let mut process = |kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(CommandKind::PlaceOrder(Side::Ask, adbe, Order::new(100.into(), 10.into())));
+    process(CommandKind::PlaceOrder(Side::Bid, adbe, Order::new(100.into(), 10.into())));
+    match process(CommandKind::GetIndex(index_symbol)) {
+      Success::GetIndex(None) => (),
+      other => panic!("unexpected {:?}", other),
+    }
+
+    process(CommandKind::PlaceOrder(Side::Ask, msft, Order::new(50.into(), 10.into())));
+    process(CommandKind::PlaceOrder(Side::Bid, msft, Order::new(50.into(), 5.into())));
+    match process(CommandKind::GetIndex(index_symbol)) {
+      Success::GetIndex(Some(value)) => assert_eq!(value, 100.0),
+      other => panic!("unexpected {:?}", other),
+    }
+
+    assert_eq!(
+      engine.drain_market_data().last(),
+      Some(&MarketData::IndexValue {
+        symbol: index_symbol,
+        value: 100.0
+      })
+    );
+  }
+
+  #[test]
+  fn auction_only_symbol_matches_on_schedule() {
+    use crate::clock::ManualClock;
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    let interval = Duration::from_secs(300);
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        trading_mode: TradingMode::PeriodicAuction { interval },
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let mut process = |kind| engine.try_process(Command { account_id, kind });
+    process(CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(102.into(), 10.into()))).unwrap();
+    process(CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 4.into()))).unwrap();
+    let ask = match process(CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(101.into(), 8.into()))) {
+      Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+      other => panic!("unexpected {:?}", other),
+    };
+    match process(CommandKind::ExecuteOrder(ask)) {
+      Err(Error::ContinuousTradingDisabled { .. }) => (),
+      other => panic!("unexpected {:?}", other),
+    }
+    assert!(engine.drain_market_data().is_empty());
+
+    clock.advance(interval);
+    let mut watched = vec![];
+    engine.tick_watched(|symbol| watched.push(symbol));
+    assert_eq!(watched, vec![symbol]);
+    assert_eq!(
+      engine.drain_market_data().last(),
+      Some(&MarketData::AuctionUncross {
+        symbol,
+        price: 101.into(),
+        quantity: 10.into()
+      })
+    );
+  }
+
+  #[test]
+  fn scheduled_auctions_call_then_uncross_a_continuous_book() {
+    use crate::clock::ManualClock;
+    use crate::config::AuctionSchedule;
+    use std::time::Duration;
+
+    let hour = Duration::from_secs(60 * 60);
+    let clock = Arc::new(ManualClock::new(Timestamp::from(10 * hour.as_nanos() as u64) + hour / 2));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        scheduled_auctions: Some(AuctionSchedule {
+          every: hour,
+          call: Duration::from_secs(120),
+          jitter: Duration::default(),
+          seed: 0,
+        }),
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    engine.try_process(Command {
+      account_id,
+      kind: CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 5.into())),
+    }).unwrap();
+    clock.advance(hour / 2 - Duration::from_secs(120));
+    engine.tick();
+    let uncrosses_at = Timestamp::from(11 * hour.as_nanos() as u64);
+    assert_eq!(engine.drain_market_data(), vec![MarketData::AuctionCall { symbol, uncrosses_at }]);
+
+    let bid = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(101.into(), 3.into()));
+    match engine.try_process(Command { account_id, kind: bid }) {
+      Ok(Success::PlaceOrder(Placement { fills, .. })) => assert!(fills.is_empty()),
+      other => panic!("unexpected {:?}", other),
+    }
+
+    clock.set(uncrosses_at);
+    engine.tick();
+    match engine.drain_market_data().last() {
+      Some(&MarketData::AuctionUncross { quantity, .. }) => assert_eq!(quantity, 3.into()),
+      other => panic!("unexpected {:?}", other),
+    }
+    let bid = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 2.into()));
+    match engine.try_process(Command { account_id, kind: bid }) {
+      Ok(Success::PlaceOrder(Placement { remaining, .. })) => assert_eq!(remaining, 0.into()),
+      other => panic!("unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn scheduled_auctions_uncross_after_their_seeded_jitter() {
+    use crate::clock::{self, ManualClock};
+    use crate::config::AuctionSchedule;
+    use std::time::Duration;
+
+    let hour = Duration::from_secs(60 * 60);
+    let schedule = AuctionSchedule {
+      every: hour,
+      call: Duration::from_secs(120),
+      jitter: Duration::from_secs(30),
+      seed: 7,
+    };
+    let scheduled_at = Timestamp::from(hour.as_nanos() as u64);
+    let jitter = clock::jitter(schedule.seed, scheduled_at, schedule.jitter);
+    assert!(jitter > Duration::default() && jitter < schedule.jitter);
+    assert_eq!(clock::jitter(schedule.seed, scheduled_at, schedule.jitter), jitter);
+
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        scheduled_auctions: Some(schedule),
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    clock.set(Timestamp::from(u64::from(scheduled_at) - 1));
+    engine.tick();
+    let mut process = |kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(101.into(), 3.into())));
+    process(CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 3.into())));
+    engine.drain_market_data();
+
+    clock.set(scheduled_at);
+    engine.tick();
+    assert!(engine.drain_market_data().is_empty());
+    clock.set(scheduled_at + jitter);
+    engine.tick();
+    match engine.drain_market_data().last() {
+      Some(&MarketData::AuctionUncross { quantity, .. }) => assert_eq!(quantity, 3.into()),
+      other => panic!("unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn auction_only_orders_wait_for_the_next_uncross() {
+    let mut engine = MatchEngine::default();
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let mut place = |side, price: u32, quantity: u64, flags| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()).with_flags(flags));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    place(Side::Ask, 100, 5, OrderFlags::empty());
+    let crossing = place(Side::Bid, 101, 3, OrderFlags::AUCTION_ONLY);
+    assert!(crossing.fills.is_empty() && crossing.state == OrderState::New);
+    let away = place(Side::Bid, 99, 2, OrderFlags::AUCTION_ONLY).id;
+
+    assert_eq!(engine.uncross(symbol), Ok(Some((100.into(), 3.into()))));
+    assert!(engine.resting_order(crossing.id).is_none());
+    assert!(engine.is_cancelled(away));
+  }
+
+  #[test]
+  fn early_cancels_are_rejected_or_delayed_for_the_minimum_quote_life() {
+    use crate::clock::ManualClock;
+    use crate::config::MinimumQuoteLife;
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let second = Duration::from_secs(1);
+    let configure = |engine: &mut MatchEngine, early_cancel| {
+      let minimum_quote_life = Some(MinimumQuoteLife { duration: second, early_cancel });
+      engine
+        .configure_symbol(symbol, SymbolConfig {
+          minimum_quote_life,
+          ..SymbolConfig::default()
+        })
+        .unwrap();
+    };
+    let place = |engine: &mut MatchEngine| {
+      let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement.id,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    let cancel = |engine: &mut MatchEngine, id| {
+      let kind = CommandKind::CancelOrder(id);
+      engine.try_process(Command { account_id, kind })
+    };
+
+    configure(&mut engine, EarlyCancel::Reject);
+    let id = place(&mut engine);
+    let rests_until = clock.now() + second;
+    assert_eq!(cancel(&mut engine, id), Err(Error::QuoteLifeNotReached { id, rests_until }));
+    clock.advance(second);
+    assert_eq!(cancel(&mut engine, id), Ok(Success::CancelOrder(true)));
+
+    configure(&mut engine, EarlyCancel::Delay);
+    let id = place(&mut engine);
+    let rests_until = clock.now() + second;
+    assert_eq!(cancel(&mut engine, id), Ok(Success::CancelPending(rests_until)));
+    assert!(engine.resting_order(id).is_some());
+    clock.advance(second);
+    engine.tick();
+    assert!(engine.is_cancelled(id));
+
+    // the cancel is audited once, when it takes effect
+    let cancels: Vec<_> = engine
+      .drain_audit_trail()
+      .into_iter()
+      .filter(|record| record.event == AuditEvent::Cancel && record.order == Some(id))
+      .collect();
+    assert_eq!(cancels.len(), 1);
+    assert_eq!((cancels[0].timestamp, cancels[0].cancel_reason), (rests_until, Some(CancelReason::Requested)));
+  }
+
+  #[test]
+  fn speed_bumped_orders_match_after_their_delay_and_replay() {
+    use crate::clock::ManualClock;
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1)));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let delay = Duration::from_micros(350);
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        speed_bump: Some(delay),
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let mut place = |side, price: u32| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), 5.into()));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement.state,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    assert_eq!(place(Side::Ask, 100), OrderState::New);
+    assert_eq!(place(Side::Bid, 101), OrderState::Held);
+    assert!(engine.drain_market_data().is_empty());
+
+    clock.advance(delay);
+    engine.tick();
+    assert_eq!(trades(engine.drain_market_data()), vec![(100.into(), 5.into())]);
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+  }
+
+  #[test]
+  fn dark_orders_match_at_midpoint() {
+    let mut engine = MatchEngine::default();
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        dark_pool: true,
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let dark = |order: Order| order.with_flags(OrderFlags::DARK);
+    let mut process = |kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 1.into())));
+    process(CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(104.into(), 1.into())));
+    process(CommandKind::PlaceOrder(
+      Side::Bid,
+      symbol,
+      dark(Order::new(103.into(), 50.into())).with_minimum_quantity(40.into()),
+    ));
+    process(CommandKind::PlaceOrder(Side::Ask, symbol, dark(Order::new(101.into(), 30.into()))));
+    process(CommandKind::PlaceOrder(Side::Ask, symbol, dark(Order::new(102.into(), 60.into()))));
+
+    match process(CommandKind::GetQuote(symbol, Side::Ask)) {
+      Success::GetQuote(price) => assert_eq!(price, 104.into()),
+      other => panic!("unexpected {:?}", other),
+    }
+
+    assert_eq!(engine.drain_market_data(), vec![MarketData::DarkTrade {
+      symbol,
+      price: 102.into(),
+      quantity: 50.into(),
+      trade: 0.into(),
+    }]);
+  }
+
+  #[test]
+  fn marketable_order_is_price_improved() {
+    use crate::clock::ManualClock;
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    let duration = Duration::from_millis(100);
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        price_improvement: Some(duration),
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(maker, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(105.into(), 10.into())));
+    process(taker, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(105.into(), 15.into())));
+    process(maker, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(104.into(), 5.into())));
+
+    clock.advance(duration);
+    engine.tick();
+    assert_eq!(trades(engine.drain_market_data()), vec![
+      (104.into(), 5.into()),
+      (105.into(), 10.into())
+    ]);
+  }
+
+  #[test]
+  fn firm_orders_are_internalized_within_bbo() {
+    let mut engine = MatchEngine::default();
+    let outsider = engine.create_account();
+    let (desk_a, desk_b) = (engine.create_firm_account(0.into()), engine.create_firm_account(0.into()));
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        internalization: true,
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(outsider, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 10.into())));
+    let outsider_ask = match process(outsider, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(102.into(), 10.into()))) {
+      Success::PlaceOrder(Placement { id, .. }) => id,
+      other => panic!("unexpected {:?}", other),
+    };
+    process(desk_a, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(102.into(), 10.into())));
+    process(desk_b, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(102.into(), 10.into())));
+
+    // desk b crosses with desk a ahead of the outsider's earlier order at the same price
+    match process(outsider, CommandKind::GetOrder(outsider_ask)) {
+      Success::GetOrder(status) => assert_eq!(status.order.filled, 0.into()),
+      other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(trades(engine.drain_market_data()), vec![(102.into(), 10.into())]);
+  }
+
+  #[test]
+  fn firm_orders_are_internalized_at_the_top_of_the_price_range() {
+    let mut engine = MatchEngine::default();
+    let (desk_a, desk_b) = (engine.create_firm_account(0.into()), engine.create_firm_account(0.into()));
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine
+      .configure_symbol(symbol, SymbolConfig {
+        internalization: true,
+        ..SymbolConfig::default()
+      })
+      .unwrap();
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind }).unwrap();
+    process(desk_a, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new((u32::MAX - 2).into(), 10.into())));
+    process(desk_b, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(u32::MAX.into(), 10.into())));
+
+    // the midpoint of prices this close to the top is taken without overflowing
+    assert_eq!(trades(engine.drain_market_data()), vec![((u32::MAX - 2).into(), 10.into())]);
+  }
+
+  #[test]
+  fn anti_internalized_orders_pass_over_their_firm() {
+    let mut engine = MatchEngine::default();
+    let outsider = engine.create_account();
+    let (desk_a, desk_b) = (engine.create_firm_account(0.into()), engine.create_firm_account(0.into()));
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind }).unwrap();
+    let ask = Order::new(101.into(), 5.into()).with_flags(OrderFlags::ANTI_INTERNALIZATION);
+    process(desk_a, CommandKind::PlaceOrder(Side::Ask, symbol, ask));
+    process(outsider, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(102.into(), 5.into())));
+    process(desk_b, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(102.into(), 5.into())));
+
+    // with only its own firm left to cross, the order is cancelled rather than left crossing the book
+    match process(desk_b, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(101.into(), 5.into()))) {
+      Success::PlaceOrder(placement) => assert_eq!(placement.state, OrderState::Cancelled),
+      other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(trades(engine.drain_market_data()), vec![(102.into(), 5.into())]);
+    assert!(engine.violations().is_empty());
+  }
+
+  #[test]
+  fn self_matches_decrement_both_orders_instead_of_trading() {
+    let mut engine = MatchEngine::default();
+    let (account_id, outsider) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let mut place = |account_id, side, quantity: u64, flags| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), quantity.into()).with_flags(flags));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    let own = place(account_id, Side::Ask, 5, OrderFlags::empty()).id;
+    place(outsider, Side::Ask, 2, OrderFlags::empty());
+    let bid = place(account_id, Side::Bid, 8, OrderFlags::DECREMENT_SELF_MATCH);
+
+    assert_eq!((bid.fills.len(), bid.remaining, bid.state), (1, 1.into(), OrderState::PartiallyFilled));
+    assert!(engine.is_cancelled(own));
+    assert_eq!(trades(engine.drain_market_data()), vec![(100.into(), 2.into())]);
+    let prevented: Vec<_> = engine
+      .drain_audit_trail()
+      .into_iter()
+      .filter(|record| record.event == AuditEvent::SelfMatchPrevented)
+      .map(|record| (record.order, record.quantity))
+      .collect();
+    assert_eq!(prevented, vec![(Some(own), Some(5.into())), (Some(bid.id), Some(5.into()))]);
+    assert!(engine.violations().is_empty());
+  }
+
+  #[test]
+  fn circular_and_wash_trades_raise_alerts() {
+    let mut engine = MatchEngine::default();
+    let accounts: Vec<_> = (0..4).map(|_| engine.create_account()).collect();
+    engine.set_beneficial_owner(accounts[3], accounts[0]).unwrap();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let mut cross = |seller, buyer| {
+      for &(account_id, side) in &[(seller, Side::Ask), (buyer, Side::Bid)] {
+        let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 10.into()));
+        engine.try_process(Command { account_id, kind }).unwrap();
+      }
+    };
+    cross(accounts[0], accounts[1]);
+    cross(accounts[1], accounts[2]);
+    cross(accounts[2], accounts[3]);
+    cross(accounts[3], accounts[0]);
+
+    assert_eq!(engine.drain_alerts(), vec![
+      Alert::CircularTrade {
+        symbol,
+        owners: vec![accounts[2], accounts[0], accounts[1]],
+      },
+      Alert::WashTrade {
+        symbol,
+        buyer: accounts[0],
+        seller: accounts[3],
+        quantity: 10.into(),
+      },
+    ]);
+    assert_eq!(engine.surveillance_score(accounts[0]), Ok(2));
+    assert_eq!(engine.surveillance_score(accounts[3]), Ok(1));
+  }
+
+  #[test]
+  fn layered_cancels_raise_spoofing_alert() {
+    let mut engine = MatchEngine::default();
+    let spoofer = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine.set_surveillance_rules(SurveillanceRules {
+      spoofing_min_orders: 3,
+      ..SurveillanceRules::default()
+    });
+
+    let mut process = |kind| engine.try_process(Command { account_id: spoofer, kind }).unwrap();
+    process(CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 1.into())));
+    let layers: Vec<Id> = (0..3)
+      .map(|i| match process(CommandKind::PlaceOrder(Side::Bid, symbol, Order::new((90 - i).into(), 100.into()))) {
+        Success::PlaceOrder(Placement { id, .. }) => id,
+        other => panic!("unexpected {:?}", other),
+      })
+      .collect();
+    for &id in &layers {
+      process(CommandKind::CancelOrder(id));
+    }
+
+    assert_eq!(engine.drain_alerts(), vec![Alert::Spoofing {
+      symbol,
+      account: spoofer,
+      side: Side::Bid,
+      orders: layers,
+    }]);
+  }
+
+  #[test]
+  fn order_to_trade_ratio_escalates_to_throttle() {
+    let mut engine = MatchEngine::default();
+    let (admin, account_id) = (engine.create_admin_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine.set_order_to_trade_rules(OrderToTradeRules {
+      min_orders: 4,
+      warning_ratio: 4.0,
+      fee_ratio: 6.0,
+      throttle_ratio: 8.0,
+      enforce: true,
+      ..OrderToTradeRules::default()
+    });
+
+    let place = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 1.into()));
+    let results: Vec<_> = (0..9).map(|_| engine.try_process(Command { account_id, kind: place })).collect();
+    assert!(results[..8].iter().all(Result::is_ok));
+    assert_eq!(results[8].clone().err(), Some(Error::OrderToTradeRatioExceeded { id: account_id }));
+
+    let consequences: Vec<_> = engine
+      .drain_alerts()
+      .into_iter()
+      .map(|alert| match alert {
+        Alert::OrderToTradeRatio { consequence, .. } => consequence,
+        other => panic!("unexpected {:?}", other),
+      })
+      .collect();
+    assert_eq!(consequences, vec![Consequence::Warning, Consequence::Fee, Consequence::Throttle]);
+
+    let kind = CommandKind::GetOrderToTradeRatio(account_id);
+    assert_eq!(
+      engine.try_process(Command { account_id, kind }).err(),
+      Some(Error::PermissionDenied { id: account_id })
+    );
+    match engine.try_process(Command { account_id: admin, kind }) {
+      Ok(Success::GetOrderToTradeRatio(status)) => assert_eq!(status.consequence, Consequence::Throttle),
+      other => panic!("unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn order_to_trade_throttles_lift_once_the_window_passes() {
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let rules = OrderToTradeRules {
+      min_orders: 4,
+      warning_ratio: 4.0,
+      fee_ratio: 6.0,
+      throttle_ratio: 8.0,
+      enforce: true,
+      ..OrderToTradeRules::default()
+    };
+    let window = rules.window;
+    engine.set_order_to_trade_rules(rules);
+
+    let place = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 1.into()));
+    let mut process = |engine: &mut MatchEngine| engine.try_process(Command { account_id, kind: place });
+    for _ in 0..8 {
+      process(&mut engine).unwrap();
+    }
+    assert_eq!(process(&mut engine).err(), Some(Error::OrderToTradeRatioExceeded { id: account_id }));
+    clock.advance(window / 2);
+    assert_eq!(process(&mut engine).err(), Some(Error::OrderToTradeRatioExceeded { id: account_id }));
+
+    clock.advance(window);
+    assert!(process(&mut engine).is_ok());
+  }
+
+  #[test]
+  fn order_events_are_audited() {
+    let mut engine = MatchEngine::default();
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let place = |side, tag| {
+      CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 10.into()).with_tag(Label::new(tag).unwrap()))
+    };
+    engine.try_process_from(1.into(), Command { account_id: maker, kind: place(Side::Ask, "") }).unwrap();
+    engine.try_process_from(2.into(), Command { account_id: taker, kind: place(Side::Bid, "arb|2") }).unwrap();
+    engine
+      .try_process_from(2.into(), Command {
+        account_id: taker,
+        kind: CommandKind::CancelOrder(1.into()),
+      })
+      .unwrap();
+
+    let trail: Vec<_> = engine
+      .drain_audit_trail()
+      .iter()
+      .map(|record| {
+        // timestamps come from the system clock
+        let flat = record.to_flat();
+        let mut fields = flat.splitn(3, '|');
+        format!("{}|{}", fields.next().unwrap(), fields.nth(1).unwrap())
+      })
+      .collect();
+    assert_eq!(trail, vec![
+      "4|1|0|RECEIVE||ADBE|ASK|100|10|||||||||",
+      "5|1|0|ACCEPT|0|ADBE|ASK|100|10|||||||||",
+      "7|2|1|RECEIVE||ADBE|BID|100|10||||||arb 2|||",
+      "8|2|1|ACCEPT|1|ADBE|BID|100|10||||||arb 2|||",
+      "9|2|1|EXECUTE|1|ADBE|BID|100|10|0|TAKER|10|100||arb 2|||",
+      "10|1|0|EXECUTE|0|ADBE|ASK|100|10|0|MAKER|10|100|||||",
+      "12|2|1|RECEIVE|1||||||||||arb 2|||",
+      "13|2|1|REJECT|1||||||||||arb 2|||",
+    ]);
+  }
+
+  #[test]
+  fn journal_replays_to_the_same_state() {
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1_000)));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let place = |side| CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 10.into()));
+    let ask = match engine.try_process(Command { account_id: maker, kind: place(Side::Ask) }) {
+      Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+      other => panic!("unexpected {:?}", other),
+    };
+    clock.advance(Duration::from_nanos(250));
+    engine.try_process(Command { account_id: taker, kind: place(Side::Bid) }).unwrap();
+    clock.advance(Duration::from_nanos(250));
+    engine
+      .try_process(Command {
+        account_id: maker,
+        kind: CommandKind::GetOrder(ask),
+      })
+      .unwrap();
+
+    let journal = engine.drain_journal();
+    assert_eq!(journal.len(), 5);
+    assert_eq!(journal[4].received_at, Timestamp::from(1_250));
+
+    engine.checkpoint();
+    let journal = [journal, engine.drain_journal()].concat();
+    let mut replayed = MatchEngine::replay(Arc::new(ManualClock::default()), journal.clone()).unwrap();
+    assert_eq!(replayed.drain_journal(), journal);
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+    assert_eq!(replayed.violations(), vec![]);
+    assert_eq!(trades(replayed.drain_market_data()), trades(engine.drain_market_data()));
+  }
+
+  #[test]
+  fn journal_replays_up_to_a_point() {
+    use std::time::Duration;
+
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1_000)));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+
+    let place = |side| CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 10.into()));
+    engine.try_process(Command { account_id: maker, kind: place(Side::Ask) }).unwrap();
+    clock.advance(Duration::from_nanos(250));
+    engine.try_process(Command { account_id: taker, kind: place(Side::Bid) }).unwrap();
+    let journal = engine.drain_journal();
+    let placed = journal[3].sequence;
+
+    let replay = |point| MatchEngine::replay_until(Arc::new(ManualClock::default()), journal.clone(), point).unwrap();
+    let before_trade = replay(JournalPoint::Time(Timestamp::from(1_100)));
+    assert_eq!(before_trade.depth(symbol, Side::Ask).unwrap(), vec![(100.into(), 10.into())]);
+    assert_eq!(replay(JournalPoint::Sequence(placed)).state_hash(), before_trade.state_hash());
+    assert_eq!(replay(JournalPoint::Time(Timestamp::from(1_250))).depth(symbol, Side::Ask).unwrap(), vec![]);
+  }
+
+  #[test]
+  fn end_of_day_expires_day_orders_and_rolls_the_day_over() {
+    let mut engine = MatchEngine::with_clock(Arc::new(ManualClock::new(Timestamp::from(1_000))));
+    let (buyer, seller) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let mut place = |account_id, side, price: u32, flags| {
+      let order = Order::new(price.into(), 10.into()).with_flags(flags);
+      let kind = CommandKind::PlaceOrder(side, symbol, order);
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    let day_order = place(buyer, Side::Bid, 99, OrderFlags::DAY);
+    place(seller, Side::Ask, 101, OrderFlags::empty());
+    place(buyer, Side::Bid, 101, OrderFlags::empty());
+
+    let report = engine.end_of_day();
+    assert_eq!(report.expired, vec![day_order]);
+    let expiry = engine.drain_audit_trail().into_iter().find(|record| record.event == AuditEvent::Cancel).unwrap();
+    assert_eq!((expiry.order, expiry.cancel_reason), (Some(day_order), Some(CancelReason::EndOfDay)));
+    assert_eq!(engine.depth(symbol, Side::Bid).unwrap(), vec![]);
+    let closes: Vec<(Price, Quantity)> = report.closes.iter().map(|close| (close.close, close.volume)).collect();
+    assert_eq!(closes, vec![(101.into(), 10.into())]);
+    let settled = |settlement: &Settlement| (settlement.account, settlement.paid, settlement.received);
+    let settlements: Vec<_> = report.settlements.iter().map(settled).collect();
+    assert_eq!(settlements, vec![(buyer, 1_010, 0), (seller, 0, 1_010)]);
+    assert!(engine.drain_market_data().contains(&MarketData::ClosingPrice {
+      symbol,
+      price: 101.into(),
+      volume: 10.into(),
+    }));
+
+    let next_day = engine.end_of_day();
+    assert_eq!((next_day.expired, next_day.closes, next_day.settlements), (vec![], vec![], vec![]));
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+  }
+
+  #[test]
+  fn stored_accounts_carry_over_to_a_new_engine() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin) = (engine.create_account(), engine.create_admin_account());
+    engine.try_get_account_mut(trader).unwrap().balance = 500.into();
+    let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 10.into()));
+    engine.try_process(Command { account_id: trader, kind }).unwrap();
+    let store = engine.account_store();
+
+    let mut restarted = MatchEngine::default();
+    restarted.insert_new_symbol(symbol).unwrap();
+    restarted.load_accounts(serde_json::from_str(&serde_json::to_string(&store).unwrap()).unwrap());
+    assert_eq!(restarted.account(trader).unwrap().balance, 500.into());
+    assert!(restarted.account(admin).unwrap().is_admin);
+    assert_eq!(restarted.account(trader).unwrap().orders, vec![]);
+    assert_eq!(usize::from(restarted.create_account()), 2);
+    match restarted.try_process(Command { account_id: trader, kind }) {
+      Ok(Success::PlaceOrder(Placement { id, .. })) => assert_eq!(usize::from(id), 1),
+      other => panic!("unexpected {:?}", other),
+    }
+
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), restarted.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), restarted.state_hash());
+  }
+
+  #[test]
+  fn accounts_are_created_once_per_reference() {
+    let mut engine = MatchEngine::default();
+    let (trader, admin) = (engine.create_account(), engine.create_admin_account());
+    let details = NewAccount {
+      reference: Label::new("crm-1042").unwrap(),
+      name: Label::new("Acme Capital").unwrap(),
+      firm: None,
+    };
+    let mut create = |account_id, details| {
+      engine.try_process(Command {
+        account_id,
+        kind: CommandKind::CreateAccount(details),
+      })
+    };
+
+    assert_eq!(create(trader, details), Err(Error::PermissionDenied { id: trader }));
+    let created = create(admin, details).unwrap();
+    assert_eq!(created, Success::CreateAccount(2.into()));
+    assert_eq!(create(admin, details).unwrap(), created);
+    let anonymous = NewAccount::default();
+    assert_eq!(create(admin, anonymous).unwrap(), Success::CreateAccount(3.into()));
+    assert_eq!(create(admin, anonymous).unwrap(), Success::CreateAccount(4.into()));
+    assert_eq!(engine.account(2.into()).unwrap().name.as_str(), "Acme Capital");
+
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+  }
+
+  #[test]
+  fn fees_follow_the_tier_of_each_account_volume() {
+    use crate::fees::FeeTier;
+
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine.set_fee_schedule(FeeSchedule {
+      tiers: vec![
+        FeeTier {
+          min_volume: 0,
+          maker_rate: -10,
+          taker_rate: 30,
+        },
+        FeeTier {
+          min_volume: 100,
+          maker_rate: -20,
+          taker_rate: 20,
+        },
+      ],
+      ..FeeSchedule::default()
+    });
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    for &account in &[maker, taker] {
+      engine.try_get_account_mut(account).unwrap().balance = 1_000.into();
+    }
+    let trade = |engine: &mut MatchEngine| {
+      for &(account_id, side) in &[(maker, Side::Ask), (taker, Side::Bid)] {
+        let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 100.into()));
+        engine.try_process(Command { account_id, kind }).unwrap();
+      }
+    };
+
+    trade(&mut engine);
+    assert_eq!(engine.account(maker).unwrap().balance, 1_010.into());
+    assert_eq!(engine.account(taker).unwrap().balance, 970.into());
+    let kind = CommandKind::GetFeeTier(taker);
+    match engine.try_process(Command { account_id: taker, kind }) {
+      Ok(Success::GetFeeTier(status)) => assert_eq!((status.tier, status.next_tier_volume), (1, None)),
+      other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+      engine.try_process(Command { account_id: maker, kind }),
+      Err(Error::PermissionDenied { id: maker })
+    );
+
+    trade(&mut engine);
+    assert_eq!(engine.account(maker).unwrap().balance, 1_030.into());
+    assert_eq!(engine.account(taker).unwrap().balance, 950.into());
+  }
+
+  #[test]
+  fn fees_accrue_by_day_for_daily_and_month_to_date_reports() {
+    use crate::clock::NANOS_PER_DAY;
+    use crate::fees::FeeTier;
+
+    // 2024-03-01, a Friday
+    let clock = Arc::new(ManualClock::new(Timestamp::from(19_783 * NANOS_PER_DAY)));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    engine.set_fee_schedule(FeeSchedule {
+      tiers: vec![FeeTier {
+        min_volume: 0,
+        maker_rate: -10,
+        taker_rate: 30,
+      }],
+      ..FeeSchedule::default()
+    });
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let admin = engine.create_account();
+    engine.try_get_account_mut(admin).unwrap().is_admin = true;
+    for &account in &[maker, taker] {
+      engine.try_get_account_mut(account).unwrap().balance = 1_000.into();
+    }
+    let trade = |engine: &mut MatchEngine| {
+      for &(account_id, side) in &[(maker, Side::Ask), (taker, Side::Bid)] {
+        let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 100.into()));
+        engine.try_process(Command { account_id, kind }).unwrap();
+      }
+    };
+
+    trade(&mut engine);
+    clock.advance(Duration::from_nanos(3 * NANOS_PER_DAY));
+    trade(&mut engine);
+    engine.end_of_day();
+    let monday = Date::from(clock.now());
+
+    let day = engine.fee_report(None, monday, FeePeriod::Day);
+    assert_eq!(day.from, monday);
+    let nets: Vec<_> = day.lines.iter().map(|line| (line.account, line.net)).collect();
+    assert_eq!(nets, vec![(maker, -10), (taker, 30)]);
+    let kind = CommandKind::GetFees(Some(taker), monday, FeePeriod::MonthToDate);
+    let month = match engine.try_process(Command { account_id: taker, kind }) {
+      Ok(Success::GetFees(report)) => report,
+      other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(month.from, monday.first_of_month());
+    assert_eq!(month.totals.len(), 1);
+    assert_eq!((month.totals[0].fill_fees, month.totals[0].net, month.totals[0].fills), (60, 60, 2));
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let everyone = CommandKind::GetFees(None, monday, FeePeriod::Day);
+    assert_eq!(process(taker, everyone), Err(Error::PermissionDenied { id: taker }));
+    assert_eq!(process(admin, everyone), Ok(Success::GetFees(day)));
+
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.fee_report(Some(taker), monday, FeePeriod::MonthToDate), month);
+  }
+
+  #[test]
+  fn symbol_fee_models_invert_or_waive_fees() {
+    use crate::fees::FeeTier;
+
+    let mut engine = MatchEngine::default();
+    let (inverted, free) = (['I', 'N', 'V', 'T'].into(), ['F', 'R', 'E', 'E'].into());
+    for &(symbol, fee_model) in &[(inverted, FeeModel::Inverted), (free, FeeModel::Free)] {
+      engine.insert_new_symbol(symbol).unwrap();
+      let config = SymbolConfig {
+        fee_model,
+        ..SymbolConfig::default()
+      };
+      engine.configure_symbol(symbol, config).unwrap();
+    }
+    engine.set_fee_schedule(FeeSchedule {
+      tiers: vec![FeeTier {
+        min_volume: 0,
+        maker_rate: -10,
+        taker_rate: 30,
+      }],
+      ..FeeSchedule::default()
+    });
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    for &account in &[maker, taker] {
+      engine.try_get_account_mut(account).unwrap().balance = 1_000.into();
+    }
+    let mut trade = |symbol| {
+      for &(account_id, side) in &[(maker, Side::Ask), (taker, Side::Bid)] {
+        let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 100.into()));
+        engine.try_process(Command { account_id, kind }).unwrap();
+      }
+    };
+
+    trade(free);
+    trade(inverted);
+    assert_eq!(engine.account(maker).unwrap().balance, 970.into());
+    assert_eq!(engine.account(taker).unwrap().balance, 1_010.into());
+    let kind = CommandKind::GetSymbolFeeTier(taker, inverted);
+    match engine.try_process(Command { account_id: taker, kind }) {
+      Ok(Success::GetFeeTier(status)) => assert_eq!((status.maker_rate, status.taker_rate), (30, -10)),
+      other => panic!("unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn fractional_quantities_trade_in_lots_and_settle_at_their_value() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['B', 'T', 'C', 'X'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let config = SymbolConfig {
+      quantity_precision: 4,
+      lot_size: Some(500.into()),
+      ..SymbolConfig::default()
+    };
+    engine.configure_symbol(symbol, config).unwrap();
+    let (buyer, seller) = (engine.create_account(), engine.create_account());
+    let mut place = |account_id, side, quantity: &str| {
+      let order = Order::new(20_000.into(), Quantity::from_decimal(quantity, 4).unwrap());
+      engine.try_process(Command {
+        account_id,
+        kind: CommandKind::PlaceOrder(side, symbol, order),
+      })
+    };
+
+    let lot_size = Err(Error::InvalidLotSize {
+      symbol,
+      lot_size: 500.into(),
+    });
+    assert_eq!(place(seller, Side::Ask, "0.0250"), lot_size);
+    match place(seller, Side::Ask, "0.25") {
+      Ok(Success::PlaceOrder(placement)) => assert_eq!(placement.quantity_precision, 4),
+      other => panic!("unexpected {:?}", other),
+    }
+    assert!(place(buyer, Side::Bid, "0.15").is_ok());
+    assert_eq!(engine.book_stats(symbol).unwrap().ask_notional, 2_000);
+
+    let settled = |settlement: &Settlement| {
+      (settlement.account, settlement.bought.to_decimal(4).to_string(), settlement.paid)
+    };
+    let settlements: Vec<_> = engine.end_of_day().settlements.iter().map(settled).collect();
+    assert_eq!(settlements, vec![(buyer, "0.1500".to_string(), 3_000), (seller, "0.0000".to_string(), 0)]);
+    assert_eq!(Quantity::from_decimal("1.00005", 4), None);
+    assert_eq!(Quantity::from_decimal("1.", 4), Some(10_000.into()));
+    assert_eq!(Quantity::from_decimal("1000000.5", 9), Some(1_000_000_500_000_000.into()));
+  }
+
+  #[test]
+  fn holdings_count_towards_buying_power_at_their_marks() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let mut holder = Account {
+      balance: 1_000.into(),
+      ..Account::default()
+    };
+    holder.portfolio.insert(symbol, 10.into());
+    let trader = Account {
+      balance: 10_000.into(),
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(0.into(), holder), (1.into(), trader.clone()), (2.into(), trader)],
+      ..AccountStore::default()
+    });
+    engine.set_collateral_rules(CollateralRules {
+      haircuts: vec![(symbol, 2_000)],
+      enforce: true,
+      ..CollateralRules::default()
+    });
+    let mut place = |account: usize, side, price: u32, quantity: u64| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()));
+      engine.try_process(Command {
+        account_id: account.into(),
+        kind,
+      })
+    };
+
+    place(1, Side::Ask, 100, 1).unwrap();
+    place(2, Side::Bid, 100, 1).unwrap();
+    // 1,000 of cash and 10 at 100 less a fifth, all committed to the first bid
+    assert!(place(0, Side::Bid, 90, 20).is_ok());
+    let short = Err(Error::InsufficientBuyingPower {
+      id: 0.into(),
+      buying_power: 0,
+    });
+    assert_eq!(place(0, Side::Bid, 90, 1), short);
+
+    place(1, Side::Ask, 150, 1).unwrap();
+    place(2, Side::Bid, 150, 1).unwrap();
+    assert!(place(0, Side::Bid, 80, 5).is_ok());
+    assert_eq!(engine.buying_power(0.into()), Ok(2_200));
+  }
+
+  #[test]
+  fn equity_marks_holdings_to_the_midpoint_or_last_trade() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let mut holder = Account {
+      balance: 1_000.into(),
+      ..Account::default()
+    };
+    holder.portfolio.insert(symbol, 10.into());
+    engine.load_accounts(AccountStore {
+      accounts: vec![(0.into(), holder), (1.into(), Account::default()), (2.into(), Account::default())],
+      ..AccountStore::default()
+    });
+    let mut process = |account: usize, kind| {
+      engine.try_process(Command {
+        account_id: account.into(),
+        kind,
+      })
+    };
+    let place = |side, price: u32| CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), 2.into()));
+    let equity = |position_value, reserved| {
+      Ok(Success::GetEquity(Equity {
+        account: 0.into(),
+        cash: 1_000,
+        position_value,
+        reserved,
+        equity: 1_000 + position_value,
+      }))
+    };
+
+    assert_eq!(process(0, CommandKind::GetEquity(0.into())), equity(0, 0));
+    process(1, place(Side::Ask, 100)).unwrap();
+    process(2, place(Side::Bid, 100)).unwrap();
+    process(0, place(Side::Bid, 90)).unwrap();
+    assert_eq!(process(0, CommandKind::GetEquity(0.into())), equity(1_000, 180));
+    process(1, place(Side::Ask, 130)).unwrap();
+    assert_eq!(process(0, CommandKind::GetEquity(0.into())), equity(1_100, 180));
+    assert_eq!(process(1, CommandKind::GetEquity(0.into())), Err(Error::PermissionDenied { id: 1.into() }));
+  }
+
+  #[test]
+  fn under_margined_accounts_are_liquidated_within_the_band() {
+    use crate::liquidation::LiquidationRules;
+
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let mut holder = Account {
+      balance: 1_000.into(),
+      ..Account::default()
+    };
+    holder.portfolio.insert(symbol, 10.into());
+    engine.load_accounts(AccountStore {
+      accounts: vec![(0.into(), holder), (1.into(), Account::default()), (2.into(), Account::default())],
+      ..AccountStore::default()
+    });
+    engine.set_collateral_rules(CollateralRules {
+      haircuts: vec![(symbol, 2_000)],
+      liquidation: Some(LiquidationRules {
+        maintenance_margin: 10_000,
+        band: 5.into(),
+      }),
+      ..CollateralRules::default()
+    });
+    let mut process = |account: usize, kind| {
+      engine.try_process(Command {
+        account_id: account.into(),
+        kind,
+      })
+    };
+    let place = |side, price: u32, quantity: u64| {
+      CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()))
+    };
+
+    process(1, place(Side::Ask, 100, 1)).unwrap();
+    process(2, place(Side::Bid, 100, 1)).unwrap();
+    // 1,000 of cash and 10 at 100 less a fifth cover a bid for 180 at 10
+    process(0, place(Side::Bid, 10, 180)).unwrap();
+    assert_eq!(process(0, CommandKind::GetLiquidation(0.into())), Ok(Success::GetLiquidation(None)));
+
+    // marked down to 50, its holdings count for 400, leaving 1,400 to cover 1,800
+    process(1, place(Side::Ask, 50, 1)).unwrap();
+    process(2, place(Side::Bid, 50, 1)).unwrap();
+    let liquidation = match process(0, CommandKind::GetLiquidation(0.into())) {
+      Ok(Success::GetLiquidation(Some(liquidation))) => liquidation,
+      other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!((liquidation.buying_power, liquidation.reserved), (1_400, 1_800));
+    assert_eq!(engine.account(0.into()).unwrap().state, AccountState::LiquidationOnly);
+    assert_eq!(engine.depth(symbol, Side::Bid).unwrap(), vec![]);
+    assert_eq!(engine.depth(symbol, Side::Ask).unwrap(), vec![(45.into(), 10.into())]);
+    let audit_trail = engine.drain_audit_trail();
+    assert!(audit_trail.iter().any(|record| record.event == AuditEvent::Liquidate && record.account == 0.into()));
+    let tag = Label::new(LIQUIDATION_TAG).unwrap();
+    assert!(audit_trail.iter().any(|record| record.order == Some(liquidation.orders[0]) && record.tag == Some(tag)));
+
+    engine.drain_market_data();
+    let kind = place(Side::Bid, 46, 4);
+    engine.try_process(Command { account_id: 2.into(), kind }).unwrap();
+    assert_eq!(trades(engine.drain_market_data()), vec![(45.into(), 4.into())]);
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+  }
+
+  #[test]
+  fn placing_an_order_reports_its_fills() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (seller, buyer) = (engine.create_account(), engine.create_account());
+    let mut place = |account_id, side, quantity: u64| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), quantity.into()));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+
+    assert_eq!(place(seller, Side::Ask, 5).state, OrderState::New);
+    let placement = place(buyer, Side::Bid, 8);
+    let quantities: Vec<_> = placement.fills.iter().map(|fill| (fill.price, fill.quantity)).collect();
+    assert_eq!(quantities, vec![(100.into(), 5.into())]);
+    assert_eq!((placement.remaining, placement.state), (3.into(), OrderState::PartiallyFilled));
+  }
+
+  #[test]
+  fn resting_orders_report_their_queue_position() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, outsider) = (engine.create_account(), engine.create_account());
+    let mut place = |quantity: u64| {
+      let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), quantity.into()));
+      match engine.try_process(Command { account_id: trader, kind }) {
+        Ok(Success::PlaceOrder(placement)) => placement.id,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    let ids: Vec<_> = [4, 6, 5].iter().map(|&quantity| place(quantity)).collect();
+
+    let kind = CommandKind::GetQueuePosition(ids[2]);
+    assert_eq!(
+      engine.try_process(Command { account_id: outsider, kind }),
+      Err(Error::PermissionDenied { id: outsider })
+    );
+    assert_eq!(
+      engine.try_process(Command { account_id: trader, kind }),
+      Ok(Success::GetQueuePosition(QueuePosition {
+        price: 100.into(),
+        position: 2,
+        ahead: 10.into(),
+        orders: 3,
+        quantity: 15.into(),
+      }))
+    );
+  }
+
+  #[test]
+  fn orders_are_updated_only_by_their_account_or_an_admin() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, outsider, admin) = (engine.create_account(), engine.create_account(), engine.create_admin_account());
+    let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let id = match engine.try_process(Command { account_id: trader, kind }) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    let kind = CommandKind::UpdateOrder(id, Some(101.into()), Some(3.into()));
+    assert_eq!(
+      engine.try_process(Command { account_id: outsider, kind }),
+      Err(Error::PermissionDenied { id: outsider })
+    );
+    assert_eq!(engine.resting_order(id).map(|order| (order.price, order.quantity)), Some((100.into(), 5.into())));
+    assert_eq!(engine.try_process(Command { account_id: trader, kind }), Ok(Success::UpdateOrder(true)));
+    let kind = CommandKind::UpdateOrder(id, Some(99.into()), None);
+    assert_eq!(engine.try_process(Command { account_id: admin, kind }), Ok(Success::UpdateOrder(true)));
+  }
+
+  #[test]
+  fn orders_are_suspended_and_resumed_only_by_their_account_or_an_admin() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, outsider, admin) = (engine.create_account(), engine.create_account(), engine.create_admin_account());
+    let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let id = match engine.try_process(Command { account_id: trader, kind }) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+
+    let denied = Err(Error::PermissionDenied { id: outsider });
+    assert_eq!(process(outsider, CommandKind::SuspendOrder(id)), denied);
+    assert_eq!(process(trader, CommandKind::SuspendOrder(id)), Ok(Success::SuspendOrder(true)));
+    assert_eq!(process(outsider, CommandKind::ResumeOrder(id)), denied);
+    assert_eq!(process(admin, CommandKind::ResumeOrder(id)), Ok(Success::ResumeOrder(true)));
+  }
+
+  #[test]
+  fn book_stats_follow_the_resting_orders() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind }).unwrap();
+    let place = |side, price: u32, quantity: u64| {
+      CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()))
+    };
+    for price in 95..102 {
+      process(maker, place(Side::Bid, price, 2));
+    }
+    let ask = match process(maker, place(Side::Ask, 105, 10)) {
+      Success::PlaceOrder(placement) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+    process(taker, place(Side::Ask, 100, 3));
+    process(maker, CommandKind::SuspendOrder(ask));
+
+    // the best five bids left are 1 at 100, then 2 at each of 99 down to 96
+    assert_eq!(engine.book_stats(symbol), Ok(BookStats {
+      symbol,
+      orders: 6,
+      bid_quantity: 11.into(),
+      ask_quantity: 0.into(),
+      bid_notional: 100 + 2 * (99 + 98 + 97 + 96),
+      ask_notional: 0,
+    }));
+    assert_eq!(engine.violations(), vec![]);
+  }
+
+  #[test]
+  fn orders_report_the_average_price_of_their_fills() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (seller, buyer) = (engine.create_account(), engine.create_account());
+    let mut process = |account_id, side, price: u32, quantity: u64| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()));
+      engine.try_process(Command { account_id, kind }).unwrap()
+    };
+    process(seller, Side::Ask, 100, 1);
+    process(seller, Side::Ask, 101, 3);
+    let bid = match process(buyer, Side::Bid, 101, 6) {
+      Success::PlaceOrder(placement) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    match engine.try_process(Command {
+      account_id: buyer,
+      kind: CommandKind::GetOrder(bid),
+    }) {
+      Ok(Success::GetOrder(status)) => {
+        assert_eq!((status.order.filled, status.average_price), (4.into(), Some(100.75)))
+      }
+      other => panic!("unexpected {:?}", other),
+    }
+    let last = engine.drain_audit_trail().into_iter().rev().find(|record| record.order == Some(bid)).unwrap();
+    assert_eq!((last.filled, last.average_price), (Some(4.into()), Some(100.75)));
+  }
+
+  #[test]
+  fn account_states_limit_what_accounts_may_do() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin) = (0.into(), 1.into());
+    let mut holder = Account::default();
+    holder.portfolio.insert(symbol, 10.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, holder), (admin, admin_account)],
+      ..AccountStore::default()
+    });
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let place = |side, quantity: u64| CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), quantity.into()));
+    let resting = match process(trader, place(Side::Bid, 5)) {
+      Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    let set_state = |state| CommandKind::SetAccountState(trader, state);
+    assert_eq!(process(trader, set_state(AccountState::Active)), Err(Error::PermissionDenied { id: trader }));
+    assert_eq!(process(admin, set_state(AccountState::Suspended)), Ok(Success::SetAccountState));
+    let restricted = |state| Err(Error::AccountRestricted { id: trader, state });
+    assert_eq!(process(trader, place(Side::Ask, 5)), restricted(AccountState::Suspended));
+    assert!(process(trader, CommandKind::GetOrder(resting)).is_ok());
+
+    process(admin, set_state(AccountState::LiquidationOnly)).unwrap();
+    assert_eq!(process(trader, place(Side::Bid, 5)), restricted(AccountState::LiquidationOnly));
+    assert_eq!(process(trader, place(Side::Ask, 11)), restricted(AccountState::LiquidationOnly));
+    assert!(process(trader, place(Side::Ask, 10)).is_ok());
+
+    process(admin, set_state(AccountState::Closed)).unwrap();
+    assert_eq!(process(trader, CommandKind::CancelOrder(resting)), restricted(AccountState::Closed));
+    assert_eq!(process(admin, set_state(AccountState::Active)), restricted(AccountState::Closed));
+    assert_eq!(engine.resting_order(resting), None);
+
+    let replayed = MatchEngine::replay(Arc::new(ManualClock::default()), engine.drain_journal()).unwrap();
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+  }
+
+  #[test]
+  fn reloading_config_is_all_or_nothing() {
+    use std::time::Duration;
+
+    let mut engine = MatchEngine::default();
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+    engine.insert_new_symbol(adbe).unwrap();
+    let auction = SymbolConfig {
+      trading_mode: TradingMode::PeriodicAuction {
+        interval: Duration::from_secs(1),
+      },
+      ..SymbolConfig::default()
+    };
+    let mut config = RuntimeConfig {
+      symbols: vec![(adbe, auction.clone()), (msft, SymbolConfig::default())],
+      ..RuntimeConfig::default()
+    };
+    config.order_to_trade.min_orders = 4;
+    assert_eq!(engine.reload_config(config.clone()), Err(Error::SymbolDoesNotExist { symbol: msft }));
+
+    config.symbols.pop();
+    config.order_to_trade.fee_ratio = config.order_to_trade.throttle_ratio + 1.0;
+    assert_eq!(
+      engine.reload_config(config.clone()),
+      Err(Error::InvalidConfig {
+        reason: ConfigError::UnorderedOrderToTradeRatios
+      })
+    );
+    assert_eq!(engine.configs.get(&adbe), None);
+    assert_eq!(engine.order_to_trade.rules, OrderToTradeRules::default());
+
+    config.order_to_trade.fee_ratio = config.order_to_trade.throttle_ratio;
+    engine.drain_journal();
+    engine.reload_config(config.clone()).unwrap();
+    assert_eq!(engine.configs[&adbe], auction);
+    assert_eq!(engine.order_to_trade.rules, config.order_to_trade);
+    // only the symbol and order-to-trade rules changed
+    assert_eq!(engine.drain_journal().len(), 2);
+    engine.reload_config(config).unwrap();
+    assert_eq!(engine.drain_journal(), vec![]);
+  }
+
+  #[test]
+  fn accounts_encode_the_same_by_reference() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (buyer, seller) = (engine.create_account(), engine.create_account());
+    for &(account, side) in &[(seller, Side::Ask), (buyer, Side::Bid)] {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(10.into(), 5.into()));
+      engine.try_process(Command { account_id: account, kind }).unwrap();
+    }
+    engine.accounts.get_mut(&buyer).unwrap().portfolio.insert(symbol, 5.into());
+
+    for &id in &[buyer, 7.into()] {
+      let command = Command {
+        account_id: buyer,
+        kind: CommandKind::GetAccount(id),
+      };
+      let mut by_reference = vec![];
+      engine.write_result(SessionId::default(), command, &mut by_reference).unwrap();
+      assert_eq!(by_reference, serde_json::to_vec(&engine.try_process(command)).unwrap());
+    }
+
+    let summary = engine.try_process(Command {
+      account_id: buyer,
+      kind: CommandKind::GetAccountSummary(buyer),
+    });
+    let expected = AccountSummary {
+      orders: 1,
+      holdings: 1,
+      ..AccountSummary::default()
+    };
+    assert_eq!(summary, Ok(Success::GetAccountSummary(expected)));
+  }
+
+  #[test]
+  fn updating_to_a_crossing_price_matches() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let account_id = engine.create_account();
+    let mut ids = vec![];
+    for &(side, price) in &[(Side::Ask, 11), (Side::Bid, 10)] {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), 5.into()));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(Placement { id, .. })) => ids.push(id),
+        result => panic!("unexpected {:?}", result),
+      }
+    }
+
+    let kind = CommandKind::UpdateOrder(ids[1], Some(11.into()), Some(3.into()));
+    assert_eq!(engine.try_process(Command { account_id, kind }), Ok(Success::UpdateOrder(true)));
+    assert_eq!(trades(engine.drain_market_data()), vec![(11.into(), 3.into())]);
+    assert_eq!(engine.try_process(Command { account_id, kind }), Ok(Success::UpdateOrder(false)));
+    let events: Vec<_> = engine.drain_audit_trail().into_iter().map(|record| record.event).collect();
+    assert_eq!(&events[events.len() - 3..], &[AuditEvent::Modify, AuditEvent::Receive, AuditEvent::Reject]);
+  }
+
+  #[test]
+  fn suspended_orders_do_not_match_until_resumed() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let account_id = engine.create_account();
+    let place = |engine: &mut MatchEngine, side| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(10.into(), 5.into()));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+        result => panic!("unexpected {:?}", result),
+      }
+    };
+    let ask = place(&mut engine, Side::Ask);
+
+    let kind = CommandKind::SuspendOrder(ask);
+    assert_eq!(engine.try_process(Command { account_id, kind }), Ok(Success::SuspendOrder(true)));
+    place(&mut engine, Side::Bid);
+    assert_eq!(trades(engine.drain_market_data()), vec![]);
+    assert_eq!(engine.depth(symbol, Side::Ask), Ok(vec![]));
+
+    let kind = CommandKind::ResumeOrder(ask);
+    assert_eq!(engine.try_process(Command { account_id, kind }), Ok(Success::ResumeOrder(true)));
+    assert_eq!(trades(engine.drain_market_data()), vec![(10.into(), 5.into())]);
+    assert_eq!(engine.try_process(Command { account_id, kind }), Ok(Success::ResumeOrder(false)));
+  }
+
+  #[test]
+  fn levels_list_their_queue_in_priority_order() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let mut place = |account_id, side, quantity: Quantity| {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(10.into(), quantity));
+      match engine.try_process(Command { account_id, kind }) {
+        Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+        result => panic!("unexpected {:?}", result),
+      }
+    };
+    let first = place(maker, Side::Ask, 5.into());
+    let second = place(maker, Side::Ask, 7.into());
+    place(taker, Side::Bid, 3.into());
+
+    let kind = CommandKind::GetLevel(symbol, Side::Ask, 10.into());
+    let queue = vec![
+      QueueEntry { id: first, remaining: 2.into(), position: 0 },
+      QueueEntry { id: second, remaining: 7.into(), position: 1 },
+    ];
+    assert_eq!(engine.try_process(Command { account_id: taker, kind }), Ok(Success::GetLevel(queue)));
+    assert_eq!(engine.level(symbol, Side::Bid, 10.into()), Ok(vec![]));
+  }
+
+  #[test]
+  fn open_orders_are_paged_by_cursor() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let account = engine.create_account();
+    let ids: Vec<Id> = (1..=5)
+      .map(|price| {
+        let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(price.into(), 5.into()));
+        match engine.try_process(Command { account_id: account, kind }) {
+          Ok(Success::PlaceOrder(Placement { id, .. })) => id,
+          result => panic!("unexpected {:?}", result),
+        }
+      })
+      .collect();
+    let kind = CommandKind::CancelOrder(ids[1]);
+    engine.try_process(Command { account_id: account, kind }).unwrap();
+
+    let mut pages = vec![];
+    let mut page = Page { after: None, limit: 2 };
+    loop {
+      let found = engine.open_orders(account, page).unwrap();
+      pages.push(found.orders.iter().map(|&(id, _)| id).collect::<Vec<_>>());
+      match found.next {
+        Some(next) => page.after = Some(next),
+        None => break,
+      }
+    }
+    assert_eq!(pages, vec![vec![ids[0], ids[2]], vec![ids[3], ids[4]]]);
+  }
+
+  #[test]
+  fn accounts_are_only_sent_the_market_data_they_are_entitled_to() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (admin, account) = (engine.create_admin_account(), engine.create_account());
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+
+    let entitle = CommandKind::SetEntitlement(account, Entitlement::Bbo);
+    assert_eq!(process(account, entitle), Err(Error::PermissionDenied { id: account }));
+    assert_eq!(process(admin, entitle), Ok(Success::SetEntitlement));
+
+    let denied = Err(Error::NotEntitled {
+      id: account,
+      entitlement: Entitlement::Bbo,
+    });
+    assert_eq!(process(account, CommandKind::Subscribe(symbol, Feed::Bbo)), Ok(Success::Subscribe));
+    assert_eq!(process(account, CommandKind::Subscribe(symbol, Feed::Depth(None))), denied);
+    assert_eq!(process(account, CommandKind::GetLevel(symbol, Side::Bid, 1.into())), denied);
+  }
+
+  fn trades(market_data: Vec<MarketData>) -> Vec<(Price, Quantity)> {
+    market_data
+      .into_iter()
+      .filter_map(|data| match data {
+        MarketData::Trade { price, quantity, .. } => Some((price, quantity)),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn subscribers_are_sent_every_event_in_order() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let receiver = engine.subscribe();
+    let (buyer, seller, admin) = (engine.create_account(), engine.create_account(), engine.create_admin_account());
+    engine.try_get_account_mut(seller).unwrap().portfolio.insert(symbol, 10.into());
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let bid = match process(buyer, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()))) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+    process(seller, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 2.into()))).unwrap();
+    process(admin, CommandKind::SetRejectAll(true)).unwrap();
+    process(buyer, CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()))).unwrap_err();
+    process(buyer, CommandKind::CancelOrder(bid)).unwrap();
+
+    let events: Vec<_> = receiver.try_iter().collect();
+    assert!(matches!(events[..3], [
+      EngineEvent::AccountUpdated { account: a, .. },
+      EngineEvent::AccountUpdated { account: b, .. },
+      EngineEvent::AccountUpdated { account: c, summary: AccountSummary { is_admin: true, .. } },
+    ] if (a, b, c) == (buyer, seller, admin)));
+    assert_eq!(events[3], EngineEvent::OrderAccepted {
+      order: bid,
+      account: buyer,
+      symbol,
+      side: Side::Bid,
+      price: 100.into(),
+      quantity: 5.into(),
+    });
+    let fills: Vec<_> = events
+      .iter()
+      .filter_map(|event| match *event {
+        EngineEvent::Fill { account, quantity, .. } => Some((account, quantity)),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(fills, vec![(buyer, 2.into()), (seller, 2.into())]);
+    assert!(events.iter().any(|event| matches!(event, EngineEvent::Trade { quantity, is_dark: false, .. }
+      if *quantity == 2.into())));
+    assert_eq!(events[events.len() - 3..], [
+      EngineEvent::Halt { is_halted: true },
+      EngineEvent::OrderRejected {
+        account: buyer,
+        order: None,
+      },
+      EngineEvent::OrderCancelled {
+        order: bid,
+        account: buyer,
+        reason: CancelReason::Requested,
+      },
+    ]);
+
+    // a copy of the engine sends its subscribers nothing
+    let mut copy = engine.clone();
+    copy.try_process(Command {
+      account_id: admin,
+      kind: CommandKind::SetRejectAll(false),
+    })
+    .unwrap();
+    assert!(receiver.try_recv().is_err());
+  }
+
+  #[test]
+  fn an_inconsistent_book_fails_the_command_rather_than_panicking() {
+    use crate::book::UnknownOrder;
+
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (buyer, seller) = (engine.create_account(), engine.create_account());
+    engine.try_get_account_mut(seller).unwrap().portfolio.insert(symbol, 10.into());
+    let place = |account_id, side| Command {
+      account_id,
+      kind: CommandKind::PlaceOrder(side, symbol, Order::new(100.into(), 5.into())),
+    };
+    let ask = match engine.try_process(place(seller, Side::Ask)) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    let path = engine.id_to_order_path_index[&ask];
+    engine.order_path_to_id_index.remove(&path);
+    assert_eq!(engine.try_process(place(buyer, Side::Bid)), Err(Error::InconsistentBook { symbol }));
+    let (_, _, book_id) = path;
+    assert_eq!(OrderBook::<Price, Quantity>::default().execute(Side::Ask, book_id), Err(UnknownOrder {
+      side: Side::Ask,
+      id: book_id,
+    }));
+  }
+
+  #[test]
+  fn listed_symbols_are_only_replaced_explicitly() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin) = (engine.create_account(), engine.create_admin_account());
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let place = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let resting = match process(trader, place) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    assert_eq!(process(admin, CommandKind::ListSymbol(symbol)), Err(Error::SymbolAlreadyExists { symbol }));
+    assert_eq!(process(admin, CommandKind::ListSymbol(['M', 'S', 'F', 'T'].into())), Ok(Success::ListSymbol));
+    assert_eq!(process(trader, CommandKind::ReplaceSymbol(symbol)), Err(Error::PermissionDenied { id: trader }));
+    assert!(process(trader, CommandKind::GetOrder(resting)).is_ok());
+
+    assert_eq!(process(admin, CommandKind::ReplaceSymbol(symbol)), Ok(Success::ReplaceSymbol(vec![resting])));
+    assert_eq!(process(trader, CommandKind::GetOrder(resting)), Err(Error::IdDoesNotExist { id: resting }));
+    let placed = match process(trader, place) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(engine.insert_new_symbol(symbol), Err(Error::SymbolAlreadyExists { symbol }));
+    let cancel = Command {
+      account_id: trader,
+      kind: CommandKind::CancelOrder(placed),
+    };
+    assert_eq!(engine.try_process(cancel), Ok(Success::CancelOrder(true)));
+    assert!(engine.drain_audit_trail().iter().any(|record| record.event == AuditEvent::Cancel
+      && record.order == Some(resting)
+      && record.account == trader));
+  }
+
+  #[test]
+  fn reject_all_stops_order_entry_but_not_cancels() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin) = (0.into(), 1.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, Account::default()), (admin, admin_account)],
+      ..AccountStore::default()
+    });
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let place = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let resting = match process(trader, place) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    assert_eq!(process(trader, CommandKind::SetRejectAll(true)), Err(Error::PermissionDenied { id: trader }));
+    assert_eq!(process(admin, CommandKind::SetRejectAll(true)), Ok(Success::SetRejectAll));
+    assert_eq!(process(trader, place), Err(Error::RejectingAllOrders { id: trader }));
+    assert!(process(trader, CommandKind::GetOrder(resting)).is_ok());
+    assert_eq!(process(trader, CommandKind::CancelOrder(resting)), Ok(Success::CancelOrder(true)));
+
+    process(admin, CommandKind::SetRejectAll(false)).unwrap();
+    assert!(process(trader, place).is_ok());
+  }
+
+  #[test]
+  fn halted_symbols_only_stop_order_entry_on_themselves() {
+    let mut engine = MatchEngine::default();
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+    engine.insert_new_symbol(adbe).unwrap();
+    engine.insert_new_symbol(msft).unwrap();
+    let (trader, admin) = (0.into(), 1.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, Account::default()), (admin, admin_account)],
+      ..AccountStore::default()
+    });
+    let place = |symbol| CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let resting = match engine.try_process(Command {
+      account_id: trader,
+      kind: place(adbe),
+    }) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    engine.halt_symbol(adbe).unwrap();
+    assert!(engine.is_halted(adbe));
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    assert_eq!(process(trader, place(adbe)), Err(Error::SymbolHalted { symbol: adbe }));
+    assert!(process(trader, place(msft)).is_ok());
+    assert_eq!(process(trader, CommandKind::CancelOrder(resting)), Ok(Success::CancelOrder(true)));
+    assert_eq!(process(trader, CommandKind::HaltSymbol(adbe, false)), Err(Error::PermissionDenied { id: trader }));
+    assert_eq!(process(admin, CommandKind::GetWatchdog), Ok(Success::GetWatchdog(vec![])));
+
+    // the halt is journaled, so a replay halts the symbol too
+    let mut replayed = MatchEngine::default();
+    for entry in engine.drain_journal() {
+      replayed.apply(entry.event).unwrap();
+    }
+    assert!(replayed.is_halted(adbe));
+    assert_eq!(replayed.state_hash(), engine.state_hash());
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    assert_eq!(process(admin, CommandKind::HaltSymbol(adbe, false)), Ok(Success::HaltSymbol));
+    assert!(process(trader, place(adbe)).is_ok());
+  }
+
+  #[test]
+  fn accounts_page_through_their_own_activity() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (buyer, seller) = (0.into(), 1.into());
+    engine.load_accounts(AccountStore {
+      accounts: vec![(buyer, Account::default()), (seller, Account::default())],
+      ..AccountStore::default()
+    });
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let order = Order::new(100.into(), 5.into());
+    process(buyer, CommandKind::PlaceOrder(Side::Bid, symbol, order)).unwrap();
+    process(seller, CommandKind::PlaceOrder(Side::Ask, symbol, order)).unwrap();
+    assert!(process(buyer, CommandKind::CancelOrder(99.into())).is_err());
+
+    let page = match process(buyer, CommandKind::GetActivity(buyer, 0, 2)) {
+      Ok(Success::GetActivity(page)) => page,
+      other => panic!("unexpected {:?}", other),
+    };
+    assert!(matches!(page.entries[0].activity, Activity::OrderAccepted { side: Side::Bid, .. }));
+    assert!(matches!(page.entries[1].activity, Activity::Fill { filled, .. } if filled == 5.into()));
+    assert_eq!((page.next, page.latest), (Some(2), 3));
+
+    let page = engine.activity(buyer, 2, 10).unwrap();
+    assert_eq!(page.entries.len(), 1);
+    assert_eq!(page.entries[0].activity, Activity::OrderRejected { order: Some(99.into()) });
+    assert_eq!(page.next, None);
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    assert_eq!(process(seller, CommandKind::GetActivity(buyer, 0, 10)), Err(Error::PermissionDenied { id: seller }));
+  }
+
+  #[test]
+  fn admin_sessions_bypass_selected_checks_with_an_audit_record() {
+    use crate::risk::RiskChecks;
+
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin) = (0.into(), 1.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, Account::default()), (admin, admin_account)],
+      ..AccountStore::default()
+    });
+    let (ops, other): (SessionId, SessionId) = (1.into(), 2.into());
+    let mut process = |session, account_id, kind| engine.try_process_from(session, Command { account_id, kind });
+    let place = CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 5.into()));
+    let bypass = |reason| {
+      CommandKind::SetRiskBypass(Some(RiskBypass {
+        checks: RiskChecks::REJECT_ALL,
+        reason: Label::new(reason).unwrap(),
+      }))
+    };
+
+    process(ops, admin, CommandKind::SetRejectAll(true)).unwrap();
+    assert_eq!(process(ops, trader, bypass("unwind")), Err(Error::PermissionDenied { id: trader }));
+    assert_eq!(process(ops, admin, bypass("")), Err(Error::MissingBypassReason));
+    assert_eq!(process(ops, admin, bypass("unwind INC-42")), Ok(Success::SetRiskBypass));
+
+    assert_eq!(process(ops, trader, place), Err(Error::RejectingAllOrders { id: trader }));
+    assert_eq!(process(other, admin, place), Err(Error::RejectingAllOrders { id: admin }));
+    assert!(matches!(process(ops, admin, place), Ok(Success::PlaceOrder(_))));
+
+    process(ops, admin, CommandKind::SetRiskBypass(None)).unwrap();
+    assert_eq!(process(ops, admin, place), Err(Error::RejectingAllOrders { id: admin }));
+
+    let bypasses: Vec<_> = engine
+      .drain_audit_trail()
+      .into_iter()
+      .filter(|record| record.event == AuditEvent::Bypass)
+      .collect();
+    assert_eq!(bypasses.len(), 1);
+    assert_eq!((bypasses[0].session, bypasses[0].account, bypasses[0].symbol), (ops, admin, Some(symbol)));
+    assert_eq!(bypasses[0].check, Some(RiskCheck::RejectAll));
+    assert_eq!(bypasses[0].reason, Some(Label::new("unwind INC-42").unwrap()));
+    assert!(bypasses[0].to_flat().ends_with("|REJECT_ALL|unwind INC-42|"));
+  }
+
+  #[test]
+  fn restricted_symbols_only_take_orders_from_their_grantees() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin, desk) = (0.into(), 1.into(), 2.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    let desk_account = Account {
+      firm: Some(7.into()),
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, Account::default()), (admin, admin_account), (desk, desk_account)],
+      ..AccountStore::default()
+    });
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let place = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(100.into(), 5.into()));
+    let resting = match process(trader, place) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    let restrict = CommandKind::RestrictSymbol(symbol, true);
+    assert_eq!(process(trader, restrict), Err(Error::PermissionDenied { id: trader }));
+    assert_eq!(process(admin, restrict), Ok(Success::RestrictSymbol));
+    let not_permitted = |id| Err(Error::SymbolNotPermitted { id, symbol });
+    assert_eq!(process(trader, place), not_permitted(trader));
+    assert_eq!(process(trader, CommandKind::UpdateOrder(resting, None, Some(9.into()))), not_permitted(trader));
+
+    let grant = |grantee, is_granted| CommandKind::SetSymbolPermission(symbol, grantee, is_granted);
+    process(admin, grant(Grantee::Account(trader), true)).unwrap();
+    process(admin, grant(Grantee::Firm(7.into()), true)).unwrap();
+    assert!(process(trader, place).is_ok());
+    assert!(process(desk, place).is_ok());
+
+    process(admin, grant(Grantee::Account(trader), false)).unwrap();
+    assert_eq!(process(trader, place), not_permitted(trader));
+    assert_eq!(process(trader, CommandKind::CancelOrder(resting)), Ok(Success::CancelOrder(true)));
+    let permissions = SymbolPermissions {
+      is_restricted: true,
+      grantees: vec![Grantee::Firm(7.into())],
+    };
+    let get = CommandKind::GetSymbolPermissions(symbol);
+    assert_eq!(process(admin, get), Ok(Success::GetSymbolPermissions(permissions)));
+  }
+
+  #[test]
+  fn cancel_tagged_flattens_one_strategy() {
+    let mut engine = MatchEngine::default();
+    let (account_id, other) = (engine.create_account(), engine.create_account());
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (runaway, steady) = (Label::new("runaway").unwrap(), Label::new("steady").unwrap());
+
+    let mut place = |account_id, price: u32, tag| {
+      let order = Order::new(price.into(), 5.into()).with_tag(tag);
+      match engine.try_process(Command { account_id, kind: CommandKind::PlaceOrder(Side::Bid, symbol, order) }) {
+        Ok(Success::PlaceOrder(placement)) => placement.id,
+        other => panic!("unexpected {:?}", other),
+      }
+    };
+    let first = place(account_id, 98, runaway);
+    let kept = place(account_id, 99, steady);
+    let second = place(account_id, 97, runaway);
+    let untagged = place(account_id, 96, Label::default());
+    let others = place(other, 95, runaway);
+
+    let cancel = |account_id| Command { account_id, kind: CommandKind::CancelTagged(account_id, runaway) };
+    let denied = Command { account_id: other, kind: CommandKind::CancelTagged(account_id, runaway) };
+    assert_eq!(engine.try_process(denied), Err(Error::PermissionDenied { id: other }));
+    assert_eq!(engine.try_process(cancel(account_id)), Ok(Success::CancelTagged(vec![first, second])));
+    assert!(engine.is_cancelled(first) && engine.is_cancelled(second));
+    assert!(!engine.is_cancelled(kept) && !engine.is_cancelled(untagged) && !engine.is_cancelled(others));
+    assert_eq!(engine.try_process(cancel(account_id)), Ok(Success::CancelTagged(vec![])));
+  }
+
+  #[test]
+  fn replaces_past_the_protection_band_are_rejected_or_capped() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (trader, admin, seller) = (0.into(), 1.into(), 2.into());
+    let admin_account = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(trader, Account::default()), (admin, admin_account), (seller, Account::default())],
+      ..AccountStore::default()
+    });
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    let place = |price: u32| CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(price.into(), 5.into()));
+    process(seller, CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(100.into(), 5.into()))).unwrap();
+    let bid = match process(trader, place(90)) {
+      Ok(Success::PlaceOrder(placement)) => placement.id,
+      other => panic!("unexpected {:?}", other),
+    };
+
+    let protect = |action| {
+      let protection = ReplaceProtection {
+        band: 2.into(),
+        action,
+      };
+      CommandKind::SetReplaceProtection(trader, Some(protection))
+    };
+    assert_eq!(process(admin, protect(ProtectionAction::Reject)), Ok(Success::SetReplaceProtection));
+    let replace = CommandKind::UpdateOrder(bid, Some(105.into()), None);
+    let outside_band = Err(Error::ReplaceOutsideBand { id: bid, limit: 102.into() });
+    assert_eq!(process(trader, replace), outside_band);
+    assert_eq!(process(admin, replace), outside_band);
+    assert_eq!(engine.resting_order(bid).map(|order| order.price), Some(90.into()));
+
+    let mut process = |account_id, kind| engine.try_process(Command { account_id, kind });
+    process(admin, protect(ProtectionAction::Cap)).unwrap();
+    assert_eq!(process(trader, replace), Ok(Success::UpdateOrder(true)));
+    assert_eq!(trades(engine.drain_market_data()), vec![(100.into(), 5.into())]);
+    assert!(engine.resting_order(bid).is_none());
+  }
+}
@@ -0,0 +1,97 @@
+//! End of day
+//!
+//! Closing the trading day expires day orders, runs a closing auction in every book and rolls over each symbol's
+//! daily statistics and each account's settlement obligations, which are reported for the day and then start afresh.
+
+use crate::clock::Timestamp;
+use crate::engine::Id;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+
+/// A symbol's lit trading over a day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DailyStats {
+  pub symbol: Symbol,
+  pub open: Price,
+  pub high: Price,
+  pub low: Price,
+  /// The official closing price, the price of the closing auction if it traded and of the last trade otherwise
+  pub close: Price,
+  pub volume: Quantity,
+  pub trades: u32,
+}
+
+impl DailyStats {
+  /// Start a day's statistics with its first trade
+  pub(crate) fn new(symbol: Symbol, price: Price) -> Self {
+    Self {
+      symbol,
+      open: price,
+      high: price,
+      low: price,
+      close: price,
+      volume: Quantity::default(),
+      trades: 0,
+    }
+  }
+
+  pub(crate) fn add_trade(&mut self, price: Price, quantity: Quantity) {
+    self.high = self.high.max(price);
+    self.low = self.low.min(price);
+    self.close = price;
+    self.volume += quantity;
+    self.trades += 1;
+  }
+}
+
+/// What an account owes and is owed for its trades of a symbol over a day, lit and dark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Settlement {
+  pub account: AccountId,
+  pub symbol: Symbol,
+  pub bought: Quantity,
+  pub sold: Quantity,
+  /// The value of everything bought, to be paid
+  pub paid: u64,
+  /// The value of everything sold, to be received
+  pub received: u64,
+}
+
+impl Settlement {
+  pub(crate) fn new(account: AccountId, symbol: Symbol) -> Self {
+    Self {
+      account,
+      symbol,
+      bought: Quantity::default(),
+      sold: Quantity::default(),
+      paid: 0,
+      received: 0,
+    }
+  }
+
+  pub(crate) fn add_fill(&mut self, side: Side, price: Price, quantity: Quantity, precision: u8) {
+    let value = quantity.value_at(price, precision);
+    match side {
+      Side::Bid => {
+        self.bought += quantity;
+        self.paid += value;
+      }
+      Side::Ask => {
+        self.sold += quantity;
+        self.received += value;
+      }
+    }
+  }
+}
+
+/// What a trading day closed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndOfDay {
+  pub closed_at: Timestamp,
+  /// The day orders that were still resting, oldest first
+  pub expired: Vec<Id>,
+  /// The statistics of every symbol that traded in its lit book, ordered by symbol
+  pub closes: Vec<DailyStats>,
+  /// The obligations of every account that traded, ordered by account and then symbol
+  pub settlements: Vec<Settlement>,
+}
@@ -0,0 +1,91 @@
+//! Typed engine events
+//!
+//! An embedder of the engine can `MatchEngine::subscribe` to be sent an `EngineEvent` for everything that happens to
+//! orders, trades and accounts as it happens, to build its own feeds and persistence on top without a server or any
+//! wire format. Every subscriber is sent every event, in the order they happened, over an unbounded channel, and is
+//! forgotten once it drops its receiver.
+//!
+//! Events are only built while something is subscribed, and a copy of an engine, e.g. a snapshot, starts without
+//! subscribers.
+
+use crate::audit::CancelReason;
+use crate::engine::{AccountSummary, Id, TradeId};
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Something that happened in the engine
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EngineEvent {
+  /// An order was given an id, before any of it traded
+  OrderAccepted {
+    order: Id,
+    account: AccountId,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+  },
+  /// An order entry command failed, with the order it named if any
+  OrderRejected { account: AccountId, order: Option<Id> },
+  /// A resting order's price or quantity changed, or it was suspended or resumed
+  OrderModified { order: Id, account: AccountId },
+  /// An order left its book before it filled
+  OrderCancelled {
+    order: Id,
+    account: AccountId,
+    reason: CancelReason,
+  },
+  /// One side of a trade
+  Fill {
+    order: Id,
+    account: AccountId,
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+    /// The order's fills so far, including this one
+    filled: Quantity,
+  },
+  /// A trade printed on a symbol, in its lit book or its non-displayed one
+  Trade {
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+    is_dark: bool,
+  },
+  /// Order entry was halted on every symbol, or resumed
+  Halt { is_halted: bool },
+  /// An account was created, or its balance or state changed
+  AccountUpdated { account: AccountId, summary: AccountSummary },
+}
+
+/// The receivers of an engine's events
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers(Vec<Sender<EngineEvent>>);
+
+impl Clone for Subscribers {
+  /// A copy of an engine starts without subscribers, so only the engine subscribed to sends them events
+  fn clone(&self) -> Self {
+    Self::default()
+  }
+}
+
+impl Subscribers {
+  pub fn subscribe(&mut self) -> Receiver<EngineEvent> {
+    let (sender, receiver) = mpsc::channel();
+    self.0.push(sender);
+    receiver
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// Send an event to every subscriber, forgetting those that dropped their receivers
+  pub fn send(&mut self, event: EngineEvent) {
+    self.0.retain(|subscriber| subscriber.send(event).is_ok());
+  }
+}
@@ -0,0 +1,118 @@
+//! Market data feed
+
+use crate::clock::Timestamp;
+use crate::engine::TradeId;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+
+/// A market data message published by the engine
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MarketData {
+  /// A trade printed on a symbol
+  Trade {
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+  },
+  /// A trade printed in a symbol's non-displayed book
+  DarkTrade {
+    symbol: Symbol,
+    price: Price,
+    quantity: Quantity,
+    trade: TradeId,
+  },
+  /// The result of an auction uncross
+  AuctionUncross { symbol: Symbol, price: Price, quantity: Quantity },
+  /// A scheduled auction has started its call, so the symbol's orders rest without matching until it uncrosses at
+  /// `uncrosses_at` or, if its schedule has jitter, at a random moment shortly after
+  AuctionCall { symbol: Symbol, uncrosses_at: Timestamp },
+  /// A marketable order is open to price-improving responses until `ends_at`
+  PriceImprovementAuction {
+    symbol: Symbol,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+    ends_at: Timestamp,
+  },
+  /// The latest value of an index
+  IndexValue { symbol: Symbol, value: f64 },
+  /// A symbol's official closing price and lit volume for the day, published at the end of day
+  ClosingPrice { symbol: Symbol, price: Price, volume: Quantity },
+}
+
+/// A kind of market data a session can subscribe to for a symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Feed {
+  /// The best bid and ask, as `Push::Bbo`
+  Bbo,
+  /// The aggregate quantity at up to this many price levels of each side, or at all of them if `None`, as
+  /// `Push::Depth`
+  Depth(Option<u32>),
+  /// Trades printed in the lit book, including by auctions, as `Push::Trade`
+  Trades,
+  /// Trades summarized over fixed intervals, as `Push::Candle`
+  Candles,
+}
+
+/// How much market data an account may be sent, each level allowing everything the ones before it do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub enum Entitlement {
+  /// The best bid and ask, trades and candles
+  Bbo,
+  /// Up to this many price levels of each side of a book
+  Depth(u32),
+  /// Every price level of a book
+  FullDepth,
+  /// The queue of orders at each price level, as `GetLevel` returns
+  #[default]
+  OrderByOrder,
+}
+
+impl Entitlement {
+  /// Returns true if the entitlement allows subscribing to `feed`
+  pub fn allows(self, feed: Feed) -> bool {
+    !matches!(feed, Feed::Depth(_)) || self != Entitlement::Bbo
+  }
+
+  /// Get the most price levels of each side the entitlement allows
+  ///
+  /// # Returns
+  /// `None` if it allows every level
+  pub fn depth(self) -> Option<usize> {
+    match self {
+      Entitlement::Bbo => Some(1),
+      Entitlement::Depth(levels) => Some(levels as usize),
+      Entitlement::FullDepth | Entitlement::OrderByOrder => None,
+    }
+  }
+}
+
+/// The trades of a symbol over one interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candle {
+  pub symbol: Symbol,
+  /// When the interval started
+  pub start: Timestamp,
+  pub open: Price,
+  pub high: Price,
+  pub low: Price,
+  pub close: Price,
+  pub volume: Quantity,
+}
+
+/// A trade in a symbol's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeTick {
+  pub timestamp: Timestamp,
+  pub price: Price,
+  pub quantity: Quantity,
+}
+
+/// A symbol's best bid and ask from `timestamp` until its next quote in its history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteTick {
+  pub timestamp: Timestamp,
+  pub bid: Option<(Price, Quantity)>,
+  pub ask: Option<(Price, Quantity)>,
+}
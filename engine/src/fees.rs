@@ -0,0 +1,337 @@
+//! Volume-tiered trading fees
+//!
+//! Each account's traded quantity, lit and dark, is tracked over a rolling window, and the account is in the highest
+//! tier whose threshold that volume reaches. A fill charges the taker, the order that arrived and traded, its tier's
+//! taker rate and the maker, the order it traded against, its tier's maker rate, which is negative for a rebate. Fills
+//! with no aggressor, in auctions and the dark pool, charge both sides the taker rate.
+//!
+//! A symbol's `FeeModel` can change which of the tier's rates each side is charged, e.g. paying takers the rebate in an
+//! inverted book, or charging nothing. Fills count towards an account's volume whatever the symbol's model.
+//!
+//! Every fee charged, on a fill or for an order beyond the order-to-trade fee threshold, is accrued in a ledger by the
+//! UTC day it was charged on, its account and its symbol, from which `CommandKind::GetFees` reports a day's or a
+//! month-to-date's totals to invoice each account with. Closing the trading day drops the days from before the month
+//! before, so last month can still be invoiced early in this one.
+
+use crate::clock::{Date, Timestamp};
+use crate::engine::Liquidity;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// One tier of a fee schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub struct FeeTier {
+  /// The volume over the window from which an account is in this tier
+  pub min_volume: u64,
+  /// In basis points of a fill's value, negative for a rebate
+  pub maker_rate: i64,
+  /// In basis points of a fill's value, negative for a rebate
+  pub taker_rate: i64,
+}
+
+/// Fee rates by rolling volume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+  pub window: Duration,
+  /// Ordered by rising volume, the first starting from none
+  pub tiers: Vec<FeeTier>,
+}
+
+impl Default for FeeSchedule {
+  fn default() -> Self {
+    Self {
+      window: Duration::from_secs(30 * 24 * 60 * 60),
+      tiers: vec![FeeTier::default()],
+    }
+  }
+}
+
+impl FeeSchedule {
+  /// Returns true if the tiers start from no volume and each needs more than the one before
+  pub fn is_ordered(&self) -> bool {
+    self.tiers.first().is_some_and(|tier| tier.min_volume == 0)
+      && self.tiers.windows(2).all(|pair| pair[0].min_volume < pair[1].min_volume)
+  }
+}
+
+/// How a symbol charges its fills
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum FeeModel {
+  /// Makers are charged their tier's maker rate and takers its taker rate
+  #[default]
+  Standard,
+  /// Makers are charged their tier's taker rate and takers its maker rate, so takers earn the rebate
+  Inverted,
+  /// Neither side is charged
+  Free,
+}
+
+impl FeeModel {
+  /// Get the rates a fill of a symbol with this model charges an account in `status`'s tier
+  pub fn apply(self, status: FeeTierStatus) -> FeeTierStatus {
+    match self {
+      FeeModel::Standard => status,
+      FeeModel::Inverted => FeeTierStatus {
+        maker_rate: status.taker_rate,
+        taker_rate: status.maker_rate,
+        ..status
+      },
+      FeeModel::Free => FeeTierStatus {
+        maker_rate: 0,
+        taker_rate: 0,
+        ..status
+      },
+    }
+  }
+}
+
+/// An account's volume over the current window and the tier it puts the account in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTierStatus {
+  /// The position of the tier in the schedule
+  pub tier: usize,
+  pub volume: u64,
+  pub maker_rate: i64,
+  pub taker_rate: i64,
+  /// The volume the account needs to reach the next tier, or `None` if it is in the top one
+  pub next_tier_volume: Option<u64>,
+}
+
+/// Tracks the volume each account traded and what its fills are charged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeMonitor {
+  pub schedule: FeeSchedule,
+  #[serde(with = "crate::types::pairs")]
+  fills: HashMap<AccountId, VecDeque<(Timestamp, Quantity)>>,
+}
+
+impl FeeMonitor {
+  /// Charge an account for a fill of `value` in a symbol with fee model `model` and count its quantity towards the
+  /// account's volume
+  ///
+  /// # Returns
+  /// the fee, negative for a rebate
+  pub fn on_fill(
+    &mut self,
+    at: Timestamp,
+    account: AccountId,
+    model: FeeModel,
+    liquidity: Liquidity,
+    value: u64,
+    quantity: Quantity,
+  ) -> i64 {
+    let status = model.apply(self.status(at, account));
+    let rate = match liquidity {
+      Liquidity::Maker => status.maker_rate,
+      Liquidity::Taker | Liquidity::Crossed => status.taker_rate,
+    };
+
+    let window = self.schedule.window;
+    let fills = self.fills.entry(account).or_default();
+    while fills.front().is_some_and(|&(time, _)| time + window < at) {
+      fills.pop_front();
+    }
+    fills.push_back((at, quantity));
+
+    (i128::from(value) * i128::from(rate) / 10_000) as i64
+  }
+
+  /// Get an account's tier over the window ending at `at`
+  pub fn status(&self, at: Timestamp, account: AccountId) -> FeeTierStatus {
+    let window = self.schedule.window;
+    let volume = self
+      .fills
+      .get(&account)
+      .map(|fills| {
+        fills
+          .iter()
+          .filter(|&&(time, _)| time + window >= at)
+          .map(|&(_, quantity)| u64::from(quantity))
+          .sum()
+      })
+      .unwrap_or_default();
+
+    let tiers = &self.schedule.tiers;
+    let tier = tiers.iter().rposition(|tier| tier.min_volume <= volume).unwrap_or_default();
+    let rates = tiers.get(tier).copied().unwrap_or_default();
+    FeeTierStatus {
+      tier,
+      volume,
+      maker_rate: rates.maker_rate,
+      taker_rate: rates.taker_rate,
+      next_tier_volume: tiers.get(tier + 1).map(|next| next.min_volume),
+    }
+  }
+}
+
+/// The days a fee report totals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FeePeriod {
+  /// The one day
+  Day,
+  /// From the first of the day's month to the day
+  MonthToDate,
+}
+
+/// The fees an account accrued on a symbol, or across every symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FeeLine {
+  pub account: AccountId,
+  /// `None` in an account's total
+  pub symbol: Option<Symbol>,
+  /// Charged on fills
+  pub fill_fees: u64,
+  /// Paid on fills
+  pub rebates: u64,
+  /// Charged for orders beyond the order-to-trade fee threshold
+  pub order_fees: u64,
+  /// What the account owes, negative if it is owed
+  pub net: i64,
+  pub fills: u64,
+}
+
+impl FeeLine {
+  fn new(account: AccountId, symbol: Option<Symbol>) -> Self {
+    Self {
+      account,
+      symbol,
+      fill_fees: 0,
+      rebates: 0,
+      order_fees: 0,
+      net: 0,
+      fills: 0,
+    }
+  }
+
+  fn add(&mut self, other: &FeeLine) {
+    self.fill_fees += other.fill_fees;
+    self.rebates += other.rebates;
+    self.order_fees += other.order_fees;
+    self.net += other.net;
+    self.fills += other.fills;
+  }
+}
+
+/// The fees accrued over a period, to invoice accounts with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeReport {
+  pub from: Date,
+  /// The last day totalled
+  pub to: Date,
+  /// Each account's fees on each symbol it was charged on, ordered by account and then symbol
+  pub lines: Vec<FeeLine>,
+  /// Each account's fees across every symbol, ordered by account
+  pub totals: Vec<FeeLine>,
+}
+
+/// The fees each account accrued on each symbol each day
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FeeLedger {
+  #[serde(with = "crate::types::pairs")]
+  days: HashMap<(Date, AccountId, Symbol), FeeLine>,
+}
+
+impl FeeLedger {
+  /// Accrue the fee of a fill, negative for a rebate
+  pub fn on_fill(&mut self, at: Timestamp, account: AccountId, symbol: Symbol, fee: i64) {
+    let line = self.line(at, account, symbol);
+    if fee > 0 {
+      line.fill_fees += fee as u64;
+    } else {
+      line.rebates += fee.unsigned_abs();
+    }
+    line.net += fee;
+    line.fills += 1;
+  }
+
+  /// Accrue the fee of an order beyond the order-to-trade fee threshold
+  pub fn on_order_fee(&mut self, at: Timestamp, account: AccountId, symbol: Symbol, fee: u64) {
+    let line = self.line(at, account, symbol);
+    line.order_fees += fee;
+    line.net += fee as i64;
+  }
+
+  /// Drop the days before `oldest`
+  pub fn prune(&mut self, oldest: Date) {
+    self.days.retain(|&(date, _, _), _| date >= oldest);
+  }
+
+  /// Total the fees of one account, or of every account if `None`, over a period ending on `date`
+  pub fn report(&self, account: Option<AccountId>, date: Date, period: FeePeriod) -> FeeReport {
+    let from = match period {
+      FeePeriod::Day => date,
+      FeePeriod::MonthToDate => date.first_of_month(),
+    };
+    let mut lines: HashMap<(AccountId, Symbol), FeeLine> = HashMap::new();
+    for (&(day, line_account, symbol), line) in &self.days {
+      if day < from || day > date || account.is_some_and(|account| account != line_account) {
+        continue;
+      }
+      lines.entry((line_account, symbol)).or_insert_with(|| FeeLine::new(line_account, Some(symbol))).add(line);
+    }
+    let mut lines: Vec<FeeLine> = lines.into_values().collect();
+    lines.sort_by_key(|line| (usize::from(line.account), line.symbol.map(|symbol| symbol.to_string())));
+
+    let mut totals: Vec<FeeLine> = vec![];
+    for line in &lines {
+      match totals.last_mut() {
+        Some(total) if total.account == line.account => total.add(line),
+        _ => {
+          let mut total = FeeLine::new(line.account, None);
+          total.add(line);
+          totals.push(total);
+        }
+      }
+    }
+    FeeReport {
+      from,
+      to: date,
+      lines,
+      totals,
+    }
+  }
+
+  fn line(&mut self, at: Timestamp, account: AccountId, symbol: Symbol) -> &mut FeeLine {
+    let date = Date::from(at);
+    self.days.entry((date, account, symbol)).or_insert_with(|| FeeLine::new(account, Some(symbol)))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::clock::NANOS_PER_DAY;
+
+  #[test]
+  fn ledgers_total_days_and_months_by_account_and_symbol() {
+    let mut ledger = FeeLedger::default();
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+    let (first, second) = (AccountId::from(1), AccountId::from(2));
+    // 2024-02-29, 2024-03-01 and 2024-03-04
+    let day = |day: u64| Timestamp::from(day * NANOS_PER_DAY);
+    let (february, friday, monday) = (day(19_782), day(19_783), day(19_786));
+
+    ledger.on_fill(february, first, adbe, 50);
+    ledger.on_fill(friday, first, adbe, 30);
+    ledger.on_fill(friday, first, msft, -10);
+    ledger.on_order_fee(monday, first, msft, 5);
+    ledger.on_fill(monday, second, adbe, 20);
+
+    let report = ledger.report(None, Date::from(monday), FeePeriod::Day);
+    assert_eq!(report.lines.iter().map(|line| line.net).collect::<Vec<_>>(), vec![5, 20]);
+    assert_eq!(report.totals.iter().map(|total| total.account).collect::<Vec<_>>(), vec![first, second]);
+
+    let report = ledger.report(Some(first), Date::from(monday), FeePeriod::MonthToDate);
+    assert_eq!(report.from.to_string(), "2024-03-01");
+    assert_eq!(report.lines.iter().map(|line| line.symbol).collect::<Vec<_>>(), vec![Some(adbe), Some(msft)]);
+    let total = report.totals[0];
+    assert_eq!((total.fill_fees, total.rebates, total.order_fees), (30, 10, 5));
+    assert_eq!((total.net, total.fills, total.symbol), (25, 2, None));
+
+    ledger.prune(Date::from(friday).first_of_month());
+    assert!(ledger.report(None, Date::from(february), FeePeriod::Day).lines.is_empty());
+    assert_eq!(ledger.report(None, Date::from(friday), FeePeriod::Day).lines.len(), 2);
+  }
+}
@@ -0,0 +1,45 @@
+//! Stable hashing of engine state
+//!
+//! `std`'s default hasher is randomly keyed and may change between releases, so state digests that are compared across
+//! processes use FNV-1a instead.
+
+use std::hash::Hasher;
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A 64-bit FNV-1a hasher
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StateHasher(u64);
+
+impl Default for StateHasher {
+  fn default() -> Self {
+    StateHasher(OFFSET_BASIS)
+  }
+}
+
+impl Hasher for StateHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.0 ^= u64::from(byte);
+      self.0 = self.0.wrapping_mul(PRIME);
+    }
+  }
+
+  // integers are written little endian at a fixed width so digests agree across platforms
+  fn write_usize(&mut self, i: usize) {
+    self.write(&(i as u64).to_le_bytes());
+  }
+
+  fn write_u32(&mut self, i: u32) {
+    self.write(&i.to_le_bytes());
+  }
+
+  fn write_u64(&mut self, i: u64) {
+    self.write(&i.to_le_bytes());
+  }
+}
@@ -0,0 +1,118 @@
+//! Weighted index calculation
+
+use crate::config::ConfigError;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// An index computed from the last-trade prices of its constituents
+///
+/// The value of the index is `sum(weight * last_price) / divisor`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "IndexFields")]
+pub struct Index {
+  #[serde(with = "crate::types::pairs")]
+  constituents: HashMap<Symbol, f64>,
+  divisor: f64,
+}
+
+/// An index as it is decoded, e.g. from a journal, before its divisor is checked
+#[derive(Deserialize)]
+struct IndexFields {
+  #[serde(with = "crate::types::pairs")]
+  constituents: HashMap<Symbol, f64>,
+  divisor: f64,
+}
+
+impl TryFrom<IndexFields> for Index {
+  type Error = ConfigError;
+
+  fn try_from(fields: IndexFields) -> Result<Self, Self::Error> {
+    check_divisor(fields.divisor)?;
+    Ok(Self {
+      constituents: fields.constituents,
+      divisor: fields.divisor,
+    })
+  }
+}
+
+impl Index {
+  /// Create a new index from `(symbol, weight)` pairs
+  ///
+  /// # Errors
+  /// `ConfigError::InvalidIndexDivisor` if `divisor` is not a positive, finite number
+  pub fn new(constituents: Vec<(Symbol, f64)>, divisor: f64) -> Result<Self, ConfigError> {
+    check_divisor(divisor)?;
+    Ok(Self {
+      constituents: constituents.into_iter().collect(),
+      divisor,
+    })
+  }
+
+  /// Returns true if `symbol` is a constituent of the index
+  pub fn contains(&self, symbol: Symbol) -> bool {
+    self.constituents.contains_key(&symbol)
+  }
+
+  /// Set the weight of a constituent, adding it if it is not already present
+  pub fn set_weight(&mut self, symbol: Symbol, weight: f64) {
+    self.constituents.insert(symbol, weight);
+  }
+
+  /// Set the divisor, e.g. to keep the index continuous across a change in constituents
+  ///
+  /// # Errors
+  /// `ConfigError::InvalidIndexDivisor` if `divisor` is not a positive, finite number, leaving the divisor as it was
+  pub fn set_divisor(&mut self, divisor: f64) -> Result<(), ConfigError> {
+    check_divisor(divisor)?;
+    self.divisor = divisor;
+    Ok(())
+  }
+
+  /// Compute the value of the index
+  ///
+  /// # Returns
+  /// `None` if any constituent has not traded yet
+  pub fn value(&self, last_prices: &HashMap<Symbol, Price>) -> Option<f64> {
+    self
+      .constituents
+      .iter()
+      .map(|(symbol, weight)| last_prices.get(symbol).map(|&price| weight * f64::from(u32::from(price))))
+      .sum::<Option<f64>>()
+      .map(|total| total / self.divisor)
+  }
+}
+
+fn check_divisor(divisor: f64) -> Result<(), ConfigError> {
+  // NaN fails the comparison too
+  if divisor > 0.0 && divisor.is_finite() {
+    Ok(())
+  } else {
+    Err(ConfigError::InvalidIndexDivisor)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn divisors_must_be_positive_and_finite() {
+    for &divisor in &[0.0, -2.0, f64::NAN, f64::INFINITY] {
+      assert_eq!(Index::new(vec![], divisor), Err(ConfigError::InvalidIndexDivisor));
+    }
+
+    let mut index = Index::new(vec![(['A', 'D', 'B', 'E'].into(), 1.0)], 2.0).unwrap();
+    assert_eq!(index.set_divisor(f64::NAN), Err(ConfigError::InvalidIndexDivisor));
+    assert_eq!(index.set_divisor(4.0), Ok(()));
+    let last_prices = vec![(['A', 'D', 'B', 'E'].into(), 100.into())].into_iter().collect();
+    assert_eq!(index.value(&last_prices), Some(25.0));
+
+    // an index read back, e.g. replaying a journal, is checked the same way
+    let encoded = serde_json::to_string(&index).unwrap();
+    assert_eq!(serde_json::from_str::<Index>(&encoded).unwrap(), index);
+    assert!(serde_json::from_str::<Index>(r#"{"constituents":[],"divisor":0.0}"#).is_err());
+    assert!(serde_json::from_str::<Index>(r#"{"constituents":[],"divisor":-1.0}"#).is_err());
+  }
+}
@@ -0,0 +1,128 @@
+//! Command journal
+//!
+//! The journal is a JSON-lines log of every event that changes engine state, in the order it was applied. Replaying it
+//! into a fresh engine with a `ManualClock` reproduces the original state.
+
+use crate::clock::Timestamp;
+use crate::collateral::CollateralRules;
+use crate::config::SymbolConfig;
+use crate::engine::{AccountStore, Command, CommandKind, EventId};
+use crate::index::Index;
+use crate::fees::FeeSchedule;
+use crate::order_to_trade::OrderToTradeRules;
+use crate::surveillance::SurveillanceRules;
+use crate::types::*;
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+
+/// Something that changed engine state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JournalEvent {
+  Command { session: SessionId, command: Command },
+  /// Scheduled work, such as an auction, ran
+  Tick,
+  /// The trading day closed
+  EndOfDay,
+  CreateAccount { firm: Option<FirmId>, is_admin: bool },
+  SetBeneficialOwner { account: AccountId, owner: AccountId },
+  InsertSymbol(Symbol),
+  /// Accounts kept from an earlier run were loaded
+  LoadAccounts(AccountStore),
+  ConfigureSymbol(Symbol, SymbolConfig),
+  InsertIndex(Symbol, Index),
+  SetSurveillanceRules(SurveillanceRules),
+  SetOrderToTradeRules(OrderToTradeRules),
+  SetFeeSchedule(FeeSchedule),
+  SetCollateralRules(CollateralRules),
+  /// The engine's `state_hash` at this point in the journal
+  StateHash(u64),
+  /// A connection or command was refused for the address it came from
+  Deny { session: SessionId, account: AccountId, source: IpAddr },
+  /// Order entry on a symbol was halted because its matching stalled
+  HaltSymbol(Symbol),
+  /// Every resting order was cancelled at once
+  CancelAll,
+}
+
+impl JournalEvent {
+  /// Returns true if the event changes the accounts themselves, rather than only their orders
+  pub fn changes_accounts(&self) -> bool {
+    match self {
+      JournalEvent::CreateAccount { .. } | JournalEvent::SetBeneficialOwner { .. } | JournalEvent::LoadAccounts(_) => {
+        true
+      }
+      JournalEvent::Command { command, .. } => matches!(
+        command.kind,
+        CommandKind::SetEntitlement(..)
+          | CommandKind::SetReplaceProtection(..)
+          | CommandKind::CreateAccount(_)
+          | CommandKind::SetAccountState(..)
+      ),
+      _ => false,
+    }
+  }
+}
+
+/// A journaled event with the times it was received and applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+  pub sequence: EventId,
+  pub received_at: Timestamp,
+  pub processed_at: Timestamp,
+  pub event: JournalEvent,
+}
+
+/// A point in a journal to replay up to, inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalPoint {
+  /// The entry with this sequence number
+  Sequence(EventId),
+  /// The last entry received at or before this time
+  Time(Timestamp),
+}
+
+impl JournalPoint {
+  /// Check whether an entry comes at or before this point
+  pub fn includes(self, entry: &JournalEntry) -> bool {
+    match self {
+      JournalPoint::Sequence(sequence) => entry.sequence <= sequence,
+      JournalPoint::Time(time) => entry.received_at <= time,
+    }
+  }
+}
+
+/// Appends entries to a journal
+#[derive(Debug)]
+pub struct JournalWriter<W: Write> {
+  writer: W,
+}
+
+impl<W: Write> JournalWriter<W> {
+  pub fn new(writer: W) -> Self {
+    Self { writer }
+  }
+
+  pub fn get_mut(&mut self) -> &mut W {
+    &mut self.writer
+  }
+
+  /// Write entries and flush them
+  pub fn write(&mut self, entries: &[JournalEntry]) -> io::Result<()> {
+    for entry in entries {
+      serde_json::to_writer(&mut self.writer, entry)?;
+      writeln!(self.writer)?;
+    }
+
+    self.writer.flush()
+  }
+}
+
+/// Read the entries of a journal in order
+pub fn read_journal<R: BufRead>(reader: R) -> impl Iterator<Item = Result<JournalEntry, Error>> {
+  reader
+    .lines()
+    .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+    .map(|line| Ok(serde_json::from_str(&line?)?))
+}
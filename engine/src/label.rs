@@ -0,0 +1,95 @@
+//! Short text kept inline
+//!
+//! Commands are `Copy`, so text they carry, like an account's external reference, is stored in a fixed-size buffer
+//! rather than a `String`. On the wire a label is a JSON string of at most `LABEL_CAPACITY` bytes.
+//!
+//! Orders carry their tag as a label too, so unlike the rest of the text on the wire, labels are built without `std`.
+
+#[cfg(feature = "std")]
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+#[cfg(feature = "std")]
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// The most bytes of UTF-8 a label holds
+pub const LABEL_CAPACITY: usize = 32;
+
+/// Text of at most `LABEL_CAPACITY` bytes
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Label {
+  len: u8,
+  bytes: [u8; LABEL_CAPACITY],
+}
+
+impl Label {
+  /// A label of no text
+  pub const EMPTY: Self = Self {
+    len: 0,
+    bytes: [0; LABEL_CAPACITY],
+  };
+
+  /// Copy text into a label
+  ///
+  /// # Returns
+  /// `None` if the text is longer than `LABEL_CAPACITY` bytes
+  pub fn new(text: &str) -> Option<Self> {
+    if text.len() > LABEL_CAPACITY {
+      return None;
+    }
+    let mut bytes = [0; LABEL_CAPACITY];
+    bytes[..text.len()].copy_from_slice(text.as_bytes());
+    Some(Self {
+      len: text.len() as u8,
+      bytes,
+    })
+  }
+
+  pub fn as_str(&self) -> &str {
+    // only ever copied from a `str`, whole
+    std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+impl fmt::Display for Label {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl fmt::Debug for Label {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fmt::Debug::fmt(self.as_str(), f)
+  }
+}
+
+#[cfg(feature = "std")]
+impl Serialize for Label {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Label {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct LabelVisitor;
+
+    impl Visitor<'_> for LabelVisitor {
+      type Value = Label;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a string of at most {} bytes", LABEL_CAPACITY)
+      }
+
+      fn visit_str<E: de::Error>(self, text: &str) -> Result<Label, E> {
+        Label::new(text).ok_or_else(|| E::invalid_length(text.len(), &self))
+      }
+    }
+
+    deserializer.deserialize_str(LabelVisitor)
+  }
+}
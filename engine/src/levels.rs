@@ -0,0 +1,328 @@
+//! Storage for the price levels of one side of a book
+//!
+//! Levels are keyed by a priority `P` whose ascending order is price priority, e.g. `Reverse<Price>` for bids.
+
+use crate::types::*;
+#[cfg(feature = "std")]
+use serde_derive::{Deserialize, Serialize};
+use alloc::collections::{btree_map, BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use alloc::vec;
+use std::hash::{Hash, Hasher};
+use std::iter::Rev;
+use std::slice;
+
+/// A queue of order ids in time priority
+pub type Level = VecDeque<OrderId>;
+
+/// Which implementation a book stores its levels in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub enum LevelStoreKind {
+  #[default]
+  Tree,
+  /// A sorted array, cheaper than a tree for books with few levels clustered around the touch
+  Array,
+  /// A linked list, best first, so the touch is always at the head
+  List,
+}
+
+/// Price levels in priority order
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub enum LevelStore<P: Ord> {
+  Tree(#[cfg_attr(feature = "std", serde(with = "crate::types::pairs"))] BTreeMap<P, Level>),
+  /// Sorted worst priority first, so the best level is removed from the end
+  Array(Vec<(P, Level)>),
+  List(LevelList<P>),
+}
+
+impl<P: Ord> Default for LevelStore<P> {
+  fn default() -> Self {
+    Self::new(LevelStoreKind::default())
+  }
+}
+
+impl<P: Ord> LevelStore<P> {
+  pub fn new(kind: LevelStoreKind) -> Self {
+    match kind {
+      LevelStoreKind::Tree => LevelStore::Tree(BTreeMap::new()),
+      LevelStoreKind::Array => LevelStore::Array(Vec::new()),
+      LevelStoreKind::List => LevelStore::List(LevelList::default()),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    match self {
+      LevelStore::Tree(levels) => levels.is_empty(),
+      LevelStore::Array(levels) => levels.is_empty(),
+      LevelStore::List(levels) => levels.head.is_none(),
+    }
+  }
+
+  /// Get the level at a priority
+  pub fn get(&self, key: &P) -> Option<&Level> {
+    match self {
+      LevelStore::Tree(levels) => levels.get(key),
+      LevelStore::Array(levels) => Self::search(levels, key).ok().map(|index| &levels[index].1),
+      LevelStore::List(levels) => levels.find(key).map(|index| &levels.node(index).level),
+    }
+  }
+
+  /// Get the level at a priority mutably
+  pub fn get_mut(&mut self, key: &P) -> Option<&mut Level> {
+    match self {
+      LevelStore::Tree(levels) => levels.get_mut(key),
+      LevelStore::Array(levels) => match Self::search(levels, key) {
+        Ok(index) => Some(&mut levels[index].1),
+        Err(_) => None,
+      },
+      LevelStore::List(levels) => match levels.find(key) {
+        Some(index) => Some(&mut levels.node_mut(index).level),
+        None => None,
+      },
+    }
+  }
+
+  /// Get the level at a priority, inserting an empty one if it does not exist
+  pub fn entry(&mut self, key: P) -> &mut Level {
+    match self {
+      LevelStore::Tree(levels) => levels.entry(key).or_default(),
+      LevelStore::Array(levels) => {
+        let index = match Self::search(levels, &key) {
+          Ok(index) => index,
+          Err(index) => {
+            levels.insert(index, (key, Level::new()));
+            index
+          }
+        };
+        &mut levels[index].1
+      }
+      LevelStore::List(levels) => levels.entry(key),
+    }
+  }
+
+  /// Remove the level at a priority
+  pub fn remove(&mut self, key: &P) {
+    match self {
+      LevelStore::Tree(levels) => {
+        levels.remove(key);
+      }
+      LevelStore::Array(levels) => {
+        if let Ok(index) = Self::search(levels, key) {
+          levels.remove(index);
+        }
+      }
+      LevelStore::List(levels) => levels.remove(key),
+    }
+  }
+
+  /// Iterate over the levels, best first
+  pub fn iter(&self) -> Iter<'_, P> {
+    match self {
+      LevelStore::Tree(levels) => Iter::Tree(levels.iter()),
+      LevelStore::Array(levels) => Iter::Array(levels.iter().rev()),
+      LevelStore::List(levels) => Iter::List(levels, levels.head),
+    }
+  }
+
+  /// Get the best level mutably
+  pub fn first_mut(&mut self) -> Option<(&P, &mut Level)> {
+    match self {
+      LevelStore::Tree(levels) => levels.iter_mut().next(),
+      LevelStore::Array(levels) => levels.last_mut().map(|(key, level)| (&*key, level)),
+      LevelStore::List(levels) => match levels.head {
+        Some(head) => {
+          let node = levels.node_mut(head);
+          Some((&node.key, &mut node.level))
+        }
+        None => None,
+      },
+    }
+  }
+
+  /// Binary search an array store, which is sorted in descending order
+  fn search(levels: &[(P, Level)], key: &P) -> Result<usize, usize> {
+    levels.binary_search_by(|(other, _)| key.cmp(other))
+  }
+}
+
+// stores hold the same state whatever their implementation, so compare and hash them by their contents
+
+impl<P: Ord> PartialEq for LevelStore<P> {
+  fn eq(&self, other: &Self) -> bool {
+    self.iter().eq(other.iter())
+  }
+}
+
+impl<P: Ord> Eq for LevelStore<P> {}
+
+impl<P: Ord + Hash> Hash for LevelStore<P> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    for (key, level) in self.iter() {
+      key.hash(state);
+      level.hash(state);
+    }
+  }
+}
+
+/// Levels linked best first, with removed nodes' slots reused
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct LevelList<P> {
+  nodes: Vec<Option<ListNode<P>>>,
+  head: Option<usize>,
+  free: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+struct ListNode<P> {
+  key: P,
+  level: Level,
+  prev: Option<usize>,
+  next: Option<usize>,
+}
+
+impl<P> Default for LevelList<P> {
+  fn default() -> Self {
+    Self {
+      nodes: vec![],
+      head: None,
+      free: vec![],
+    }
+  }
+}
+
+impl<P: Ord> LevelList<P> {
+  fn node(&self, index: usize) -> &ListNode<P> {
+    self.nodes[index].as_ref().unwrap()
+  }
+
+  fn node_mut(&mut self, index: usize) -> &mut ListNode<P> {
+    self.nodes[index].as_mut().unwrap()
+  }
+
+  /// Walk from the head to the first node not better than `key`
+  ///
+  /// # Returns
+  /// the node before it, and the node itself if there is one
+  fn seek(&self, key: &P) -> (Option<usize>, Option<usize>) {
+    let (mut prev, mut cursor) = (None, self.head);
+    while let Some(index) = cursor {
+      let node = self.node(index);
+      if node.key >= *key {
+        break;
+      }
+      prev = cursor;
+      cursor = node.next;
+    }
+
+    (prev, cursor)
+  }
+
+  fn find(&self, key: &P) -> Option<usize> {
+    match self.seek(key) {
+      (_, Some(index)) if self.node(index).key == *key => Some(index),
+      _ => None,
+    }
+  }
+
+  fn entry(&mut self, key: P) -> &mut Level {
+    let (prev, next) = self.seek(&key);
+    if let Some(index) = next.filter(|&index| self.node(index).key == key) {
+      return &mut self.node_mut(index).level;
+    }
+
+    let node = Some(ListNode {
+      key,
+      level: Level::new(),
+      prev,
+      next,
+    });
+    let index = match self.free.pop() {
+      Some(index) => {
+        self.nodes[index] = node;
+        index
+      }
+      None => {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+      }
+    };
+
+    match prev {
+      Some(prev) => self.node_mut(prev).next = Some(index),
+      None => self.head = Some(index),
+    }
+    if let Some(next) = next {
+      self.node_mut(next).prev = Some(index);
+    }
+
+    &mut self.node_mut(index).level
+  }
+
+  fn remove(&mut self, key: &P) {
+    if let Some(index) = self.find(key) {
+      let node = self.nodes[index].take().unwrap();
+      match node.prev {
+        Some(prev) => self.node_mut(prev).next = node.next,
+        None => self.head = node.next,
+      }
+      if let Some(next) = node.next {
+        self.node_mut(next).prev = node.prev;
+      }
+      self.free.push(index);
+    }
+  }
+}
+
+/// Levels best first
+pub enum Iter<'a, P> {
+  Tree(btree_map::Iter<'a, P, Level>),
+  Array(Rev<slice::Iter<'a, (P, Level)>>),
+  /// The list and the next node to visit
+  List(&'a LevelList<P>, Option<usize>),
+}
+
+impl<'a, P: Ord> Iterator for Iter<'a, P> {
+  type Item = (&'a P, &'a Level);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self {
+      Iter::Tree(iter) => iter.next(),
+      Iter::Array(iter) => iter.next().map(|(key, level)| (key, level)),
+      Iter::List(list, cursor) => cursor.map(|index| {
+        let node = list.nodes[index].as_ref().unwrap();
+        *cursor = node.next;
+        (&node.key, &node.level)
+      }),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::cmp::Reverse;
+
+  #[test]
+  fn stores_hold_the_same_levels() {
+    let kinds = [LevelStoreKind::Tree, LevelStoreKind::Array, LevelStoreKind::List];
+    let mut stores: Vec<LevelStore<Reverse<Price>>> = kinds.iter().map(|&kind| LevelStore::new(kind)).collect();
+    for store in stores.iter_mut() {
+      for (i, &price) in [100u32, 98, 103, 100, 97].iter().enumerate() {
+        store.entry(Reverse(price.into())).push_back(i.into());
+      }
+      store.remove(&Reverse(98.into()));
+      store.remove(&Reverse(103.into()));
+      store.entry(Reverse(99.into())).push_back(5.into());
+    }
+
+    let levels: Vec<_> = stores[0].iter().map(|(key, level)| (key.0, level.len())).collect();
+    assert_eq!(levels, vec![(100.into(), 2), (99.into(), 1), (97.into(), 1)]);
+    assert_eq!(stores[0], stores[1]);
+    assert_eq!(stores[0], stores[2]);
+    assert_eq!(stores[2].first_mut().map(|(key, _)| key.0), Some(100.into()));
+  }
+}
@@ -1,8 +1,112 @@
+#![recursion_limit = "256"]
 #![feature(test)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// without `std`, only the book is built, using `alloc` for its collections
+extern crate alloc;
+// derives expand to `::std` paths, and `core` has everything the book's derives need
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+
+#[cfg(feature = "std")]
+mod activity;
+#[cfg(feature = "std")]
+mod auction;
+#[cfg(feature = "std")]
+mod accounts;
+#[cfg(feature = "std")]
+mod audit;
 mod book;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod collateral;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+mod dark;
 
+#[cfg(feature = "std")]
 mod engine;
+#[cfg(feature = "std")]
+mod eod;
+#[cfg(feature = "std")]
+mod events;
+#[cfg(feature = "std")]
+mod feed;
+#[cfg(feature = "std")]
+mod fees;
+#[cfg(feature = "std")]
+mod hash;
+#[cfg(feature = "std")]
+mod index;
+#[cfg(feature = "std")]
+mod journal;
+mod label;
+mod levels;
+#[cfg(feature = "std")]
+mod liquidation;
+#[cfg(feature = "std")]
+mod order_to_trade;
+#[cfg(feature = "std")]
+mod replication;
+#[cfg(feature = "std")]
+mod risk;
+#[cfg(feature = "std")]
+mod router;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+mod shadow;
+#[cfg(feature = "std")]
+mod surveillance;
+#[cfg(feature = "std")]
+mod triggers;
 mod types;
 
+#[cfg(feature = "std")]
+pub use accounts::*;
+#[cfg(feature = "std")]
+pub use activity::*;
+#[cfg(feature = "std")]
+pub use audit::*;
+pub use book::{Contra, Executed, OrderBook, TopOfBook, UnknownOrder, Violation};
+#[cfg(feature = "std")]
+pub use clock::*;
+#[cfg(feature = "std")]
+pub use collateral::*;
+#[cfg(feature = "std")]
+pub use config::*;
+#[cfg(feature = "std")]
 pub use engine::*;
+#[cfg(feature = "std")]
+pub use eod::*;
+#[cfg(feature = "std")]
+pub use events::*;
+#[cfg(feature = "std")]
+pub use feed::*;
+#[cfg(feature = "std")]
+pub use fees::*;
+#[cfg(feature = "std")]
+pub use index::*;
+#[cfg(feature = "std")]
+pub use journal::*;
+pub use label::*;
+pub use levels::LevelStoreKind;
+#[cfg(feature = "std")]
+pub use liquidation::*;
+#[cfg(feature = "std")]
+pub use order_to_trade::*;
+#[cfg(feature = "std")]
+pub use replication::*;
+#[cfg(feature = "std")]
+pub use risk::*;
+#[cfg(feature = "std")]
+pub use router::*;
+#[cfg(feature = "std")]
+pub use shadow::*;
+#[cfg(feature = "std")]
+pub use surveillance::*;
+#[cfg(feature = "std")]
+pub use triggers::*;
 pub use types::*;
@@ -0,0 +1,55 @@
+//! Liquidation
+//!
+//! An account is under-margined once its buying power, its balance and its holdings valued as collateral, no longer
+//! covers the maintenance margin share of what its resting bids would cost, e.g. after the marks of its holdings fall.
+//! It is then liquidated: moved to `AccountState::LiquidationOnly`, its resting orders cancelled, and an ask placed for
+//! each of its holdings, tagged `LIQUIDATION_TAG`. Liquidation asks are priced at the band below the symbol's reference
+//! price, so they trade with the bids within it and rest at its edge rather than sweeping a thin book.
+//!
+//! Accounts are checked after every command, tick and change of rules, in id order, so replays liquidate the same
+//! accounts with the same orders. An account is liquidated once, until an admin moves it out of
+//! `AccountState::LiquidationOnly`.
+
+use crate::clock::Timestamp;
+use crate::collateral::FULL_HAIRCUT;
+use crate::engine::Id;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+
+/// The tag of every liquidation ask
+pub const LIQUIDATION_TAG: &str = "liquidation";
+
+/// When accounts are liquidated and how their holdings are sold
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiquidationRules {
+  /// The share of the cost of an account's resting bids its buying power must cover, in basis points, e.g.
+  /// `FULL_HAIRCUT` for all of it
+  pub maintenance_margin: u32,
+  /// How far below a symbol's reference price liquidation asks are priced
+  pub band: Price,
+}
+
+impl LiquidationRules {
+  /// Returns true if `buying_power` does not cover the maintenance margin of resting bids costing `reserved`
+  pub fn is_breached(&self, buying_power: u64, reserved: u64) -> bool {
+    u128::from(buying_power) * u128::from(FULL_HAIRCUT) < u128::from(reserved) * u128::from(self.maintenance_margin)
+  }
+
+  /// Get the price a liquidation ask is placed at, given the symbol's reference price
+  pub fn ask_price(&self, reference: Price) -> Price {
+    u32::from(reference).saturating_sub(u32::from(self.band)).into()
+  }
+}
+
+/// An account being liquidated
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Liquidation {
+  pub account: AccountId,
+  pub started_at: Timestamp,
+  /// The account's buying power when it was found under-margined
+  pub buying_power: u64,
+  /// The cost of the resting bids it had then, which were cancelled
+  pub reserved: u64,
+  /// The liquidation asks placed for its holdings, by symbol
+  pub orders: Vec<Id>,
+}
@@ -0,0 +1,157 @@
+//! Order-to-trade ratio monitoring
+
+use crate::clock::Timestamp;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// What happens to an account as its order-to-trade ratio climbs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Default)]
+pub enum Consequence {
+  #[default]
+  None,
+  Warning,
+  /// Each order placed is charged `excess_order_fee`
+  Fee,
+  /// New orders are rejected
+  Throttle,
+}
+
+/// Thresholds for order-to-trade ratios over a rolling window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderToTradeRules {
+  pub window: Duration,
+  /// Accounts sending fewer order messages than this within the window are never penalized
+  pub min_orders: usize,
+  pub warning_ratio: f64,
+  pub fee_ratio: f64,
+  pub throttle_ratio: f64,
+  pub excess_order_fee: Price,
+  /// Apply fees and throttling, rather than only raising alerts
+  pub enforce: bool,
+}
+
+impl Default for OrderToTradeRules {
+  fn default() -> Self {
+    Self {
+      window: Duration::from_secs(60),
+      min_orders: 100,
+      warning_ratio: 20.0,
+      fee_ratio: 50.0,
+      throttle_ratio: 100.0,
+      excess_order_fee: 1.into(),
+      enforce: false,
+    }
+  }
+}
+
+/// An account's order-to-trade ratio over the current window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OrderToTradeStatus {
+  pub orders: usize,
+  pub trades: usize,
+  pub ratio: f64,
+  pub consequence: Consequence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Event {
+  Order,
+  Trade,
+}
+
+/// Tracks order-to-trade ratios per account
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderToTradeMonitor {
+  pub rules: OrderToTradeRules,
+  #[serde(with = "crate::types::pairs")]
+  events: HashMap<AccountId, VecDeque<(Timestamp, Event)>>,
+  #[serde(with = "crate::types::pairs")]
+  consequences: HashMap<AccountId, Consequence>,
+}
+
+impl OrderToTradeMonitor {
+  /// Record an order message (place or cancel) from an account
+  ///
+  /// # Returns
+  /// The account's new consequence, if it escalated
+  pub fn on_order(&mut self, at: Timestamp, account: AccountId) -> Option<Consequence> {
+    let previous = self.consequences.get(&account).copied().unwrap_or_default();
+    self.record(at, account, Event::Order);
+    let current = self.consequences.get(&account).copied().unwrap_or_default();
+
+    if current > previous {
+      Some(current)
+    } else {
+      None
+    }
+  }
+
+  /// Record a trade by an account
+  pub fn on_trade(&mut self, at: Timestamp, account: AccountId) {
+    self.record(at, account, Event::Trade);
+  }
+
+  /// Get the consequence applied to an account at `at`, once the events before its window are dropped
+  ///
+  /// The consequence is worked out afresh rather than kept from the last event, so a throttle lifts as the window
+  /// rolls past the orders that caused it, even though the throttled orders themselves are never recorded.
+  pub fn consequence(&mut self, at: Timestamp, account: AccountId) -> Consequence {
+    self.prune(at, account);
+    let consequence = self.status(at, account).consequence;
+    self.consequences.insert(account, consequence);
+    consequence
+  }
+
+  /// Get an account's ratio over the window ending at `at`
+  pub fn status(&self, at: Timestamp, account: AccountId) -> OrderToTradeStatus {
+    let window = self.rules.window;
+    let in_window = |event: Event| {
+      self
+        .events
+        .get(&account)
+        .map(|events| events.iter().filter(|&&(time, other)| time + window >= at && other == event).count())
+        .unwrap_or_default()
+    };
+    let (orders, trades) = (in_window(Event::Order), in_window(Event::Trade));
+    let ratio = orders as f64 / trades.max(1) as f64;
+
+    let consequence = if orders < self.rules.min_orders {
+      Consequence::None
+    } else if ratio >= self.rules.throttle_ratio {
+      Consequence::Throttle
+    } else if ratio >= self.rules.fee_ratio {
+      Consequence::Fee
+    } else if ratio >= self.rules.warning_ratio {
+      Consequence::Warning
+    } else {
+      Consequence::None
+    };
+
+    OrderToTradeStatus {
+      orders,
+      trades,
+      ratio,
+      consequence,
+    }
+  }
+
+  fn record(&mut self, at: Timestamp, account: AccountId, event: Event) {
+    self.prune(at, account);
+    self.events.entry(account).or_default().push_back((at, event));
+
+    let consequence = self.status(at, account).consequence;
+    self.consequences.insert(account, consequence);
+  }
+
+  /// Drop an account's events from before the window ending at `at`
+  fn prune(&mut self, at: Timestamp, account: AccountId) {
+    let window = self.rules.window;
+    if let Some(events) = self.events.get_mut(&account) {
+      while events.front().is_some_and(|&(time, _)| time + window < at) {
+        events.pop_front();
+      }
+    }
+  }
+}
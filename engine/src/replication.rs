@@ -0,0 +1,208 @@
+//! Warm standby replication
+//!
+//! A primary streams its journal to a `Standby`, which applies each entry as it arrives with its clock set to when the
+//! primary received it. The primary's periodic state hashes are checked as they are applied, so a standby that has
+//! diverged fails instead of taking over. Once the primary is lost, the standby is promoted to a live engine.
+//!
+//! A standby joining late starts from a `Snapshot` of the primary instead of replaying its whole journal, and only the
+//! entries after the snapshot are applied.
+
+use crate::clock::{Clock, ManualClock};
+use crate::engine::{Error, EventId, MatchEngine};
+use crate::journal::{JournalEntry, JournalEvent};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A primary's state partway through its journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+  /// The sequence of the last entry included in the state
+  pub last: Option<EventId>,
+  /// The hash of the state when it was taken
+  pub state_hash: u64,
+  pub engine: MatchEngine,
+}
+
+impl Snapshot {
+  /// Take a snapshot of `engine`, which has applied every entry up to `last`
+  pub fn new(engine: &MatchEngine, last: Option<EventId>) -> Self {
+    Self {
+      last,
+      state_hash: engine.state_hash(),
+      engine: engine.clone(),
+    }
+  }
+}
+
+/// An engine kept in step with a primary by applying its journal
+#[derive(Debug, Default)]
+pub struct Standby {
+  engine: MatchEngine,
+  clock: Arc<ManualClock>,
+  /// The sequence of the last entry applied
+  last: Option<EventId>,
+  /// The last state hash the primary journaled, once checked
+  verified: Option<u64>,
+}
+
+impl Standby {
+  pub fn new() -> Self {
+    let clock = Arc::new(ManualClock::default());
+    Self {
+      engine: MatchEngine::with_clock(clock.clone()),
+      clock,
+      last: None,
+      verified: None,
+    }
+  }
+
+  /// Start from a snapshot of the primary, to apply the entries after it
+  ///
+  /// # Returns
+  /// an error if the restored state does not match the snapshot's hash
+  pub fn restore(snapshot: Snapshot) -> Result<Self, Error> {
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = snapshot.engine;
+    engine.set_clock(clock.clone());
+    let actual = engine.state_hash();
+    if actual != snapshot.state_hash {
+      return Err(Error::StateHashMismatch {
+        expected: snapshot.state_hash,
+        actual,
+      });
+    }
+
+    let mut standby = Self {
+      engine,
+      clock,
+      last: snapshot.last,
+      verified: Some(actual),
+    };
+    standby.drain_outputs();
+    Ok(standby)
+  }
+
+  /// Apply the primary's next journal entry
+  ///
+  /// # Returns
+  /// an error if the entry is not after the last one applied, or the standby's state does not match a journaled hash
+  pub fn apply(&mut self, entry: JournalEntry) -> Result<(), Error> {
+    if let Some(last) = self.last {
+      if entry.sequence <= last {
+        return Err(Error::JournalOutOfOrder {
+          last,
+          sequence: entry.sequence,
+        });
+      }
+    }
+
+    self.clock.set(entry.received_at);
+    let hash = match entry.event {
+      JournalEvent::StateHash(hash) => Some(hash),
+      _ => None,
+    };
+    self.engine.apply(entry.event)?;
+    self.verified = hash.or(self.verified);
+    self.last = Some(entry.sequence);
+    self.drain_outputs();
+    Ok(())
+  }
+
+  /// Discard the standby's own outputs, which the primary already published
+  fn drain_outputs(&mut self) {
+    self.engine.drain_journal();
+    self.engine.drain_audit_trail();
+    self.engine.drain_market_data();
+    self.engine.drain_alerts();
+  }
+
+  /// Get the sequence of the last entry applied
+  pub fn last(&self) -> Option<EventId> {
+    self.last
+  }
+
+  /// Get the last state hash checked against the primary's
+  pub fn verified(&self) -> Option<u64> {
+    self.verified
+  }
+
+  pub fn engine(&self) -> &MatchEngine {
+    &self.engine
+  }
+
+  /// Take over from the primary, reading time from `clock`
+  pub fn promote(self, clock: Arc<dyn Clock>) -> MatchEngine {
+    let mut engine = self.engine;
+    engine.set_clock(clock);
+    engine
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::engine::*;
+  use crate::types::*;
+  use crate::Timestamp;
+
+  #[test]
+  fn standbys_follow_the_primary_and_take_over() {
+    let clock = Arc::new(ManualClock::default());
+    let mut primary = MatchEngine::with_clock(clock.clone());
+    let symbol: Symbol = ['A', 'D', 'B', 'E'].into();
+    primary.insert_new_symbol(symbol).unwrap();
+    let account_id = primary.create_account();
+    for &(side, price) in &[(Side::Ask, 10), (Side::Bid, 11)] {
+      clock.set(Timestamp::from(price as u64));
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), 5.into()));
+      primary.try_process(Command { account_id, kind }).unwrap();
+    }
+    let hash = primary.checkpoint();
+    let journal = primary.drain_journal();
+
+    let mut standby = Standby::new();
+    for entry in journal.iter().cloned() {
+      standby.apply(entry).unwrap();
+    }
+    assert_eq!(standby.verified(), Some(hash));
+    let last = standby.last().unwrap();
+    let repeated = journal[0].clone();
+    let sequence = repeated.sequence;
+    assert_eq!(standby.apply(repeated), Err(Error::JournalOutOfOrder { last, sequence }));
+
+    let mut promoted = standby.promote(clock);
+    assert_eq!(promoted.state_hash(), primary.state_hash());
+    let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(9.into(), 5.into()));
+    assert_eq!(primary.try_process(Command { account_id, kind }), promoted.try_process(Command { account_id, kind }));
+  }
+
+  #[test]
+  fn standbys_restored_from_a_snapshot_apply_only_the_suffix() {
+    let clock = Arc::new(ManualClock::default());
+    let mut primary = MatchEngine::with_clock(clock.clone());
+    let symbol: Symbol = ['A', 'D', 'B', 'E'].into();
+    primary.insert_new_symbol(symbol).unwrap();
+    let account_id = primary.create_account();
+    let kind = CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(10.into(), 5.into()));
+    primary.try_process(Command { account_id, kind }).unwrap();
+    let last = primary.drain_journal().last().map(|entry| entry.sequence);
+
+    let json = serde_json::to_string(&Snapshot::new(&primary, last)).unwrap();
+    let mut snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+    let mut standby = Standby::restore(snapshot.clone()).unwrap();
+    assert_eq!(standby.last(), last);
+
+    let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(11.into(), 3.into()));
+    primary.try_process(Command { account_id, kind }).unwrap();
+    let hash = primary.checkpoint();
+    for entry in primary.drain_journal() {
+      standby.apply(entry).unwrap();
+    }
+    assert_eq!(standby.verified(), Some(hash));
+
+    snapshot.state_hash += 1;
+    let actual = snapshot.state_hash - 1;
+    let expected = snapshot.state_hash;
+    assert_eq!(Standby::restore(snapshot).unwrap_err(), Error::StateHashMismatch { expected, actual });
+  }
+}
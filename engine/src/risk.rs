@@ -0,0 +1,83 @@
+//! Pre-trade risk checks
+//!
+//! Order entry commands pass a series of checks before they reach a book. To unwind an incident, an admin can have its
+//! session bypass some of them with `CommandKind::SetRiskBypass`, giving the reason. Commands an admin account sends
+//! on that session then pass those checks even when they fail, and each check passed that way is audited as
+//! `AuditEvent::Bypass` with the check and the reason, so nothing is skipped without a record.
+
+use crate::label::Label;
+use bitflags::bitflags;
+use serde_derive::{Deserialize, Serialize};
+
+/// A pre-trade check an admin session may bypass
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RiskCheck {
+  /// Whether the account's state allows the command
+  AccountState,
+  /// Whether the engine is rejecting every order entry command
+  RejectAll,
+  /// Whether the account may enter orders on the symbol
+  SymbolPermission,
+  /// Whether the quantity is a multiple of the symbol's lot size
+  LotSize,
+  /// Whether the account's order-to-trade ratio is throttled
+  OrderToTrade,
+  /// Whether the account can afford a bid
+  BuyingPower,
+}
+
+impl RiskCheck {
+  /// The check's code in the flat audit export format
+  pub fn code(self) -> &'static str {
+    use RiskCheck::*;
+    match self {
+      AccountState => "ACCOUNT_STATE",
+      RejectAll => "REJECT_ALL",
+      SymbolPermission => "SYMBOL_PERMISSION",
+      LotSize => "LOT_SIZE",
+      OrderToTrade => "ORDER_TO_TRADE",
+      BuyingPower => "BUYING_POWER",
+    }
+  }
+}
+
+bitflags! {
+  /// A set of pre-trade checks
+  #[derive(Default, Serialize, Deserialize)]
+  pub struct RiskChecks: u32 {
+    const ACCOUNT_STATE = 0b00_0001;
+    const REJECT_ALL = 0b00_0010;
+    const SYMBOL_PERMISSION = 0b00_0100;
+    const LOT_SIZE = 0b00_1000;
+    const ORDER_TO_TRADE = 0b01_0000;
+    const BUYING_POWER = 0b10_0000;
+  }
+}
+
+impl From<RiskCheck> for RiskChecks {
+  fn from(check: RiskCheck) -> Self {
+    match check {
+      RiskCheck::AccountState => RiskChecks::ACCOUNT_STATE,
+      RiskCheck::RejectAll => RiskChecks::REJECT_ALL,
+      RiskCheck::SymbolPermission => RiskChecks::SYMBOL_PERMISSION,
+      RiskCheck::LotSize => RiskChecks::LOT_SIZE,
+      RiskCheck::OrderToTrade => RiskChecks::ORDER_TO_TRADE,
+      RiskCheck::BuyingPower => RiskChecks::BUYING_POWER,
+    }
+  }
+}
+
+/// The checks an admin session bypasses and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RiskBypass {
+  pub checks: RiskChecks,
+  /// Why, recorded on every check bypassed; must not be empty
+  pub reason: Label,
+}
+
+impl RiskBypass {
+  /// Returns true if `check` is bypassed
+  pub fn bypasses(&self, check: RiskCheck) -> bool {
+    self.checks.contains(check.into())
+  }
+}
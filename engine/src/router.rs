@@ -0,0 +1,335 @@
+//! Order routing across venues
+//!
+//! A `Venue` is anywhere an order can be sent: the local engine through `LoopbackVenue`, or a remote market behind an
+//! adapter. `MockVenue` stands in for a remote market in tests, with quotes and fills set by hand.
+
+use crate::audit::AuditEvent;
+use crate::engine::*;
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+
+/// A fill of an order submitted to a venue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VenueExecution {
+  /// The order's id at the venue
+  pub order: Id,
+  pub price: Price,
+  pub quantity: Quantity,
+}
+
+/// A market orders can be routed to
+pub trait Venue {
+  /// A name to tell the venue apart by
+  fn name(&self) -> &str;
+
+  /// Get the best price resting on a side
+  ///
+  /// # Returns
+  /// `None` if nothing rests on the side
+  fn quote(&self, symbol: Symbol, side: Side) -> Result<Option<Price>, Error>;
+
+  /// Submit an order on behalf of an account
+  ///
+  /// # Returns
+  /// the order's id at the venue
+  fn submit(&mut self, account: AccountId, side: Side, symbol: Symbol, order: Order) -> Result<Id, Error>;
+
+  /// Cancel an order submitted to the venue
+  ///
+  /// # Returns
+  /// false if the order had already filled or been cancelled
+  fn cancel(&mut self, account: AccountId, id: Id) -> Result<bool, Error>;
+
+  /// Take the fills of submitted orders since the last call, oldest first
+  fn drain_executions(&mut self) -> Vec<VenueExecution>;
+}
+
+/// The local engine as a venue
+#[derive(Debug, Default)]
+pub struct LoopbackVenue {
+  engine: MatchEngine,
+  submitted: HashSet<Id>,
+}
+
+impl LoopbackVenue {
+  /// Route to `engine`
+  ///
+  /// The venue reads fills from the engine's audit trail, so nothing else should drain it.
+  pub fn new(engine: MatchEngine) -> Self {
+    Self {
+      engine,
+      submitted: HashSet::new(),
+    }
+  }
+
+  pub fn engine(&self) -> &MatchEngine {
+    &self.engine
+  }
+
+  pub fn engine_mut(&mut self) -> &mut MatchEngine {
+    &mut self.engine
+  }
+}
+
+impl Venue for LoopbackVenue {
+  fn name(&self) -> &str {
+    "loopback"
+  }
+
+  fn quote(&self, symbol: Symbol, side: Side) -> Result<Option<Price>, Error> {
+    Ok(self.engine.depth(symbol, side)?.first().map(|&(price, _)| price))
+  }
+
+  fn submit(&mut self, account: AccountId, side: Side, symbol: Symbol, order: Order) -> Result<Id, Error> {
+    let command = Command {
+      account_id: account,
+      kind: CommandKind::PlaceOrder(side, symbol, order),
+    };
+    match self.engine.try_process(command)? {
+      Success::PlaceOrder(Placement { id, .. }) => {
+        self.submitted.insert(id);
+        Ok(id)
+      }
+      success => unreachable!("placing an order succeeded with {:?}", success),
+    }
+  }
+
+  fn cancel(&mut self, account: AccountId, id: Id) -> Result<bool, Error> {
+    let command = Command {
+      account_id: account,
+      kind: CommandKind::CancelOrder(id),
+    };
+    match self.engine.try_process(command)? {
+      Success::CancelOrder(is_cancelled) => Ok(is_cancelled),
+      // the order is cancelled once its minimum quote life ends, unless it fills first
+      Success::CancelPending(_) => Ok(true),
+      success => unreachable!("cancelling an order succeeded with {:?}", success),
+    }
+  }
+
+  fn drain_executions(&mut self) -> Vec<VenueExecution> {
+    let submitted = &self.submitted;
+    self
+      .engine
+      .drain_audit_trail()
+      .into_iter()
+      .filter(|record| record.event == AuditEvent::Execute)
+      .filter_map(|record| match (record.order, record.price, record.quantity) {
+        (Some(order), Some(price), Some(quantity)) if submitted.contains(&order) => Some(VenueExecution {
+          order,
+          price,
+          quantity,
+        }),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// A remote venue whose quotes and fills are set by hand
+#[derive(Debug)]
+pub struct MockVenue {
+  name: String,
+  symbols: HashSet<Symbol>,
+  quotes: HashMap<(Symbol, Side), Price>,
+  orders: HashMap<Id, Order>,
+  next_order_id: Id,
+  executions: Vec<VenueExecution>,
+}
+
+impl MockVenue {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      symbols: HashSet::new(),
+      quotes: HashMap::new(),
+      orders: HashMap::new(),
+      next_order_id: Id::default(),
+      executions: vec![],
+    }
+  }
+
+  /// List a symbol with the best price on each side
+  pub fn with_quote(mut self, symbol: Symbol, bid: Option<Price>, ask: Option<Price>) -> Self {
+    self.symbols.insert(symbol);
+    self.quotes.remove(&(symbol, Side::Bid));
+    self.quotes.remove(&(symbol, Side::Ask));
+    self.quotes.extend(bid.map(|price| ((symbol, Side::Bid), price)));
+    self.quotes.extend(ask.map(|price| ((symbol, Side::Ask), price)));
+    self
+  }
+
+  /// Get an order submitted to the venue that has not filled or been cancelled
+  pub fn order(&self, id: Id) -> Option<&Order> {
+    self.orders.get(&id)
+  }
+
+  /// Fill part of a submitted order at its limit price
+  ///
+  /// # Returns
+  /// false if the order is not open
+  pub fn fill(&mut self, id: Id, quantity: Quantity) -> bool {
+    let order = match self.orders.get_mut(&id) {
+      Some(order) => order,
+      None => return false,
+    };
+
+    order.filled += quantity;
+    self.executions.push(VenueExecution {
+      order: id,
+      price: order.price,
+      quantity,
+    });
+    if order.is_filled() {
+      self.orders.remove(&id);
+    }
+    true
+  }
+
+  fn try_listed(&self, symbol: Symbol) -> Result<(), Error> {
+    if self.symbols.contains(&symbol) {
+      Ok(())
+    } else {
+      Err(Error::SymbolDoesNotExist { symbol })
+    }
+  }
+}
+
+impl Venue for MockVenue {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn quote(&self, symbol: Symbol, side: Side) -> Result<Option<Price>, Error> {
+    self.try_listed(symbol)?;
+    Ok(self.quotes.get(&(symbol, side)).cloned())
+  }
+
+  fn submit(&mut self, _account: AccountId, _side: Side, symbol: Symbol, order: Order) -> Result<Id, Error> {
+    self.try_listed(symbol)?;
+    let id = self.next_order_id;
+    self.next_order_id += 1.into();
+    self.orders.insert(id, order);
+    Ok(id)
+  }
+
+  fn cancel(&mut self, _account: AccountId, id: Id) -> Result<bool, Error> {
+    if usize::from(id) >= usize::from(self.next_order_id) {
+      return Err(Error::IdDoesNotExist { id });
+    }
+    Ok(self.orders.remove(&id).is_some())
+  }
+
+  fn drain_executions(&mut self) -> Vec<VenueExecution> {
+    self.executions.drain(..).collect()
+  }
+}
+
+/// Venues to route orders between, by their index
+#[derive(Default)]
+pub struct Router {
+  venues: Vec<Box<dyn Venue>>,
+}
+
+impl Router {
+  /// Add a venue to route to
+  ///
+  /// # Returns
+  /// the venue's index
+  pub fn add_venue<V: Venue + 'static>(&mut self, venue: V) -> usize {
+    self.venues.push(Box::new(venue));
+    self.venues.len() - 1
+  }
+
+  pub fn venue(&self, index: usize) -> Option<&dyn Venue> {
+    self.venues.get(index).map(|venue| venue.as_ref())
+  }
+
+  pub fn venue_mut(&mut self, index: usize) -> Option<&mut (dyn Venue + 'static)> {
+    self.venues.get_mut(index).map(|venue| venue.as_mut())
+  }
+
+  /// Submit an order to the venue with the best opposite price, preferring the first added on a tie
+  ///
+  /// Venues that do not list the symbol are skipped, and venues with nothing on the opposite side are used only if no
+  /// venue has.
+  ///
+  /// # Returns
+  /// the chosen venue's index and the order's id there
+  pub fn route(&mut self, account: AccountId, side: Side, symbol: Symbol, order: Order) -> Result<(usize, Id), Error> {
+    let mut best: Option<(usize, Option<Price>)> = None;
+    for (index, venue) in self.venues.iter().enumerate() {
+      let price = match venue.quote(symbol, side.opposite()) {
+        Ok(price) => price,
+        Err(_) => continue,
+      };
+
+      let is_better = match (best, price) {
+        (None, _) => true,
+        (Some((_, None)), Some(_)) => true,
+        (Some((_, Some(best))), Some(price)) => match side {
+          Side::Bid => price < best,
+          Side::Ask => price > best,
+        },
+        _ => false,
+      };
+      if is_better {
+        best = Some((index, price));
+      }
+    }
+
+    let index = match best {
+      Some((index, _)) => index,
+      None => return Err(Error::SymbolDoesNotExist { symbol }),
+    };
+    let id = self.venues[index].submit(account, side, symbol, order)?;
+    Ok((index, id))
+  }
+
+  /// Take the fills of every venue since the last call, with the index of the venue each came from
+  pub fn drain_executions(&mut self) -> Vec<(usize, VenueExecution)> {
+    self
+      .venues
+      .iter_mut()
+      .enumerate()
+      .flat_map(|(index, venue)| venue.drain_executions().into_iter().map(move |execution| (index, execution)))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn orders_route_to_the_best_opposite_price() {
+    let symbol: Symbol = ['A', 'D', 'B', 'E'].into();
+    let mut engine = MatchEngine::default();
+    engine.insert_new_symbol(symbol).unwrap();
+    let (maker, taker) = (engine.create_account(), engine.create_account());
+    let kind = CommandKind::PlaceOrder(Side::Ask, symbol, Order::new(11.into(), 5.into()));
+    engine.try_process(Command { account_id: maker, kind }).unwrap();
+
+    let mut router = Router::default();
+    let local = router.add_venue(LoopbackVenue::new(engine));
+    let remote = router.add_venue(MockVenue::new("remote").with_quote(symbol, Some(9.into()), Some(12.into())));
+    router.add_venue(MockVenue::new("unlisted"));
+
+    let (venue, bid) = router.route(taker, Side::Bid, symbol, Order::new(11.into(), 5.into())).unwrap();
+    assert_eq!(venue, local);
+    let (venue, ask) = router.route(taker, Side::Ask, symbol, Order::new(9.into(), 5.into())).unwrap();
+    assert_eq!(venue, remote);
+
+    let other: Symbol = ['M', 'S', 'F', 'T'].into();
+    let order = Order::new(9.into(), 5.into());
+    assert_eq!(router.route(taker, Side::Ask, other, order), Err(Error::SymbolDoesNotExist { symbol: other }));
+
+    assert_eq!(router.drain_executions(), vec![(local, VenueExecution {
+      order: bid,
+      price: 11.into(),
+      quantity: 5.into(),
+    })]);
+    assert_eq!(router.venue_mut(remote).unwrap().cancel(taker, ask), Ok(true));
+    assert_eq!(router.venue(remote).unwrap().name(), "remote");
+  }
+}
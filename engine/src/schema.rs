@@ -0,0 +1,920 @@
+//! JSON Schema for the wire messages
+//!
+//! Schemas are written out by hand to match the encodings serde derives, and are checked against the golden-file
+//! encodings of every message in `tests/golden`.
+
+use crate::label::LABEL_CAPACITY;
+use crate::types::MAX_QUANTITY_PRECISION;
+use serde_json::{json, Map, Value};
+
+/// The messages a schema can be generated for
+pub const MESSAGES: [&str; 6] = ["Command", "Success", "Error", "Reply", "MarketData", "Push"];
+
+/// Generate a JSON Schema (draft 7) document for one of `MESSAGES`
+///
+/// # Returns
+/// `None` if `message` is not one of `MESSAGES`
+pub fn schema(message: &str) -> Option<Value> {
+  if !MESSAGES.contains(&message) {
+    return None;
+  }
+
+  Some(json!({
+    "$schema": "http://json-schema.org/draft-07/schema#",
+    "title": message,
+    "$ref": format!("#/definitions/{}", message),
+    "definitions": definitions(),
+  }))
+}
+
+fn reference(name: &str) -> Value {
+  json!({ "$ref": format!("#/definitions/{}", name) })
+}
+
+fn unsigned(maximum: u64) -> Value {
+  json!({ "type": "integer", "minimum": 0, "maximum": maximum })
+}
+
+fn nullable(schema: Value) -> Value {
+  json!({ "oneOf": [schema, { "type": "null" }] })
+}
+
+/// A fixed-length array, as serde encodes tuples
+fn tuple(items: Vec<Value>) -> Value {
+  json!({ "type": "array", "items": items, "minItems": items.len(), "maxItems": items.len() })
+}
+
+/// An object with the given fields, of which `required` must be present
+fn object(fields: &[(&str, Value)], required: &[&str]) -> Value {
+  let properties: Map<String, Value> = fields
+    .iter()
+    .map(|(name, schema)| (name.to_string(), schema.clone()))
+    .collect();
+  json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// An externally tagged enum variant with a payload, encoded as `{ "Variant": payload }`
+fn variant(name: &str, payload: Value) -> Value {
+  json!({
+    "type": "object",
+    "properties": { name: payload },
+    "required": [name],
+    "additionalProperties": false,
+  })
+}
+
+fn definitions() -> Value {
+  let symbol = json!({
+    "type": "array",
+    "items": { "type": "string", "minLength": 1, "maxLength": 1 },
+    "minItems": 4,
+    "maxItems": 4,
+  });
+  let uncross = object(
+    &[
+      ("symbol", reference("Symbol")),
+      ("price", reference("Price")),
+      ("quantity", reference("Quantity")),
+    ],
+    &["symbol", "price", "quantity"],
+  );
+  let trade = object(
+    &[
+      ("symbol", reference("Symbol")),
+      ("price", reference("Price")),
+      ("quantity", reference("Quantity")),
+      ("trade", reference("TradeId")),
+    ],
+    &["symbol", "price", "quantity", "trade"],
+  );
+  let level = tuple(vec![reference("Price"), reference("Quantity")]);
+  let precision = unsigned(MAX_QUANTITY_PRECISION.into());
+
+  json!({
+    "Symbol": symbol,
+    "Price": unsigned(u32::MAX.into()),
+    "Quantity": unsigned(u64::MAX),
+    "Id": unsigned(u64::MAX),
+    "AccountId": unsigned(u64::MAX),
+    "TradeId": unsigned(u64::MAX),
+    "Liquidity": { "enum": ["Maker", "Taker", "Crossed"] },
+    "FirmId": unsigned(u64::MAX),
+    "Timestamp": unsigned(u64::MAX),
+    "Side": { "enum": ["Bid", "Ask"] },
+    "Date": object(
+      &[
+        ("year", unsigned(u32::MAX.into())),
+        ("month", unsigned(12)),
+        ("day", unsigned(31)),
+      ],
+      &["year", "month", "day"],
+    ),
+    "OrderFlags": object(&[("bits", unsigned(u32::MAX.into()))], &["bits"]),
+    "Order": object(
+      &[
+        ("price", reference("Price")),
+        ("quantity", reference("Quantity")),
+        ("filled", reference("Quantity")),
+        ("is_cancelled", json!({ "type": "boolean" })),
+        ("flags", reference("OrderFlags")),
+        ("minimum_quantity", reference("Quantity")),
+        ("tag", reference("Label")),
+      ],
+      &["price", "quantity", "filled", "is_cancelled"],
+    ),
+    "Command": object(
+      &[("account_id", reference("AccountId")), ("kind", reference("CommandKind"))],
+      &["account_id", "kind"],
+    ),
+    "CommandKind": { "oneOf": [
+      variant("CancelOrder", reference("Id")),
+      variant("PlaceOrder", tuple(vec![reference("Side"), reference("Symbol"), reference("Order")])),
+      variant("GetOrder", reference("Id")),
+      variant("ExecuteOrder", reference("Id")),
+      variant("GetQuote", tuple(vec![reference("Symbol"), reference("Side")])),
+      variant("GetAccount", reference("AccountId")),
+      variant("GetAccountSummary", reference("AccountId")),
+      variant("GetOpenOrders", tuple(vec![reference("AccountId"), reference("Page")])),
+      variant("GetLevel", tuple(vec![reference("Symbol"), reference("Side"), reference("Price")])),
+      variant(
+        "UpdateOrder",
+        tuple(vec![reference("Id"), nullable(reference("Price")), nullable(reference("Quantity"))]),
+      ),
+      variant("SuspendOrder", reference("Id")),
+      variant("ResumeOrder", reference("Id")),
+      variant("SubscribeExecutions", reference("AccountId")),
+      variant("Subscribe", tuple(vec![reference("Symbol"), reference("Feed")])),
+      variant("Unsubscribe", tuple(vec![reference("Symbol"), reference("Feed")])),
+      variant("Conflate", nullable(reference("Duration"))),
+      variant("SetAckMode", reference("AckMode")),
+      variant("SetEntitlement", tuple(vec![reference("AccountId"), reference("Entitlement")])),
+      variant(
+        "SetReplaceProtection",
+        tuple(vec![reference("AccountId"), nullable(reference("ReplaceProtection"))]),
+      ),
+      variant(
+        "GetTradesHistory",
+        tuple(vec![reference("Symbol"), reference("Timestamp"), reference("Timestamp")]),
+      ),
+      variant(
+        "GetQuotesHistory",
+        tuple(vec![reference("Symbol"), reference("Timestamp"), reference("Timestamp")]),
+      ),
+      variant("GetIndex", reference("Symbol")),
+      variant("CreateAccount", reference("NewAccount")),
+      variant("SetAccountState", tuple(vec![reference("AccountId"), reference("AccountState")])),
+      variant("GetOrderToTradeRatio", reference("AccountId")),
+      variant("GetFeeTier", reference("AccountId")),
+      variant("GetSymbolFeeTier", tuple(vec![reference("AccountId"), reference("Symbol")])),
+      variant("GetEquity", reference("AccountId")),
+      variant("GetLiquidation", reference("AccountId")),
+      variant("GetQueuePosition", reference("Id")),
+      variant("GetBookStats", reference("Symbol")),
+      variant("SetRejectAll", json!({ "type": "boolean" })),
+      variant("SetRiskBypass", nullable(reference("RiskBypass"))),
+      { "enum": ["GetDeadLetters"] },
+      variant("DumpBook", tuple(vec![reference("Symbol"), reference("DumpFormat")])),
+      variant("RestrictSymbol", tuple(vec![reference("Symbol"), json!({ "type": "boolean" })])),
+      variant(
+        "SetSymbolPermission",
+        tuple(vec![reference("Symbol"), reference("Grantee"), json!({ "type": "boolean" })]),
+      ),
+      variant("GetSymbolPermissions", reference("Symbol")),
+      variant("CancelTagged", tuple(vec![reference("AccountId"), reference("Label")])),
+      variant("ListSymbol", reference("Symbol")),
+      variant("ReplaceSymbol", reference("Symbol")),
+      variant("HaltSymbol", tuple(vec![reference("Symbol"), json!({ "type": "boolean" })])),
+      { "enum": ["GetWatchdog"] },
+      variant("GetActivity", tuple(vec![reference("AccountId"), unsigned(u64::MAX), unsigned(u64::MAX)])),
+      variant("GetFees", tuple(vec![nullable(reference("AccountId")), reference("Date"), reference("FeePeriod")])),
+    ]},
+    "AckMode": { "enum": ["Full", "Fast"] },
+    "RiskCheck": { "enum": [
+      "AccountState",
+      "RejectAll",
+      "SymbolPermission",
+      "LotSize",
+      "OrderToTrade",
+      "BuyingPower",
+    ] },
+    "RiskChecks": object(&[("bits", unsigned(u32::MAX.into()))], &["bits"]),
+    "RiskBypass": object(
+      &[("checks", reference("RiskChecks")), ("reason", reference("Label"))],
+      &["checks", "reason"],
+    ),
+    "Grantee": { "oneOf": [variant("Account", reference("AccountId")), variant("Firm", reference("FirmId"))] },
+    "SymbolPermissions": object(
+      &[
+        ("is_restricted", json!({ "type": "boolean" })),
+        ("grantees", json!({ "type": "array", "items": reference("Grantee") })),
+      ],
+      &["is_restricted", "grantees"],
+    ),
+    "DumpFormat": { "enum": ["Json", "Csv"] },
+    "EndpointRole": { "enum": ["All", "OrderEntry", "MarketData", "DropCopy", "Admin"] },
+    "Execution": object(
+      &[
+        ("id", reference("Id")),
+        ("trade", reference("TradeId")),
+        ("quantity", reference("Quantity")),
+        ("is_filled", json!({ "type": "boolean" })),
+        ("received_at", reference("Timestamp")),
+        ("matched_at", reference("Timestamp")),
+      ],
+      &["id", "trade", "quantity", "is_filled", "received_at", "matched_at"],
+    ),
+    "OrderStatus": object(
+      &[("order", reference("Order")), ("average_price", nullable(json!({ "type": "number" })))],
+      &["order", "average_price"],
+    ),
+    "Placement": object(
+      &[
+        ("id", reference("Id")),
+        ("fills", json!({ "type": "array", "items": reference("Fill") })),
+        ("remaining", reference("Quantity")),
+        ("state", reference("OrderState")),
+        ("quantity_precision", precision.clone()),
+      ],
+      &["id", "fills", "remaining", "state"],
+    ),
+    "Fill": object(
+      &[
+        ("execution", unsigned(u64::MAX)),
+        ("trade", reference("TradeId")),
+        ("liquidity", reference("Liquidity")),
+        ("price", reference("Price")),
+        ("quantity", reference("Quantity")),
+      ],
+      &["execution", "trade", "liquidity", "price", "quantity"],
+    ),
+    "OrderState": { "enum": ["New", "PartiallyFilled", "Filled", "Held", "Cancelled"] },
+    "Account": object(
+      &[
+        ("firm", nullable(reference("FirmId"))),
+        ("beneficial_owner", nullable(reference("AccountId"))),
+        ("is_admin", json!({ "type": "boolean" })),
+        ("balance", reference("Price")),
+        ("orders", json!({ "type": "array", "items": reference("Id") })),
+        ("portfolio", json!({ "type": "array", "items": tuple(vec![reference("Symbol"), reference("Quantity")]) })),
+        ("entitlement", reference("Entitlement")),
+        ("reference", nullable(reference("Label"))),
+        ("name", reference("Label")),
+        ("state", reference("AccountState")),
+        ("replace_protection", nullable(reference("ReplaceProtection"))),
+      ],
+      &[
+        "firm",
+        "beneficial_owner",
+        "is_admin",
+        "balance",
+        "orders",
+        "portfolio",
+        "entitlement",
+        "reference",
+        "name",
+        "state",
+        "replace_protection",
+      ],
+    ),
+    "ReplaceProtection": object(
+      &[("band", reference("Price")), ("action", reference("ProtectionAction"))],
+      &["band", "action"],
+    ),
+    "ProtectionAction": { "enum": ["Reject", "Cap"] },
+    "AccountState": { "enum": ["Active", "Suspended", "LiquidationOnly", "Closed"] },
+    "Label": { "type": "string", "maxLength": LABEL_CAPACITY },
+    "NewAccount": object(
+      &[("reference", reference("Label")), ("name", reference("Label")), ("firm", nullable(reference("FirmId")))],
+      &["reference"],
+    ),
+    "AccountSummary": object(
+      &[
+        ("firm", nullable(reference("FirmId"))),
+        ("beneficial_owner", nullable(reference("AccountId"))),
+        ("is_admin", json!({ "type": "boolean" })),
+        ("balance", reference("Price")),
+        ("orders", unsigned(u64::MAX)),
+        ("holdings", unsigned(u64::MAX)),
+        ("state", reference("AccountState")),
+      ],
+      &["firm", "beneficial_owner", "is_admin", "balance", "orders", "holdings", "state"],
+    ),
+    "Page": object(
+      &[("after", nullable(reference("Id"))), ("limit", unsigned(u64::MAX))],
+      &["after", "limit"],
+    ),
+    "OrderPage": object(
+      &[
+        ("orders", json!({ "type": "array", "items": tuple(vec![reference("Id"), reference("Order")]) })),
+        ("next", nullable(reference("Id"))),
+      ],
+      &["orders", "next"],
+    ),
+    "Activity": { "oneOf": [
+      variant(
+        "OrderAccepted",
+        object(
+          &[
+            ("order", reference("Id")),
+            ("symbol", reference("Symbol")),
+            ("side", reference("Side")),
+            ("price", reference("Price")),
+            ("quantity", reference("Quantity")),
+          ],
+          &["order", "symbol", "side", "price", "quantity"],
+        ),
+      ),
+      variant("OrderRejected", object(&[("order", nullable(reference("Id")))], &["order"])),
+      variant("OrderModified", object(&[("order", reference("Id"))], &["order"])),
+      variant(
+        "OrderCancelled",
+        object(&[("order", reference("Id")), ("reason", reference("CancelReason"))], &["order", "reason"]),
+      ),
+      variant(
+        "Fill",
+        object(
+          &[
+            ("order", reference("Id")),
+            ("symbol", reference("Symbol")),
+            ("side", reference("Side")),
+            ("price", reference("Price")),
+            ("quantity", reference("Quantity")),
+            ("trade", reference("TradeId")),
+            ("filled", reference("Quantity")),
+          ],
+          &["order", "symbol", "side", "price", "quantity", "trade", "filled"],
+        ),
+      ),
+      variant(
+        "Fee",
+        object(
+          &[
+            ("amount", json!({ "type": "integer" })),
+            ("trade", nullable(reference("TradeId"))),
+            ("balance", reference("Price")),
+          ],
+          &["amount", "trade", "balance"],
+        ),
+      ),
+      variant(
+        "Settlement",
+        object(
+          &[
+            ("symbol", reference("Symbol")),
+            ("bought", reference("Quantity")),
+            ("sold", reference("Quantity")),
+            ("paid", unsigned(u64::MAX)),
+            ("received", unsigned(u64::MAX)),
+          ],
+          &["symbol", "bought", "sold", "paid", "received"],
+        ),
+      ),
+    ]},
+    "ActivityEntry": object(
+      &[
+        ("cursor", unsigned(u64::MAX)),
+        ("timestamp", reference("Timestamp")),
+        ("activity", reference("Activity")),
+      ],
+      &["cursor", "timestamp", "activity"],
+    ),
+    "ActivityPage": object(
+      &[
+        ("entries", json!({ "type": "array", "items": reference("ActivityEntry") })),
+        ("next", nullable(unsigned(u64::MAX))),
+        ("latest", unsigned(u64::MAX)),
+      ],
+      &["entries", "next", "latest"],
+    ),
+    "FeePeriod": { "enum": ["Day", "MonthToDate"] },
+    "FeeLine": object(
+      &[
+        ("account", reference("AccountId")),
+        ("symbol", nullable(reference("Symbol"))),
+        ("fill_fees", unsigned(u64::MAX)),
+        ("rebates", unsigned(u64::MAX)),
+        ("order_fees", unsigned(u64::MAX)),
+        ("net", json!({ "type": "integer" })),
+        ("fills", unsigned(u64::MAX)),
+      ],
+      &["account", "symbol", "fill_fees", "rebates", "order_fees", "net", "fills"],
+    ),
+    "FeeReport": object(
+      &[
+        ("from", reference("Date")),
+        ("to", reference("Date")),
+        ("lines", json!({ "type": "array", "items": reference("FeeLine") })),
+        ("totals", json!({ "type": "array", "items": reference("FeeLine") })),
+      ],
+      &["from", "to", "lines", "totals"],
+    ),
+    "QueueEntry": object(
+      &[("id", reference("Id")), ("remaining", reference("Quantity")), ("position", unsigned(u64::MAX))],
+      &["id", "remaining", "position"],
+    ),
+    "QueuePosition": object(
+      &[
+        ("price", reference("Price")),
+        ("position", unsigned(u64::MAX)),
+        ("ahead", reference("Quantity")),
+        ("orders", unsigned(u64::MAX)),
+        ("quantity", reference("Quantity")),
+      ],
+      &["price", "position", "ahead", "orders", "quantity"],
+    ),
+    "BookStats": object(
+      &[
+        ("symbol", reference("Symbol")),
+        ("orders", unsigned(u64::MAX)),
+        ("bid_quantity", reference("Quantity")),
+        ("ask_quantity", reference("Quantity")),
+        ("bid_notional", unsigned(u64::MAX)),
+        ("ask_notional", unsigned(u64::MAX)),
+      ],
+      &["symbol", "orders", "bid_quantity", "ask_quantity", "bid_notional", "ask_notional"],
+    ),
+    "DeadLetters": object(
+      &[
+        ("session", unsigned(u64::MAX)),
+        ("count", unsigned(u64::MAX)),
+        ("inputs", json!({ "type": "array", "items": { "type": "string" } })),
+      ],
+      &["session", "count", "inputs"],
+    ),
+    "SymbolProgress": object(
+      &[
+        ("symbol", reference("Symbol")),
+        ("sequence", nullable(unsigned(u64::MAX))),
+        ("processed_at", reference("Timestamp")),
+        ("busy_since", nullable(reference("Timestamp"))),
+        ("processed", unsigned(u64::MAX)),
+        ("pending", unsigned(u64::MAX)),
+        ("max_latency", unsigned(u64::MAX)),
+        ("stalls", unsigned(u64::MAX)),
+        ("is_stalled", json!({ "type": "boolean" })),
+        ("is_halted", json!({ "type": "boolean" })),
+      ],
+      &[
+        "symbol",
+        "sequence",
+        "processed_at",
+        "busy_since",
+        "processed",
+        "max_latency",
+        "stalls",
+        "is_stalled",
+        "is_halted",
+      ],
+    ),
+    "AuditEvent": { "enum": [
+      "Receive",
+      "Accept",
+      "Reject",
+      "Modify",
+      "Cancel",
+      "Execute",
+      "Deny",
+      "SelfMatchPrevented",
+      "Liquidate",
+      "Bypass",
+    ] },
+    "CancelReason": { "enum": [
+      "Requested",
+      "EndOfDay",
+      "AuctionUnfilled",
+      "SelfMatch",
+      "AccountClosed",
+      "Liquidation",
+      "SymbolReplaced",
+      "KillSwitch",
+    ] },
+    "AuditRecord": object(
+      &[
+        ("sequence", unsigned(u64::MAX)),
+        ("timestamp", reference("Timestamp")),
+        ("session", unsigned(u64::MAX)),
+        ("account", reference("AccountId")),
+        ("event", reference("AuditEvent")),
+        ("order", nullable(reference("Id"))),
+        ("symbol", nullable(reference("Symbol"))),
+        ("side", nullable(reference("Side"))),
+        ("price", nullable(reference("Price"))),
+        ("quantity", nullable(reference("Quantity"))),
+        ("trade", nullable(reference("TradeId"))),
+        ("liquidity", nullable(reference("Liquidity"))),
+        ("filled", nullable(reference("Quantity"))),
+        ("average_price", nullable(json!({ "type": "number" }))),
+        ("source", nullable(json!({ "type": "string" }))),
+        ("tag", nullable(reference("Label"))),
+        ("check", nullable(reference("RiskCheck"))),
+        ("reason", nullable(reference("Label"))),
+        ("cancel_reason", nullable(reference("CancelReason"))),
+      ],
+      &[
+        "sequence",
+        "timestamp",
+        "session",
+        "account",
+        "event",
+        "order",
+        "symbol",
+        "side",
+        "price",
+        "quantity",
+        "trade",
+        "liquidity",
+        "filled",
+        "average_price",
+      ],
+    ),
+    "Consequence": { "enum": ["None", "Warning", "Fee", "Throttle"] },
+    "OrderToTradeStatus": object(
+      &[
+        ("orders", unsigned(u64::MAX)),
+        ("trades", unsigned(u64::MAX)),
+        ("ratio", json!({ "type": "number" })),
+        ("consequence", reference("Consequence")),
+      ],
+      &["orders", "trades", "ratio", "consequence"],
+    ),
+    "FeeTierStatus": object(
+      &[
+        ("tier", unsigned(u64::MAX)),
+        ("volume", unsigned(u64::MAX)),
+        ("maker_rate", json!({ "type": "integer" })),
+        ("taker_rate", json!({ "type": "integer" })),
+        ("next_tier_volume", nullable(unsigned(u64::MAX))),
+      ],
+      &["tier", "volume", "maker_rate", "taker_rate", "next_tier_volume"],
+    ),
+    "Equity": object(
+      &[
+        ("account", reference("AccountId")),
+        ("cash", unsigned(u64::MAX)),
+        ("position_value", unsigned(u64::MAX)),
+        ("reserved", unsigned(u64::MAX)),
+        ("equity", unsigned(u64::MAX)),
+      ],
+      &["account", "cash", "position_value", "reserved", "equity"],
+    ),
+    "Liquidation": object(
+      &[
+        ("account", reference("AccountId")),
+        ("started_at", reference("Timestamp")),
+        ("buying_power", unsigned(u64::MAX)),
+        ("reserved", unsigned(u64::MAX)),
+        ("orders", json!({ "type": "array", "items": reference("Id") })),
+      ],
+      &["account", "started_at", "buying_power", "reserved", "orders"],
+    ),
+    "Success": { "oneOf": [
+      variant("GetOrder", reference("OrderStatus")),
+      variant("PlaceOrder", reference("Placement")),
+      variant("Accepted", reference("Id")),
+      variant("CancelOrder", json!({ "type": "boolean" })),
+      variant("CancelPending", reference("Timestamp")),
+      variant("UpdateOrder", json!({ "type": "boolean" })),
+      variant("SuspendOrder", json!({ "type": "boolean" })),
+      variant("ResumeOrder", json!({ "type": "boolean" })),
+      variant(
+        "ExecuteOrder",
+        tuple(vec![json!({ "type": "boolean" }), json!({ "type": "array", "items": reference("Execution") })]),
+      ),
+      variant("GetQuote", reference("Price")),
+      variant("GetAccount", reference("Account")),
+      variant("GetAccountSummary", reference("AccountSummary")),
+      variant("GetOpenOrders", reference("OrderPage")),
+      variant("GetLevel", json!({ "type": "array", "items": reference("QueueEntry") })),
+      variant("GetIndex", nullable(json!({ "type": "number" }))),
+      variant("GetOrderToTradeRatio", reference("OrderToTradeStatus")),
+      variant("GetFeeTier", reference("FeeTierStatus")),
+      variant("GetEquity", reference("Equity")),
+      variant("GetLiquidation", nullable(reference("Liquidation"))),
+      variant("GetQueuePosition", reference("QueuePosition")),
+      variant("GetBookStats", reference("BookStats")),
+      variant("GetDeadLetters", json!({ "type": "array", "items": reference("DeadLetters") })),
+      variant("DumpBook", json!({ "type": "string" })),
+      variant("GetTradesHistory", json!({ "type": "array", "items": reference("TradeTick") })),
+      variant("GetQuotesHistory", json!({ "type": "array", "items": reference("QuoteTick") })),
+      variant("CreateAccount", reference("AccountId")),
+      variant("GetSymbolPermissions", reference("SymbolPermissions")),
+      variant("CancelTagged", json!({ "type": "array", "items": reference("Id") })),
+      variant("ReplaceSymbol", json!({ "type": "array", "items": reference("Id") })),
+      variant("GetWatchdog", json!({ "type": "array", "items": reference("SymbolProgress") })),
+      variant("GetActivity", reference("ActivityPage")),
+      variant("GetFees", reference("FeeReport")),
+      { "enum": [
+        "SubscribeExecutions",
+        "Subscribe",
+        "Unsubscribe",
+        "Conflate",
+        "SetAckMode",
+        "SetEntitlement",
+        "SetReplaceProtection",
+        "SetAccountState",
+        "SetRejectAll",
+        "SetRiskBypass",
+        "RestrictSymbol",
+        "SetSymbolPermission",
+        "ListSymbol",
+        "HaltSymbol",
+      ] },
+    ]},
+    "Error": { "oneOf": [
+      variant("AccountDoesNotExist", object(&[("id", reference("AccountId"))], &["id"])),
+      variant("SymbolDoesNotExist", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("IdDoesNotExist", object(&[("id", reference("Id"))], &["id"])),
+      variant("IndexDoesNotExist", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("ContinuousTradingDisabled", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("DarkPoolDisabled", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("PermissionDenied", object(&[("id", reference("AccountId"))], &["id"])),
+      variant("OrderToTradeRatioExceeded", object(&[("id", reference("AccountId"))], &["id"])),
+      variant(
+        "StateHashMismatch",
+        object(&[("expected", unsigned(u64::MAX)), ("actual", unsigned(u64::MAX))], &["expected", "actual"]),
+      ),
+      variant("InvalidConfig", object(&[("reason", reference("ConfigError"))], &["reason"])),
+      variant("TooManyConnections", object(&[("limit", unsigned(u64::MAX))], &["limit"])),
+      variant(
+        "JournalOutOfOrder",
+        object(&[("last", unsigned(u64::MAX)), ("sequence", unsigned(u64::MAX))], &["last", "sequence"]),
+      ),
+      variant(
+        "NotEntitled",
+        object(&[("id", reference("AccountId")), ("entitlement", reference("Entitlement"))], &["id", "entitlement"]),
+      ),
+      variant(
+        "AccountRestricted",
+        object(&[("id", reference("AccountId")), ("state", reference("AccountState"))], &["id", "state"]),
+      ),
+      variant(
+        "InsufficientBuyingPower",
+        object(&[("id", reference("AccountId")), ("buying_power", unsigned(u64::MAX))], &["id", "buying_power"]),
+      ),
+      variant("RejectingAllOrders", object(&[("id", reference("AccountId"))], &["id"])),
+      variant(
+        "MalformedCommand",
+        object(&[("line", unsigned(u64::MAX)), ("column", unsigned(u64::MAX))], &["line", "column"]),
+      ),
+      variant(
+        "QuoteLifeNotReached",
+        object(&[("id", reference("Id")), ("rests_until", reference("Timestamp"))], &["id", "rests_until"]),
+      ),
+      variant("DumpFailed", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("NotServedOnEndpoint", object(&[("role", reference("EndpointRole"))], &["role"])),
+      variant(
+        "SourceNotAllowed",
+        object(&[("id", reference("AccountId")), ("source", json!({ "type": "string" }))], &["id", "source"]),
+      ),
+      variant(
+        "SymbolNotPermitted",
+        object(&[("id", reference("AccountId")), ("symbol", reference("Symbol"))], &["id", "symbol"]),
+      ),
+      variant(
+        "ReplaceOutsideBand",
+        object(&[("id", reference("Id")), ("limit", reference("Price"))], &["id", "limit"]),
+      ),
+      variant(
+        "InvalidLotSize",
+        object(&[("symbol", reference("Symbol")), ("lot_size", reference("Quantity"))], &["symbol", "lot_size"]),
+      ),
+      variant(
+        "InsufficientHoldings",
+        object(
+          &[("id", reference("AccountId")), ("symbol", reference("Symbol")), ("holding", reference("Quantity"))],
+          &["id", "symbol", "holding"],
+        ),
+      ),
+      { "enum": ["MissingBypassReason"] },
+      variant("SymbolAlreadyExists", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("InconsistentBook", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("SymbolHalted", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant(
+        "ActivityExpired",
+        object(&[("id", reference("AccountId")), ("oldest", unsigned(u64::MAX))], &["id", "oldest"]),
+      ),
+    ]},
+    "ConfigError": { "oneOf": [
+      variant("ZeroAuctionInterval", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("InvalidAuctionSchedule", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("HaircutOutOfRange", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("QuantityPrecisionOutOfRange", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      variant("ZeroLotSize", object(&[("symbol", reference("Symbol"))], &["symbol"])),
+      { "enum": ["ZeroOrderToTradeWindow", "UnorderedOrderToTradeRatios", "UnorderedFeeTiers", "InvalidIndexDivisor"] },
+    ]},
+    "Reply": { "oneOf": [variant("Ok", reference("Success")), variant("Err", reference("Error"))] },
+    "MarketData": { "oneOf": [
+      variant("Trade", trade.clone()),
+      variant("DarkTrade", trade),
+      variant("AuctionUncross", uncross),
+      variant("AuctionCall", object(
+        &[("symbol", reference("Symbol")), ("uncrosses_at", reference("Timestamp"))],
+        &["symbol", "uncrosses_at"],
+      )),
+      variant("PriceImprovementAuction", object(
+        &[
+          ("symbol", reference("Symbol")),
+          ("side", reference("Side")),
+          ("price", reference("Price")),
+          ("quantity", reference("Quantity")),
+          ("ends_at", reference("Timestamp")),
+        ],
+        &["symbol", "side", "price", "quantity", "ends_at"],
+      )),
+      variant(
+        "IndexValue",
+        object(&[("symbol", reference("Symbol")), ("value", json!({ "type": "number" }))], &["symbol", "value"]),
+      ),
+      variant("ClosingPrice", object(
+        &[("symbol", reference("Symbol")), ("price", reference("Price")), ("volume", reference("Quantity"))],
+        &["symbol", "price", "volume"],
+      )),
+    ]},
+    "Duration": object(&[("secs", unsigned(u64::MAX)), ("nanos", unsigned(999_999_999))], &["secs", "nanos"]),
+    "Entitlement": { "oneOf": [
+      variant("Depth", unsigned(u32::MAX.into())),
+      { "enum": ["Bbo", "FullDepth", "OrderByOrder"] },
+    ]},
+    "Feed": { "oneOf": [
+      variant("Depth", nullable(unsigned(u32::MAX.into()))),
+      { "enum": ["Bbo", "Trades", "Candles"] },
+    ]},
+    "Candle": object(
+      &[
+        ("symbol", reference("Symbol")),
+        ("start", reference("Timestamp")),
+        ("open", reference("Price")),
+        ("high", reference("Price")),
+        ("low", reference("Price")),
+        ("close", reference("Price")),
+        ("volume", reference("Quantity")),
+      ],
+      &["symbol", "start", "open", "high", "low", "close", "volume"],
+    ),
+    "TradeTick": object(
+      &[("timestamp", reference("Timestamp")), ("price", reference("Price")), ("quantity", reference("Quantity"))],
+      &["timestamp", "price", "quantity"],
+    ),
+    "QuoteTick": object(
+      &[("timestamp", reference("Timestamp")), ("bid", nullable(level.clone())), ("ask", nullable(level.clone()))],
+      &["timestamp", "bid", "ask"],
+    ),
+    "Push": { "oneOf": [
+      variant(
+        "ExecutionReport",
+        object(
+          &[
+            ("sequence", unsigned(u64::MAX)),
+            ("record", reference("AuditRecord")),
+            ("quantity_precision", nullable(precision.clone())),
+          ],
+          &["sequence", "record"],
+        ),
+      ),
+      variant("Bbo", object(
+        &[
+          ("symbol", reference("Symbol")),
+          ("bid", nullable(level.clone())),
+          ("ask", nullable(level.clone())),
+          ("quantity_precision", precision.clone()),
+        ],
+        &["symbol", "bid", "ask"],
+      )),
+      variant("Depth", object(
+        &[
+          ("symbol", reference("Symbol")),
+          ("bids", json!({ "type": "array", "items": level.clone() })),
+          ("asks", json!({ "type": "array", "items": level })),
+          ("quantity_precision", precision.clone()),
+        ],
+        &["symbol", "bids", "asks"],
+      )),
+      variant("Trade", object(
+        &[
+          ("symbol", reference("Symbol")),
+          ("price", reference("Price")),
+          ("quantity", reference("Quantity")),
+          ("trade", reference("TradeId")),
+          ("quantity_precision", precision),
+        ],
+        &["symbol", "price", "quantity", "trade"],
+      )),
+      variant("Candle", reference("Candle")),
+      variant("SelfMatchPrevented", reference("AuditRecord")),
+      variant("Cancelled", reference("AuditRecord")),
+      variant("Latency", object(
+        &[
+          ("received_at", reference("Timestamp")),
+          ("dequeued_at", reference("Timestamp")),
+          ("matched_at", reference("Timestamp")),
+          ("sent_at", reference("Timestamp")),
+        ],
+        &["received_at", "dequeued_at", "matched_at", "sent_at"],
+      )),
+    ]},
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs;
+  use std::path::PathBuf;
+
+  /// Check `value` against the subset of JSON Schema the generated schemas use
+  fn is_valid(value: &Value, schema: &Value, definitions: &Value) -> bool {
+    if let Some(path) = schema["$ref"].as_str() {
+      let name = path.trim_start_matches("#/definitions/");
+      return is_valid(value, &definitions[name], definitions);
+    }
+    if let Some(options) = schema["oneOf"].as_array() {
+      return options.iter().filter(|option| is_valid(value, option, definitions)).count() == 1;
+    }
+    if let Some(options) = schema["enum"].as_array() {
+      return options.contains(value);
+    }
+
+    let is_type = match schema["type"].as_str() {
+      Some("object") => value.is_object(),
+      Some("array") => value.is_array(),
+      Some("string") => value.is_string(),
+      Some("integer") => value.is_u64() || value.is_i64(),
+      Some("number") => value.is_number(),
+      Some("boolean") => value.is_boolean(),
+      Some("null") => value.is_null(),
+      _ => true,
+    };
+    let in_range = match value.as_f64() {
+      Some(number) => {
+        schema["minimum"].as_f64().is_none_or(|minimum| number >= minimum)
+          && schema["maximum"].as_f64().is_none_or(|maximum| number <= maximum)
+      }
+      None => true,
+    };
+    let has_length = match value.as_str() {
+      Some(string) => {
+        let length = string.chars().count() as u64;
+        schema["minLength"].as_u64().is_none_or(|minimum| length >= minimum)
+          && schema["maxLength"].as_u64().is_none_or(|maximum| length <= maximum)
+      }
+      None => true,
+    };
+
+    let has_items = match value.as_array() {
+      Some(elements) => {
+        let length = elements.len() as u64;
+        let items = match &schema["items"] {
+          Value::Array(items) => elements.iter().zip(items).all(|(element, item)| is_valid(element, item, definitions)),
+          Value::Null => true,
+          item => elements.iter().all(|element| is_valid(element, item, definitions)),
+        };
+        items
+          && schema["minItems"].as_u64().is_none_or(|minimum| length >= minimum)
+          && schema["maxItems"].as_u64().is_none_or(|maximum| length <= maximum)
+      }
+      None => true,
+    };
+
+    let has_properties = match value.as_object() {
+      Some(fields) => {
+        let properties = schema["properties"].as_object();
+        let required = schema["required"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let is_known = |name: &String| properties.is_some_and(|properties| properties.contains_key(name));
+        required.iter().all(|name| name.as_str().is_some_and(|name| fields.contains_key(name)))
+          && (schema["additionalProperties"] != false || fields.keys().all(is_known))
+          && fields.iter().all(|(name, field)| match properties.and_then(|properties| properties.get(name)) {
+            Some(property) => is_valid(field, property, definitions),
+            None => true,
+          })
+      }
+      None => true,
+    };
+
+    is_type && in_range && has_length && has_items && has_properties
+  }
+
+  #[test]
+  fn golden_messages_match_their_schemas() {
+    let golden = [
+      ("command.jsonl", "Command"),
+      ("success.jsonl", "Success"),
+      ("error.jsonl", "Error"),
+      ("reply.jsonl", "Reply"),
+      ("market_data.jsonl", "MarketData"),
+      ("push.jsonl", "Push"),
+    ];
+    for &(file, message) in golden.iter() {
+      let schema = schema(message).unwrap();
+      let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", file].iter().collect();
+      for line in fs::read_to_string(path).unwrap().lines() {
+        let value: Value = serde_json::from_str(line).unwrap();
+        assert!(is_valid(&value, &schema, &schema["definitions"]), "{} does not match {}", line, message);
+      }
+    }
+
+    let wrong: Value = serde_json::from_str(r#"{"account_id":1,"kind":{"GetQuote":["Bid",["A","D","B","E"]]}}"#).unwrap();
+    let schema = schema("Command").unwrap();
+    assert!(!is_valid(&wrong, &schema, &schema["definitions"]));
+    assert_eq!(super::schema("Order"), None);
+  }
+}
@@ -0,0 +1,193 @@
+//! Dual-run verification
+//!
+//! A `ShadowEngine` applies every event to two engines whose books store levels differently and compares their
+//! results, market data and state hashes, so a new level store can be proven against the current one before it is
+//! switched on. Both engines read a clock that is frozen while each event is applied, so their timestamps agree.
+
+use crate::clock::{Clock, ManualClock};
+use crate::engine::{Command, Error, MatchEngine, Success};
+use crate::feed::MarketData;
+use crate::journal::JournalEvent;
+use crate::levels::LevelStoreKind;
+use crate::types::*;
+use std::sync::Arc;
+
+/// A difference between the primary and shadow engines after applying an event
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+  /// The results are boxed, being many times the size of the other variants
+  Result {
+    event: JournalEvent,
+    primary: Box<Result<Success, Error>>,
+    shadow: Box<Result<Success, Error>>,
+  },
+  MarketData {
+    event: JournalEvent,
+    primary: Vec<MarketData>,
+    shadow: Vec<MarketData>,
+  },
+  State {
+    event: JournalEvent,
+    primary: u64,
+    shadow: u64,
+  },
+}
+
+/// Two engines run in lockstep
+pub struct ShadowEngine {
+  clock: Arc<dyn Clock>,
+  frozen: Arc<ManualClock>,
+  primary: MatchEngine,
+  shadow: MatchEngine,
+  market_data: Vec<MarketData>,
+  divergences: Vec<Divergence>,
+}
+
+impl ShadowEngine {
+  /// Create a pair of engines reading time from `clock`, the primary storing levels in `primary` and the shadow in
+  /// `shadow`
+  pub fn new(clock: Arc<dyn Clock>, primary: LevelStoreKind, shadow: LevelStoreKind) -> Self {
+    let frozen = Arc::new(ManualClock::new(clock.now()));
+    let engine = |kind| {
+      let mut engine = MatchEngine::with_clock(frozen.clone());
+      engine.set_level_store(kind);
+      engine
+    };
+
+    Self {
+      primary: engine(primary),
+      shadow: engine(shadow),
+      clock,
+      frozen,
+      market_data: vec![],
+      divergences: vec![],
+    }
+  }
+
+  /// Process a command on both engines
+  ///
+  /// # Returns
+  /// the primary's result
+  pub fn try_process_from(&mut self, session: SessionId, command: Command) -> Result<Success, Error> {
+    self.frozen.set(self.clock.now());
+    let primary = self.primary.try_process_from(session, command);
+    let shadow = self.shadow.try_process_from(session, command);
+
+    let event = JournalEvent::Command { session, command };
+    if primary != shadow {
+      self.divergences.push(Divergence::Result {
+        event: event.clone(),
+        primary: Box::new(primary.clone()),
+        shadow: Box::new(shadow),
+      });
+    }
+    self.compare(event);
+
+    primary
+  }
+
+  /// Apply a journaled event to both engines
+  ///
+  /// # Returns
+  /// the primary's result
+  pub fn apply(&mut self, event: JournalEvent) -> Result<(), Error> {
+    if let JournalEvent::Command { session, command } = event {
+      // the result is compared, but a rejected command is not an error when it is replayed
+      let _ = self.try_process_from(session, command);
+      return Ok(());
+    }
+
+    self.frozen.set(self.clock.now());
+    let result = self.primary.apply(event.clone());
+    // only a journaled state hash can fail, and a mismatch between the engines is caught comparing their states
+    let _ = self.shadow.apply(event.clone());
+    self.compare(event);
+
+    result
+  }
+
+  /// Run scheduled work on both engines
+  pub fn tick(&mut self) {
+    self.frozen.set(self.clock.now());
+    self.primary.tick();
+    self.shadow.tick();
+    self.compare(JournalEvent::Tick);
+  }
+
+  /// The primary engine, e.g. to drain its audit trail or journal
+  pub fn primary_mut(&mut self) -> &mut MatchEngine {
+    &mut self.primary
+  }
+
+  /// Take all market data the primary published since the last call
+  pub fn drain_market_data(&mut self) -> Vec<MarketData> {
+    std::mem::take(&mut self.market_data)
+  }
+
+  /// Take all divergences found since the last call
+  pub fn drain_divergences(&mut self) -> Vec<Divergence> {
+    std::mem::take(&mut self.divergences)
+  }
+
+  /// Compare the engines' market data and state after `event`
+  fn compare(&mut self, event: JournalEvent) {
+    let (primary, shadow) = (self.primary.drain_market_data(), self.shadow.drain_market_data());
+    if primary != shadow {
+      self.divergences.push(Divergence::MarketData {
+        event: event.clone(),
+        primary: primary.clone(),
+        shadow,
+      });
+    }
+    self.market_data.extend(primary);
+
+    // the shadow's outputs are only compared, so keep them from growing
+    self.shadow.drain_audit_trail();
+    self.shadow.drain_alerts();
+    self.shadow.drain_journal();
+
+    let (primary, shadow) = (self.primary.state_hash(), self.shadow.state_hash());
+    if primary != shadow {
+      self.divergences.push(Divergence::State { event, primary, shadow });
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::clock::Timestamp;
+  use crate::engine::CommandKind;
+
+  #[test]
+  fn array_store_shadows_tree_store() {
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1)));
+    let mut engine = ShadowEngine::new(clock, LevelStoreKind::Tree, LevelStoreKind::Array);
+    engine.apply(JournalEvent::CreateAccount { firm: None, is_admin: false }).unwrap();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.apply(JournalEvent::InsertSymbol(symbol)).unwrap();
+
+    let orders = [
+      (Side::Ask, 101, 5),
+      (Side::Ask, 99, 5),
+      (Side::Ask, 100, 5),
+      (Side::Bid, 100, 12),
+      (Side::Bid, 98, 3),
+    ];
+    for &(side, price, quantity) in orders.iter() {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()));
+      engine
+        .try_process_from(SessionId::default(), Command { account_id: 0.into(), kind })
+        .unwrap();
+    }
+    engine
+      .try_process_from(SessionId::default(), Command {
+        account_id: 0.into(),
+        kind: CommandKind::CancelOrder(0.into()),
+      })
+      .unwrap();
+
+    assert_eq!(engine.drain_divergences(), vec![]);
+    assert_eq!(engine.drain_market_data().len(), 2);
+  }
+}
@@ -0,0 +1,253 @@
+//! Trade surveillance
+
+use crate::clock::Timestamp;
+use crate::engine::Id;
+use crate::order_to_trade::Consequence;
+use crate::types::*;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Which patterns surveillance looks for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SurveillanceRules {
+  /// Alert when the same beneficial owner is on both sides of a trade
+  pub wash_trades: bool,
+  /// Alert when trades between owners form a cycle within `circular_window`
+  pub circular_trades: bool,
+  pub circular_window: Duration,
+  /// The most owners a cycle may pass through and still be detected
+  pub max_cycle_length: usize,
+  /// Alert when an account rapidly places and cancels orders away from the touch on one side
+  pub spoofing: bool,
+  pub spoofing_window: Duration,
+  /// Orders cancelled within this long of being placed count towards spoofing
+  pub spoofing_max_lifetime: Duration,
+  /// How far from the best price on its side an order must be placed to count towards spoofing
+  pub spoofing_min_distance: Price,
+  /// How many such cancels within `spoofing_window` raise an alert
+  pub spoofing_min_orders: usize,
+  /// Score added to each account involved in an alert
+  pub alert_score: u32,
+}
+
+impl Default for SurveillanceRules {
+  fn default() -> Self {
+    Self {
+      wash_trades: true,
+      circular_trades: true,
+      circular_window: Duration::from_secs(60),
+      max_cycle_length: 4,
+      spoofing: true,
+      spoofing_window: Duration::from_secs(10),
+      spoofing_max_lifetime: Duration::from_secs(1),
+      spoofing_min_distance: 5.into(),
+      spoofing_min_orders: 5,
+      alert_score: 1,
+    }
+  }
+}
+
+/// A surveillance alert, published on the ops channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Alert {
+  /// Both sides of a trade share a beneficial owner
+  WashTrade {
+    symbol: Symbol,
+    buyer: AccountId,
+    seller: AccountId,
+    quantity: Quantity,
+  },
+  /// Stock passed through `owners` and back to the first of them
+  CircularTrade { symbol: Symbol, owners: Vec<AccountId> },
+  /// An account's order-to-trade ratio, rounded down, crossed a threshold
+  OrderToTradeRatio { account: AccountId, ratio: u64, consequence: Consequence },
+  /// An account placed and quickly cancelled `orders` away from the touch
+  Spoofing {
+    symbol: Symbol,
+    account: AccountId,
+    side: Side,
+    orders: Vec<Id>,
+  },
+}
+
+/// One side of a trade, as seen by surveillance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Party {
+  pub account: AccountId,
+  pub owner: AccountId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct RecentTrade {
+  at: Timestamp,
+  symbol: Symbol,
+  seller: AccountId,
+  buyer: AccountId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Placement {
+  at: Timestamp,
+  symbol: Symbol,
+  account: AccountId,
+  side: Side,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct QuickCancel {
+  at: Timestamp,
+  id: Id,
+  placement: Placement,
+}
+
+/// Monitors executions for wash and circular trading and the order event stream for spoofing, scoring the accounts
+/// involved
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Surveillance {
+  pub rules: SurveillanceRules,
+  #[serde(with = "crate::types::pairs")]
+  scores: HashMap<AccountId, u32>,
+  recent: VecDeque<RecentTrade>,
+  /// Orders placed away from the touch that are still open
+  #[serde(with = "crate::types::pairs")]
+  away_placements: HashMap<Id, Placement>,
+  quick_cancels: VecDeque<QuickCancel>,
+}
+
+impl Surveillance {
+  pub fn new(rules: SurveillanceRules) -> Self {
+    Self {
+      rules,
+      ..Self::default()
+    }
+  }
+
+  /// Get the score of an account
+  pub fn score(&self, account: AccountId) -> u32 {
+    self.scores.get(&account).cloned().unwrap_or_default()
+  }
+
+  /// Check a trade
+  ///
+  /// # Returns
+  /// Any alerts raised by the trade
+  pub fn on_trade(&mut self, at: Timestamp, symbol: Symbol, buyer: Party, seller: Party, quantity: Quantity) -> Vec<Alert> {
+    let mut alerts = vec![];
+
+    if self.rules.wash_trades && buyer.owner == seller.owner {
+      self.raise(&[buyer.account, seller.account]);
+      alerts.push(Alert::WashTrade {
+        symbol,
+        buyer: buyer.account,
+        seller: seller.account,
+        quantity,
+      });
+    } else if self.rules.circular_trades {
+      let window = self.rules.circular_window;
+      while self.recent.front().is_some_and(|trade| trade.at + window < at) {
+        self.recent.pop_front();
+      }
+
+      if let Some(owners) = self.find_cycle(symbol, seller.owner, buyer.owner) {
+        self.raise(&owners);
+        alerts.push(Alert::CircularTrade { symbol, owners });
+      }
+
+      self.recent.push_back(RecentTrade {
+        at,
+        symbol,
+        seller: seller.owner,
+        buyer: buyer.owner,
+      });
+    }
+
+    alerts
+  }
+
+  /// Record an order placed `distance` from the best price on its side
+  pub fn on_place(&mut self, at: Timestamp, symbol: Symbol, account: AccountId, side: Side, id: Id, distance: Price) {
+    // orders that outlive the lifetime can't count towards spoofing
+    let lifetime = self.rules.spoofing_max_lifetime;
+    self.away_placements.retain(|_, placement| placement.at + lifetime >= at);
+
+    if self.rules.spoofing && distance >= self.rules.spoofing_min_distance {
+      self.away_placements.insert(id, Placement {
+        at,
+        symbol,
+        account,
+        side,
+      });
+    }
+  }
+
+  /// Check a cancel
+  ///
+  /// # Returns
+  /// Any alerts raised by the cancel
+  pub fn on_cancel(&mut self, at: Timestamp, id: Id) -> Vec<Alert> {
+    let placement = match self.away_placements.remove(&id) {
+      Some(placement) if placement.at + self.rules.spoofing_max_lifetime >= at => placement,
+      _ => return vec![],
+    };
+
+    let window = self.rules.spoofing_window;
+    while self.quick_cancels.front().is_some_and(|cancel| cancel.at + window < at) {
+      self.quick_cancels.pop_front();
+    }
+    self.quick_cancels.push_back(QuickCancel { at, id, placement });
+
+    let is_related = |cancel: &QuickCancel| {
+      (cancel.placement.symbol, cancel.placement.account, cancel.placement.side)
+        == (placement.symbol, placement.account, placement.side)
+    };
+    let orders: Vec<Id> = self.quick_cancels.iter().filter(|cancel| is_related(cancel)).map(|cancel| cancel.id).collect();
+    if orders.len() < self.rules.spoofing_min_orders {
+      return vec![];
+    }
+
+    // each cancel only supports a single alert
+    self.quick_cancels.retain(|cancel| !is_related(cancel));
+    self.raise(&[placement.account]);
+    vec![Alert::Spoofing {
+      symbol: placement.symbol,
+      account: placement.account,
+      side: placement.side,
+      orders,
+    }]
+  }
+
+  /// Search recent trades for stock flowing from `buyer` back to `seller`
+  fn find_cycle(&self, symbol: Symbol, seller: AccountId, buyer: AccountId) -> Option<Vec<AccountId>> {
+    // most trades go nowhere, so don't allocate paths to find that out
+    self.recent.iter().find(|trade| trade.symbol == symbol && trade.seller == buyer)?;
+
+    let mut paths = VecDeque::new();
+    paths.push_back(vec![seller, buyer]);
+
+    while let Some(path) = paths.pop_front() {
+      if path.len() > self.rules.max_cycle_length {
+        continue;
+      }
+
+      let last = path[path.len() - 1];
+      for trade in self.recent.iter().filter(|trade| trade.symbol == symbol && trade.seller == last) {
+        if trade.buyer == seller {
+          return Some(path);
+        } else if !path.contains(&trade.buyer) {
+          let mut next = path.clone();
+          next.push(trade.buyer);
+          paths.push_back(next);
+        }
+      }
+    }
+
+    None
+  }
+
+  fn raise(&mut self, accounts: &[AccountId]) {
+    for account in accounts {
+      *self.scores.entry(*account).or_default() += self.rules.alert_score;
+    }
+  }
+}
@@ -0,0 +1,149 @@
+//! Trigger scheduling
+//!
+//! Orders that wait for the market to reach a price, like stops, and orders that follow it, like pegs, are activated
+//! by book events, and one event can activate many of them. Replays and replicas only agree if they activate them in
+//! the same order, so every such feature schedules its triggers with a `TriggerScheduler` rather than keeping its own.
+//!
+//! Bid triggers fire once the price rises to theirs and ask triggers once it falls to theirs. Of the triggers a price
+//! fires, bids fire before asks, each side in the order the price would have reached them on its way, i.e. lowest bid
+//! and highest ask first, and triggers at the same price in the order they were scheduled.
+
+use crate::types::{Price, Side};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A scheduled trigger, numbered in the order triggers were scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TriggerId(u64);
+
+/// Triggers waiting on a price, each activating a `T`
+#[derive(Debug, Clone)]
+pub struct TriggerScheduler<T> {
+  bids: BTreeMap<(Price, TriggerId), T>,
+  asks: BTreeMap<(Price, TriggerId), T>,
+  /// The side and price of each scheduled trigger
+  scheduled: HashMap<TriggerId, (Side, Price)>,
+  next_id: u64,
+}
+
+impl<T> Default for TriggerScheduler<T> {
+  fn default() -> Self {
+    Self {
+      bids: BTreeMap::new(),
+      asks: BTreeMap::new(),
+      scheduled: HashMap::new(),
+      next_id: 0,
+    }
+  }
+}
+
+impl<T> TriggerScheduler<T> {
+  /// Schedule `item` to activate once the price reaches `price` from the side of `side`
+  pub fn schedule(&mut self, side: Side, price: Price, item: T) -> TriggerId {
+    let id = TriggerId(self.next_id);
+    self.next_id += 1;
+    self.side_mut(side).insert((price, id), item);
+    self.scheduled.insert(id, (side, price));
+    id
+  }
+
+  /// Move a trigger to `price`, keeping its place among triggers at the same price, as a peg does when it reprices
+  ///
+  /// # Returns
+  /// `false` if the trigger already fired or was cancelled
+  pub fn reschedule(&mut self, id: TriggerId, price: Price) -> bool {
+    let scheduled = match self.scheduled.get_mut(&id) {
+      Some(scheduled) => scheduled,
+      None => return false,
+    };
+    let (side, old) = *scheduled;
+    scheduled.1 = price;
+    let levels = self.side_mut(side);
+    let item = levels.remove(&(old, id)).expect("every scheduled trigger is on its side");
+    levels.insert((price, id), item);
+    true
+  }
+
+  /// Cancel a trigger that has not fired
+  pub fn cancel(&mut self, id: TriggerId) -> Option<T> {
+    let (side, price) = self.scheduled.remove(&id)?;
+    self.side_mut(side).remove(&(price, id))
+  }
+
+  /// Take every trigger `price` fires, in the order they activate
+  pub fn fire(&mut self, price: Price) -> Vec<(TriggerId, T)> {
+    let rising = self.bids.split_off(&(price, TriggerId(u64::MAX)));
+    let fired_bids = std::mem::replace(&mut self.bids, rising);
+    let fired_asks = self.asks.split_off(&(price, TriggerId(0)));
+
+    let fired: Vec<_> = fired_bids
+      .into_iter()
+      .chain(highest_first(fired_asks))
+      .map(|((_, id), item)| (id, item))
+      .collect();
+    for (id, _) in &fired {
+      self.scheduled.remove(id);
+    }
+    fired
+  }
+
+  pub fn len(&self) -> usize {
+    self.scheduled.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.scheduled.is_empty()
+  }
+
+  fn side_mut(&mut self, side: Side) -> &mut BTreeMap<(Price, TriggerId), T> {
+    match side {
+      Side::Bid => &mut self.bids,
+      Side::Ask => &mut self.asks,
+    }
+  }
+}
+
+/// Order triggers highest price first, keeping the order they were scheduled in at each price
+fn highest_first<T>(triggers: BTreeMap<(Price, TriggerId), T>) -> Vec<((Price, TriggerId), T)> {
+  let mut triggers: Vec<_> = triggers.into_iter().collect();
+  triggers.sort_by(|((a, a_id), _), ((b, b_id), _)| b.cmp(a).then(a_id.cmp(b_id)));
+  triggers
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn triggers_fire_in_price_then_schedule_order() {
+    let mut scheduler = TriggerScheduler::default();
+    scheduler.schedule(Side::Bid, 102.into(), "bid at 102");
+    scheduler.schedule(Side::Ask, 97.into(), "ask at 97");
+    scheduler.schedule(Side::Bid, 101.into(), "first bid at 101");
+    let cancelled = scheduler.schedule(Side::Bid, 100.into(), "cancelled bid");
+    scheduler.schedule(Side::Bid, 101.into(), "second bid at 101");
+    scheduler.schedule(Side::Ask, 98.into(), "ask at 98");
+    scheduler.schedule(Side::Bid, 103.into(), "bid at 103");
+    scheduler.cancel(cancelled);
+
+    let fired = |scheduler: &mut TriggerScheduler<_>, price: u32| -> Vec<_> {
+      scheduler.fire(price.into()).into_iter().map(|(_, item)| item).collect()
+    };
+    assert_eq!(fired(&mut scheduler, 102), vec!["first bid at 101", "second bid at 101", "bid at 102"]);
+    assert_eq!(fired(&mut scheduler, 96), vec!["ask at 98", "ask at 97"]);
+    assert_eq!(scheduler.len(), 1);
+  }
+
+  #[test]
+  fn rescheduled_triggers_keep_their_place_at_a_price() {
+    let mut scheduler = TriggerScheduler::default();
+    let peg = scheduler.schedule(Side::Ask, 105.into(), "peg");
+    scheduler.schedule(Side::Ask, 100.into(), "stop");
+    assert!(scheduler.reschedule(peg, 100.into()));
+
+    let fired: Vec<_> = scheduler.fire(99.into()).into_iter().map(|(_, item)| item).collect();
+    assert_eq!(fired, vec!["peg", "stop"]);
+    assert!(!scheduler.reschedule(peg, 101.into()));
+    assert!(scheduler.is_empty());
+  }
+}
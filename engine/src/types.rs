@@ -1,13 +1,19 @@
 //! Order structs
 
+use crate::label::Label;
+use bitflags::bitflags;
 use derivative::Derivative;
 use derive_more::{Add, AddAssign, From, Into, Sub, Display};
+#[cfg(feature = "std")]
 use serde_derive::{Deserialize, Serialize};
-use std::cmp::Reverse;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops;
 
 
 /// A product symbol
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Derivative, From, Into)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Derivative, From, Into)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derivative(Debug = "transparent")]
 pub struct Symbol([char; 4]);
 
@@ -18,33 +24,46 @@ impl std::fmt::Display for Symbol {
 }
 
 /// Side of an order
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Hash)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub enum Side {
   Bid,
   Ask,
 }
 
+impl Side {
+  /// Return the side an order on this side executes against
+  pub fn opposite(self) -> Self {
+    match self {
+      Side::Bid => Side::Ask,
+      Side::Ask => Side::Bid,
+    }
+  }
+}
+
 /// An account ID
-#[derive(Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, Display, Add, AddAssign, Derivative, From, Into, Default)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Display, Add, AddAssign, Derivative, From, Into, Default)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derivative(Debug = "transparent")]
 pub struct AccountId(usize);
 
-/// An `Order` id local to the book
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, AddAssign, Derivative, From, Into, Serialize, Deserialize, Display)]
+/// A client session, e.g. one connection to the server
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Display, Derivative, From, Into, Default)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derivative(Debug = "transparent")]
-pub struct OrderId(usize);
+pub struct SessionId(usize);
 
-impl From<Price> for Reverse<Price> {
-  fn from(value: Price) -> Self {
-    Reverse(value)
-  }
-}
+/// A firm, grouping the accounts of one participant
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Display, Derivative, From, Into, Default)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derivative(Debug = "transparent")]
+pub struct FirmId(usize);
 
-impl Into<Price> for Reverse<Price> {
-  fn into(self) -> Price {
-    self.0
-  }
-}
+/// An `Order` id local to the book
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, AddAssign, Derivative, From, Into, Display)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derivative(Debug = "transparent")]
+pub struct OrderId(usize);
 
 impl Ord for OrderId {
   fn cmp(&self, other: &Self) -> std::cmp::Ordering {
@@ -66,6 +85,7 @@ impl PartialOrd for OrderId {
   Eq,
   PartialOrd,
   Ord,
+  Hash,
   Add,
   AddAssign,
   Sub,
@@ -73,10 +93,9 @@ impl PartialOrd for OrderId {
   Default,
   From,
   Into,
-  Serialize,
-  Deserialize,
   Display,
 )]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derivative(Debug = "transparent")]
 pub struct Price(u32);
 
@@ -88,6 +107,7 @@ pub struct Price(u32);
   Eq,
   PartialOrd,
   Ord,
+  Hash,
   Add,
   AddAssign,
   Sub,
@@ -95,20 +115,144 @@ pub struct Price(u32);
   Default,
   From,
   Into,
-  Serialize,
-  Deserialize,
   Display,
 )]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derivative(Debug = "transparent")]
-pub struct Quantity(u32);
-
-/// An order
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
-pub struct Order {
-  pub price: Price,
-  pub quantity: Quantity,
-  pub filled: Quantity,
+pub struct Quantity(u64);
+
+/// The most decimal places a symbol can quote quantities to
+///
+/// A `Quantity` counts the smallest increment of its symbol, i.e. units of `10^-precision`, so a symbol quoting more
+/// places can hold less: at 9 places the largest order is still over 18 billion whole units.
+pub const MAX_QUANTITY_PRECISION: u8 = 9;
+
+impl Quantity {
+  /// Get the value of this quantity at `price`, for a symbol quoting quantities to `precision` places, rounded down
+  ///
+  /// A value beyond `u64::MAX` is written as `u64::MAX`.
+  pub fn value_at(self, price: Price, precision: u8) -> u64 {
+    let value = u128::from(price.0) * u128::from(self.0) / 10u128.pow(u32::from(precision));
+    value.min(u128::from(u64::MAX)) as u64
+  }
+
+  /// Write this quantity as a decimal with `precision` places, e.g. 150000 at 5 places as `1.50000`
+  pub fn to_decimal(self, precision: u8) -> DecimalQuantity {
+    DecimalQuantity {
+      quantity: self,
+      precision,
+    }
+  }
+
+  /// Read a decimal with at most `precision` places, e.g. `1.5` at 5 places as 150000
+  ///
+  /// # Returns
+  /// `None` if `decimal` is not a decimal, has too many places or is too large a quantity
+  pub fn from_decimal(decimal: &str, precision: u8) -> Option<Self> {
+    let (whole, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+    let is_digits = |digits: &str| digits.bytes().all(|digit| digit.is_ascii_digit());
+    if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) || fraction.len() > usize::from(precision) {
+      return None;
+    }
+
+    let places = u32::from(precision);
+    let whole: u64 = whole.parse().ok()?;
+    let padding = 10u64.checked_pow(places - fraction.len() as u32)?;
+    let fraction: u64 = if fraction.is_empty() { 0 } else { fraction.parse().ok()? };
+    let fraction = fraction.checked_mul(padding)?;
+    whole.checked_mul(10u64.checked_pow(places)?)?.checked_add(fraction).map(Quantity)
+  }
+}
+
+/// A quantity written as a decimal, made by `Quantity::to_decimal`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalQuantity {
+  quantity: Quantity,
+  precision: u8,
+}
+
+impl std::fmt::Display for DecimalQuantity {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let scale = 10u64.pow(u32::from(self.precision));
+    let quantity = self.quantity.0;
+    match self.precision {
+      0 => write!(f, "{}", quantity),
+      places => write!(f, "{}.{:0places$}", quantity / scale, quantity % scale, places = usize::from(places)),
+    }
+  }
+}
+
+/// A type a book can price orders in, e.g. integer ticks or a fixed-point decimal
+pub trait BookPrice: Copy + Ord + Hash + Default + Debug + ops::Sub<Output = Self> {
+  /// Return the price halfway between this one and `other`, rounded down
+  fn midpoint(self, other: Self) -> Self;
+}
+
+impl BookPrice for Price {
+  fn midpoint(self, other: Self) -> Self {
+    Price(self.0.midpoint(other.0))
+  }
+}
+
+macro_rules! impl_book_price {
+  ($($integer:ty),*) => {
+    $(
+      impl BookPrice for $integer {
+        fn midpoint(self, other: Self) -> Self {
+          <$integer>::midpoint(self, other)
+        }
+      }
+    )*
+  };
+}
+
+impl_book_price!(u32, u64, u128, usize);
+
+/// A type a book can fill orders in
+pub trait BookQuantity:
+  Copy + Ord + Hash + Default + Debug + ops::Add<Output = Self> + ops::Sub<Output = Self> + ops::AddAssign
+{
+}
+
+impl<T> BookQuantity for T where
+  T: Copy + Ord + Hash + Default + Debug + ops::Add<Output = T> + ops::Sub<Output = T> + ops::AddAssign
+{
+}
+
+bitflags! {
+  /// Order entry flags
+  #[derive(Default)]
+  #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+  pub struct OrderFlags: u32 {
+    /// Rest in the symbol's non-displayed midpoint book
+    const DARK = 0b0001;
+    /// Expire at the end of the trading day, if still resting
+    const DAY = 0b0010;
+    /// Never match an order from another account of the same firm, whichever of the two arrived first
+    const ANTI_INTERNALIZATION = 0b0100;
+    /// Only match in the symbol's next auction uncross, and be cancelled by it if not filled
+    const AUCTION_ONLY = 0b1000;
+    /// When matching an order of its own account, take the quantity they would trade off both instead of trading
+    const DECREMENT_SELF_MATCH = 0b1_0000;
+  }
+}
+
+/// An order, priced in `P` and filled in `Q`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Order<P = Price, Q = Quantity> {
+  pub price: P,
+  pub quantity: Q,
+  pub filled: Q,
   pub is_cancelled: bool,
+  #[cfg_attr(feature = "std", serde(default))]
+  pub flags: OrderFlags,
+  /// The smallest quantity the order may be filled in a single execution
+  #[cfg_attr(feature = "std", serde(default))]
+  pub minimum_quantity: Q,
+  /// The client's own text for the order, e.g. the strategy that placed it, echoed on every event of the order
+  #[cfg_attr(feature = "std", serde(default))]
+  pub tag: Label,
 }
 
 impl Order {
@@ -118,6 +262,9 @@ impl Order {
       quantity,
       filled: Quantity(0),
       is_cancelled: false,
+      flags: OrderFlags::empty(),
+      minimum_quantity: Quantity(0),
+      tag: Label::EMPTY,
     }
   }
 
@@ -128,10 +275,44 @@ impl Order {
       quantity,
       filled,
       is_cancelled: false,
+      flags: OrderFlags::empty(),
+      minimum_quantity: Quantity(0),
+      tag: Label::EMPTY,
     }
   }
 
-  pub fn remaining(&self) -> Quantity {
+}
+
+impl<P: BookPrice, Q: BookQuantity> Order<P, Q> {
+  /// Create an order priced in any `BookPrice`
+  pub fn limit(price: P, quantity: Q) -> Self {
+    Self {
+      price,
+      quantity,
+      filled: Q::default(),
+      is_cancelled: false,
+      flags: OrderFlags::empty(),
+      minimum_quantity: Q::default(),
+      tag: Label::EMPTY,
+    }
+  }
+
+  pub fn with_flags(self, flags: OrderFlags) -> Self {
+    Self { flags, ..self }
+  }
+
+  pub fn with_minimum_quantity(self, minimum_quantity: Q) -> Self {
+    Self {
+      minimum_quantity,
+      ..self
+    }
+  }
+
+  pub fn with_tag(self, tag: Label) -> Self {
+    Self { tag, ..self }
+  }
+
+  pub fn remaining(&self) -> Q {
     self.quantity - self.filled
   }
 
@@ -139,3 +320,30 @@ impl Order {
     self.filled >= self.quantity
   }
 }
+
+/// Maps encoded as `[key, value]` pairs, as JSON object keys can only be strings
+#[cfg(feature = "std")]
+pub(crate) mod pairs {
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+  use std::iter::FromIterator;
+
+  pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+    K: Serialize + 'a,
+    V: Serialize + 'a,
+    S: Serializer,
+  {
+    serializer.collect_seq(map)
+  }
+
+  pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+  where
+    M: FromIterator<(K, V)>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+  {
+    Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+  }
+}
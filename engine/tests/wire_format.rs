@@ -0,0 +1,634 @@
+//! Golden-file tests pinning the JSON encoding of every message on the wire
+//!
+//! Each file under `tests/golden` holds one encoded message per line. An intended change to the format is recorded by
+//! running the tests with `UPDATE_GOLDEN=1` and reviewing the diff of the golden files.
+
+use engine::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const ADBE: [char; 4] = ['A', 'D', 'B', 'E'];
+
+fn march_4th() -> Date {
+  Date {
+    year: 2024,
+    month: 3,
+    day: 4,
+  }
+}
+
+/// Check that `messages` encode to the lines of a golden file, and that each line decodes back to its message
+fn check_golden<T: Serialize + DeserializeOwned + PartialEq + Debug>(name: &str, messages: &[T]) {
+  let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name].iter().collect();
+  let encoded: String = messages
+    .iter()
+    .map(|message| serde_json::to_string(message).unwrap() + "\n")
+    .collect();
+
+  if env::var_os("UPDATE_GOLDEN").is_some() {
+    fs::write(&path, &encoded).unwrap();
+  }
+
+  let golden = fs::read_to_string(&path).unwrap();
+  assert_eq!(encoded, golden, "{} no longer matches; rerun with UPDATE_GOLDEN=1 if this is intended", name);
+  for (line, message) in golden.lines().zip(messages) {
+    assert_eq!(&serde_json::from_str::<T>(line).unwrap(), message);
+  }
+}
+
+#[test]
+fn commands() {
+  let order = Order::new(25.into(), 100.into())
+    .with_flags(OrderFlags::DARK)
+    .with_minimum_quantity(10.into())
+    .with_tag(Label::new("momentum-7").unwrap());
+  let kinds = [
+    CommandKind::CancelOrder(3.into()),
+    CommandKind::PlaceOrder(Side::Ask, ADBE.into(), Order::new(25.into(), 100.into())),
+    CommandKind::PlaceOrder(Side::Bid, ADBE.into(), order),
+    CommandKind::GetOrder(3.into()),
+    CommandKind::ExecuteOrder(3.into()),
+    CommandKind::GetQuote(ADBE.into(), Side::Bid),
+    CommandKind::GetAccount(1.into()),
+    CommandKind::GetAccountSummary(1.into()),
+    CommandKind::GetIndex(ADBE.into()),
+    CommandKind::GetOrderToTradeRatio(1.into()),
+    CommandKind::GetOpenOrders(1.into(), Page { after: Some(3.into()), limit: 100 }),
+    CommandKind::GetLevel(ADBE.into(), Side::Ask, 25.into()),
+    CommandKind::UpdateOrder(3.into(), Some(26.into()), None),
+    CommandKind::SuspendOrder(3.into()),
+    CommandKind::ResumeOrder(3.into()),
+    CommandKind::SubscribeExecutions(1.into()),
+    CommandKind::Subscribe(ADBE.into(), Feed::Depth(Some(10))),
+    CommandKind::Unsubscribe(ADBE.into(), Feed::Candles),
+    CommandKind::Conflate(Some(Duration::from_millis(250))),
+    CommandKind::SetAckMode(AckMode::Fast),
+    CommandKind::SetEntitlement(1.into(), Entitlement::FullDepth),
+    CommandKind::SetReplaceProtection(
+      1.into(),
+      Some(ReplaceProtection {
+        band: 2.into(),
+        action: ProtectionAction::Cap,
+      }),
+    ),
+    CommandKind::GetTradesHistory(ADBE.into(), Timestamp::from(1_000), Timestamp::from(2_000)),
+    CommandKind::GetQuotesHistory(ADBE.into(), Timestamp::from(1_000), Timestamp::from(2_000)),
+    CommandKind::CreateAccount(NewAccount {
+      reference: Label::new("crm-1042").unwrap(),
+      name: Label::new("Acme Capital").unwrap(),
+      firm: Some(2.into()),
+    }),
+    CommandKind::SetAccountState(1.into(), AccountState::LiquidationOnly),
+    CommandKind::GetFeeTier(1.into()),
+    CommandKind::GetSymbolFeeTier(1.into(), ADBE.into()),
+    CommandKind::GetEquity(1.into()),
+    CommandKind::GetLiquidation(1.into()),
+    CommandKind::GetQueuePosition(3.into()),
+    CommandKind::GetBookStats(ADBE.into()),
+    CommandKind::SetRejectAll(true),
+    CommandKind::SetRiskBypass(Some(RiskBypass {
+      checks: RiskChecks::REJECT_ALL | RiskChecks::BUYING_POWER,
+      reason: Label::new("unwind INC-42").unwrap(),
+    })),
+    CommandKind::SetRiskBypass(None),
+    CommandKind::GetDeadLetters,
+    CommandKind::DumpBook(ADBE.into(), DumpFormat::Csv),
+    CommandKind::RestrictSymbol(ADBE.into(), true),
+    CommandKind::SetSymbolPermission(ADBE.into(), Grantee::Firm(2.into()), true),
+    CommandKind::GetSymbolPermissions(ADBE.into()),
+    CommandKind::CancelTagged(1.into(), Label::new("momentum-7").unwrap()),
+    CommandKind::ListSymbol(ADBE.into()),
+    CommandKind::ReplaceSymbol(ADBE.into()),
+    CommandKind::HaltSymbol(ADBE.into(), true),
+    CommandKind::GetWatchdog,
+    CommandKind::GetActivity(1.into(), 10, 100),
+    CommandKind::GetFees(Some(1.into()), march_4th(), FeePeriod::MonthToDate),
+    CommandKind::GetFees(None, march_4th(), FeePeriod::Day),
+  ];
+  let commands: Vec<_> = kinds
+    .iter()
+    .map(|&kind| Command {
+      account_id: 1.into(),
+      kind,
+    })
+    .collect();
+
+  check_golden("command.jsonl", &commands);
+}
+
+#[test]
+fn successes() {
+  let mut portfolio = HashMap::new();
+  portfolio.insert(ADBE.into(), 40.into());
+  let account = Account {
+    firm: Some(2.into()),
+    beneficial_owner: Some(0.into()),
+    is_admin: false,
+    balance: 1_000.into(),
+    orders: vec![3.into(), 4.into()],
+    portfolio,
+    entitlement: Entitlement::Depth(5),
+    reference: Label::new("crm-1042"),
+    name: Label::new("Acme Capital").unwrap(),
+    state: AccountState::Suspended,
+    replace_protection: Some(ReplaceProtection {
+      band: 3.into(),
+      action: ProtectionAction::Reject,
+    }),
+  };
+  let execution = Execution {
+    id: 3.into(),
+    trade: 7.into(),
+    quantity: 60.into(),
+    is_filled: true,
+    received_at: Timestamp::from(1_000),
+    matched_at: Timestamp::from(1_500),
+  };
+
+  let placement = Placement {
+    id: 3.into(),
+    fills: vec![Fill {
+      execution: 9.into(),
+      trade: 7.into(),
+      liquidity: Liquidity::Taker,
+      price: 25.into(),
+      quantity: 40.into(),
+    }],
+    remaining: 60.into(),
+    state: OrderState::PartiallyFilled,
+    quantity_precision: 2,
+  };
+
+  check_golden("success.jsonl", &[
+    Success::GetOrder(OrderStatus {
+      order: Order::new_partially_filled(25.into(), 100.into(), 40.into()),
+      average_price: Some(24.5),
+    }),
+    Success::PlaceOrder(placement),
+    Success::CancelOrder(true),
+    Success::CancelPending(Timestamp::from(2_000)),
+    Success::UpdateOrder(false),
+    Success::SuspendOrder(true),
+    Success::ResumeOrder(false),
+    Success::ExecuteOrder(false, vec![execution]),
+    Success::GetQuote(25.into()),
+    Success::GetAccountSummary(AccountSummary::from(&account)),
+    Success::GetAccount(account),
+    Success::GetIndex(Some(12.5)),
+    Success::GetIndex(None),
+    Success::GetOrderToTradeRatio(OrderToTradeStatus {
+      orders: 30,
+      trades: 1,
+      ratio: 30.0,
+      consequence: Consequence::Warning,
+    }),
+    Success::GetOpenOrders(OrderPage {
+      orders: vec![(4.into(), Order::new(25.into(), 100.into()))],
+      next: Some(4.into()),
+    }),
+    Success::GetLevel(vec![QueueEntry {
+      id: 4.into(),
+      remaining: 60.into(),
+      position: 0,
+    }]),
+    Success::SubscribeExecutions,
+    Success::Subscribe,
+    Success::Unsubscribe,
+    Success::Conflate,
+    Success::SetEntitlement,
+    Success::SetReplaceProtection,
+    Success::GetTradesHistory(vec![TradeTick {
+      timestamp: Timestamp::from(1_500),
+      price: 25.into(),
+      quantity: 40.into(),
+    }]),
+    Success::GetQuotesHistory(vec![QuoteTick {
+      timestamp: Timestamp::from(1_500),
+      bid: Some((24.into(), 10.into())),
+      ask: None,
+    }]),
+    Success::CreateAccount(1.into()),
+    Success::SetAccountState,
+    Success::SetRejectAll,
+    Success::SetRiskBypass,
+    Success::GetFeeTier(FeeTierStatus {
+      tier: 1,
+      volume: 12_000,
+      maker_rate: -2,
+      taker_rate: 3,
+      next_tier_volume: Some(50_000),
+    }),
+    Success::GetEquity(Equity {
+      account: 1.into(),
+      cash: 10_000,
+      position_value: 2_500,
+      reserved: 1_200,
+      equity: 12_500,
+    }),
+    Success::GetLiquidation(Some(Liquidation {
+      account: 1.into(),
+      started_at: 1_500.into(),
+      buying_power: 900,
+      reserved: 1_200,
+      orders: vec![5.into(), 6.into()],
+    })),
+    Success::GetLiquidation(None),
+    Success::GetQueuePosition(QueuePosition {
+      price: 25.into(),
+      position: 1,
+      ahead: 60.into(),
+      orders: 3,
+      quantity: 100.into(),
+    }),
+    Success::GetBookStats(BookStats {
+      symbol: ADBE.into(),
+      orders: 4,
+      bid_quantity: 150.into(),
+      ask_quantity: 100.into(),
+      bid_notional: 3_700,
+      ask_notional: 2_600,
+    }),
+    Success::GetDeadLetters(vec![DeadLetters {
+      session: 2.into(),
+      count: 3,
+      inputs: vec!["{\"account_id\":1,\"kind\":".to_string()],
+    }]),
+    Success::DumpBook("dumps/ADBE-1000.csv".to_string()),
+    Success::Accepted(3.into()),
+    Success::SetAckMode,
+    Success::RestrictSymbol,
+    Success::SetSymbolPermission,
+    Success::GetSymbolPermissions(SymbolPermissions {
+      is_restricted: true,
+      grantees: vec![Grantee::Account(1.into()), Grantee::Firm(2.into())],
+    }),
+    Success::CancelTagged(vec![3.into(), 4.into()]),
+    Success::ListSymbol,
+    Success::ReplaceSymbol(vec![3.into(), 4.into()]),
+    Success::HaltSymbol,
+    Success::GetWatchdog(vec![SymbolProgress {
+      symbol: ADBE.into(),
+      sequence: Some(12.into()),
+      processed_at: Timestamp::from(1_000),
+      busy_since: Some(Timestamp::from(2_000)),
+      processed: 4,
+      pending: 2,
+      max_latency: 250,
+      stalls: 1,
+      is_stalled: true,
+      is_halted: false,
+    }]),
+    Success::GetActivity(ActivityPage {
+      entries: vec![
+        ActivityEntry {
+          cursor: 11,
+          timestamp: Timestamp::from(1_000),
+          activity: Activity::OrderAccepted {
+            order: 3.into(),
+            symbol: ADBE.into(),
+            side: Side::Bid,
+            price: 100.into(),
+            quantity: 10.into(),
+          },
+        },
+        ActivityEntry {
+          cursor: 12,
+          timestamp: Timestamp::from(1_000),
+          activity: Activity::OrderRejected { order: None },
+        },
+        ActivityEntry {
+          cursor: 13,
+          timestamp: Timestamp::from(1_000),
+          activity: Activity::OrderModified { order: 3.into() },
+        },
+        ActivityEntry {
+          cursor: 14,
+          timestamp: Timestamp::from(2_000),
+          activity: Activity::Fill {
+            order: 3.into(),
+            symbol: ADBE.into(),
+            side: Side::Bid,
+            price: 100.into(),
+            quantity: 4.into(),
+            trade: 7.into(),
+            filled: 4.into(),
+          },
+        },
+        ActivityEntry {
+          cursor: 15,
+          timestamp: Timestamp::from(2_000),
+          activity: Activity::Fee {
+            amount: -2,
+            trade: Some(7.into()),
+            balance: 1_002.into(),
+          },
+        },
+        ActivityEntry {
+          cursor: 16,
+          timestamp: Timestamp::from(3_000),
+          activity: Activity::OrderCancelled {
+            order: 3.into(),
+            reason: CancelReason::EndOfDay,
+          },
+        },
+        ActivityEntry {
+          cursor: 17,
+          timestamp: Timestamp::from(4_000),
+          activity: Activity::Settlement {
+            symbol: ADBE.into(),
+            bought: 4.into(),
+            sold: 0.into(),
+            paid: 400,
+            received: 0,
+          },
+        },
+      ],
+      next: Some(17),
+      latest: 20,
+    }),
+    Success::GetFees(FeeReport {
+      from: Date { day: 1, ..march_4th() },
+      to: march_4th(),
+      lines: vec![FeeLine {
+        account: 1.into(),
+        symbol: Some(ADBE.into()),
+        fill_fees: 30,
+        rebates: 10,
+        order_fees: 5,
+        net: 25,
+        fills: 2,
+      }],
+      totals: vec![FeeLine {
+        account: 1.into(),
+        symbol: None,
+        fill_fees: 30,
+        rebates: 10,
+        order_fees: 5,
+        net: 25,
+        fills: 2,
+      }],
+    }),
+  ]);
+}
+
+#[test]
+fn errors() {
+  check_golden("error.jsonl", &[
+    Error::AccountDoesNotExist { id: 1.into() },
+    Error::SymbolDoesNotExist { symbol: ADBE.into() },
+    Error::IdDoesNotExist { id: 3.into() },
+    Error::IndexDoesNotExist { symbol: ADBE.into() },
+    Error::ContinuousTradingDisabled { symbol: ADBE.into() },
+    Error::DarkPoolDisabled { symbol: ADBE.into() },
+    Error::PermissionDenied { id: 1.into() },
+    Error::OrderToTradeRatioExceeded { id: 1.into() },
+    Error::StateHashMismatch { expected: 1, actual: 2 },
+    Error::InvalidConfig {
+      reason: ConfigError::ZeroAuctionInterval { symbol: ADBE.into() },
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::UnorderedOrderToTradeRatios,
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::UnorderedFeeTiers,
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::HaircutOutOfRange { symbol: ADBE.into() },
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::InvalidAuctionSchedule { symbol: ADBE.into() },
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::QuantityPrecisionOutOfRange { symbol: ADBE.into() },
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::ZeroLotSize { symbol: ADBE.into() },
+    },
+    Error::InvalidConfig {
+      reason: ConfigError::InvalidIndexDivisor,
+    },
+    Error::TooManyConnections { limit: 1024 },
+    Error::JournalOutOfOrder {
+      last: 7.into(),
+      sequence: 5.into(),
+    },
+    Error::NotEntitled {
+      id: 1.into(),
+      entitlement: Entitlement::Bbo,
+    },
+    Error::AccountRestricted {
+      id: 1.into(),
+      state: AccountState::Closed,
+    },
+    Error::InsufficientBuyingPower {
+      id: 1.into(),
+      buying_power: 2_500,
+    },
+    Error::RejectingAllOrders { id: 1.into() },
+    Error::MalformedCommand { line: 1, column: 12 },
+    Error::QuoteLifeNotReached {
+      id: 3.into(),
+      rests_until: Timestamp::from(1_500),
+    },
+    Error::DumpFailed { symbol: ADBE.into() },
+    Error::NotServedOnEndpoint {
+      role: EndpointRole::DropCopy,
+    },
+    Error::SourceNotAllowed {
+      id: 1.into(),
+      source: "10.1.2.3".parse().unwrap(),
+    },
+    Error::SymbolNotPermitted {
+      id: 1.into(),
+      symbol: ADBE.into(),
+    },
+    Error::ReplaceOutsideBand {
+      id: 3.into(),
+      limit: 27.into(),
+    },
+    Error::InvalidLotSize {
+      symbol: ADBE.into(),
+      lot_size: 100.into(),
+    },
+    Error::MissingBypassReason,
+    Error::InsufficientHoldings {
+      id: 1.into(),
+      symbol: ADBE.into(),
+      holding: 4.into(),
+    },
+    Error::SymbolAlreadyExists { symbol: ADBE.into() },
+    Error::InconsistentBook { symbol: ADBE.into() },
+    Error::SymbolHalted { symbol: ADBE.into() },
+    Error::ActivityExpired { id: 1.into(), oldest: 5 },
+  ]);
+}
+
+#[test]
+fn market_data() {
+  check_golden("market_data.jsonl", &[
+    MarketData::Trade {
+      symbol: ADBE.into(),
+      price: 25.into(),
+      quantity: 100.into(),
+      trade: 7.into(),
+    },
+    MarketData::DarkTrade {
+      symbol: ADBE.into(),
+      price: 25.into(),
+      quantity: 100.into(),
+      trade: 8.into(),
+    },
+    MarketData::AuctionUncross {
+      symbol: ADBE.into(),
+      price: 25.into(),
+      quantity: 100.into(),
+    },
+    MarketData::AuctionCall {
+      symbol: ADBE.into(),
+      uncrosses_at: Timestamp::from(3_600_000_000_000),
+    },
+    MarketData::PriceImprovementAuction {
+      symbol: ADBE.into(),
+      side: Side::Bid,
+      price: 25.into(),
+      quantity: 100.into(),
+      ends_at: Timestamp::from(1_000) + Duration::from_millis(5),
+    },
+    MarketData::IndexValue {
+      symbol: ADBE.into(),
+      value: 12.5,
+    },
+    MarketData::ClosingPrice {
+      symbol: ADBE.into(),
+      price: 25.into(),
+      volume: 1_000.into(),
+    },
+  ]);
+}
+
+#[test]
+fn pushes() {
+  check_golden("push.jsonl", &[
+    Push::ExecutionReport {
+      sequence: 1,
+      record: AuditRecord {
+        sequence: 9.into(),
+        timestamp: Timestamp::from(1_000),
+        session: 2.into(),
+        account: 1.into(),
+        event: AuditEvent::Execute,
+        order: Some(4.into()),
+        symbol: Some(ADBE.into()),
+        side: Some(Side::Ask),
+        price: Some(25.into()),
+        quantity: Some(40.into()),
+        trade: Some(7.into()),
+        liquidity: Some(Liquidity::Maker),
+        filled: Some(40.into()),
+        average_price: Some(25.0),
+        source: None,
+        tag: Some(Label::new("momentum-7").unwrap()),
+        check: None,
+        reason: None,
+        cancel_reason: None,
+      },
+      quantity_precision: Some(2),
+    },
+    Push::Bbo {
+      symbol: ADBE.into(),
+      bid: Some((24.into(), 10.into())),
+      ask: None,
+      quantity_precision: 2,
+    },
+    Push::Depth {
+      symbol: ADBE.into(),
+      bids: vec![(24.into(), 10.into()), (23.into(), 5.into())],
+      asks: vec![(26.into(), 1.into())],
+      quantity_precision: 2,
+    },
+    Push::Trade {
+      symbol: ADBE.into(),
+      price: 25.into(),
+      quantity: 40.into(),
+      trade: 7.into(),
+      quantity_precision: 2,
+    },
+    Push::Candle(Candle {
+      symbol: ADBE.into(),
+      start: Timestamp::from(60_000_000_000),
+      open: 25.into(),
+      high: 27.into(),
+      low: 24.into(),
+      close: 26.into(),
+      volume: 90.into(),
+    }),
+    Push::SelfMatchPrevented(AuditRecord {
+      sequence: 10.into(),
+      timestamp: Timestamp::from(1_000),
+      session: 2.into(),
+      account: 1.into(),
+      event: AuditEvent::SelfMatchPrevented,
+      order: Some(5.into()),
+      symbol: Some(ADBE.into()),
+      side: Some(Side::Bid),
+      price: Some(25.into()),
+      quantity: Some(10.into()),
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    }),
+    Push::Cancelled(AuditRecord {
+      sequence: 11.into(),
+      timestamp: Timestamp::from(2_000),
+      session: 2.into(),
+      account: 1.into(),
+      event: AuditEvent::Cancel,
+      order: Some(5.into()),
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: Some(CancelReason::EndOfDay),
+    }),
+    Push::Latency(LatencyTrace {
+      received_at: Timestamp::from(1_000),
+      dequeued_at: Timestamp::from(1_500),
+      matched_at: Timestamp::from(4_000),
+      sent_at: Timestamp::from(9_000),
+    }),
+  ]);
+}
+
+#[test]
+fn replies() {
+  check_golden("reply.jsonl", &[
+    Ok(Success::PlaceOrder(Placement {
+      id: 3.into(),
+      fills: vec![],
+      remaining: 100.into(),
+      state: OrderState::New,
+      quantity_precision: 0,
+    })),
+    Err(Error::IdDoesNotExist { id: 3.into() }),
+  ]);
+}
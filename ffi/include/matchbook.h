@@ -0,0 +1,197 @@
+/* Generated by cbindgen from src/lib.rs. Do not edit by hand. */
+
+#ifndef MATCHBOOK_H
+#define MATCHBOOK_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define MATCHBOOK_SIDE_BID 0
+
+#define MATCHBOOK_SIDE_ASK 1
+
+#define MATCHBOOK_COMMAND_PLACE 0
+
+#define MATCHBOOK_COMMAND_CANCEL 1
+
+#define MATCHBOOK_COMMAND_EXECUTE 2
+
+typedef enum MatchbookEventKind {
+  MATCHBOOK_EVENT_KIND_TRADE,
+  MATCHBOOK_EVENT_KIND_DARK_TRADE,
+  MATCHBOOK_EVENT_KIND_AUCTION_UNCROSS,
+  MATCHBOOK_EVENT_KIND_AUCTION_CALL,
+  MATCHBOOK_EVENT_KIND_PRICE_IMPROVEMENT_AUCTION,
+  MATCHBOOK_EVENT_KIND_INDEX_VALUE,
+  MATCHBOOK_EVENT_KIND_CLOSING_PRICE,
+} MatchbookEventKind;
+
+/**
+ * The result of a call
+ */
+typedef enum MatchbookStatus {
+  MATCHBOOK_STATUS_OK = 0,
+  MATCHBOOK_STATUS_INVALID_ARGUMENT,
+  MATCHBOOK_STATUS_PANIC,
+  MATCHBOOK_STATUS_ACCOUNT_DOES_NOT_EXIST,
+  MATCHBOOK_STATUS_SYMBOL_DOES_NOT_EXIST,
+  MATCHBOOK_STATUS_ID_DOES_NOT_EXIST,
+  MATCHBOOK_STATUS_INDEX_DOES_NOT_EXIST,
+  MATCHBOOK_STATUS_CONTINUOUS_TRADING_DISABLED,
+  MATCHBOOK_STATUS_DARK_POOL_DISABLED,
+  MATCHBOOK_STATUS_PERMISSION_DENIED,
+  MATCHBOOK_STATUS_ORDER_TO_TRADE_RATIO_EXCEEDED,
+  MATCHBOOK_STATUS_STATE_HASH_MISMATCH,
+  MATCHBOOK_STATUS_INVALID_CONFIG,
+  MATCHBOOK_STATUS_TOO_MANY_CONNECTIONS,
+  MATCHBOOK_STATUS_JOURNAL_OUT_OF_ORDER,
+  MATCHBOOK_STATUS_NOT_ENTITLED,
+  MATCHBOOK_STATUS_ACCOUNT_RESTRICTED,
+  MATCHBOOK_STATUS_INSUFFICIENT_BUYING_POWER,
+  MATCHBOOK_STATUS_REJECTING_ALL_ORDERS,
+  MATCHBOOK_STATUS_MALFORMED_COMMAND,
+  MATCHBOOK_STATUS_QUOTE_LIFE_NOT_REACHED,
+  MATCHBOOK_STATUS_DUMP_FAILED,
+  MATCHBOOK_STATUS_NOT_SERVED_ON_ENDPOINT,
+  MATCHBOOK_STATUS_SOURCE_NOT_ALLOWED,
+  MATCHBOOK_STATUS_SYMBOL_NOT_PERMITTED,
+  MATCHBOOK_STATUS_REPLACE_OUTSIDE_BAND,
+  MATCHBOOK_STATUS_INVALID_LOT_SIZE,
+  MATCHBOOK_STATUS_MISSING_BYPASS_REASON,
+  MATCHBOOK_STATUS_INSUFFICIENT_HOLDINGS,
+  MATCHBOOK_STATUS_SYMBOL_ALREADY_EXISTS,
+  MATCHBOOK_STATUS_INCONSISTENT_BOOK,
+  MATCHBOOK_STATUS_SYMBOL_HALTED,
+  MATCHBOOK_STATUS_ACTIVITY_EXPIRED,
+} MatchbookStatus;
+
+/**
+ * An engine handle
+ */
+typedef struct MatchbookEngine MatchbookEngine;
+
+/**
+ * A market data event
+ *
+ * `side` is only set for price improvement auctions, `ends_at` only for them and auction calls, `value` only for index
+ * values and `trade` only for trades and dark trades.
+ */
+typedef struct MatchbookEvent {
+  MatchbookEventKind kind;
+  uint8_t symbol[4];
+  uint32_t side;
+  uint32_t price;
+  uint64_t quantity;
+  /**
+   * Nanoseconds since the unix epoch
+   */
+  uint64_t ends_at;
+  double value;
+  uint64_t trade;
+} MatchbookEvent;
+
+/**
+ * Called with each market data event and the context it was registered with
+ */
+typedef void (*MatchbookEventCallback)(const MatchbookEvent *event, void *context);
+
+/**
+ * An order entry command
+ *
+ * `kind` is one of the `MATCHBOOK_COMMAND_*` constants. `side`, `symbol`, `price` and `quantity` are read for places,
+ * and `order` for cancels and executes.
+ */
+typedef struct MatchbookCommand {
+  uint64_t account;
+  uint32_t kind;
+  uint32_t side;
+  /**
+   * ASCII, not nul terminated
+   */
+  uint8_t symbol[4];
+  uint32_t price;
+  uint64_t quantity;
+  uint64_t order;
+} MatchbookCommand;
+
+/**
+ * What a successful command did
+ */
+typedef struct MatchbookReply {
+  /**
+   * The id of a placed order
+   */
+  uint64_t order;
+  /**
+   * A cancelled order was resting, or an executed order is filled
+   */
+  bool is_done;
+} MatchbookReply;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an engine
+ *
+ * # Returns
+ * a handle to free with `matchbook_engine_free`
+ */
+MatchbookEngine *matchbook_engine_new(void);
+
+/**
+ * Free an engine
+ *
+ * # Safety
+ * `engine` must be null or a handle from `matchbook_engine_new` that has not been freed
+ */
+void matchbook_engine_free(MatchbookEngine *engine);
+
+/**
+ * Register the callback market data is delivered to, replacing any previous one, or clear it with null
+ *
+ * # Safety
+ * `engine` must be null or a live handle, and `context` must stay valid for as long as the callback is registered
+ */
+MatchbookStatus matchbook_set_event_callback(MatchbookEngine *engine, MatchbookEventCallback callback, void *context);
+
+/**
+ * Create an account
+ *
+ * # Safety
+ * `engine` must be null or a live handle, and `account` must be null or writable
+ */
+MatchbookStatus matchbook_create_account(MatchbookEngine *engine, uint64_t *account);
+
+/**
+ * Insert a symbol, failing with `MATCHBOOK_STATUS_SYMBOL_ALREADY_EXISTS` if it was already inserted
+ *
+ * # Safety
+ * `engine` must be null or a live handle, and `symbol` must be null or point to 4 readable bytes
+ */
+MatchbookStatus matchbook_insert_symbol(MatchbookEngine *engine, const uint8_t *symbol);
+
+/**
+ * Process an order entry command
+ *
+ * # Safety
+ * `engine` must be null or a live handle, `command` must be null or readable, and `reply` must be null or writable
+ */
+MatchbookStatus matchbook_process(MatchbookEngine *engine, const MatchbookCommand *command, MatchbookReply *reply);
+
+/**
+ * Run scheduled work, such as auctions, that is due
+ *
+ * # Safety
+ * `engine` must be null or a live handle
+ */
+MatchbookStatus matchbook_tick(MatchbookEngine *engine);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* MATCHBOOK_H */
@@ -0,0 +1,458 @@
+//! C API
+//!
+//! The engine is an opaque `MatchbookEngine` handle. Commands are plain `MatchbookCommand` structs, and market data is
+//! delivered synchronously to a registered callback as each command or tick publishes it. Every function is safe to
+//! call with a null handle, which is reported as `MATCHBOOK_STATUS_INVALID_ARGUMENT`, and a panic inside the engine is
+//! reported as `MATCHBOOK_STATUS_PANIC` rather than unwinding into C.
+//!
+//! `include/matchbook.h` is generated from this file; see `cbindgen.toml`.
+
+use engine::*;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+
+pub const MATCHBOOK_SIDE_BID: u32 = 0;
+pub const MATCHBOOK_SIDE_ASK: u32 = 1;
+
+pub const MATCHBOOK_COMMAND_PLACE: u32 = 0;
+pub const MATCHBOOK_COMMAND_CANCEL: u32 = 1;
+pub const MATCHBOOK_COMMAND_EXECUTE: u32 = 2;
+
+/// An engine handle
+pub struct MatchbookEngine {
+  engine: MatchEngine,
+  callback: Option<MatchbookEventCallback>,
+  context: *mut c_void,
+}
+
+/// The result of a call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchbookStatus {
+  Ok = 0,
+  InvalidArgument,
+  Panic,
+  AccountDoesNotExist,
+  SymbolDoesNotExist,
+  IdDoesNotExist,
+  IndexDoesNotExist,
+  ContinuousTradingDisabled,
+  DarkPoolDisabled,
+  PermissionDenied,
+  OrderToTradeRatioExceeded,
+  StateHashMismatch,
+  InvalidConfig,
+  TooManyConnections,
+  JournalOutOfOrder,
+  NotEntitled,
+  AccountRestricted,
+  InsufficientBuyingPower,
+  RejectingAllOrders,
+  MalformedCommand,
+  QuoteLifeNotReached,
+  DumpFailed,
+  NotServedOnEndpoint,
+  SourceNotAllowed,
+  SymbolNotPermitted,
+  ReplaceOutsideBand,
+  InvalidLotSize,
+  MissingBypassReason,
+  InsufficientHoldings,
+  SymbolAlreadyExists,
+  InconsistentBook,
+  SymbolHalted,
+  ActivityExpired,
+}
+
+impl From<Error> for MatchbookStatus {
+  fn from(error: Error) -> Self {
+    use Error::*;
+    match error {
+      AccountDoesNotExist { .. } => MatchbookStatus::AccountDoesNotExist,
+      SymbolDoesNotExist { .. } => MatchbookStatus::SymbolDoesNotExist,
+      IdDoesNotExist { .. } => MatchbookStatus::IdDoesNotExist,
+      IndexDoesNotExist { .. } => MatchbookStatus::IndexDoesNotExist,
+      ContinuousTradingDisabled { .. } => MatchbookStatus::ContinuousTradingDisabled,
+      DarkPoolDisabled { .. } => MatchbookStatus::DarkPoolDisabled,
+      PermissionDenied { .. } => MatchbookStatus::PermissionDenied,
+      OrderToTradeRatioExceeded { .. } => MatchbookStatus::OrderToTradeRatioExceeded,
+      StateHashMismatch { .. } => MatchbookStatus::StateHashMismatch,
+      InvalidConfig { .. } => MatchbookStatus::InvalidConfig,
+      TooManyConnections { .. } => MatchbookStatus::TooManyConnections,
+      JournalOutOfOrder { .. } => MatchbookStatus::JournalOutOfOrder,
+      NotEntitled { .. } => MatchbookStatus::NotEntitled,
+      AccountRestricted { .. } => MatchbookStatus::AccountRestricted,
+      InsufficientBuyingPower { .. } => MatchbookStatus::InsufficientBuyingPower,
+      RejectingAllOrders { .. } => MatchbookStatus::RejectingAllOrders,
+      MalformedCommand { .. } => MatchbookStatus::MalformedCommand,
+      QuoteLifeNotReached { .. } => MatchbookStatus::QuoteLifeNotReached,
+      DumpFailed { .. } => MatchbookStatus::DumpFailed,
+      NotServedOnEndpoint { .. } => MatchbookStatus::NotServedOnEndpoint,
+      SourceNotAllowed { .. } => MatchbookStatus::SourceNotAllowed,
+      SymbolNotPermitted { .. } => MatchbookStatus::SymbolNotPermitted,
+      ReplaceOutsideBand { .. } => MatchbookStatus::ReplaceOutsideBand,
+      InvalidLotSize { .. } => MatchbookStatus::InvalidLotSize,
+      MissingBypassReason => MatchbookStatus::MissingBypassReason,
+      InsufficientHoldings { .. } => MatchbookStatus::InsufficientHoldings,
+      SymbolAlreadyExists { .. } => MatchbookStatus::SymbolAlreadyExists,
+      InconsistentBook { .. } => MatchbookStatus::InconsistentBook,
+      SymbolHalted { .. } => MatchbookStatus::SymbolHalted,
+      ActivityExpired { .. } => MatchbookStatus::ActivityExpired,
+    }
+  }
+}
+
+/// An order entry command
+///
+/// `kind` is one of the `MATCHBOOK_COMMAND_*` constants. `side`, `symbol`, `price` and `quantity` are read for places,
+/// and `order` for cancels and executes.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MatchbookCommand {
+  pub account: u64,
+  pub kind: u32,
+  pub side: u32,
+  /// ASCII, not nul terminated
+  pub symbol: [u8; 4],
+  pub price: u32,
+  pub quantity: u64,
+  pub order: u64,
+}
+
+/// What a successful command did
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchbookReply {
+  /// The id of a placed order
+  pub order: u64,
+  /// A cancelled order was resting, or an executed order is filled
+  pub is_done: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchbookEventKind {
+  Trade,
+  DarkTrade,
+  AuctionUncross,
+  AuctionCall,
+  PriceImprovementAuction,
+  IndexValue,
+  ClosingPrice,
+}
+
+/// A market data event
+///
+/// `side` is only set for price improvement auctions, `ends_at` only for them and auction calls, `value` only for index
+/// values and `trade` only for trades and dark trades.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchbookEvent {
+  pub kind: MatchbookEventKind,
+  pub symbol: [u8; 4],
+  pub side: u32,
+  pub price: u32,
+  pub quantity: u64,
+  /// Nanoseconds since the unix epoch
+  pub ends_at: u64,
+  pub value: f64,
+  pub trade: u64,
+}
+
+impl From<MarketData> for MatchbookEvent {
+  fn from(data: MarketData) -> Self {
+    let event = |kind, symbol: Symbol, price: Price, quantity: Quantity| MatchbookEvent {
+      kind,
+      symbol: symbol_to_bytes(symbol),
+      side: MATCHBOOK_SIDE_BID,
+      price: price.into(),
+      quantity: quantity.into(),
+      ends_at: 0,
+      value: 0.0,
+      trade: 0,
+    };
+
+    match data {
+      MarketData::Trade {
+        symbol,
+        price,
+        quantity,
+        trade,
+      } => MatchbookEvent {
+        trade: trade.into(),
+        ..event(MatchbookEventKind::Trade, symbol, price, quantity)
+      },
+      MarketData::DarkTrade {
+        symbol,
+        price,
+        quantity,
+        trade,
+      } => MatchbookEvent {
+        trade: trade.into(),
+        ..event(MatchbookEventKind::DarkTrade, symbol, price, quantity)
+      },
+      MarketData::AuctionUncross { symbol, price, quantity } => {
+        event(MatchbookEventKind::AuctionUncross, symbol, price, quantity)
+      }
+      MarketData::AuctionCall { symbol, uncrosses_at } => MatchbookEvent {
+        ends_at: uncrosses_at.into(),
+        ..event(MatchbookEventKind::AuctionCall, symbol, 0.into(), 0.into())
+      },
+      MarketData::PriceImprovementAuction {
+        symbol,
+        side,
+        price,
+        quantity,
+        ends_at,
+      } => MatchbookEvent {
+        side: side_to_u32(side),
+        ends_at: ends_at.into(),
+        ..event(MatchbookEventKind::PriceImprovementAuction, symbol, price, quantity)
+      },
+      MarketData::IndexValue { symbol, value } => MatchbookEvent {
+        value,
+        ..event(MatchbookEventKind::IndexValue, symbol, 0.into(), 0.into())
+      },
+      MarketData::ClosingPrice { symbol, price, volume } => {
+        event(MatchbookEventKind::ClosingPrice, symbol, price, volume)
+      }
+    }
+  }
+}
+
+/// Called with each market data event and the context it was registered with
+pub type MatchbookEventCallback = extern "C" fn(event: *const MatchbookEvent, context: *mut c_void);
+
+/// Create an engine
+///
+/// # Returns
+/// a handle to free with `matchbook_engine_free`
+#[no_mangle]
+pub extern "C" fn matchbook_engine_new() -> *mut MatchbookEngine {
+  Box::into_raw(Box::new(MatchbookEngine {
+    engine: MatchEngine::default(),
+    callback: None,
+    context: std::ptr::null_mut(),
+  }))
+}
+
+/// Free an engine
+///
+/// # Safety
+/// `engine` must be null or a handle from `matchbook_engine_new` that has not been freed
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_engine_free(engine: *mut MatchbookEngine) {
+  if !engine.is_null() {
+    drop(Box::from_raw(engine));
+  }
+}
+
+/// Register the callback market data is delivered to, replacing any previous one, or clear it with null
+///
+/// # Safety
+/// `engine` must be null or a live handle, and `context` must stay valid for as long as the callback is registered
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_set_event_callback(
+  engine: *mut MatchbookEngine,
+  callback: Option<MatchbookEventCallback>,
+  context: *mut c_void,
+) -> MatchbookStatus {
+  match engine.as_mut() {
+    Some(engine) => {
+      engine.callback = callback;
+      engine.context = context;
+      MatchbookStatus::Ok
+    }
+    None => MatchbookStatus::InvalidArgument,
+  }
+}
+
+/// Create an account
+///
+/// # Safety
+/// `engine` must be null or a live handle, and `account` must be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_create_account(engine: *mut MatchbookEngine, account: *mut u64) -> MatchbookStatus {
+  guard(engine, |engine| {
+    let id = engine.engine.create_account();
+    if let Some(account) = account.as_mut() {
+      *account = usize::from(id) as u64;
+    }
+    Ok(())
+  })
+}
+
+/// Insert a symbol, failing with `MATCHBOOK_STATUS_SYMBOL_ALREADY_EXISTS` if it was already inserted
+///
+/// # Safety
+/// `engine` must be null or a live handle, and `symbol` must be null or point to 4 readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_insert_symbol(engine: *mut MatchbookEngine, symbol: *const u8) -> MatchbookStatus {
+  if symbol.is_null() {
+    return MatchbookStatus::InvalidArgument;
+  }
+  let symbol = bytes_to_symbol(*(symbol as *const [u8; 4]));
+
+  guard(engine, |engine| engine.engine.insert_new_symbol(symbol))
+}
+
+/// Process an order entry command
+///
+/// # Safety
+/// `engine` must be null or a live handle, `command` must be null or readable, and `reply` must be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_process(
+  engine: *mut MatchbookEngine,
+  command: *const MatchbookCommand,
+  reply: *mut MatchbookReply,
+) -> MatchbookStatus {
+  let command = match command.as_ref() {
+    Some(command) => *command,
+    None => return MatchbookStatus::InvalidArgument,
+  };
+  let kind = match (command.kind, command.side) {
+    (MATCHBOOK_COMMAND_PLACE, side @ MATCHBOOK_SIDE_BID..=MATCHBOOK_SIDE_ASK) => CommandKind::PlaceOrder(
+      if side == MATCHBOOK_SIDE_BID { Side::Bid } else { Side::Ask },
+      bytes_to_symbol(command.symbol),
+      Order::new(command.price.into(), command.quantity.into()),
+    ),
+    (MATCHBOOK_COMMAND_CANCEL, _) => CommandKind::CancelOrder((command.order as usize).into()),
+    (MATCHBOOK_COMMAND_EXECUTE, _) => CommandKind::ExecuteOrder((command.order as usize).into()),
+    _ => return MatchbookStatus::InvalidArgument,
+  };
+  let command = Command {
+    account_id: (command.account as usize).into(),
+    kind,
+  };
+
+  guard(engine, |engine| {
+    let result = engine.engine.try_process(command);
+    engine.publish();
+
+    let written = match result? {
+      Success::PlaceOrder(Placement { id, .. }) => MatchbookReply {
+        order: usize::from(id) as u64,
+        is_done: false,
+      },
+      Success::CancelOrder(is_done) | Success::ExecuteOrder(is_done, _) => MatchbookReply { order: 0, is_done },
+      _ => MatchbookReply::default(),
+    };
+    if let Some(reply) = reply.as_mut() {
+      *reply = written;
+    }
+    Ok(())
+  })
+}
+
+/// Run scheduled work, such as auctions, that is due
+///
+/// # Safety
+/// `engine` must be null or a live handle
+#[no_mangle]
+pub unsafe extern "C" fn matchbook_tick(engine: *mut MatchbookEngine) -> MatchbookStatus {
+  guard(engine, |engine| {
+    engine.engine.tick();
+    engine.publish();
+    Ok(())
+  })
+}
+
+impl MatchbookEngine {
+  /// Deliver pending market data to the callback
+  fn publish(&mut self) {
+    for data in self.engine.drain_market_data() {
+      if let Some(callback) = self.callback {
+        callback(&MatchbookEvent::from(data), self.context);
+      }
+    }
+  }
+}
+
+/// Run `f` on a live handle, catching any panic
+unsafe fn guard<F>(engine: *mut MatchbookEngine, f: F) -> MatchbookStatus
+where
+  F: FnOnce(&mut MatchbookEngine) -> Result<(), Error>,
+{
+  let engine = match engine.as_mut() {
+    Some(engine) => engine,
+    None => return MatchbookStatus::InvalidArgument,
+  };
+
+  match panic::catch_unwind(AssertUnwindSafe(|| f(engine))) {
+    Ok(Ok(())) => MatchbookStatus::Ok,
+    Ok(Err(e)) => e.into(),
+    Err(_) => MatchbookStatus::Panic,
+  }
+}
+
+fn bytes_to_symbol(bytes: [u8; 4]) -> Symbol {
+  let mut chars = ['\0'; 4];
+  for (c, &byte) in chars.iter_mut().zip(bytes.iter()) {
+    *c = char::from(byte);
+  }
+  chars.into()
+}
+
+fn symbol_to_bytes(symbol: Symbol) -> [u8; 4] {
+  let chars: [char; 4] = symbol.into();
+  let mut bytes = [0; 4];
+  for (byte, &c) in bytes.iter_mut().zip(chars.iter()) {
+    *byte = c as u8;
+  }
+  bytes
+}
+
+fn side_to_u32(side: Side) -> u32 {
+  match side {
+    Side::Bid => MATCHBOOK_SIDE_BID,
+    Side::Ask => MATCHBOOK_SIDE_ASK,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  extern "C" fn collect(event: *const MatchbookEvent, context: *mut c_void) {
+    let events = unsafe { &mut *(context as *mut Vec<MatchbookEvent>) };
+    events.push(unsafe { *event });
+  }
+
+  #[test]
+  fn commands_publish_to_the_callback() {
+    let mut events: Vec<MatchbookEvent> = vec![];
+    let mut account = 0;
+    let mut reply = MatchbookReply::default();
+    let place = |account, side, quantity| MatchbookCommand {
+      account,
+      kind: MATCHBOOK_COMMAND_PLACE,
+      side,
+      symbol: *b"ADBE",
+      price: 100,
+      quantity,
+      order: 0,
+    };
+
+    unsafe {
+      let engine = matchbook_engine_new();
+      let context = &mut events as *mut Vec<MatchbookEvent> as *mut c_void;
+      assert_eq!(matchbook_set_event_callback(engine, Some(collect), context), MatchbookStatus::Ok);
+      assert_eq!(matchbook_create_account(engine, &mut account), MatchbookStatus::Ok);
+      assert_eq!(matchbook_insert_symbol(engine, b"ADBE".as_ptr()), MatchbookStatus::Ok);
+
+      assert_eq!(matchbook_process(engine, &place(account, MATCHBOOK_SIDE_ASK, 10), &mut reply), MatchbookStatus::Ok);
+      assert_eq!(matchbook_process(engine, &place(account, MATCHBOOK_SIDE_BID, 4), &mut reply), MatchbookStatus::Ok);
+      assert_eq!(reply.order, 1);
+      assert_eq!(matchbook_process(engine, &place(account, 7, 4), &mut reply), MatchbookStatus::InvalidArgument);
+      matchbook_engine_free(engine);
+    }
+
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].kind, events[0].symbol, events[0].price, events[0].quantity), (
+      MatchbookEventKind::Trade,
+      *b"ADBE",
+      100,
+      4
+    ));
+  }
+}
@@ -0,0 +1,218 @@
+//! In-process throughput benchmark
+//!
+//! Runs a synthetic workload of limit orders and cancels straight into an engine, without the network, and reports
+//! sustained throughput, how often placed orders matched, how often commands allocated, and the latency of each
+//! command.
+
+use engine::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Percent of commands that cancel a previously placed order
+const CANCEL_PERCENT: u64 = 20;
+/// Commands between draining the engine's outboxes, outside the timed section
+const DRAIN_INTERVAL: usize = 1024;
+/// The price orders are placed around
+const MID: u32 = 10_000;
+
+/// Heap allocations made by the process so far
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting allocations so runs can report their allocation rate
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+/// A synthetic workload
+#[derive(Debug, Clone, Copy)]
+pub struct Workload {
+  /// Number of commands to process
+  pub commands: usize,
+  /// Number of price levels either side of the mid orders are placed in
+  pub spread: u32,
+  pub seed: u64,
+}
+
+/// The results of a run
+#[derive(Debug, Clone)]
+pub struct Report {
+  pub commands: usize,
+  pub orders: usize,
+  /// Orders that matched at least once on entry
+  pub matched: usize,
+  pub elapsed: Duration,
+  /// Heap allocations and reallocations made while processing commands
+  pub allocations: usize,
+  /// Latency of every command, sorted ascending
+  latencies: Vec<Duration>,
+}
+
+impl Report {
+  pub fn commands_per_sec(&self) -> f64 {
+    self.commands as f64 / self.elapsed.as_secs_f64()
+  }
+
+  pub fn match_rate(&self) -> f64 {
+    self.matched as f64 / self.orders.max(1) as f64
+  }
+
+  pub fn allocations_per_command(&self) -> f64 {
+    self.allocations as f64 / self.commands.max(1) as f64
+  }
+
+  /// Get the latency below which `percentile` percent of commands completed
+  pub fn latency(&self, percentile: f64) -> Duration {
+    if self.latencies.is_empty() {
+      return Duration::default();
+    }
+    let index = ((self.latencies.len() - 1) as f64 * percentile / 100.0).round() as usize;
+    self.latencies[index]
+  }
+}
+
+impl fmt::Display for Report {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "commands:    {} in {:?}", self.commands, self.elapsed)?;
+    writeln!(f, "throughput:  {:.0} commands/sec", self.commands_per_sec())?;
+    writeln!(f, "match rate:  {:.1}% of {} orders", self.match_rate() * 100.0, self.orders)?;
+    writeln!(f, "allocations: {:.2} per command", self.allocations_per_command())?;
+    write!(f, "latency:")?;
+    for &percentile in [50.0, 90.0, 99.0, 99.9, 100.0].iter() {
+      write!(f, "  p{}={:?}", percentile, self.latency(percentile))?;
+    }
+    Ok(())
+  }
+}
+
+/// Run a workload against a fresh engine
+pub fn run(workload: Workload) -> Report {
+  let mut engine = MatchEngine::default();
+  let mut generator = Generator::new(&mut engine, workload);
+  let mut latencies = Vec::with_capacity(workload.commands);
+  let (mut orders, mut matched, mut allocations) = (0, 0, 0);
+
+  let started = Instant::now();
+  for i in 0..workload.commands {
+    let command = generator.command();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let result = engine.try_process(command);
+    latencies.push(start.elapsed());
+    allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    if let Ok(Success::PlaceOrder(Placement { id, .. })) = result {
+      orders += 1;
+      generator.placed(id);
+      if !engine.drain_market_data().is_empty() {
+        matched += 1;
+      }
+    }
+
+    if i % DRAIN_INTERVAL == 0 {
+      drain(&mut engine);
+    }
+  }
+  let elapsed = started.elapsed();
+
+  latencies.sort();
+  Report {
+    commands: workload.commands,
+    orders,
+    matched,
+    elapsed,
+    allocations,
+    latencies,
+  }
+}
+
+/// Take everything the engine has queued for output, so it does not grow between runs of commands
+pub fn drain(engine: &mut MatchEngine) {
+  engine.drain_market_data();
+  engine.drain_audit_trail();
+  engine.drain_alerts();
+  engine.drain_journal();
+}
+
+/// The commands of a workload, placing orders around a fixed mid and cancelling previously placed ones
+pub struct Generator {
+  symbol: Symbol,
+  accounts: [AccountId; 2],
+  spread: u32,
+  rng: XorShift,
+  /// Orders that may still be resting
+  placed: Vec<Id>,
+  sent: usize,
+}
+
+impl Generator {
+  /// Create the workload's accounts in `engine`, and its symbol unless `engine` already lists it
+  pub fn new(engine: &mut MatchEngine, workload: Workload) -> Self {
+    let symbol = ['B', 'N', 'C', 'H'].into();
+    let _ = engine.insert_new_symbol(symbol);
+
+    Self {
+      symbol,
+      accounts: [engine.create_account(), engine.create_account()],
+      spread: workload.spread,
+      rng: XorShift::new(workload.seed),
+      placed: vec![],
+      sent: 0,
+    }
+  }
+
+  /// Generate the next command
+  pub fn command(&mut self) -> Command {
+    let account_id = self.accounts[self.sent % self.accounts.len()];
+    self.sent += 1;
+
+    let kind = if !self.placed.is_empty() && self.rng.below(100) < CANCEL_PERCENT {
+      let index = self.rng.below(self.placed.len() as u64) as usize;
+      CommandKind::CancelOrder(self.placed.swap_remove(index))
+    } else {
+      let side = if self.rng.below(2) == 0 { Side::Bid } else { Side::Ask };
+      let price = MID - self.spread + self.rng.below(2 * u64::from(self.spread) + 1) as u32;
+      let quantity = 1 + self.rng.below(100);
+      CommandKind::PlaceOrder(side, self.symbol, Order::new(price.into(), quantity.into()))
+    };
+
+    Command { account_id, kind }
+  }
+
+  /// Record an order the engine accepted, so it may be cancelled later
+  pub fn placed(&mut self, id: Id) {
+    self.placed.push(id);
+  }
+}
+
+/// A small seeded generator so runs are reproducible
+pub struct XorShift(u64);
+
+impl XorShift {
+  pub fn new(seed: u64) -> Self {
+    XorShift(seed.max(1))
+  }
+
+  /// Return a number in `0..n`
+  pub fn below(&mut self, n: u64) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0 % n
+  }
+}
@@ -0,0 +1,347 @@
+//! Publishing to a message bus
+//!
+//! Executions, the rest of the order lifecycle, periodic book snapshots and periodic book statistics are published to
+//! four topics under a prefix, `<prefix>.executions`, `<prefix>.orders`, `<prefix>.books` and `<prefix>.stats`, each
+//! message one JSON object. At the end of each day, closing prices and settlement obligations follow on
+//! `<prefix>.closes` and `<prefix>.settlements`.
+//!
+//! A `Publisher` hands messages to a `Sink` on its own thread, so a slow or unreachable bus costs dropped messages
+//! rather than stalling the event loop.
+
+use crate::threads;
+use engine::*;
+use serde_derive::Serialize;
+use std::convert::TryInto;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Batches of messages waiting for the sink before more are dropped
+const PUBLISH_QUEUE: usize = 1024;
+
+/// Somewhere messages can be published to by topic
+pub trait Sink {
+  /// Publish messages to a topic, in order
+  fn publish(&mut self, topic: &str, messages: &[Vec<u8>]) -> io::Result<()>;
+}
+
+/// The depth of both sides of a book when it was published
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookSnapshot {
+  pub symbol: Symbol,
+  pub timestamp: Timestamp,
+  pub bids: Vec<(Price, Quantity)>,
+  pub asks: Vec<(Price, Quantity)>,
+}
+
+/// Publishes the engine's events to a sink, from a thread of its own
+pub struct Publisher {
+  prefix: String,
+  batches: SyncSender<(String, Vec<Vec<u8>>)>,
+  /// Messages dropped since the last one that was queued
+  dropped: usize,
+}
+
+impl Publisher {
+  /// Publish to `sink` on topics under `prefix`
+  pub fn spawn<S: Sink + Send + 'static>(mut sink: S, prefix: &str) -> io::Result<Self> {
+    let (batches, queued) = mpsc::sync_channel::<(String, Vec<Vec<u8>>)>(PUBLISH_QUEUE);
+    threads::spawn("bus", None, move || {
+      for (topic, messages) in queued {
+        if let Err(e) = sink.publish(&topic, &messages) {
+          eprintln!("failed to publish {} messages to {}: {}", messages.len(), topic, e);
+        }
+      }
+    })?;
+
+    Ok(Self {
+      prefix: prefix.to_string(),
+      batches,
+      dropped: 0,
+    })
+  }
+
+  /// Publish order events, executions to the executions topic and the rest to the orders topic
+  pub fn publish_audit_trail(&mut self, records: &[AuditRecord]) {
+    let is_execution = |record: &&AuditRecord| record.event == AuditEvent::Execute;
+    let (executions, orders): (Vec<&AuditRecord>, Vec<_>) = records.iter().partition(is_execution);
+    self.send("executions", &executions);
+    self.send("orders", &orders);
+  }
+
+  /// Publish the depth of every book
+  pub fn publish_books(&mut self, engine: &MatchEngine, timestamp: Timestamp) {
+    let books: Vec<_> = engine
+      .symbols()
+      .into_iter()
+      .filter_map(|symbol| {
+        Some(BookSnapshot {
+          symbol,
+          timestamp,
+          bids: engine.depth(symbol, Side::Bid).ok()?,
+          asks: engine.depth(symbol, Side::Ask).ok()?,
+        })
+      })
+      .collect();
+    self.send("books", &books);
+  }
+
+  /// Publish the resting liquidity of every book
+  pub fn publish_stats(&mut self, engine: &MatchEngine) {
+    let stats: Vec<_> = engine
+      .symbols()
+      .into_iter()
+      .filter_map(|symbol| engine.book_stats(symbol).ok())
+      .collect();
+    self.send("stats", &stats);
+  }
+
+  /// Publish each symbol's close and each account's settlement obligations for a day
+  pub fn publish_end_of_day(&mut self, report: &EndOfDay) {
+    self.send("closes", &report.closes);
+    self.send("settlements", &report.settlements);
+  }
+
+  fn send<T: serde::Serialize>(&mut self, topic: &str, messages: &[T]) {
+    if messages.is_empty() {
+      return;
+    }
+    let messages = messages
+      .iter()
+      .map(|message| serde_json::to_vec(message).expect("bus messages always serialize"))
+      .collect::<Vec<_>>();
+    let count = messages.len();
+    match self.batches.try_send((format!("{}.{}", self.prefix, topic), messages)) {
+      Ok(()) if self.dropped > 0 => {
+        eprintln!("dropped {} messages while the bus fell behind", self.dropped);
+        self.dropped = 0;
+      }
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => self.dropped += count,
+    }
+  }
+}
+
+/// Publishes to a NATS server over its text protocol
+pub struct NatsSink {
+  /// Shared with the thread answering the server's pings
+  writer: Arc<Mutex<TcpStream>>,
+}
+
+impl NatsSink {
+  pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut info = String::new();
+    reader.read_line(&mut info)?;
+    if !info.starts_with("INFO ") {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, format!("not a NATS server: {}", info.trim())));
+    }
+    let writer = Arc::new(Mutex::new(stream));
+    threads::lock(&writer).write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")?;
+
+    // the server drops clients that do not answer its pings
+    let ponger = writer.clone();
+    threads::spawn("nats", None, move || {
+      for line in reader.lines() {
+        let line = match line {
+          Ok(line) => line,
+          Err(_) => return,
+        };
+        if line.starts_with("PING") {
+          if threads::lock(&ponger).write_all(b"PONG\r\n").is_err() {
+            return;
+          }
+        } else if line.starts_with("-ERR") {
+          eprintln!("NATS error: {}", line);
+        }
+      }
+    })?;
+    Ok(Self { writer })
+  }
+}
+
+impl Sink for NatsSink {
+  fn publish(&mut self, topic: &str, messages: &[Vec<u8>]) -> io::Result<()> {
+    let mut bytes = vec![];
+    for message in messages {
+      write!(bytes, "PUB {} {}\r\n", topic, message.len())?;
+      bytes.extend_from_slice(message);
+      bytes.extend_from_slice(b"\r\n");
+    }
+    threads::lock(&self.writer).write_all(&bytes)
+  }
+}
+
+/// Publishes to partition 0 of each topic on a Kafka broker that leads it, one record batch per call
+///
+/// The broker is not asked for metadata, so topics must already exist with their first partition led by the broker
+/// connected to.
+pub struct KafkaSink {
+  stream: TcpStream,
+  correlation_id: i32,
+}
+
+/// The produce request version sent, the oldest that takes version 2 record batches
+const PRODUCE_VERSION: i16 = 3;
+const CLIENT_ID: &str = "matchbook";
+
+impl KafkaSink {
+  pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+    Ok(Self {
+      stream: TcpStream::connect(address)?,
+      correlation_id: 0,
+    })
+  }
+}
+
+impl Sink for KafkaSink {
+  fn publish(&mut self, topic: &str, messages: &[Vec<u8>]) -> io::Result<()> {
+    self.correlation_id = self.correlation_id.wrapping_add(1);
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as i64);
+    let request = produce_request(self.correlation_id, topic, &record_batch(messages, timestamp));
+    self.stream.write_all(&(request.len() as i32).to_be_bytes())?;
+    self.stream.write_all(&request)?;
+
+    let mut size = [0; 4];
+    self.stream.read_exact(&mut size)?;
+    let mut response = vec![0; i32::from_be_bytes(size).max(0) as usize];
+    self.stream.read_exact(&mut response)?;
+    match produce_error(&response) {
+      Some(0) => Ok(()),
+      Some(code) => Err(io::Error::other(format!("broker rejected the batch with error code {}", code))),
+      None => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed produce response")),
+    }
+  }
+}
+
+/// Encode a produce request for partition 0 of `topic`, waiting on the leader's acknowledgement
+fn produce_request(correlation_id: i32, topic: &str, batch: &[u8]) -> Vec<u8> {
+  let mut bytes = vec![];
+  bytes.extend_from_slice(&0i16.to_be_bytes());
+  bytes.extend_from_slice(&PRODUCE_VERSION.to_be_bytes());
+  bytes.extend_from_slice(&correlation_id.to_be_bytes());
+  put_string(&mut bytes, CLIENT_ID);
+  // no transactional id
+  bytes.extend_from_slice(&(-1i16).to_be_bytes());
+  // acks from the leader, waiting at most 30 seconds
+  bytes.extend_from_slice(&1i16.to_be_bytes());
+  bytes.extend_from_slice(&30_000i32.to_be_bytes());
+  bytes.extend_from_slice(&1i32.to_be_bytes());
+  put_string(&mut bytes, topic);
+  bytes.extend_from_slice(&1i32.to_be_bytes());
+  bytes.extend_from_slice(&0i32.to_be_bytes());
+  bytes.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+  bytes.extend_from_slice(batch);
+  bytes
+}
+
+/// Get the error code of the first partition in a produce response
+fn produce_error(response: &[u8]) -> Option<i16> {
+  // correlation id, topic count, then the first topic's name
+  let name_len = i16::from_be_bytes(response.get(8..10)?.try_into().ok()?) as usize;
+  // the partition count and first partition's index follow the name
+  let error = 10 + name_len + 8;
+  Some(i16::from_be_bytes(response.get(error..error + 2)?.try_into().ok()?))
+}
+
+/// Encode messages as a version 2 record batch, without keys or headers
+fn record_batch(messages: &[Vec<u8>], timestamp: i64) -> Vec<u8> {
+  let mut records = vec![];
+  for (offset, message) in messages.iter().enumerate() {
+    let mut record = vec![0];
+    put_varint(&mut record, 0);
+    put_varint(&mut record, offset as i64);
+    put_varint(&mut record, -1);
+    put_varint(&mut record, message.len() as i64);
+    record.extend_from_slice(message);
+    put_varint(&mut record, 0);
+    put_varint(&mut records, record.len() as i64);
+    records.extend(record);
+  }
+
+  // everything after the checksum, which covers it
+  let mut checked = vec![];
+  checked.extend_from_slice(&0i16.to_be_bytes());
+  checked.extend_from_slice(&(messages.len() as i32 - 1).to_be_bytes());
+  checked.extend_from_slice(&timestamp.to_be_bytes());
+  checked.extend_from_slice(&timestamp.to_be_bytes());
+  // no producer id, epoch or sequence, as the producer is not idempotent
+  checked.extend_from_slice(&(-1i64).to_be_bytes());
+  checked.extend_from_slice(&(-1i16).to_be_bytes());
+  checked.extend_from_slice(&(-1i32).to_be_bytes());
+  checked.extend_from_slice(&(messages.len() as i32).to_be_bytes());
+  checked.extend(records);
+
+  let mut batch = vec![];
+  batch.extend_from_slice(&0i64.to_be_bytes());
+  // the length counts from the leader epoch on: 4 bytes of it, 1 of magic and 4 of checksum
+  batch.extend_from_slice(&((checked.len() + 9) as i32).to_be_bytes());
+  batch.extend_from_slice(&(-1i32).to_be_bytes());
+  batch.push(2);
+  batch.extend_from_slice(&crc32c(&checked).to_be_bytes());
+  batch.extend(checked);
+  batch
+}
+
+fn put_string(bytes: &mut Vec<u8>, string: &str) {
+  bytes.extend_from_slice(&(string.len() as i16).to_be_bytes());
+  bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Append a zigzag encoded variable length integer
+fn put_varint(bytes: &mut Vec<u8>, value: i64) {
+  let mut value = ((value << 1) ^ (value >> 63)) as u64;
+  while value >= 0x80 {
+    bytes.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  bytes.push(value as u8);
+}
+
+/// The Castagnoli CRC that record batches are checked with
+fn crc32c(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for &byte in bytes {
+    crc ^= u32::from(byte);
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::net::TcpListener;
+  use std::thread;
+
+  #[test]
+  fn record_batches_are_checked_with_crc32c() {
+    assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    let batch = record_batch(&[b"{}".to_vec()], 0);
+    let length = i32::from_be_bytes(batch[8..12].try_into().unwrap()) as usize;
+    assert_eq!(length, batch.len() - 12);
+    assert_eq!(u32::from_be_bytes(batch[17..21].try_into().unwrap()), crc32c(&batch[21..]));
+  }
+
+  #[test]
+  fn nats_messages_are_published_to_a_subject() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      stream.write_all(b"INFO {}\r\n").unwrap();
+      let mut lines = BufReader::new(stream).lines();
+      (0..3).map(|_| lines.next().unwrap().unwrap()).collect::<Vec<_>>()
+    });
+
+    let mut sink = NatsSink::connect(address).unwrap();
+    sink.publish("matchbook.orders", &[b"{}".to_vec()]).unwrap();
+    let lines = server.join().unwrap();
+    assert_eq!(&lines[1..], ["PUB matchbook.orders 2", "{}"]);
+  }
+}
@@ -0,0 +1,85 @@
+//! Book dumps
+//!
+//! An admin can have the server write a symbol's whole lit book to a file, for offline inspection. A JSON dump holds a
+//! `BookDump`, which `BookDump::to_book` can rebuild a book from; a CSV dump holds one row per queued order.
+
+use engine::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// The header a CSV dump starts with
+pub const CSV_HEADER: &str = "side,price,position,id,remaining";
+
+/// Writes book dumps to a directory
+pub struct BookDumper {
+  directory: PathBuf,
+}
+
+impl BookDumper {
+  /// Write dumps to `directory`, creating it if needed
+  pub fn new<P: Into<PathBuf>>(directory: P) -> io::Result<Self> {
+    let directory = directory.into();
+    fs::create_dir_all(&directory)?;
+    Ok(Self { directory })
+  }
+
+  /// Write a dump to a file named for its symbol and the time it was taken
+  ///
+  /// # Returns
+  /// the file written
+  pub fn write(&self, dump: &BookDump, format: DumpFormat) -> io::Result<PathBuf> {
+    let extension = match format {
+      DumpFormat::Json => "json",
+      DumpFormat::Csv => "csv",
+    };
+    let path = self.directory.join(format!("{}-{}.{}", dump.symbol, u64::from(dump.taken_at), extension));
+    let mut file = BufWriter::new(File::create(&path)?);
+    match format {
+      DumpFormat::Json => serde_json::to_writer_pretty(&mut file, dump)?,
+      DumpFormat::Csv => {
+        writeln!(file, "{}", CSV_HEADER)?;
+        for &(side, levels) in &[(Side::Bid, &dump.bids), (Side::Ask, &dump.asks)] {
+          for level in levels {
+            for entry in &level.orders {
+              writeln!(file, "{},{},{},{},{}", side, level.price, entry.position, entry.id, entry.remaining)?;
+            }
+          }
+        }
+      }
+    }
+    file.flush()?;
+    Ok(path)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn a_json_dump_rebuilds_the_book() {
+    let mut engine = MatchEngine::default();
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    engine.insert_new_symbol(symbol).unwrap();
+    let account_id = engine.create_account();
+    for &(side, price, quantity) in &[(Side::Bid, 99, 5), (Side::Bid, 99, 2), (Side::Bid, 98, 1), (Side::Ask, 101, 3)] {
+      let kind = CommandKind::PlaceOrder(side, symbol, Order::new(price.into(), quantity.into()));
+      engine.try_process(Command { account_id, kind }).unwrap();
+    }
+
+    let directory = std::env::temp_dir().join(format!("matchbook-dump-test-{}", std::process::id()));
+    let dumper = BookDumper::new(&directory).unwrap();
+    let dump = engine.dump_book(symbol).unwrap();
+    let path = dumper.write(&dump, DumpFormat::Json).unwrap();
+    let loaded: BookDump = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+    let csv = fs::read_to_string(dumper.write(&dump, DumpFormat::Csv).unwrap()).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(loaded, dump);
+    assert_eq!(csv.lines().count(), 5);
+    let book = loaded.to_book(LevelStoreKind::default());
+    assert_eq!(book.depth(Side::Bid), vec![(99.into(), 7.into()), (98.into(), 1.into())]);
+    assert_eq!(book.depth(Side::Ask), vec![(101.into(), 3.into())]);
+  }
+}
@@ -0,0 +1,326 @@
+//! Journal durability
+//!
+//! How much of the journal survives a crash is chosen with a `Durability`:
+//!
+//! - `EveryCommand` writes and syncs the entries of each step on the matching thread before any reply is sent, so no
+//!   acknowledged command is ever lost.
+//! - `GroupCommit` hands entries to a writer thread, which writes them as they come and syncs everything written at
+//!   most `interval` after it arrived, so a crash loses at most that much of the acknowledged commands.
+//! - `Async` hands entries to the writer thread, which writes them but leaves syncing them to the operating system
+//!   until the server shuts down, so a crash of the process loses nothing written but a crash of the machine can lose
+//!   anything not yet synced.
+//!
+//! The health endpoints report the durability chosen and how many journaled entries are not yet as durable as it
+//! promises.
+
+use crate::threads;
+use engine::*;
+use failure::format_err;
+use serde_derive::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A writer that can make what it wrote survive a crash of the machine
+pub trait SyncWrite: Write {
+  fn sync(&mut self) -> io::Result<()>;
+}
+
+impl SyncWrite for File {
+  fn sync(&mut self) -> io::Result<()> {
+    self.sync_data()
+  }
+}
+
+/// How durably the journal is written before replies are sent, written as `every-command`, `group-commit:<ms>` or
+/// `async`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(into = "String")]
+pub enum Durability {
+  EveryCommand,
+  GroupCommit { interval: Duration },
+  Async,
+}
+
+impl fmt::Display for Durability {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Durability::EveryCommand => write!(f, "every-command"),
+      Durability::GroupCommit { interval } => write!(f, "group-commit:{}", interval.as_millis()),
+      Durability::Async => write!(f, "async"),
+    }
+  }
+}
+
+impl From<Durability> for String {
+  fn from(durability: Durability) -> Self {
+    durability.to_string()
+  }
+}
+
+impl FromStr for Durability {
+  type Err = failure::Error;
+
+  fn from_str(durability: &str) -> Result<Self, Self::Err> {
+    match durability.split_once(':') {
+      None if durability == "every-command" => Ok(Durability::EveryCommand),
+      None if durability == "async" => Ok(Durability::Async),
+      Some(("group-commit", interval)) => match interval.parse()? {
+        0 => Err(format_err!("a group commit interval must be at least a millisecond")),
+        interval => Ok(Durability::GroupCommit {
+          interval: Duration::from_millis(interval),
+        }),
+      },
+      _ => Err(format_err!("expected every-command, group-commit:<ms> or async, not {}", durability)),
+    }
+  }
+}
+
+/// How far the writer thread has got
+#[derive(Debug, Default)]
+struct Progress {
+  /// Entries that are as durable as promised
+  durable: AtomicU64,
+  /// Entries that failed to be written since the last successful write
+  failed: AtomicUsize,
+}
+
+enum Writer {
+  /// Written and synced by the matching thread
+  Inline(JournalWriter<Box<dyn SyncWrite>>),
+  /// Handed to a writer thread
+  Background {
+    entries: Option<Sender<Vec<JournalEntry>>>,
+    thread: Option<JoinHandle<()>>,
+  },
+}
+
+/// The journal of a server, written as durably as its `Durability` promises
+pub struct DurableJournal {
+  durability: Durability,
+  writer: Writer,
+  progress: Arc<Progress>,
+  /// Entries handed to the journal so far
+  appended: u64,
+}
+
+impl DurableJournal {
+  /// Journal to `file` as durably as `durability` promises, starting a writer thread unless it is `EveryCommand`
+  pub fn new<W: SyncWrite + Send + 'static>(file: W, durability: Durability) -> io::Result<Self> {
+    if durability == Durability::EveryCommand {
+      return Ok(Self::every_command(Box::new(file)));
+    }
+
+    let progress = Arc::new(Progress::default());
+    let (sender, receiver) = mpsc::channel();
+    let thread = threads::spawn("journal", None, {
+      let progress = progress.clone();
+      move || write_in_background(JournalWriter::new(file), durability, &receiver, &progress)
+    })?;
+    Ok(Self {
+      durability,
+      writer: Writer::Background {
+        entries: Some(sender),
+        thread: Some(thread),
+      },
+      progress,
+      appended: 0,
+    })
+  }
+
+  /// Journal to `writer` from the matching thread, syncing every step before its replies are sent
+  pub fn every_command(writer: Box<dyn SyncWrite>) -> Self {
+    Self {
+      durability: Durability::EveryCommand,
+      writer: Writer::Inline(JournalWriter::new(writer)),
+      progress: Arc::default(),
+      appended: 0,
+    }
+  }
+
+  pub fn durability(&self) -> Durability {
+    self.durability
+  }
+
+  /// Journal the entries of a step
+  pub fn append(&mut self, entries: &[JournalEntry]) {
+    if entries.is_empty() {
+      return;
+    }
+
+    self.appended += entries.len() as u64;
+    match &mut self.writer {
+      Writer::Inline(writer) => match writer.write(entries).and_then(|()| writer.get_mut().sync()) {
+        Ok(()) => {
+          self.progress.durable.store(self.appended, Ordering::Relaxed);
+          self.progress.failed.store(0, Ordering::Relaxed);
+        }
+        Err(e) => {
+          self.progress.failed.fetch_add(entries.len(), Ordering::Relaxed);
+          eprintln!("failed to write journal: {}", e);
+        }
+      },
+      Writer::Background { entries: sender, .. } => {
+        let sent = sender.as_ref().is_some_and(|sender| sender.send(entries.to_vec()).is_ok());
+        if !sent {
+          self.progress.failed.fetch_add(entries.len(), Ordering::Relaxed);
+          eprintln!("failed to write journal: the journal thread stopped");
+        }
+      }
+    }
+  }
+
+  /// Get how many journaled entries are not yet as durable as promised
+  pub fn pending(&self) -> u64 {
+    self.appended.saturating_sub(self.progress.durable.load(Ordering::Relaxed))
+  }
+
+  /// Get how many entries failed to be written since the last successful write
+  pub fn lag(&self) -> usize {
+    self.progress.failed.load(Ordering::Relaxed)
+  }
+}
+
+impl Drop for DurableJournal {
+  /// Write and sync everything handed to the writer thread before it stops
+  fn drop(&mut self) {
+    if let Writer::Background { entries, thread } = &mut self.writer {
+      entries.take();
+      if let Some(thread) = thread.take() {
+        let _ = thread.join();
+      }
+    }
+  }
+}
+
+/// Write entries as they arrive until the journal is dropped, syncing as `durability` asks
+fn write_in_background<W: SyncWrite>(
+  mut writer: JournalWriter<W>,
+  durability: Durability,
+  receiver: &mpsc::Receiver<Vec<JournalEntry>>,
+  progress: &Progress,
+) {
+  let (mut written, mut synced) = (0, 0);
+  // the time by which everything written must be synced, once something is
+  let mut deadline: Option<Instant> = None;
+  loop {
+    let received = match deadline {
+      Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+      None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    let is_closed = matches!(received, Err(RecvTimeoutError::Disconnected));
+
+    if let Ok(entries) = received {
+      match writer.write(&entries) {
+        Ok(()) => {
+          written += entries.len() as u64;
+          progress.failed.store(0, Ordering::Relaxed);
+        }
+        Err(e) => {
+          progress.failed.fetch_add(entries.len(), Ordering::Relaxed);
+          eprintln!("failed to write journal: {}", e);
+        }
+      }
+      match durability {
+        Durability::GroupCommit { interval } => {
+          deadline.get_or_insert_with(|| Instant::now() + interval);
+        }
+        _ => progress.durable.store(written, Ordering::Relaxed),
+      }
+    }
+
+    let is_due = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    if (is_due || is_closed) && written > synced {
+      match writer.get_mut().sync() {
+        Ok(()) => synced = written,
+        Err(e) => eprintln!("failed to sync journal: {}", e),
+      }
+      progress.durable.store(synced, Ordering::Relaxed);
+      deadline = None;
+    }
+    if is_closed {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::Mutex;
+  use std::thread;
+
+  /// A file in memory counting the times it was synced
+  #[derive(Debug, Clone, Default)]
+  struct MemoryFile(Arc<Mutex<(Vec<u8>, usize)>>);
+
+  impl Write for MemoryFile {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+      self.0.lock().unwrap().0.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  impl SyncWrite for MemoryFile {
+    fn sync(&mut self) -> io::Result<()> {
+      self.0.lock().unwrap().1 += 1;
+      Ok(())
+    }
+  }
+
+  fn entries(count: u64) -> Vec<JournalEntry> {
+    (0..count)
+      .map(|sequence| JournalEntry {
+        sequence: sequence.into(),
+        received_at: Timestamp::default(),
+        processed_at: Timestamp::default(),
+        event: JournalEvent::Tick,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn durability_is_written_as_its_name() {
+    for name in ["every-command", "group-commit:5", "async"] {
+      assert_eq!(name.parse::<Durability>().unwrap().to_string(), name);
+    }
+    assert!("group-commit:0".parse::<Durability>().is_err());
+    assert!("fsync".parse::<Durability>().is_err());
+  }
+
+  #[test]
+  fn group_commits_sync_batches_within_the_interval() {
+    let file = MemoryFile::default();
+    let progress = Arc::new(Progress::default());
+    let (sender, receiver) = mpsc::channel();
+    let durability = Durability::GroupCommit {
+      interval: Duration::from_millis(20),
+    };
+    let writer = thread::spawn({
+      let (file, progress) = (file.clone(), progress.clone());
+      move || write_in_background(JournalWriter::new(file), durability, &receiver, &progress)
+    });
+
+    sender.send(entries(2)).unwrap();
+    sender.send(entries(3)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(progress.durable.load(Ordering::Relaxed), 5);
+    assert_eq!(file.0.lock().unwrap().1, 1);
+
+    sender.send(entries(1)).unwrap();
+    drop(sender);
+    writer.join().unwrap();
+    let (bytes, syncs) = file.0.lock().unwrap().clone();
+    assert_eq!((String::from_utf8(bytes).unwrap().lines().count(), syncs), (6, 2));
+    assert_eq!(progress.durable.load(Ordering::Relaxed), 6);
+  }
+}
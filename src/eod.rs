@@ -0,0 +1,114 @@
+//! The end-of-day job
+//!
+//! The trading calendar closes every weekday at the same time of day, in UTC. At each close the server closes the
+//! engine's trading day and writes an end-of-day snapshot, holding the day's report, the state hash, what is left in
+//! every book and the fees accrued that day, to a file named for the day.
+
+use engine::*;
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// When trading days close
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calendar {
+  /// The time of day trading closes, since midnight UTC
+  close: Duration,
+}
+
+impl Calendar {
+  /// Close every weekday at `close` past midnight UTC, which must be under a day
+  pub fn new(close: Duration) -> Self {
+    assert!(close < Duration::from_nanos(NANOS_PER_DAY), "trading must close within the day");
+    Self { close }
+  }
+
+  /// Get the first close after `after`
+  pub fn next_close(&self, after: Timestamp) -> Timestamp {
+    let mut day = u64::from(after) / NANOS_PER_DAY;
+    if self.close_of(day) <= after {
+      day += 1;
+    }
+    // the epoch fell on a Thursday, so the weekend is days 2 and 3 of every week counted from it
+    while (day + 5) % 7 < 2 {
+      day += 1;
+    }
+    self.close_of(day)
+  }
+
+  fn close_of(&self, day: u64) -> Timestamp {
+    Timestamp::from(day * NANOS_PER_DAY + self.close.as_nanos() as u64)
+  }
+}
+
+/// Closes the trading day on the calendar, writing a snapshot of each to a directory
+pub struct EndOfDayJob {
+  calendar: Calendar,
+  next: Timestamp,
+  directory: PathBuf,
+}
+
+impl EndOfDayJob {
+  /// Close days from `now` on, writing snapshots to `directory`, creating it if needed
+  pub fn new<P: Into<PathBuf>>(calendar: Calendar, directory: P, now: Timestamp) -> io::Result<Self> {
+    let directory = directory.into();
+    fs::create_dir_all(&directory)?;
+    Ok(Self {
+      calendar,
+      next: calendar.next_close(now),
+      directory,
+    })
+  }
+
+  /// Returns true if the day has reached its close
+  pub fn is_due(&self, now: Timestamp) -> bool {
+    now >= self.next
+  }
+
+  /// Write the snapshot of a day the engine just closed, and wait for the next close
+  ///
+  /// # Returns
+  /// the file written
+  pub fn finish(&mut self, engine: &MatchEngine, report: &EndOfDay) -> Result<PathBuf, failure::Error> {
+    self.next = self.calendar.next_close(report.closed_at);
+    let books = engine
+      .symbols()
+      .into_iter()
+      .map(|symbol| {
+        Ok(json!({
+          "symbol": symbol,
+          "bids": engine.depth(symbol, Side::Bid)?,
+          "asks": engine.depth(symbol, Side::Ask)?,
+        }))
+      })
+      .collect::<Result<Vec<_>, Error>>()?;
+
+    let date = Date::from(report.closed_at);
+    let fees = engine.fee_report(None, date, FeePeriod::Day);
+    let path = self.directory.join(format!("{}.json", date));
+    let snapshot = json!({ "report": report, "state_hash": engine.state_hash(), "books": books, "fees": fees });
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), &snapshot)?;
+    Ok(path)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn days_close_on_weekdays_only() {
+    let calendar = Calendar::new(Duration::from_secs(16 * 60 * 60 + 30 * 60));
+    let at = |day: u64, seconds: u64| Timestamp::from(day * NANOS_PER_DAY + seconds * 1_000_000_000);
+
+    // 2024-03-01 was a Friday
+    let friday = 19_783;
+    assert_eq!(Date::from(at(friday, 0)).to_string(), "2024-03-01");
+    assert_eq!(calendar.next_close(at(friday, 0)), at(friday, 59_400));
+    let monday = calendar.next_close(at(friday, 59_400));
+    assert_eq!(monday, at(friday + 3, 59_400));
+    assert_eq!(Date::from(monday).to_string(), "2024-03-04");
+  }
+}
@@ -0,0 +1,228 @@
+//! Readiness-based network on Linux `epoll`
+//!
+//! An `EpollNetwork` waits on the listener and every client socket from the thread calling `poll`, so the event loop
+//! reads and writes its clients itself instead of handing bytes across threads.
+
+use crate::listeners;
+use crate::server::{self, NetEvent, Network, TcpConfig, WaitStrategy};
+use engine::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+/// Most readiness events taken from the kernel per wait
+const MAX_EVENTS: usize = 64;
+/// The token of the listener, as sessions are tokened by their id plus one
+const LISTENER: u64 = 0;
+
+/// A client socket and the bytes that could not yet be written to it
+struct Connection {
+  stream: TcpStream,
+  unsent: Vec<u8>,
+}
+
+/// Clients connected over TCP, all waited on together by the polling thread
+pub struct EpollNetwork {
+  clock: Arc<dyn Clock>,
+  epoll: RawFd,
+  listener: TcpListener,
+  max_connections: usize,
+  wait: WaitStrategy,
+  connections: HashMap<SessionId, Connection>,
+  next_session: usize,
+  /// Events read from the sockets that `poll` has not returned yet
+  ready: VecDeque<NetEvent>,
+}
+
+impl EpollNetwork {
+  /// Listen for clients on `address`, measuring poll deadlines by `clock`
+  ///
+  /// There are no workers, so `config.workers` and `config.io_cores` are ignored. `address` must resolve to a single
+  /// address.
+  pub fn bind<A: ToSocketAddrs>(address: A, clock: Arc<dyn Clock>, config: TcpConfig) -> io::Result<Self> {
+    let mut listeners = listeners::bind(address)?;
+    if listeners.len() > 1 {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, "the epoll backend listens on a single address"));
+    }
+    let listener = listeners.remove(0);
+    listener.set_nonblocking(true)?;
+    // SAFETY: epoll_create1 has no preconditions
+    let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    if epoll < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    let network = Self {
+      clock,
+      epoll,
+      listener,
+      max_connections: config.max_connections,
+      wait: config.wait,
+      connections: HashMap::new(),
+      next_session: 0,
+      ready: VecDeque::new(),
+    };
+    network.control(libc::EPOLL_CTL_ADD, network.listener.as_raw_fd(), LISTENER, libc::EPOLLIN)?;
+    Ok(network)
+  }
+
+  fn control(&self, operation: libc::c_int, fd: RawFd, token: u64, events: libc::c_int) -> io::Result<()> {
+    let mut event = libc::epoll_event {
+      events: events as u32,
+      u64: token,
+    };
+    // SAFETY: `event` outlives the call, and the kernel copies it
+    if unsafe { libc::epoll_ctl(self.epoll, operation, fd, &mut event) } < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  /// Wait up to `timeout_ms` for sockets to become ready, and read whatever they have
+  fn wait(&mut self, timeout_ms: libc::c_int) {
+    let mut events = [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+    // SAFETY: `events` holds `MAX_EVENTS` writable entries
+    let count = unsafe { libc::epoll_wait(self.epoll, events.as_mut_ptr(), MAX_EVENTS as libc::c_int, timeout_ms) };
+    for event in events.iter().take(count.max(0) as usize) {
+      let (token, flags) = (event.u64, event.events);
+      if token == LISTENER {
+        self.accept();
+        continue;
+      }
+
+      let session = SessionId::from(token as usize - 1);
+      if flags & libc::EPOLLOUT as u32 != 0 {
+        self.flush(session);
+      }
+      if flags & (libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0 {
+        self.read(session);
+      }
+    }
+  }
+
+  fn accept(&mut self) {
+    loop {
+      let mut stream = match self.listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+        Err(e) => {
+          eprintln!("failed to accept connection: {}", e);
+          return;
+        }
+      };
+
+      if self.connections.len() >= self.max_connections {
+        let _ = stream.write_all(&server::rejection(self.max_connections));
+        continue;
+      }
+
+      let session = SessionId::from(self.next_session);
+      self.next_session += 1;
+      let token = usize::from(session) as u64 + 1;
+      let registered = stream
+        .set_nonblocking(true)
+        .and_then(|()| self.control(libc::EPOLL_CTL_ADD, stream.as_raw_fd(), token, libc::EPOLLIN));
+      if let Err(e) = registered {
+        eprintln!("failed to accept connection: {}", e);
+        continue;
+      }
+
+      self.connections.insert(session, Connection { stream, unsent: vec![] });
+      self.ready.push_back(NetEvent::Connected(session));
+    }
+  }
+
+  /// Read everything available on a session, disconnecting it once it closes
+  fn read(&mut self, session: SessionId) {
+    let connection = match self.connections.get_mut(&session) {
+      Some(connection) => connection,
+      None => return,
+    };
+
+    let mut buffer = [0; 4096];
+    loop {
+      match connection.stream.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(read) => self.ready.push_back(NetEvent::Received(session, buffer[..read].to_vec(), self.clock.now())),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(_) => break,
+      }
+    }
+    self.disconnect(session);
+  }
+
+  /// Write as much of a session's unsent bytes as it will take, waiting for it to be writable if any are left
+  fn flush(&mut self, session: SessionId) {
+    let connection = match self.connections.get_mut(&session) {
+      Some(connection) => connection,
+      None => return,
+    };
+
+    let was_waiting = !connection.unsent.is_empty();
+    let mut written = 0;
+    while written < connection.unsent.len() {
+      match connection.stream.write(&connection.unsent[written..]) {
+        Ok(0) => break,
+        Ok(bytes) => written += bytes,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(_) => return self.disconnect(session),
+      }
+    }
+    connection.unsent.drain(..written);
+
+    let is_waiting = !connection.unsent.is_empty();
+    if is_waiting != was_waiting {
+      let fd = connection.stream.as_raw_fd();
+      let events = if is_waiting { libc::EPOLLIN | libc::EPOLLOUT } else { libc::EPOLLIN };
+      if self.control(libc::EPOLL_CTL_MOD, fd, usize::from(session) as u64 + 1, events).is_err() {
+        self.disconnect(session);
+      }
+    }
+  }
+
+  fn disconnect(&mut self, session: SessionId) {
+    if let Some(connection) = self.connections.remove(&session) {
+      let _ = self.control(libc::EPOLL_CTL_DEL, connection.stream.as_raw_fd(), 0, 0);
+      self.ready.push_back(NetEvent::Disconnected(session));
+    }
+  }
+}
+
+impl Network for EpollNetwork {
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent> {
+    let mut idle = 0;
+    while self.ready.is_empty() && self.wait.spins(idle) && self.clock.now() < deadline {
+      self.wait(0);
+      idle += 1;
+    }
+
+    if self.ready.is_empty() && self.clock.now() < deadline {
+      let timeout = u64::from(deadline).saturating_sub(self.clock.now().into());
+      // round up, so a wait never ends just short of the deadline and spins
+      let timeout_ms = timeout.div_ceil(1_000_000).min(libc::c_int::MAX as u64);
+      self.wait(timeout_ms as libc::c_int);
+    }
+
+    self.ready.pop_front()
+  }
+
+  fn send(&mut self, session: SessionId, bytes: &[u8]) {
+    if let Some(connection) = self.connections.get_mut(&session) {
+      connection.unsent.extend_from_slice(bytes);
+      self.flush(session);
+    }
+  }
+}
+
+impl Drop for EpollNetwork {
+  fn drop(&mut self) {
+    // SAFETY: the epoll fd is owned by this network and closed only here
+    unsafe {
+      libc::close(self.epoll);
+    }
+  }
+}
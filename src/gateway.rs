@@ -0,0 +1,349 @@
+//! Gateways running in their own processes, in front of the matcher
+//!
+//! A gateway accepts clients and splits what they send into commands, forwarding only whole, well-formed commands to
+//! the matcher over one connection. The matcher serves its gateways through a `GatewayNetwork`, so slow clients and
+//! connection handling cost the gateway's process rather than the matcher's.
+//!
+//! Gateway and matcher exchange frames of a one byte kind, a little-endian `u64` session and, for the kinds that
+//! carry them, a little-endian `u32` length and that many bytes. A command's bytes are preceded by the little-endian
+//! `u64` time the gateway received it, so the matcher's latency budget starts at the gateway.
+
+use crate::listeners;
+use crate::server::{self, NetEvent, Network, TcpNetwork};
+use crate::threads;
+use engine::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long a write to a gateway may block the matching thread before the gateway is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a gateway waits for clients before waiting again
+const GATEWAY_POLL: Duration = Duration::from_secs(1);
+
+/// A message between a gateway and the matcher, about one of the gateway's clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+  Connected(SessionId),
+  /// One whole command, as the client sent it, and when the gateway received it
+  Command(SessionId, Timestamp, Vec<u8>),
+  Disconnected(SessionId),
+  /// Bytes for the gateway to send to the client
+  Reply(SessionId, Vec<u8>),
+}
+
+impl Frame {
+  /// Write the frame, without flushing
+  pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    let (kind, session, received_at, bytes) = match self {
+      Frame::Connected(session) => (0u8, *session, None, None),
+      Frame::Command(session, received_at, bytes) => (1, *session, Some(*received_at), Some(bytes)),
+      Frame::Disconnected(session) => (2, *session, None, None),
+      Frame::Reply(session, bytes) => (3, *session, None, Some(bytes)),
+    };
+    writer.write_all(&[kind])?;
+    writer.write_all(&(usize::from(session) as u64).to_le_bytes())?;
+    if let Some(received_at) = received_at {
+      writer.write_all(&u64::from(received_at).to_le_bytes())?;
+    }
+    if let Some(bytes) = bytes {
+      writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+      writer.write_all(bytes)?;
+    }
+    Ok(())
+  }
+
+  /// Read the next frame
+  ///
+  /// # Returns
+  /// `None` if the stream ended between frames
+  pub fn read<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+    let mut kind = [0];
+    if reader.read(&mut kind)? == 0 {
+      return Ok(None);
+    }
+    let read_u64 = |reader: &mut R| -> io::Result<u64> {
+      let mut word = [0; 8];
+      reader.read_exact(&mut word)?;
+      Ok(u64::from_le_bytes(word))
+    };
+    let read_bytes = |reader: &mut R| -> io::Result<Vec<u8>> {
+      let mut len = [0; 4];
+      reader.read_exact(&mut len)?;
+      let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+      reader.read_exact(&mut bytes)?;
+      Ok(bytes)
+    };
+    let session = SessionId::from(read_u64(reader)? as usize);
+
+    let frame = match kind[0] {
+      0 => Frame::Connected(session),
+      1 => Frame::Command(session, Timestamp::from(read_u64(reader)?), read_bytes(reader)?),
+      2 => Frame::Disconnected(session),
+      3 => Frame::Reply(session, read_bytes(reader)?),
+      kind => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown frame kind {}", kind))),
+    };
+    Ok(Some(frame))
+  }
+}
+
+/// Forward the commands of `network`'s clients to the matcher at `matcher`, and its replies back, until either side
+/// stops
+pub fn run<A: ToSocketAddrs>(network: &mut TcpNetwork, clock: &dyn Clock, matcher: A) -> io::Result<()> {
+  let stream = TcpStream::connect(matcher)?;
+  stream.set_nodelay(true)?;
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let clients = network.sender();
+  // a slow client stalls only the replies behind it, on this thread
+  let replies = threads::spawn("replies", None, move || loop {
+    match Frame::read(&mut reader) {
+      Ok(Some(Frame::Reply(session, bytes))) => clients.send(session, &bytes),
+      Ok(Some(frame)) => eprintln!("unexpected frame from matcher: {:?}", frame),
+      Ok(None) => return,
+      Err(e) => {
+        eprintln!("lost matcher: {}", e);
+        return;
+      }
+    }
+  })?;
+
+  let mut writer = BufWriter::new(stream);
+  let mut buffers: HashMap<SessionId, Vec<u8>> = HashMap::new();
+  while !replies.is_finished() {
+    let event = match network.poll(clock.now() + GATEWAY_POLL) {
+      Some(event) => event,
+      None => continue,
+    };
+    match event {
+      NetEvent::Connected(session) => {
+        buffers.insert(session, vec![]);
+        Frame::Connected(session).write(&mut writer)?;
+      }
+      NetEvent::Received(session, bytes, received_at) => {
+        let buffer = match buffers.get_mut(&session) {
+          Some(buffer) => buffer,
+          None => continue,
+        };
+        buffer.extend(bytes);
+        let mut commands = vec![];
+        if let Some((_, error)) = server::drain_commands(buffer, |command| commands.push(command)) {
+          let mut reply = vec![];
+          server::write_reply(&mut reply, &Err(error));
+          network.send(session, &reply);
+        }
+        for command in commands {
+          let bytes = serde_json::to_vec(&command).expect("commands always serialize");
+          Frame::Command(session, received_at, bytes).write(&mut writer)?;
+        }
+      }
+      NetEvent::Disconnected(session) => {
+        buffers.remove(&session);
+        Frame::Disconnected(session).write(&mut writer)?;
+      }
+      NetEvent::Refused(_, source) => eprintln!("refused a connection from {}", source),
+    }
+    writer.flush()?;
+  }
+  Ok(())
+}
+
+/// Accept gateways on `listener`, numbering them from `next_gateway` and sending their frames to `sender`
+fn accept_gateways(
+  listener: TcpListener,
+  sender: Sender<(usize, Option<Frame>)>,
+  writers: Arc<Mutex<HashMap<usize, TcpStream>>>,
+  next_gateway: Arc<AtomicUsize>,
+) {
+  for stream in listener.incoming() {
+    let gateway = next_gateway.fetch_add(1, Ordering::Relaxed);
+    let stream = match stream.and_then(|stream| {
+      stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+      stream.set_nodelay(true)?;
+      Ok((stream.try_clone()?, stream))
+    }) {
+      Ok((reader, writer)) => {
+        threads::lock(&writers).insert(gateway, writer);
+        reader
+      }
+      Err(e) => {
+        eprintln!("failed to accept gateway: {}", e);
+        continue;
+      }
+    };
+
+    let sender = sender.clone();
+    let spawned = threads::spawn(&format!("gateway-{}", gateway), None, move || {
+      let mut reader = BufReader::new(stream);
+      loop {
+        let frame = Frame::read(&mut reader).unwrap_or_else(|e| {
+          eprintln!("lost gateway {}: {}", gateway, e);
+          None
+        });
+        let is_lost = frame.is_none();
+        if sender.send((gateway, frame)).is_err() || is_lost {
+          return;
+        }
+      }
+    });
+    if let Err(e) = spawned {
+      eprintln!("failed to read gateway {}: {}", gateway, e);
+    }
+  }
+}
+
+/// The gateways connected to the matcher, and their clients
+pub struct GatewayNetwork {
+  address: SocketAddr,
+  clock: Arc<dyn Clock>,
+  frames: Receiver<(usize, Option<Frame>)>,
+  gateways: Arc<Mutex<HashMap<usize, TcpStream>>>,
+  /// The gateway and gateway's session of each of the matcher's sessions
+  sessions: HashMap<SessionId, (usize, SessionId)>,
+  session_ids: HashMap<(usize, SessionId), SessionId>,
+  next_session: usize,
+  /// Events handled but not yet polled, e.g. the disconnections of a lost gateway's clients
+  pending: VecDeque<NetEvent>,
+}
+
+impl GatewayNetwork {
+  /// Accept gateways on every address `address` resolves to, measuring poll deadlines by `clock`
+  pub fn bind<A: ToSocketAddrs>(address: A, clock: Arc<dyn Clock>) -> io::Result<Self> {
+    let listeners = listeners::bind(address)?;
+    let address = listeners[0].local_addr()?;
+    let (sender, frames) = mpsc::channel();
+    let gateways = Arc::new(Mutex::new(HashMap::new()));
+    let next_gateway = Arc::new(AtomicUsize::new(0));
+
+    for listener in listeners {
+      let (sender, writers, next_gateway) = (sender.clone(), gateways.clone(), next_gateway.clone());
+      threads::spawn("accept-gateways", None, move || accept_gateways(listener, sender, writers, next_gateway))?;
+    }
+
+    Ok(Self {
+      address,
+      clock,
+      frames,
+      gateways,
+      sessions: HashMap::new(),
+      session_ids: HashMap::new(),
+      next_session: 0,
+      pending: VecDeque::new(),
+    })
+  }
+
+  /// Get the address gateways connect to
+  pub fn local_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  fn received(&mut self, gateway: usize, frame: Option<Frame>) {
+    let event = match frame {
+      Some(Frame::Connected(local)) => {
+        let session = SessionId::from(self.next_session);
+        self.next_session += 1;
+        self.sessions.insert(session, (gateway, local));
+        self.session_ids.insert((gateway, local), session);
+        NetEvent::Connected(session)
+      }
+      Some(Frame::Command(local, received_at, bytes)) => match self.session_ids.get(&(gateway, local)) {
+        Some(&session) => NetEvent::Received(session, bytes, received_at),
+        None => return,
+      },
+      Some(Frame::Disconnected(local)) => match self.session_ids.remove(&(gateway, local)) {
+        Some(session) => {
+          self.sessions.remove(&session);
+          NetEvent::Disconnected(session)
+        }
+        None => return,
+      },
+      Some(Frame::Reply(..)) => return,
+      None => {
+        threads::lock(&self.gateways).remove(&gateway);
+        let lost: Vec<_> = self.sessions.iter().filter(|(_, &(from, _))| from == gateway).map(|(&s, _)| s).collect();
+        for session in lost {
+          let (_, local) = self.sessions.remove(&session).unwrap();
+          self.session_ids.remove(&(gateway, local));
+          self.pending.push_back(NetEvent::Disconnected(session));
+        }
+        return;
+      }
+    };
+    self.pending.push_back(event);
+  }
+}
+
+impl Network for GatewayNetwork {
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent> {
+    loop {
+      if let Some(event) = self.pending.pop_front() {
+        return Some(event);
+      }
+
+      let timeout = u64::from(deadline).saturating_sub(self.clock.now().into());
+      match self.frames.recv_timeout(Duration::from_nanos(timeout)) {
+        Ok((gateway, frame)) => self.received(gateway, frame),
+        Err(RecvTimeoutError::Timeout) => return None,
+        Err(RecvTimeoutError::Disconnected) => {
+          // the listener has stopped, so there is nothing left but ticks
+          thread::sleep(Duration::from_nanos(timeout));
+          return None;
+        }
+      }
+    }
+  }
+
+  fn send(&mut self, session: SessionId, bytes: &[u8]) {
+    let (gateway, local) = match self.sessions.get(&session) {
+      Some(&to) => to,
+      None => return,
+    };
+    let mut gateways = threads::lock(&self.gateways);
+    let failed = match gateways.get_mut(&gateway) {
+      Some(stream) => Frame::Reply(local, bytes.to_vec()).write(&mut *stream).is_err(),
+      None => false,
+    };
+    // its reader finds it lost and disconnects its clients
+    if failed {
+      if let Some(stream) = gateways.remove(&gateway) {
+        let _ = stream.shutdown(Shutdown::Both);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn gateway_sessions_are_served_as_the_matchers_own() {
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let mut network = GatewayNetwork::bind("127.0.0.1:0", clock.clone()).unwrap();
+    let address = network.local_addr();
+    let deadline = || clock.now() + Duration::from_secs(5);
+
+    let gateway = TcpStream::connect(address).unwrap();
+    let mut writer = gateway.try_clone().unwrap();
+    let local = SessionId::from(7);
+    Frame::Connected(local).write(&mut writer).unwrap();
+    Frame::Command(local, Timestamp::from(42), b"{}".to_vec()).write(&mut writer).unwrap();
+    let session = match network.poll(deadline()) {
+      Some(NetEvent::Connected(session)) => session,
+      event => panic!("expected a connection, got {:?}", event),
+    };
+    assert_eq!(network.poll(deadline()), Some(NetEvent::Received(session, b"{}".to_vec(), Timestamp::from(42))));
+
+    network.send(session, b"reply\n");
+    let mut reader = BufReader::new(gateway.try_clone().unwrap());
+    assert_eq!(Frame::read(&mut reader).unwrap(), Some(Frame::Reply(local, b"reply\n".to_vec())));
+
+    drop((writer, reader, gateway));
+    assert_eq!(network.poll(deadline()), Some(NetEvent::Disconnected(session)));
+  }
+}
@@ -0,0 +1,207 @@
+//! Health and readiness endpoints
+//!
+//! The server publishes a `Health` snapshot on every tick, and a small HTTP listener on its own thread answers
+//! `/healthz` and `/readyz` from the latest snapshot, so probes never wait on the event loop. `/threads` lists each
+//! thread and the core it is pinned to. Both health endpoints report how the latency of answering commands splits
+//! between queueing, matching and sending.
+
+use crate::durability::Durability;
+use crate::listeners;
+use crate::threads;
+use engine::*;
+use serde_derive::Serialize;
+use serde_json::json;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the event loop may go without ticking before it is considered stuck
+pub const STALE_AFTER: Duration = Duration::from_secs(1);
+/// The most of a request that is read, which is plenty for a request line and a few headers
+const MAX_REQUEST: usize = 4096;
+
+/// The state of a running server, as of its last tick
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Health {
+  /// When the event loop last ticked
+  pub heartbeat: Timestamp,
+  /// Journal entries that failed to be written since the last successful write
+  pub journal_lag: usize,
+  /// How durably the journal is written, if the server journals
+  pub journal_durability: Option<Durability>,
+  /// Journal entries not yet as durable as `journal_durability` promises
+  pub journal_pending: u64,
+  pub sessions: usize,
+  /// Bytes received that do not yet make up a whole command, across all sessions
+  pub buffered: usize,
+  /// Inputs dropped as undecodable since the server started, across all sessions
+  pub dead_letters: usize,
+  /// How the matching of each symbol is progressing, if the server watches it
+  pub watchdog: Vec<SymbolProgress>,
+  /// Panics caught in any thread since the server started, each of which skipped a step or restarted a thread
+  pub panics: usize,
+  /// Standbys the journal is streamed to, if the server replicates
+  pub standbys: Option<usize>,
+  pub queues: QueueDepths,
+  pub symbols: Vec<SymbolHealth>,
+  pub latency: LatencyBudget,
+}
+
+/// How long the commands answered since the server started spent in each stage, in nanoseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LatencyBudget {
+  /// From being read off the network to being taken off it by the matching thread
+  pub queued: StageLatency,
+  /// From being taken off the network to the engine having processed it
+  pub matching: StageLatency,
+  /// From being processed to the reply being sent, which includes writing the journal
+  pub sending: StageLatency,
+}
+
+impl LatencyBudget {
+  /// Count a command answered as `trace` says
+  pub fn record(&mut self, trace: &LatencyTrace) {
+    self.queued.record(trace.received_at, trace.dequeued_at);
+    self.matching.record(trace.dequeued_at, trace.matched_at);
+    self.sending.record(trace.matched_at, trace.sent_at);
+  }
+}
+
+/// How long commands spent in one stage, in nanoseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StageLatency {
+  pub count: u64,
+  pub total: u64,
+  pub max: u64,
+}
+
+impl StageLatency {
+  fn record(&mut self, from: Timestamp, to: Timestamp) {
+    // stamps from a gateway's clock may be ahead of the matcher's
+    let elapsed = u64::from(to).saturating_sub(from.into());
+    self.count += 1;
+    self.total += elapsed;
+    self.max = self.max.max(elapsed);
+  }
+}
+
+/// Whether a symbol is trading
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SymbolHealth {
+  pub symbol: Symbol,
+  /// Continuous trading is halted while a symbol trades in periodic auctions
+  pub trading_mode: TradingMode,
+}
+
+impl Health {
+  /// Returns true if the event loop is still ticking
+  pub fn is_live(&self, now: Timestamp) -> bool {
+    u64::from(now).saturating_sub(self.heartbeat.into()) <= STALE_AFTER.as_nanos() as u64
+  }
+
+  /// Returns true if the server is live and journaling everything it processes
+  pub fn is_ready(&self, now: Timestamp) -> bool {
+    self.is_live(now) && self.journal_lag == 0
+  }
+}
+
+/// Answer a request for `path`
+///
+/// # Returns
+/// the HTTP status code and JSON body of the response
+pub fn respond(path: &str, health: &Health, now: Timestamp) -> (u16, String) {
+  let (live, ready) = (health.is_live(now), health.is_ready(now));
+  let passed = match path {
+    "/healthz" => live,
+    "/readyz" => ready,
+    "/threads" => return (200, json!(threads::layout()).to_string()),
+    _ => return (404, json!({ "error": "not found" }).to_string()),
+  };
+
+  let body = json!({ "live": live, "ready": ready, "health": health });
+  (if passed { 200 } else { 503 }, body.to_string())
+}
+
+/// Serve the endpoints on every address `address` resolves to from background threads, answering from `health` as of
+/// `clock`
+pub fn serve<A: ToSocketAddrs>(address: A, health: Arc<Mutex<Health>>, clock: Arc<dyn Clock>) -> io::Result<()> {
+  for listener in listeners::bind(address)? {
+    let (health, clock) = (health.clone(), clock.clone());
+    threads::supervise("health", None, move || {
+      for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+          let snapshot = threads::lock(&health).clone();
+          answer(stream, &snapshot, clock.now())
+        });
+        if let Err(e) = result {
+          eprintln!("failed to answer health check: {}", e);
+        }
+      }
+    })?;
+  }
+
+  Ok(())
+}
+
+fn answer(mut stream: TcpStream, health: &Health, now: Timestamp) -> io::Result<()> {
+  stream.set_read_timeout(Some(STALE_AFTER))?;
+  let mut request = vec![];
+  let mut buffer = [0; 512];
+  while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+    match stream.read(&mut buffer)? {
+      0 => break,
+      read => request.extend_from_slice(&buffer[..read]),
+    }
+  }
+
+  let request = String::from_utf8_lossy(&request);
+  let mut words = request.split_whitespace();
+  let (status, body) = match (words.next(), words.next()) {
+    (Some("GET"), Some(path)) => respond(path, health, now),
+    _ => (405, json!({ "error": "method not allowed" }).to_string()),
+  };
+
+  let reason = match status {
+    200 => "OK",
+    404 => "Not Found",
+    405 => "Method Not Allowed",
+    _ => "Service Unavailable",
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    reason,
+    body.len(),
+    body
+  )
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn readiness_needs_a_recent_tick_and_no_journal_lag() {
+    let health = Health {
+      heartbeat: Timestamp::from(1_000),
+      ..Health::default()
+    };
+    let now = health.heartbeat + Duration::from_millis(100);
+    assert_eq!(respond("/healthz", &health, now).0, 200);
+    assert_eq!(respond("/readyz", &health, now).0, 200);
+    assert_eq!(respond("/metrics", &health, now).0, 404);
+
+    let lagging = Health {
+      journal_lag: 3,
+      ..health.clone()
+    };
+    assert_eq!(respond("/healthz", &lagging, now).0, 200);
+    assert_eq!(respond("/readyz", &lagging, now).0, 503);
+
+    let stuck = health.heartbeat + STALE_AFTER + Duration::from_nanos(1);
+    assert_eq!(respond("/healthz", &health, stuck).0, 503);
+    assert_eq!(respond("/readyz", &health, stuck).0, 503);
+  }
+}
@@ -0,0 +1,155 @@
+//! Bulk order entry from a file
+//!
+//! Orders are read from a CSV file with a header of `account,side,symbol,price,quantity`, one order per row, or from a
+//! file of `Command`s in their wire encoding, one per line. Each order is sent to a running server as a command of its
+//! own, so it is validated and journaled like any other, and every row is reported with the server's answer or with
+//! why it could not be read.
+
+use engine::*;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// The header a CSV file of orders starts with
+pub const CSV_HEADER: &str = "account,side,symbol,price,quantity";
+
+/// How a file of orders is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Csv,
+  /// One `Command` per line
+  Json,
+}
+
+/// What became of one row of the file
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowResult {
+  /// The row could not be read as an order, so was not sent
+  Invalid(String),
+  /// The server's answer to the order
+  Sent(Result<Success, engine::Error>),
+}
+
+impl RowResult {
+  /// Returns true if the order rests or traded
+  pub fn is_placed(&self) -> bool {
+    matches!(self, RowResult::Sent(Ok(Success::PlaceOrder(_))))
+  }
+}
+
+impl fmt::Display for RowResult {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RowResult::Invalid(reason) => write!(f, "invalid: {}", reason),
+      RowResult::Sent(Ok(Success::PlaceOrder(placement))) => {
+        write!(f, "placed order {}, {:?} with {} left", placement.id, placement.state, placement.remaining)
+      }
+      RowResult::Sent(Ok(other)) => write!(f, "unexpected reply {:?}", other),
+      RowResult::Sent(Err(e)) => write!(f, "rejected: {}", e),
+    }
+  }
+}
+
+/// Read the orders of a file, by the line number of each, skipping blank lines and a CSV file's header
+///
+/// # Returns
+/// each row's command, or why it is not an order
+pub fn read_orders(text: &str, format: Format) -> Vec<(usize, Result<Command, String>)> {
+  text
+    .lines()
+    .enumerate()
+    .map(|(index, line)| (index + 1, line.trim()))
+    .filter(|&(number, line)| !line.is_empty() && (format != Format::Csv || number != 1 || line != CSV_HEADER))
+    .map(|(number, line)| {
+      let command = match format {
+        Format::Csv => csv_order(line),
+        Format::Json => json_order(line),
+      };
+      (number, command)
+    })
+    .collect()
+}
+
+fn csv_order(line: &str) -> Result<Command, String> {
+  let fields: Vec<_> = line.split(',').map(str::trim).collect();
+  let (account, side, symbol, price, quantity) = match fields[..] {
+    [account, side, symbol, price, quantity] => (account, side, symbol, price, quantity),
+    _ => return Err(format!("expected the {} fields of {}", CSV_HEADER.split(',').count(), CSV_HEADER)),
+  };
+
+  let account: usize = account.parse().map_err(|_| format!("'{}' is not an account", account))?;
+  let side = match side.to_ascii_lowercase().as_str() {
+    "bid" => Side::Bid,
+    "ask" => Side::Ask,
+    _ => return Err(format!("'{}' is not a side, which is bid or ask", side)),
+  };
+  let chars: Vec<char> = symbol.chars().collect();
+  let symbol: [char; 4] = chars.try_into().map_err(|_| format!("'{}' is not a symbol of four characters", symbol))?;
+  let price: u32 = price.parse().map_err(|_| format!("'{}' is not a price", price))?;
+  let quantity: u64 = quantity.parse().map_err(|_| format!("'{}' is not a quantity", quantity))?;
+
+  Ok(Command {
+    account_id: account.into(),
+    kind: CommandKind::PlaceOrder(side, symbol.into(), Order::new(price.into(), quantity.into())),
+  })
+}
+
+fn json_order(line: &str) -> Result<Command, String> {
+  let command: Command = serde_json::from_str(line).map_err(|e| e.to_string())?;
+  match command.kind {
+    CommandKind::PlaceOrder(..) => Ok(command),
+    other => Err(format!("{:?} is not an order", other)),
+  }
+}
+
+/// Send each order to a server, waiting for its answer before sending the next
+///
+/// # Returns
+/// what became of each row, by its line number
+pub fn import<R: BufRead, W: Write>(
+  rows: Vec<(usize, Result<Command, String>)>,
+  replies: &mut R,
+  requests: &mut W,
+) -> io::Result<Vec<(usize, RowResult)>> {
+  let mut results = Vec::with_capacity(rows.len());
+  for (number, row) in rows {
+    let command = match row {
+      Ok(command) => command,
+      Err(reason) => {
+        results.push((number, RowResult::Invalid(reason)));
+        continue;
+      }
+    };
+
+    serde_json::to_writer(&mut *requests, &command)?;
+    requests.write_all(b"\n")?;
+    requests.flush()?;
+    let mut reply = String::new();
+    if replies.read_line(&mut reply)? == 0 {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the server closed the connection"));
+    }
+    let reply = serde_json::from_str(&reply).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    results.push((number, RowResult::Sent(reply)));
+  }
+
+  Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn every_row_is_read_or_reported_invalid() {
+    let text = format!("{}\n0,bid,ADBE,100,5\n\n1,Ask,ADBE,101\n2,sell,ADBE,101,5\n", CSV_HEADER);
+    let rows = read_orders(&text, Format::Csv);
+
+    let numbers: Vec<_> = rows.iter().map(|&(number, _)| number).collect();
+    assert_eq!(numbers, vec![2, 4, 5]);
+    assert_eq!(rows[0].1, Ok(Command {
+      account_id: 0.into(),
+      kind: CommandKind::PlaceOrder(Side::Bid, ['A', 'D', 'B', 'E'].into(), Order::new(100.into(), 5.into())),
+    }));
+    assert!(rows[1].1.is_err() && rows[2].1.is_err());
+  }
+}
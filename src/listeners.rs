@@ -0,0 +1,217 @@
+//! Listener endpoints
+//!
+//! The TCP server can listen on several addresses at once, e.g. one port for order entry, one for market data, one for
+//! drop copies and one for admins, each given in a JSON listeners file with the `EndpointRole` limiting what its
+//! sessions may send and the subnets it accepts connections from.
+//!
+//! Every port is bound on each address it resolves to, so `--bind` can put one port on several interfaces, e.g. both
+//! `0.0.0.0` and `::`.
+//!
+//! Accounts can also be limited to subnets of their own, given in a JSON account sources file. Connections and
+//! commands refused for where they came from are journaled and audited as `AuditEvent::Deny`.
+
+use engine::*;
+use failure::format_err;
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A range of addresses, written as an address and an optional prefix length, e.g. `10.0.0.0/8` or `::1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Subnet {
+  address: IpAddr,
+  prefix: u8,
+}
+
+impl Subnet {
+  /// Returns true if `address` is in the subnet
+  pub fn contains(&self, address: IpAddr) -> bool {
+    let (network, address, bits) = match (self.address, address) {
+      (IpAddr::V4(network), IpAddr::V4(address)) => (u32::from(network).into(), u32::from(address).into(), 32),
+      (IpAddr::V6(network), IpAddr::V6(address)) => (u128::from(network), u128::from(address), 128),
+      _ => return false,
+    };
+    self.prefix == 0 || (network ^ address) >> (bits - u32::from(self.prefix)) == 0
+  }
+}
+
+impl FromStr for Subnet {
+  type Err = failure::Error;
+
+  fn from_str(subnet: &str) -> Result<Self, Self::Err> {
+    let (address, prefix) = match subnet.split_once('/') {
+      Some((address, prefix)) => (address.parse::<IpAddr>()?, Some(prefix.parse::<u8>()?)),
+      None => (subnet.parse::<IpAddr>()?, None),
+    };
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(bits);
+    if prefix > bits {
+      return Err(format_err!("{} has a prefix longer than its address", subnet));
+    }
+    Ok(Self { address, prefix })
+  }
+}
+
+impl TryFrom<String> for Subnet {
+  type Error = failure::Error;
+
+  fn try_from(subnet: String) -> Result<Self, Self::Error> {
+    subnet.parse()
+  }
+}
+
+/// Returns true if `address` is in any of `subnets`, or if there are none to limit it
+pub fn allows(subnets: &[Subnet], address: IpAddr) -> bool {
+  subnets.is_empty() || subnets.iter().any(|subnet| subnet.contains(address))
+}
+
+/// Parse the interface addresses given to `--bind`, e.g. `0.0.0.0`, `::` or `[::1]`, which must differ
+pub fn interfaces<'a, I: IntoIterator<Item = &'a str>>(addresses: I) -> Result<Vec<IpAddr>, failure::Error> {
+  let mut interfaces = vec![];
+  for address in addresses {
+    let interface = address
+      .trim_start_matches('[')
+      .trim_end_matches(']')
+      .parse::<IpAddr>()
+      .map_err(|_| format_err!("cannot bind to '{}': expected an IPv4 or IPv6 address, e.g. 0.0.0.0 or ::", address))?;
+    if interfaces.contains(&interface) {
+      return Err(format_err!("cannot bind to {} more than once", interface));
+    }
+    interfaces.push(interface);
+  }
+  Ok(interfaces)
+}
+
+/// Listen on every address `address` resolves to, naming the address in any error
+pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Vec<TcpListener>> {
+  let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+  if addresses.is_empty() {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind to"));
+  }
+  addresses
+    .into_iter()
+    .map(|address| {
+      TcpListener::bind(address).map_err(|e| io::Error::new(e.kind(), format!("failed to bind {}: {}", address, e)))
+    })
+    .collect()
+}
+
+/// An address to listen on, as read from a listeners file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Listener {
+  pub address: String,
+  #[serde(default)]
+  pub role: EndpointRole,
+  /// The subnets connections are accepted from, or any if empty
+  #[serde(default)]
+  pub allow: Vec<Subnet>,
+}
+
+/// Read the listeners of a JSON listeners file, of which there must be at least one, each on a different address
+pub fn load(path: &Path) -> Result<Vec<Listener>, failure::Error> {
+  let listeners: Vec<Listener> = serde_json::from_slice(&fs::read(path)?)?;
+  if listeners.is_empty() {
+    return Err(format_err!("{} lists no listeners", path.display()));
+  }
+  let mut addresses = HashSet::new();
+  for listener in &listeners {
+    if !addresses.insert(&listener.address) {
+      return Err(format_err!("{} lists {} more than once", path.display(), listener.address));
+    }
+  }
+  Ok(listeners)
+}
+
+/// The subnets an account may be used from, as read from an account sources file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AccountSources {
+  pub account: AccountId,
+  pub allow: Vec<Subnet>,
+}
+
+/// Read a JSON account sources file, by account
+pub fn load_account_sources(path: &Path) -> Result<HashMap<AccountId, Vec<Subnet>>, failure::Error> {
+  let sources: Vec<AccountSources> = serde_json::from_slice(&fs::read(path)?)?;
+  Ok(sources.into_iter().map(|sources| (sources.account, sources.allow)).collect())
+}
+
+/// Where a session connected to and from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Connection {
+  pub role: EndpointRole,
+  /// The client's address, if the network has one
+  pub peer: Option<IpAddr>,
+}
+
+/// Check a command against the endpoint its session connected to and the subnets its account may be used from
+///
+/// A session without a peer address, e.g. one over shared memory, is not limited by account sources.
+pub fn permit(
+  engine: &MatchEngine,
+  connection: Connection,
+  account_sources: &HashMap<AccountId, Vec<Subnet>>,
+  command: &Command,
+) -> Result<(), Error> {
+  let role = connection.role;
+  if !role.permits(&command.kind) {
+    return Err(Error::NotServedOnEndpoint { role });
+  }
+  if role.requires_admin() && !engine.account(command.account_id).is_ok_and(|account| account.is_admin) {
+    return Err(Error::PermissionDenied { id: command.account_id });
+  }
+  if let (Some(peer), Some(subnets)) = (connection.peer, account_sources.get(&command.account_id)) {
+    if !allows(subnets, peer) {
+      return Err(Error::SourceNotAllowed {
+        id: command.account_id,
+        source: peer,
+      });
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn subnets_contain_the_addresses_under_their_prefix() {
+    let subnet: Subnet = "10.1.0.0/16".parse().unwrap();
+    assert!(subnet.contains("10.1.200.7".parse().unwrap()));
+    assert!(!subnet.contains("10.2.0.1".parse().unwrap()));
+    assert!(!subnet.contains("::ffff:10.1.0.1".parse().unwrap()));
+
+    let host: Subnet = "::1".parse().unwrap();
+    assert!(host.contains("::1".parse().unwrap()));
+    assert!(!host.contains("::2".parse().unwrap()));
+    assert!("0.0.0.0/0".parse::<Subnet>().unwrap().contains("192.0.2.1".parse().unwrap()));
+    assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+  }
+
+  #[test]
+  fn interfaces_must_be_distinct_addresses() {
+    let expected: Vec<IpAddr> = vec!["0.0.0.0".parse().unwrap(), "::1".parse().unwrap()];
+    assert_eq!(interfaces(vec!["0.0.0.0", "[::1]"]).unwrap(), expected);
+    assert!(interfaces(vec!["localhost"]).is_err());
+    assert!(interfaces(vec!["0.0.0.0:2556"]).is_err());
+    assert!(interfaces(vec!["::", "0::0"]).is_err());
+  }
+
+  #[test]
+  fn bind_listens_on_every_address() {
+    let addresses: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap(), "127.0.0.1:0".parse().unwrap()];
+    let listeners = bind(&addresses[..]).unwrap();
+    assert_eq!(listeners.len(), 2);
+    assert_ne!(listeners[0].local_addr().unwrap(), listeners[1].local_addr().unwrap());
+
+    let taken = listeners[0].local_addr().unwrap();
+    let error = bind(taken).unwrap_err();
+    assert!(error.to_string().contains(&taken.to_string()));
+  }
+}
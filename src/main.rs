@@ -1,53 +1,923 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use engine::*;
 
+mod bench;
+mod bus;
+mod dump;
+mod durability;
+mod eod;
+#[cfg(feature = "epoll")]
+mod epoll;
+mod gateway;
+mod health;
+mod import;
+mod listeners;
+mod replication;
+mod segments;
+mod server;
+mod shm;
+#[cfg(test)]
+mod sim;
+mod soak;
+mod subscriptions;
+mod threads;
+mod ticks;
+mod watchdog;
+mod webhook;
+
+use durability::DurableJournal;
 use failure::Error;
 
-use serde_json::{Deserializer, StreamDeserializer};
-use std::io::{BufRead, BufReader, Read};
-use std::net::{TcpListener, TcpStream};
+use serde_json::json;
+use gateway::GatewayNetwork;
+use health::Health;
+use replication::Replicator;
+use segments::{Compactor, Retention, SegmentedJournal};
+use server::{Server, ShutdownPolicy, TcpConfig, TcpNetwork, WaitStrategy};
+use watchdog::{Watchdog, WatchdogConfig};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::thread;
-const DEFAULT_PORT: &'static str = "2556";
+use std::time::Duration;
+#[global_allocator]
+static ALLOCATOR: bench::CountingAllocator = bench::CountingAllocator;
 
-fn handle_connection(stream: TcpStream, engine: Arc<Mutex<MatchEngine>>) {
-  // let deserializer = Deserializer::from_reader(BufReader::new(stream));
-  for command in Deserializer::from_reader(stream).into_iter().filter_map(move |x| {
-    dbg!(&x);
-    Result::ok(x)
-  }) {
-    let mut lock = engine.lock().unwrap();
-    // TODO: need to send this result back
-    let _ = lock.try_process(command);
-    dbg!(&lock);
-  }
-}
+const DEFAULT_PORT: &'static str = "2556";
+const DEFAULT_INTERFACE: &str = "127.0.0.1";
+#[cfg(feature = "epoll")]
+const IO_BACKENDS: &[&str] = &["threads", "epoll"];
+#[cfg(not(feature = "epoll"))]
+const IO_BACKENDS: &[&str] = &["threads"];
 
 fn main() -> Result<(), Error> {
   let matches = App::new(env!("CARGO_PKG_NAME"))
     .version(env!("CARGO_PKG_VERSION"))
     .author(env!("CARGO_PKG_AUTHORS"))
     .about(env!("CARGO_PKG_DESCRIPTION"))
-    .arg(Arg::with_name("port").short("p").long("port").help("port to bind to"))
+    .setting(AppSettings::SubcommandRequiredElseHelp)
+    .subcommand(
+      SubCommand::with_name("serve")
+        .about("serve order entry over TCP")
+        .arg(
+          Arg::with_name("port")
+            .short("p")
+            .long("port")
+            .takes_value(true)
+            .default_value(DEFAULT_PORT)
+            .help("port to bind to"),
+        )
+        .arg(
+          Arg::with_name("bind")
+            .long("bind")
+            .takes_value(true)
+            .use_delimiter(true)
+            .default_value(DEFAULT_INTERFACE)
+            .help("comma-separated interface addresses to bind every port on, e.g. 0.0.0.0 or ::"),
+        )
+        .arg(
+          Arg::with_name("audit-log")
+            .long("audit-log")
+            .takes_value(true)
+            .help("file to append the order event audit trail to"),
+        )
+        .arg(
+          Arg::with_name("journal")
+            .long("journal")
+            .takes_value(true)
+            .help("file to append the timestamped command journal to"),
+        )
+        .arg(
+          Arg::with_name("journal-dir")
+            .long("journal-dir")
+            .takes_value(true)
+            .conflicts_with("journal")
+            .help("directory to journal to in segments, with snapshots to compact it behind and recover from"),
+        )
+        .arg(
+          Arg::with_name("segment-size")
+            .long("segment-size")
+            .takes_value(true)
+            .requires("journal-dir")
+            .help("bytes after which the journal rolls over into a new segment"),
+        )
+        .arg(
+          Arg::with_name("keep-segments")
+            .long("keep-segments")
+            .takes_value(true)
+            .requires("journal-dir")
+            .help("segments to keep after a snapshot includes them"),
+        )
+        .arg(
+          Arg::with_name("keep-snapshots")
+            .long("keep-snapshots")
+            .takes_value(true)
+            .requires("journal-dir")
+            .help("snapshots to keep, including the latest"),
+        )
+        .arg(
+          Arg::with_name("journal-durability")
+            .long("journal-durability")
+            .takes_value(true)
+            .default_value("every-command")
+            .help("when the journal is synced: every-command, group-commit:<ms> or async"),
+        )
+        .arg(
+          Arg::with_name("cancel-on-shutdown")
+            .long("cancel-on-shutdown")
+            .help("cancel every resting order on SIGTERM or SIGINT, before exiting"),
+        )
+        .arg(
+          Arg::with_name("trace-latency")
+            .long("trace-latency")
+            .help("follow every reply with when its command was received, dequeued, matched and answered"),
+        )
+        .arg(
+          Arg::with_name("stall-after")
+            .long("stall-after")
+            .takes_value(true)
+            .help("milliseconds one command may take on a symbol's book before the symbol is reported stalled"),
+        )
+        .arg(
+          Arg::with_name("halt-stalled")
+            .long("halt-stalled")
+            .requires("stall-after")
+            .help("halt order entry on a stalled symbol until an admin resumes it"),
+        )
+        .arg(
+          Arg::with_name("accounts")
+            .long("accounts")
+            .takes_value(true)
+            .help("JSON file to keep accounts in across restarts, loaded at startup if it exists"),
+        )
+        .arg(
+          Arg::with_name("end-of-day")
+            .long("end-of-day")
+            .takes_value(true)
+            .value_name("HH:MM")
+            .help("close the trading day every weekday at this time, in UTC"),
+        )
+        .arg(
+          Arg::with_name("eod-dir")
+            .long("eod-dir")
+            .takes_value(true)
+            .default_value("eod")
+            .help("directory to write end-of-day snapshots to"),
+        )
+        .arg(
+          Arg::with_name("dump-dir")
+            .long("dump-dir")
+            .takes_value(true)
+            .help("directory to write book dumps to, without which admins' dump commands fail"),
+        )
+        .arg(
+          Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .help("JSON runtime configuration to load, and reload on SIGHUP"),
+        )
+        .arg(
+          Arg::with_name("listeners")
+            .long("listeners")
+            .takes_value(true)
+            .help("JSON file of addresses to listen on instead of the port, each with the role its sessions serve"),
+        )
+        .arg(
+          Arg::with_name("account-sources")
+            .long("account-sources")
+            .takes_value(true)
+            .help("JSON file of the subnets each listed account may only be used from"),
+        )
+        .arg(
+          Arg::with_name("health-port")
+            .long("health-port")
+            .takes_value(true)
+            .help("port to serve /healthz and /readyz on over HTTP"),
+        )
+        .arg(
+          Arg::with_name("workers")
+            .long("workers")
+            .takes_value(true)
+            .help("threads reading client connections"),
+        )
+        .arg(
+          Arg::with_name("max-connections")
+            .long("max-connections")
+            .takes_value(true)
+            .help("connections to reject beyond"),
+        )
+        .arg(
+          Arg::with_name("io")
+            .long("io")
+            .takes_value(true)
+            .possible_values(IO_BACKENDS)
+            .default_value("threads")
+            .help("how client connections are read"),
+        )
+        .arg(
+          Arg::with_name("busy-poll")
+            .long("busy-poll")
+            .help("spin waiting for input instead of blocking, trading CPU for wake-up latency"),
+        )
+        .arg(
+          Arg::with_name("spin-limit")
+            .long("spin-limit")
+            .takes_value(true)
+            .requires("busy-poll")
+            .help("empty checks in a row after which a busy-polling thread blocks until woken"),
+        )
+        .arg(
+          Arg::with_name("matching-core")
+            .long("matching-core")
+            .takes_value(true)
+            .help("core to pin the matching thread, which also writes the journal, to"),
+        )
+        .arg(
+          Arg::with_name("io-cores")
+            .long("io-cores")
+            .takes_value(true)
+            .use_delimiter(true)
+            .help("comma-separated cores to pin connection workers to"),
+        )
+        .arg(
+          Arg::with_name("replication-port")
+            .long("replication-port")
+            .takes_value(true)
+            .help("port to stream the journal to standbys on"),
+        )
+        .arg(
+          Arg::with_name("standby-of")
+            .long("standby-of")
+            .takes_value(true)
+            .help("address of a primary's replication port to follow, taking over once it is lost"),
+        )
+        .arg(
+          Arg::with_name("gateway-port")
+            .long("gateway-port")
+            .takes_value(true)
+            .conflicts_with_all(&["io", "port"])
+            .help("port to accept gateway processes on, serving their clients instead of accepting any directly"),
+        )
+        .arg(
+          Arg::with_name("shm")
+            .long("shm")
+            .takes_value(true)
+            .conflicts_with_all(&["io", "gateway-port"])
+            .help("file to serve clients on this host through shared memory in, instead of over TCP"),
+        )
+        .arg(
+          Arg::with_name("bus")
+            .long("bus")
+            .takes_value(true)
+            .help("nats://host:port or kafka://host:port of a bus to publish order events and book snapshots to"),
+        )
+        .arg(
+          Arg::with_name("bus-prefix")
+            .long("bus-prefix")
+            .takes_value(true)
+            .default_value("matchbook")
+            .help("prefix of the topics published to the bus"),
+        )
+        .arg(
+          Arg::with_name("webhooks")
+            .long("webhooks")
+            .takes_value(true)
+            .help("JSON list of accounts' webhook URLs to post fills and rejections to, reloaded on SIGHUP"),
+        )
+        .arg(
+          Arg::with_name("ticks")
+            .long("ticks")
+            .takes_value(true)
+            .help("directory to store trade and quote history in, and answer history queries from"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("gateway")
+        .about("accept clients in a separate process, forwarding their commands to a matcher")
+        .arg(
+          Arg::with_name("port")
+            .short("p")
+            .long("port")
+            .takes_value(true)
+            .default_value(DEFAULT_PORT)
+            .help("port to bind to"),
+        )
+        .arg(
+          Arg::with_name("bind")
+            .long("bind")
+            .takes_value(true)
+            .use_delimiter(true)
+            .default_value(DEFAULT_INTERFACE)
+            .help("comma-separated interface addresses to bind every port on, e.g. 0.0.0.0 or ::"),
+        )
+        .arg(
+          Arg::with_name("matcher")
+            .long("matcher")
+            .takes_value(true)
+            .required(true)
+            .help("address of the matcher's gateway port"),
+        )
+        .arg(
+          Arg::with_name("workers")
+            .long("workers")
+            .takes_value(true)
+            .help("threads reading client connections"),
+        )
+        .arg(
+          Arg::with_name("max-connections")
+            .long("max-connections")
+            .takes_value(true)
+            .help("connections to reject beyond"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("shm-latency")
+        .about("measure order entry round trips to a server over shared memory")
+        .arg(Arg::with_name("shm").required(true).help("file the server serves clients through"))
+        .arg(
+          Arg::with_name("commands")
+            .long("commands")
+            .takes_value(true)
+            .default_value("100000")
+            .help("commands to send, one at a time"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("replay")
+        .about("rebuild the engine from a journal, checking every state hash recorded in it")
+        .arg(journal_arg()),
+    )
+    .subcommand(
+      SubCommand::with_name("snapshot")
+        .about("write the books rebuilt from a journal to a JSON file")
+        .arg(journal_arg())
+        .arg(Arg::with_name("out").required(true).help("file to write the snapshot to")),
+    )
+    .subcommand(
+      SubCommand::with_name("book")
+        .about("print a symbol's book, order by order, as it was at a point in a journal")
+        .arg(journal_arg())
+        .arg(Arg::with_name("symbol").required(true).help("symbol of the book"))
+        .arg(
+          Arg::with_name("sequence")
+            .long("sequence")
+            .takes_value(true)
+            .required_unless("time")
+            .conflicts_with("time")
+            .help("sequence number of the last entry to replay"),
+        )
+        .arg(
+          Arg::with_name("time")
+            .long("time")
+            .takes_value(true)
+            .help("replay the entries received at or before this time, in nanoseconds"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("export")
+        .about("write the audit trail of a journal's commands to a file")
+        .arg(journal_arg())
+        .arg(Arg::with_name("out").required(true).help("file to write the audit trail to")),
+    )
+    .subcommand(
+      SubCommand::with_name("fees")
+        .about("write the fees each account of a journal accrued on each symbol over a day to a CSV file")
+        .arg(journal_arg())
+        .arg(Arg::with_name("date").required(true).help("day to total, as YYYY-MM-DD in UTC"))
+        .arg(Arg::with_name("out").required(true).help("file to write the fees to"))
+        .arg(
+          Arg::with_name("month-to-date")
+            .long("month-to-date")
+            .help("total from the first of the day's month to the day"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("import")
+        .about("place the orders of a CSV or JSON file through a running server, reporting what became of each row")
+        .arg(Arg::with_name("file").required(true).help("file of orders to place"))
+        .arg(
+          Arg::with_name("server")
+            .long("server")
+            .takes_value(true)
+            .default_value("127.0.0.1:2556")
+            .help("address of the server to place the orders through"),
+        )
+        .arg(
+          Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["csv", "json"])
+            .help("how the file is encoded, by default csv if its name ends in .csv and json otherwise"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("bench")
+        .about("run a synthetic workload in-process and report throughput and latency")
+        .arg(
+          Arg::with_name("commands")
+            .short("n")
+            .long("commands")
+            .takes_value(true)
+            .default_value("100000")
+            .help("number of commands to process"),
+        )
+        .args(&workload_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("soak")
+        .about("run a synthetic workload for a long time, failing if invariants break or memory grows")
+        .arg(
+          Arg::with_name("hours")
+            .long("hours")
+            .takes_value(true)
+            .default_value("1")
+            .help("how long to run for"),
+        )
+        .arg(
+          Arg::with_name("check-interval")
+            .long("check-interval")
+            .takes_value(true)
+            .default_value("100000")
+            .help("commands between invariant and memory checks"),
+        )
+        .arg(
+          Arg::with_name("max-rss-growth")
+            .long("max-rss-growth")
+            .takes_value(true)
+            .default_value("512")
+            .help("MiB resident memory may grow after the first check"),
+        )
+        .args(&workload_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("schema")
+        .about("print the JSON Schema of a wire message")
+        .arg(
+          Arg::with_name("message")
+            .required(true)
+            .possible_values(&schema::MESSAGES)
+            .help("message to describe"),
+        ),
+    )
     .get_matches();
 
-  let port = matches.value_of("port").unwrap_or(DEFAULT_PORT).parse::<usize>()?;
-  let mut engine = MatchEngine::default();
-  engine.insert_new_symbol(['A', 'D', 'B', 'E'].into());
-  println!("created account {}", engine.create_account());
-  let engine = Arc::new(Mutex::new(engine));
+  match matches.subcommand() {
+    ("serve", Some(matches)) => serve(matches),
+    ("gateway", Some(matches)) => {
+      let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+      let interfaces = listeners::interfaces(matches.values_of("bind").unwrap())?;
+      let port = matches.value_of("port").unwrap().parse::<u16>()?;
+      let mut network = TcpNetwork::bind(&on(&interfaces, port)[..], clock.clone(), tcp_config(matches)?)?;
+      gateway::run(&mut network, clock.as_ref(), matches.value_of("matcher").unwrap())?;
+      Ok(())
+    }
+    ("shm-latency", Some(matches)) => {
+      let mut client = shm::ShmClient::connect(matches.value_of("shm").unwrap())?;
+      let command = Command {
+        account_id: 0.into(),
+        kind: CommandKind::GetAccountSummary(0.into()),
+      };
+      let mut round_trips = shm::round_trips(&mut client, &command, matches.value_of("commands").unwrap().parse()?)?;
+      round_trips.sort();
+      let percentile = |p: usize| round_trips.get(round_trips.len() * p / 100).copied().unwrap_or_default();
+      println!("p50 {:?}, p99 {:?}, max {:?}", percentile(50), percentile(99), round_trips.last());
+      Ok(())
+    }
+    ("replay", Some(matches)) => {
+      let (engine, entries) = replay(matches)?;
+      println!("replayed {} entries to state hash {:016x}", entries, engine.state_hash());
+      Ok(())
+    }
+    ("snapshot", Some(matches)) => snapshot(matches),
+    ("book", Some(matches)) => book(matches),
+    ("export", Some(matches)) => {
+      let (mut engine, _) = replay(matches)?;
+      let mut exporter = AuditExporter::new(BufWriter::new(File::create(matches.value_of("out").unwrap())?))?;
+      exporter.export(&engine.drain_audit_trail())?;
+      Ok(())
+    }
+    ("fees", Some(matches)) => fees(matches),
+    ("import", Some(matches)) => import(matches),
+    ("bench", Some(matches)) => {
+      let report = bench::run(workload(matches, matches.value_of("commands").unwrap().parse()?)?);
+      println!("{}", report);
+      Ok(())
+    }
+    ("soak", Some(matches)) => {
+      let hours: f64 = matches.value_of("hours").unwrap().parse()?;
+      let commands = soak::run(soak::Soak {
+        workload: workload(matches, 0)?,
+        duration: Duration::from_secs_f64(hours * 60.0 * 60.0),
+        check_interval: matches.value_of("check-interval").unwrap().parse()?,
+        max_rss_growth: matches.value_of("max-rss-growth").unwrap().parse::<u64>()? << 20,
+      })?;
+      println!("soaked {} commands", commands);
+      Ok(())
+    }
+    ("schema", Some(matches)) => {
+      let schema = schema::schema(matches.value_of("message").unwrap()).unwrap();
+      println!("{}", serde_json::to_string_pretty(&schema)?);
+      Ok(())
+    }
+    _ => unreachable!("a subcommand is required"),
+  }
+}
+
+fn journal_arg() -> Arg<'static, 'static> {
+  Arg::with_name("journal").required(true).help("journal file or directory to read")
+}
+
+/// Read the journal the `journal` argument names, a file or an uncompacted journal directory
+fn read_entries(matches: &ArgMatches) -> Result<Vec<JournalEntry>, Error> {
+  let path = Path::new(matches.value_of("journal").unwrap());
+  if !path.is_dir() {
+    return read_journal(BufReader::new(File::open(path)?)).collect();
+  }
 
+  if segments::segments(path)?.first().is_some_and(|&(segment, _)| segment != 1) {
+    return Err(failure::format_err!("{} was compacted, so its first entries are only in a snapshot", path.display()));
+  }
+  segments::read_segments(path)
+}
+
+/// Arguments shaping the synthetic workload
+fn workload_args() -> Vec<Arg<'static, 'static>> {
+  vec![
+    Arg::with_name("spread")
+      .long("spread")
+      .takes_value(true)
+      .default_value("10")
+      .help("price levels either side of the mid to place orders in"),
+    Arg::with_name("seed")
+      .long("seed")
+      .takes_value(true)
+      .default_value("1")
+      .help("seed for the workload generator"),
+  ]
+}
+
+fn workload(matches: &ArgMatches, commands: usize) -> Result<bench::Workload, Error> {
+  Ok(bench::Workload {
+    commands,
+    spread: matches.value_of("spread").unwrap().parse()?,
+    seed: matches.value_of("seed").unwrap().parse()?,
+  })
+}
+
+/// Rebuild an engine from the journal named by the `journal` argument
+///
+/// # Returns
+/// the engine and the number of entries replayed
+fn replay(matches: &ArgMatches) -> Result<(MatchEngine, usize), Error> {
+  let entries = read_entries(matches)?;
+  let count = entries.len();
+  let engine = MatchEngine::replay(Arc::new(ManualClock::default()), entries)?;
+  Ok((engine, count))
+}
+
+fn snapshot(matches: &ArgMatches) -> Result<(), Error> {
+  let (engine, _) = replay(matches)?;
+  let books = engine
+    .symbols()
+    .into_iter()
+    .map(|symbol| {
+      Ok(json!({
+        "symbol": symbol,
+        "bids": engine.depth(symbol, Side::Bid)?,
+        "asks": engine.depth(symbol, Side::Ask)?,
+      }))
+    })
+    .collect::<Result<Vec<_>, engine::Error>>()?;
+
+  let out = BufWriter::new(File::create(matches.value_of("out").unwrap())?);
+  serde_json::to_writer_pretty(out, &json!({ "state_hash": engine.state_hash(), "books": books }))?;
+  Ok(())
+}
+
+/// Write the fee report the arguments ask for of a journal to a CSV file, a row per account and symbol followed by a
+/// row of each account's total with no symbol
+fn fees(matches: &ArgMatches) -> Result<(), Error> {
+  let (engine, _) = replay(matches)?;
+  let period = if matches.is_present("month-to-date") { FeePeriod::MonthToDate } else { FeePeriod::Day };
+  let report = engine.fee_report(None, date(matches.value_of("date").unwrap())?, period);
+
+  let mut out = BufWriter::new(File::create(matches.value_of("out").unwrap())?);
+  writeln!(out, "account,symbol,fill_fees,rebates,order_fees,net,fills")?;
+  for line in report.lines.iter().chain(&report.totals) {
+    let symbol = line.symbol.map(|symbol| symbol.to_string()).unwrap_or_default();
+    writeln!(
+      out,
+      "{},{},{},{},{},{},{}",
+      line.account, symbol, line.fill_fees, line.rebates, line.order_fees, line.net, line.fills
+    )?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// Place the orders of the file the arguments name through a server, printing what became of each row
+fn import(matches: &ArgMatches) -> Result<(), Error> {
+  let path = Path::new(matches.value_of("file").unwrap());
+  let format = match matches.value_of("format") {
+    Some("csv") => import::Format::Csv,
+    Some(_) => import::Format::Json,
+    None if path.extension().is_some_and(|extension| extension == "csv") => import::Format::Csv,
+    None => import::Format::Json,
+  };
+  let rows = import::read_orders(&std::fs::read_to_string(path)?, format);
+
+  let stream = TcpStream::connect(matches.value_of("server").unwrap())?;
+  let mut replies = BufReader::new(stream.try_clone()?);
+  let results = import::import(rows, &mut replies, &mut BufWriter::new(stream))?;
+  for (number, result) in &results {
+    println!("row {}: {}", number, result);
+  }
+  let placed = results.iter().filter(|(_, result)| result.is_placed()).count();
+  println!("placed {} of {} orders", placed, results.len());
+  Ok(())
+}
+
+/// Print the orders resting in a symbol's book at the point in a journal the arguments ask for
+fn book(matches: &ArgMatches) -> Result<(), Error> {
+  let point = match matches.value_of("sequence") {
+    Some(sequence) => JournalPoint::Sequence(sequence.parse::<u64>()?.into()),
+    None => JournalPoint::Time(matches.value_of("time").unwrap().parse::<u64>()?.into()),
+  };
+  let entries = read_entries(matches)?;
+  let engine = MatchEngine::replay_until(Arc::new(ManualClock::default()), entries, point)?;
+
+  let name = matches.value_of("symbol").unwrap();
+  let symbol = match engine.symbols().into_iter().find(|symbol| symbol.to_string() == name) {
+    Some(symbol) => symbol,
+    None => return Err(failure::format_err!("{} is not listed at that point in the journal", name)),
+  };
+  let side = |side| -> Result<Vec<_>, engine::Error> {
+    let levels = engine.depth(symbol, side)?;
+    levels
+      .into_iter()
+      .map(|(price, quantity)| {
+        Ok(json!({ "price": price, "quantity": quantity, "orders": engine.level(symbol, side, price)? }))
+      })
+      .collect()
+  };
+
+  let book = json!({
+    "symbol": symbol,
+    "state_hash": engine.state_hash(),
+    "bids": side(Side::Bid)?,
+    "asks": side(Side::Ask)?,
+  });
+  println!("{}", serde_json::to_string_pretty(&book)?);
+  Ok(())
+}
+
+/// Connect to the message bus at `url`
+fn publisher(url: &str, prefix: &str) -> Result<bus::Publisher, Error> {
+  if let Some(address) = url.strip_prefix("nats://") {
+    Ok(bus::Publisher::spawn(bus::NatsSink::connect(address)?, prefix)?)
+  } else if let Some(address) = url.strip_prefix("kafka://") {
+    Ok(bus::Publisher::spawn(bus::KafkaSink::connect(address)?, prefix)?)
+  } else {
+    Err(failure::format_err!("unknown message bus {}, expected nats:// or kafka://", url))
+  }
+}
+
+/// Parse a time of day written `HH:MM`
+fn time_of_day(time: &str) -> Result<Duration, Error> {
+  let (hours, minutes) = match time.split_once(':') {
+    Some((hours, minutes)) => (hours.parse::<u64>()?, minutes.parse::<u64>()?),
+    None => return Err(failure::format_err!("expected a time of day as HH:MM, not {}", time)),
+  };
+  if hours >= 24 || minutes >= 60 {
+    return Err(failure::format_err!("{} is not a time of day", time));
+  }
+  Ok(Duration::from_secs((hours * 60 + minutes) * 60))
+}
+
+/// Parse a UTC date written `YYYY-MM-DD`
+fn date(date: &str) -> Result<Date, Error> {
+  let parts: Vec<&str> = date.split('-').collect();
+  let (year, month, day) = match parts[..] {
+    [year, month, day] => (year.parse()?, month.parse()?, day.parse()?),
+    _ => return Err(failure::format_err!("expected a date as YYYY-MM-DD, not {}", date)),
+  };
+  if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+    return Err(failure::format_err!("{} is not a date", date));
+  }
+  Ok(Date { year, month, day })
+}
+
+/// Share out connections as the `workers` and `max-connections` arguments ask
+fn tcp_config(matches: &ArgMatches) -> Result<TcpConfig, Error> {
+  let mut config = TcpConfig::default();
+  if let Some(workers) = matches.value_of("workers") {
+    config.workers = workers.parse()?;
+  }
+  if let Some(max_connections) = matches.value_of("max-connections") {
+    config.max_connections = max_connections.parse()?;
+  }
+  Ok(config)
+}
+
+/// Get the addresses of `port` on each of `interfaces`
+fn on(interfaces: &[IpAddr], port: u16) -> Vec<SocketAddr> {
+  interfaces.iter().map(|&interface| SocketAddr::new(interface, port)).collect()
+}
+
+/// Recover the state journaled to the `journal-dir` directory, if it has any, reporting how it was recovered
+fn recover(matches: &ArgMatches, clock: Arc<dyn Clock>) -> Result<Option<MatchEngine>, Error> {
+  let directory = match matches.value_of("journal-dir") {
+    Some(directory) => Path::new(directory),
+    None => return Ok(None),
+  };
+  let recovery = match segments::recover(directory, clock)? {
+    Some(recovery) => recovery,
+    None => return Ok(None),
+  };
+
+  match recovery.snapshot {
+    Some(last) => println!("recovered from the snapshot after entry {:?}", last),
+    None => println!("recovered without a snapshot"),
+  }
+  println!(
+    "replayed {} journaled entries in {:?}, last verified state hash {:?}",
+    recovery.replayed, recovery.elapsed, recovery.verified
+  );
+  Ok(Some(recovery.engine))
+}
+
+fn serve(matches: &ArgMatches) -> Result<(), Error> {
+  let interfaces = listeners::interfaces(matches.values_of("bind").unwrap())?;
+  let port = matches.value_of("port").unwrap().parse::<u16>()?;
+  let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+  let durability = matches.value_of("journal-durability").unwrap().parse()?;
+  let mut journal = match (matches.value_of("journal"), matches.value_of("journal-dir")) {
+    (Some(path), _) => Some(DurableJournal::new(OpenOptions::new().create(true).append(true).open(path)?, durability)?),
+    (_, Some(directory)) => {
+      let segment_size = matches.value_of("segment-size").map(str::parse).transpose()?;
+      let segments = SegmentedJournal::open(directory, segment_size.unwrap_or(segments::DEFAULT_SEGMENT_BYTES))?;
+      Some(DurableJournal::new(segments, durability)?)
+    }
+    _ => None,
+  };
+
+  let engine = match matches.value_of("standby-of") {
+    Some(primary) => {
+      let standby = replication::follow(primary, journal.as_mut())?;
+      println!("taking over after entry {:?}, last verified state hash {:?}", standby.last(), standby.verified());
+      standby.promote(clock.clone())
+    }
+    None => match recover(matches, clock.clone())? {
+      Some(engine) => engine,
+      None => {
+        let mut engine = MatchEngine::with_clock(clock.clone());
+        engine.insert_new_symbol(['A', 'D', 'B', 'E'].into())?;
+        match matches.value_of("accounts").map(Path::new).filter(|path| path.exists()) {
+          Some(path) => {
+            let store = server::load_accounts(path)?;
+            println!("loaded {} accounts from {}", store.accounts.len(), path.display());
+            engine.load_accounts(store);
+          }
+          None => println!("created account {}", engine.create_account()),
+        }
+        engine
+      }
+    },
+  };
+
+  let mut server = Server::new(engine, clock.clone());
+  if let Some(path) = matches.value_of("accounts") {
+    server = server.with_account_store(path);
+  }
+  if let Some(path) = matches.value_of("audit-log") {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    let file: Box<dyn Write> = Box::new(file);
+    server = server.with_audit_exporter(AuditExporter::resume(file, written)?);
+  }
+  if let Some(journal) = journal {
+    server = server.with_journal(journal);
+  }
+  if let Some(directory) = matches.value_of("journal-dir") {
+    let mut retention = Retention::default();
+    if let Some(segments) = matches.value_of("keep-segments") {
+      retention.segments = segments.parse()?;
+    }
+    if let Some(snapshots) = matches.value_of("keep-snapshots") {
+      retention.snapshots = snapshots.parse()?;
+    }
+    server = server.with_compactor(Compactor::new(directory, retention));
+  }
+  if let Some(port) = matches.value_of("replication-port") {
+    let replicator = Replicator::bind(&on(&interfaces, port.parse()?)[..])?;
+    println!("streaming the journal to standbys on {}", replicator.local_addr());
+    server = server.with_replicator(replicator);
+  }
+
+  if let Some(url) = matches.value_of("bus") {
+    server = server.with_publisher(publisher(url, matches.value_of("bus-prefix").unwrap())?);
+  }
+
+  if let Some(path) = matches.value_of("webhooks") {
+    server = server.with_notifier(webhook::Notifier::spawn(path)?);
+  }
+  if let Some(path) = matches.value_of("ticks") {
+    server = server.with_tick_store(ticks::TickStore::open(path)?);
+  }
+  if let Some(close) = matches.value_of("end-of-day") {
+    let calendar = eod::Calendar::new(time_of_day(close)?);
+    let job = eod::EndOfDayJob::new(calendar, matches.value_of("eod-dir").unwrap(), clock.now())?;
+    server = server.with_end_of_day(job);
+  }
+
+  if let Some(path) = matches.value_of("dump-dir") {
+    server = server.with_book_dumper(dump::BookDumper::new(path)?);
+  }
+
+  if let Some(path) = matches.value_of("account-sources") {
+    server = server.with_account_sources(listeners::load_account_sources(Path::new(path))?);
+  }
+
+  if let Some(port) = matches.value_of("health-port") {
+    let health = Arc::new(Mutex::new(Health::default()));
+    health::serve(&on(&interfaces, port.parse()?)[..], health.clone(), clock.clone())?;
+    server = server.with_health(health);
+  }
+  if let Some(path) = matches.value_of("config") {
+    server = server.with_config(path)?;
+  }
+  if matches.is_present("config") || matches.is_present("webhooks") {
+    server::reload_on_sighup();
+  }
+  if matches.is_present("trace-latency") {
+    server = server.with_latency_traces();
+  }
+  if let Some(stall_after) = matches.value_of("stall-after") {
+    let config = WatchdogConfig {
+      stall_after: Duration::from_millis(stall_after.parse()?),
+      halts_stalled: matches.is_present("halt-stalled"),
+    };
+    let watchdog = Watchdog::new(config, clock.clone());
+    watchdog.spawn()?;
+    server = server.with_watchdog(watchdog);
+  }
+  if matches.is_present("cancel-on-shutdown") {
+    server = server.with_shutdown_policy(ShutdownPolicy::CancelAll);
+  }
+  if matches.is_present("cancel-on-shutdown") || matches.is_present("accounts") {
+    server::shutdown_on_sigterm();
+  }
+
+  let mut config = tcp_config(matches)?;
+  if let Some(cores) = matches.values_of("io-cores") {
+    config.io_cores = cores.map(str::parse).collect::<Result<_, _>>()?;
+  }
+  if matches.is_present("busy-poll") {
+    let limit = matches.value_of("spin-limit").map(str::parse).transpose()?;
+    config.wait = WaitStrategy::Spin { limit };
+  }
+  let matching_core = matches.value_of("matching-core").map(str::parse).transpose()?;
+  threads::register("match", matching_core);
+
+  if let Some(path) = matches.value_of("shm") {
+    let mut network = shm::ShmNetwork::bind(path, clock, config.wait)?;
+    println!("serving clients through {}", path);
+    server.run(&mut network);
+    return Ok(());
+  }
+  if let Some(port) = matches.value_of("gateway-port") {
+    let mut network = GatewayNetwork::bind(&on(&interfaces, port.parse()?)[..], clock)?;
+    println!("accepting gateways on {}", network.local_addr());
+    server.run(&mut network);
+    return Ok(());
+  }
+
+  if let Some(path) = matches.value_of("listeners") {
+    if matches.value_of("io") != Some("threads") {
+      return Err(failure::format_err!("listeners are only served by the threads backend"));
+    }
+    let listeners = listeners::load(Path::new(path))?;
+    let mut network = TcpNetwork::bind_listeners(&listeners, clock, config)?;
+    for listener in &listeners {
+      println!("serving {:?} sessions on {}", listener.role, listener.address);
+    }
+    server.run(&mut network);
+    return Ok(());
+  }
 
-  let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
-  for stream in listener.incoming() {
-    // FIXME: this shouldn't return out of main
-    let stream = stream?;
-    let engine = engine.clone();
-    thread::spawn(move || {
-      handle_connection(stream, engine.clone());
-    });
+  let addresses = on(&interfaces, port);
+  #[cfg(feature = "epoll")]
+  {
+    if matches.value_of("io") == Some("epoll") {
+      let mut network = epoll::EpollNetwork::bind(&addresses[..], clock, config)?;
+      server.run(&mut network);
+      return Ok(());
+    }
   }
 
+  let mut network = TcpNetwork::bind(&addresses[..], clock, config)?;
+  server.run(&mut network);
   Ok(())
 }
 
@@ -0,0 +1,167 @@
+//! Journal streaming between a primary server and its standbys
+//!
+//! The primary sends every journal entry to each connected standby before replying to the command that produced it,
+//! so a promoted standby has every result a client was sent. Each standby is first sent a snapshot of the primary as
+//! one line, then the journal from where the snapshot left off.
+
+use crate::durability::DurableJournal;
+use crate::listeners;
+use crate::threads;
+use engine::*;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::slice;
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+/// How long a write to a standby may block the matching thread before the standby is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The primary's end of replication, streaming the journal to standbys
+pub struct Replicator {
+  address: SocketAddr,
+  incoming: Receiver<TcpStream>,
+  standbys: Vec<TcpStream>,
+  /// The sequence of the last entry sent
+  last: Option<EventId>,
+}
+
+impl Replicator {
+  /// Accept standbys on every address `address` resolves to
+  pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+    let listeners = listeners::bind(address)?;
+    let address = listeners[0].local_addr()?;
+    let (sender, incoming) = mpsc::channel();
+    for listener in listeners {
+      let sender = sender.clone();
+      threads::spawn("replication", None, move || {
+        for stream in listener.incoming() {
+          let stream = match stream.and_then(|stream| stream.set_write_timeout(Some(WRITE_TIMEOUT)).map(|()| stream)) {
+            Ok(stream) => stream,
+            Err(e) => {
+              eprintln!("failed to accept standby: {}", e);
+              continue;
+            }
+          };
+          if sender.send(stream).is_err() {
+            return;
+          }
+        }
+      })?;
+    }
+
+    Ok(Self {
+      address,
+      incoming,
+      standbys: vec![],
+      last: None,
+    })
+  }
+
+  /// Get the first address standbys connect to
+  pub fn local_addr(&self) -> SocketAddr {
+    self.address
+  }
+
+  /// Send journal entries to every standby, then a snapshot of `engine` to any that connected since the last call
+  ///
+  /// `engine` must have applied every entry in `journal`. A standby that cannot keep up is dropped rather than stalling
+  /// the primary.
+  pub fn replicate(&mut self, journal: &[JournalEntry], engine: &MatchEngine) {
+    let mut lines = vec![];
+    for entry in journal {
+      serde_json::to_writer(&mut lines, entry).expect("journal entries always serialize");
+      lines.push(b'\n');
+      self.last = Some(entry.sequence);
+    }
+    self.standbys.retain(|mut standby| match standby.write_all(&lines) {
+      Ok(()) => true,
+      Err(e) => {
+        eprintln!("dropped standby: {}", e);
+        false
+      }
+    });
+
+    let mut incoming = self.incoming.try_iter().peekable();
+    if incoming.peek().is_none() {
+      return;
+    }
+    let mut snapshot = serde_json::to_vec(&Snapshot::new(engine, self.last)).expect("engines always serialize");
+    snapshot.push(b'\n');
+    for mut standby in incoming {
+      match standby.write_all(&snapshot) {
+        Ok(()) => self.standbys.push(standby),
+        Err(e) => eprintln!("failed to send snapshot to standby: {}", e),
+      }
+    }
+  }
+
+  /// Get the number of standbys being streamed to
+  pub fn standbys(&self) -> usize {
+    self.standbys.len()
+  }
+}
+
+/// Restore from a primary's snapshot, then apply its journal until the primary is lost
+///
+/// Each applied entry is also written to `journal`. Entries before the snapshot are not, so replaying the journal
+/// alone does not rebuild the standby.
+///
+/// # Returns
+/// the standby, ready to be promoted, or an error if it diverged from the primary
+pub fn follow<A: ToSocketAddrs>(
+  primary: A,
+  mut journal: Option<&mut DurableJournal>,
+) -> Result<Standby, failure::Error> {
+  let mut reader = BufReader::new(TcpStream::connect(primary)?);
+  let mut line = String::new();
+  reader.read_line(&mut line)?;
+  let mut standby = Standby::restore(serde_json::from_str(&line)?)?;
+  for entry in read_journal(reader) {
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(e) => {
+        eprintln!("lost primary: {}", e);
+        break;
+      }
+    };
+
+    if let Some(journal) = journal.as_mut() {
+      journal.append(slice::from_ref(&entry));
+    }
+    standby.apply(entry)?;
+  }
+
+  Ok(standby)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::Arc;
+  use std::thread;
+  use std::time::Duration;
+
+  #[test]
+  fn standbys_joining_late_start_from_a_snapshot() {
+    let mut replicator = Replicator::bind("127.0.0.1:0").unwrap();
+    let address = replicator.local_addr();
+    let clock = Arc::new(ManualClock::default());
+    let mut primary = MatchEngine::with_clock(clock);
+    primary.insert_new_symbol(['A', 'D', 'B', 'E'].into()).unwrap();
+    replicator.replicate(&primary.drain_journal(), &primary);
+
+    let follower = thread::spawn(move || follow(address, None).unwrap());
+    while replicator.standbys() == 0 {
+      thread::sleep(Duration::from_millis(1));
+      primary.create_account();
+      replicator.replicate(&primary.drain_journal(), &primary);
+    }
+    let hash = primary.checkpoint();
+    replicator.replicate(&primary.drain_journal(), &primary);
+    drop(replicator);
+
+    let standby = follower.join().unwrap();
+    assert_eq!(standby.verified(), Some(hash));
+  }
+}
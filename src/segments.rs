@@ -0,0 +1,384 @@
+//! Journal segments and compaction
+//!
+//! A journal directory holds the journal rolled into segments of about a set size, numbered in the order they were
+//! started, e.g. `journal-00000001.jsonl`, and snapshots of the engine named for the sequence of the last entry they
+//! include, e.g. `snapshot-00000000000000001234.json`. Reading the segments in order gives every entry kept.
+//!
+//! Segments only roll over between entries, and the finished segment is synced first, so a crash never splits an entry
+//! across segments. Compaction drops the segments whose every entry is included in the latest snapshot, as recovering
+//! from it never reads them, and all but the latest snapshots, keeping as many of each as its `Retention` asks.
+//!
+//! On startup, `recover` restores the latest snapshot whose state matches its hash, falling back to older ones, and
+//! replays only the entries journaled after it, checking every state hash journaled on the way.
+
+use crate::durability::SyncWrite;
+use engine::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SEGMENT_PREFIX: &str = "journal-";
+const SEGMENT_SUFFIX: &str = ".jsonl";
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+/// The default size segments roll over at
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A journal written to a directory, rolled into a new segment once the current one reaches `max_bytes`
+#[derive(Debug)]
+pub struct SegmentedJournal {
+  directory: PathBuf,
+  max_bytes: u64,
+  file: File,
+  /// The number of the segment being written
+  segment: u64,
+  size: u64,
+}
+
+impl SegmentedJournal {
+  /// Append to the last segment in `directory`, creating the directory and the first segment if needed
+  pub fn open<P: Into<PathBuf>>(directory: P, max_bytes: u64) -> io::Result<Self> {
+    let directory = directory.into();
+    fs::create_dir_all(&directory)?;
+    let segment = segments(&directory)?.last().map_or(1, |&(segment, _)| segment);
+    let file = open_segment(&directory, segment)?;
+    Ok(Self {
+      size: file.metadata()?.len(),
+      directory,
+      max_bytes,
+      file,
+      segment,
+    })
+  }
+
+  fn roll_over(&mut self) -> io::Result<()> {
+    self.file.sync_data()?;
+    self.file = open_segment(&self.directory, self.segment + 1)?;
+    self.segment += 1;
+    self.size = 0;
+    Ok(())
+  }
+}
+
+impl Write for SegmentedJournal {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    let written = self.file.write(bytes)?;
+    self.size += written as u64;
+    // entries end with their own write of a line break, so a full segment rolls over only between entries
+    if self.size >= self.max_bytes && bytes[..written].ends_with(b"\n") {
+      self.roll_over()?;
+    }
+    Ok(written)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+impl SyncWrite for SegmentedJournal {
+  fn sync(&mut self) -> io::Result<()> {
+    self.file.sync_data()
+  }
+}
+
+fn open_segment(directory: &Path, segment: u64) -> io::Result<File> {
+  let path = directory.join(format!("{}{:08}{}", SEGMENT_PREFIX, segment, SEGMENT_SUFFIX));
+  OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Get the number of every file in `directory` named with `prefix` and `suffix` around it, in order
+fn numbered(directory: &Path, prefix: &str, suffix: &str) -> io::Result<Vec<(u64, PathBuf)>> {
+  let mut files = vec![];
+  for entry in fs::read_dir(directory)? {
+    let path = entry?.path();
+    let number = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.strip_prefix(prefix)?.strip_suffix(suffix)?.parse().ok());
+    if let Some(number) = number {
+      files.push((number, path));
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+/// Get the segments of a journal directory, numbered in order
+pub fn segments(directory: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+  numbered(directory, SEGMENT_PREFIX, SEGMENT_SUFFIX)
+}
+
+/// Get the snapshots of a journal directory, by the sequence of the last entry each includes, oldest first
+pub fn snapshots(directory: &Path) -> io::Result<Vec<(EventId, PathBuf)>> {
+  let snapshots = numbered(directory, SNAPSHOT_PREFIX, SNAPSHOT_SUFFIX)?;
+  Ok(snapshots.into_iter().map(|(last, path)| (last.into(), path)).collect())
+}
+
+/// Read every entry kept in a journal directory, in order
+///
+/// Once compaction has removed the first segment, these are only the entries after a snapshot.
+pub fn read_segments(directory: &Path) -> Result<Vec<JournalEntry>, failure::Error> {
+  let mut entries = vec![];
+  for (_, path) in segments(directory)? {
+    for entry in read_journal(BufReader::new(File::open(path)?)) {
+      entries.push(entry?);
+    }
+  }
+  Ok(entries)
+}
+
+/// Get the sequence of the last entry of a segment, if it has any
+fn last_sequence(path: &Path) -> Result<Option<EventId>, failure::Error> {
+  let segment = fs::read_to_string(path)?;
+  match segment.lines().rev().find(|line| !line.trim().is_empty()) {
+    Some(line) => Ok(Some(serde_json::from_str::<JournalEntry>(line)?.sequence)),
+    None => Ok(None),
+  }
+}
+
+/// How much of a journal directory compaction keeps beyond what recovering from the latest snapshot needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+  /// Segments included in the latest snapshot kept anyway, newest first, e.g. for audits
+  pub segments: usize,
+  /// Snapshots kept, including the latest
+  pub snapshots: usize,
+}
+
+impl Default for Retention {
+  fn default() -> Self {
+    Self {
+      segments: 0,
+      snapshots: 1,
+    }
+  }
+}
+
+/// What a compaction removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compaction {
+  pub segments: usize,
+  pub snapshots: usize,
+}
+
+/// Writes snapshots of the engine to a journal directory and compacts it
+#[derive(Debug, Clone)]
+pub struct Compactor {
+  directory: PathBuf,
+  retention: Retention,
+}
+
+impl Compactor {
+  pub fn new<P: Into<PathBuf>>(directory: P, retention: Retention) -> Self {
+    Self {
+      directory: directory.into(),
+      retention,
+    }
+  }
+
+  /// Write a snapshot of `engine`, which has applied every entry up to `last`, then compact the directory
+  ///
+  /// The snapshot is written to a temporary file and synced before it is renamed into place, so a crash never leaves
+  /// a partial snapshot to recover from.
+  pub fn snapshot(&self, engine: &MatchEngine, last: EventId) -> Result<Compaction, failure::Error> {
+    let name = format!("{}{:020}{}", SNAPSHOT_PREFIX, u64::from(last), SNAPSHOT_SUFFIX);
+    let temporary = self.directory.join(format!("{}.tmp", name));
+    let mut file = File::create(&temporary)?;
+    serde_json::to_writer(&mut file, &Snapshot::new(engine, Some(last)))?;
+    file.sync_data()?;
+    fs::rename(&temporary, self.directory.join(name))?;
+    self.compact()
+  }
+
+  /// Remove the segments included in the latest snapshot and the older snapshots, except those `Retention` keeps
+  pub fn compact(&self) -> Result<Compaction, failure::Error> {
+    let mut snapshots = snapshots(&self.directory)?;
+    let latest = match snapshots.last() {
+      Some(&(latest, _)) => latest,
+      None => return Ok(Compaction::default()),
+    };
+
+    let mut segments = segments(&self.directory)?;
+    // the last segment is still being written
+    segments.pop();
+    let mut covered = vec![];
+    for (_, path) in segments {
+      match last_sequence(&path)? {
+        Some(last) if last <= latest => covered.push(path),
+        Some(_) => break,
+        None => covered.push(path),
+      }
+    }
+    covered.truncate(covered.len().saturating_sub(self.retention.segments));
+    snapshots.truncate(snapshots.len().saturating_sub(self.retention.snapshots.max(1)));
+
+    for path in covered.iter().chain(snapshots.iter().map(|(_, path)| path)) {
+      fs::remove_file(path)?;
+    }
+    Ok(Compaction {
+      segments: covered.len(),
+      snapshots: snapshots.len(),
+    })
+  }
+}
+
+/// The state recovered from a journal directory
+#[derive(Debug)]
+pub struct Recovery {
+  pub engine: MatchEngine,
+  /// The sequence of the last entry included in the snapshot recovered from, if there was one
+  pub snapshot: Option<EventId>,
+  /// Entries replayed after the snapshot
+  pub replayed: usize,
+  /// The last state hash journaled and checked, if any was
+  pub verified: Option<u64>,
+  pub elapsed: Duration,
+}
+
+/// Recover the state journaled to `directory` from its latest valid snapshot and the entries after it
+///
+/// # Returns
+/// `None` if nothing was journaled, or an error if replaying diverges from a journaled state hash, or the first
+/// entries were compacted away with no valid snapshot to recover them from
+pub fn recover(directory: &Path, clock: Arc<dyn Clock>) -> Result<Option<Recovery>, failure::Error> {
+  if !directory.exists() {
+    return Ok(None);
+  }
+
+  let started = Instant::now();
+  let mut standby = None;
+  for (last, path) in snapshots(directory)?.into_iter().rev() {
+    match read_snapshot(&path) {
+      Ok(restored) => {
+        standby = Some(restored);
+        break;
+      }
+      Err(e) => eprintln!("skipping snapshot {} after entry {:?}: {}", path.display(), last, e),
+    }
+  }
+
+  let segments = segments(directory)?;
+  if standby.is_none() && segments.first().is_some_and(|&(segment, _)| segment != 1) {
+    let directory = directory.display();
+    return Err(failure::format_err!("{} was compacted, and has no valid snapshot to recover from", directory));
+  }
+  let snapshot = standby.as_ref().and_then(Standby::last);
+  let mut standby = standby.unwrap_or_else(Standby::new);
+  let mut replayed = 0;
+  for (_, path) in segments {
+    for entry in read_journal(BufReader::new(File::open(path)?)) {
+      let entry = entry?;
+      if snapshot.is_some_and(|last| entry.sequence <= last) {
+        continue;
+      }
+      standby.apply(entry)?;
+      replayed += 1;
+    }
+  }
+
+  if snapshot.is_none() && replayed == 0 {
+    return Ok(None);
+  }
+  Ok(Some(Recovery {
+    snapshot,
+    replayed,
+    verified: standby.verified(),
+    engine: standby.promote(clock),
+    elapsed: started.elapsed(),
+  }))
+}
+
+/// Read a snapshot, checking its state matches its hash
+fn read_snapshot(path: &Path) -> Result<Standby, failure::Error> {
+  let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+  Ok(Standby::restore(snapshot)?)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::durability::DurableJournal;
+  use std::env;
+  use std::process;
+
+  /// An empty directory for a test
+  fn directory(name: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("matchbook-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+  }
+
+  /// Place `count` bids, journaling each
+  fn bid(engine: &mut MatchEngine, journal: &mut DurableJournal, count: usize) -> EventId {
+    let symbol = ['A', 'D', 'B', 'E'].into();
+    let account_id = engine.create_account();
+    for _ in 0..count {
+      let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(99.into(), 1.into()));
+      engine.try_process(Command { account_id, kind }).unwrap();
+    }
+    let entries = engine.drain_journal();
+    journal.append(&entries);
+    entries.last().unwrap().sequence
+  }
+
+  #[test]
+  fn segments_roll_over_and_compact_behind_snapshots() {
+    let directory = directory("segments");
+    let mut engine = MatchEngine::default();
+    engine.insert_new_symbol(['A', 'D', 'B', 'E'].into()).unwrap();
+    let mut journal = DurableJournal::every_command(Box::new(SegmentedJournal::open(&directory, 200).unwrap()));
+    let last = bid(&mut engine, &mut journal, 10);
+    assert!(segments(&directory).unwrap().len() > 2);
+    let all = read_segments(&directory).unwrap();
+    assert_eq!(all.last().unwrap().sequence, last);
+
+    // every finished segment is covered, and one of them is kept
+    let compactor = Compactor::new(&directory, Retention {
+      segments: 1,
+      snapshots: 1,
+    });
+    let compaction = compactor.snapshot(&engine, last).unwrap();
+    assert_eq!(segments(&directory).unwrap().len(), 2);
+    let kept = read_segments(&directory).unwrap();
+    assert!(compaction.segments > 0 && kept.len() < all.len());
+    assert_eq!(kept[..], all[all.len() - kept.len()..]);
+
+    let newer = bid(&mut engine, &mut journal, 1);
+    assert_eq!(compactor.snapshot(&engine, newer).unwrap().snapshots, 1);
+    assert_eq!(snapshots(&directory).unwrap().iter().map(|&(last, _)| last).collect::<Vec<_>>(), vec![newer]);
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn recovery_replays_the_tail_after_the_latest_valid_snapshot() {
+    let directory = directory("recovery");
+    let clock: Arc<dyn Clock> = Arc::new(ManualClock::default());
+    assert!(recover(&directory, clock.clone()).unwrap().is_none());
+
+    let mut engine = MatchEngine::default();
+    engine.insert_new_symbol(['A', 'D', 'B', 'E'].into()).unwrap();
+    let mut journal = DurableJournal::every_command(Box::new(SegmentedJournal::open(&directory, 200).unwrap()));
+    let compactor = Compactor::new(&directory, Retention::default());
+    let last = bid(&mut engine, &mut journal, 10);
+    compactor.snapshot(&engine, last).unwrap();
+    bid(&mut engine, &mut journal, 3);
+    let hash = engine.checkpoint();
+    journal.append(&engine.drain_journal());
+
+    let recovery = recover(&directory, clock.clone()).unwrap().unwrap();
+    assert_eq!((recovery.snapshot, recovery.verified), (Some(last), Some(hash)));
+    let tail = read_segments(&directory).unwrap().into_iter().filter(|entry| entry.sequence > last).count();
+    assert_eq!(recovery.replayed, tail);
+    assert_eq!(recovery.engine.state_hash(), engine.state_hash());
+
+    // without its only snapshot, the compacted journal cannot be recovered
+    let (_, path) = snapshots(&directory).unwrap().pop().unwrap();
+    fs::write(path, "{").unwrap();
+    assert!(recover(&directory, clock).is_err());
+    fs::remove_dir_all(directory).unwrap();
+  }
+}
@@ -0,0 +1,1283 @@
+//! Order entry server
+//!
+//! A `Server` owns the engine and runs one event loop, reading time from a `Clock` and connections from a `Network`,
+//! so the same loop serves TCP clients in production and simulated ones in tests.
+
+use crate::bus::Publisher;
+use crate::dump::BookDumper;
+use crate::durability::DurableJournal;
+use crate::eod::EndOfDayJob;
+use crate::health::{Health, LatencyBudget, SymbolHealth};
+use crate::listeners::{self, Connection, Listener, Subnet};
+use crate::replication::Replicator;
+use crate::segments::Compactor;
+use crate::subscriptions::Subscriptions;
+use crate::ticks::TickStore;
+use crate::threads;
+use crate::watchdog::Watchdog;
+use crate::webhook::Notifier;
+use engine::*;
+use serde_json::Deserializer;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::hint;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Ticks between state hashes recorded in the journal
+pub const CHECKPOINT_TICKS: usize = 100;
+/// Ticks between snapshots of the engine written to the journal directory, if the server keeps one
+pub const JOURNAL_SNAPSHOT_TICKS: usize = 600;
+/// Ticks between snapshots of every book published to the bus
+pub const BOOK_SNAPSHOT_TICKS: usize = 10;
+/// Ticks between statistics of every book published to the bus
+pub const BOOK_STATS_TICKS: usize = 10;
+/// Undecodable inputs kept for each session, beyond which its oldest are dropped
+pub const DEAD_LETTERS_KEPT: usize = 16;
+/// The most bytes kept of each undecodable input
+const DEAD_LETTER_BYTES: usize = 1024;
+
+/// Set when the runtime configuration should be reloaded, e.g. by `SIGHUP`
+pub static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Set when the server should stop, e.g. by `SIGTERM`
+pub static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_: libc::c_int) {
+  RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn request_shutdown(_: libc::c_int) {
+  SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Request a configuration reload whenever the process receives `SIGHUP`
+pub fn reload_on_sighup() {
+  let handler = request_reload as extern "C" fn(libc::c_int);
+  // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+  unsafe {
+    libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+  }
+}
+
+/// Request a shutdown whenever the process receives `SIGTERM` or `SIGINT`
+pub fn shutdown_on_sigterm() {
+  let handler = request_shutdown as extern "C" fn(libc::c_int);
+  // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+  unsafe {
+    libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+    libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+  }
+}
+
+/// What happens to resting orders when the server shuts down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutdownPolicy {
+  /// Leave them resting, for a restart that recovers from the journal
+  #[default]
+  LeaveResting,
+  /// Cancel every one, so clients are not left with orders in a book that will not survive the restart
+  CancelAll,
+}
+
+/// Something that happened on a network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetEvent {
+  Connected(SessionId),
+  /// Bytes arrived on a session, which may hold any part of one or more commands, when the network read them
+  Received(SessionId, Vec<u8>, Timestamp),
+  Disconnected(SessionId),
+  /// A connection from an address its listener does not accept was closed before it became a session
+  Refused(SessionId, IpAddr),
+}
+
+/// The client connections of a server
+pub trait Network {
+  /// Wait for the next event until `deadline`
+  ///
+  /// # Returns
+  /// `None` if nothing happened by then
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent>;
+
+  /// Send bytes to a session, dropping them if it has disconnected
+  fn send(&mut self, session: SessionId, bytes: &[u8]);
+
+  /// Get where a session connected to and from, which limits what it may send
+  fn connection(&self, _session: SessionId) -> Connection {
+    Connection::default()
+  }
+}
+
+/// An engine serving JSON commands from a network, replying to each with its JSON result on its own line
+pub struct Server {
+  engine: MatchEngine,
+  clock: Arc<dyn Clock>,
+  /// Bytes received on each connected session that do not yet make up a whole command
+  sessions: HashMap<SessionId, Vec<u8>>,
+  /// Where each connected session connected to and from
+  connections: HashMap<SessionId, Connection>,
+  /// The subnets each account limited to some may be used from
+  account_sources: HashMap<AccountId, Vec<Subnet>>,
+  /// What each connected session sent that could not be decoded as commands
+  dead_letters: HashMap<SessionId, DeadLetters>,
+  /// Inputs dropped as undecodable since the server started, across all sessions
+  dead_letter_count: usize,
+  /// Replies to send once the journal has been written, as ranges of `reply_bytes`
+  replies: Vec<(SessionId, Range<usize>)>,
+  /// The encoded replies of a step, in one buffer reused across steps
+  reply_bytes: Vec<u8>,
+  /// The trace of each reply to a command, by its index in `replies`
+  traces: Vec<(usize, LatencyTrace)>,
+  /// How long the commands answered so far spent in each stage
+  latency: LatencyBudget,
+  /// Whether to follow each reply to a command with its trace
+  trace_latency: bool,
+  next_tick: Timestamp,
+  ticks: usize,
+  audit_exporter: Option<AuditExporter<Box<dyn Write>>>,
+  journal: Option<DurableJournal>,
+  compactor: Option<Compactor>,
+  /// The sequence of the last entry journaled, and of the last one included in a snapshot
+  last_journaled: Option<EventId>,
+  last_snapshot: Option<EventId>,
+  replicator: Option<Replicator>,
+  publisher: Option<Publisher>,
+  notifier: Option<Notifier>,
+  /// How each session that chose to be acknowledged other than in full wants its placed orders acknowledged
+  ack_modes: HashMap<SessionId, AckMode>,
+  /// The sessions subscribed to each account's order events, with the sequence number of the last one pushed to each
+  execution_subscribers: HashMap<AccountId, Vec<(SessionId, u64)>>,
+  market_data: Subscriptions,
+  tick_store: Option<TickStore>,
+  /// The file the runtime configuration is reloaded from
+  config: Option<PathBuf>,
+  health: Option<Arc<Mutex<Health>>>,
+  shutdown_policy: ShutdownPolicy,
+  end_of_day: Option<EndOfDayJob>,
+  /// The file accounts are kept in across restarts
+  account_store: Option<PathBuf>,
+  book_dumper: Option<BookDumper>,
+  watchdog: Option<Watchdog>,
+}
+
+impl Server {
+  /// Serve `engine`, which must read time from `clock`
+  pub fn new(engine: MatchEngine, clock: Arc<dyn Clock>) -> Self {
+    Self {
+      next_tick: clock.now() + TICK_INTERVAL,
+      engine,
+      clock,
+      sessions: HashMap::new(),
+      connections: HashMap::new(),
+      account_sources: HashMap::new(),
+      dead_letters: HashMap::new(),
+      dead_letter_count: 0,
+      replies: vec![],
+      reply_bytes: vec![],
+      traces: vec![],
+      latency: LatencyBudget::default(),
+      trace_latency: false,
+      ticks: 0,
+      audit_exporter: None,
+      journal: None,
+      compactor: None,
+      last_journaled: None,
+      last_snapshot: None,
+      replicator: None,
+      publisher: None,
+      notifier: None,
+      ack_modes: HashMap::new(),
+      execution_subscribers: HashMap::new(),
+      market_data: Subscriptions::default(),
+      tick_store: None,
+      config: None,
+      health: None,
+      shutdown_policy: ShutdownPolicy::default(),
+      end_of_day: None,
+      account_store: None,
+      book_dumper: None,
+      watchdog: None,
+    }
+  }
+
+  pub fn with_audit_exporter(self, audit_exporter: AuditExporter<Box<dyn Write>>) -> Self {
+    Self {
+      audit_exporter: Some(audit_exporter),
+      ..self
+    }
+  }
+
+  pub fn with_journal(self, journal: DurableJournal) -> Self {
+    Self {
+      journal: Some(journal),
+      ..self
+    }
+  }
+
+  /// Write a snapshot of the engine to the journal directory every `JOURNAL_SNAPSHOT_TICKS`, compacting it behind
+  /// each
+  pub fn with_compactor(self, compactor: Compactor) -> Self {
+    Self {
+      compactor: Some(compactor),
+      ..self
+    }
+  }
+
+  /// Stream the journal to standbys before replying to the commands in it
+  pub fn with_replicator(self, replicator: Replicator) -> Self {
+    Self {
+      replicator: Some(replicator),
+      ..self
+    }
+  }
+
+  /// Publish order events once they are journaled, book snapshots every `BOOK_SNAPSHOT_TICKS` and book statistics
+  /// every `BOOK_STATS_TICKS`
+  pub fn with_publisher(self, publisher: Publisher) -> Self {
+    Self {
+      publisher: Some(publisher),
+      ..self
+    }
+  }
+
+  /// Post fills and rejections to accounts' webhooks once they are journaled, reloading the webhooks with the
+  /// configuration
+  pub fn with_notifier(self, notifier: Notifier) -> Self {
+    Self {
+      notifier: Some(notifier),
+      ..self
+    }
+  }
+
+  /// Store trades and quotes in `tick_store`, and answer history queries from it
+  pub fn with_tick_store(self, tick_store: TickStore) -> Self {
+    Self {
+      tick_store: Some(tick_store),
+      ..self
+    }
+  }
+
+  /// Close the trading day whenever `end_of_day` is due, publishing the closes and settlements if there is a publisher
+  pub fn with_end_of_day(self, end_of_day: EndOfDayJob) -> Self {
+    Self {
+      end_of_day: Some(end_of_day),
+      ..self
+    }
+  }
+
+  /// Keep the accounts in a JSON file, rewritten whenever an account changes, every `CHECKPOINT_TICKS` and on shutdown
+  pub fn with_account_store<P: Into<PathBuf>>(self, path: P) -> Self {
+    Self {
+      account_store: Some(path.into()),
+      ..self
+    }
+  }
+
+  /// Answer book dump commands by writing the dumps with `book_dumper`
+  pub fn with_book_dumper(self, book_dumper: BookDumper) -> Self {
+    Self {
+      book_dumper: Some(book_dumper),
+      ..self
+    }
+  }
+
+  /// Only serve each account in `account_sources` to sessions connected from one of its subnets
+  pub fn with_account_sources(self, account_sources: HashMap<AccountId, Vec<Subnet>>) -> Self {
+    Self {
+      account_sources,
+      ..self
+    }
+  }
+
+  /// Follow each reply to a command with a `Push::Latency` of when the command reached each stage, for debugging
+  pub fn with_latency_traces(self) -> Self {
+    Self {
+      trace_latency: true,
+      ..self
+    }
+  }
+
+  /// Publish the server's health to `health` on every tick
+  pub fn with_health(self, health: Arc<Mutex<Health>>) -> Self {
+    Self {
+      health: Some(health),
+      ..self
+    }
+  }
+
+  /// Record how the matching of each symbol progresses in `watchdog`, halting the symbols it finds stalled if it
+  /// halts them
+  pub fn with_watchdog(self, watchdog: Watchdog) -> Self {
+    Self {
+      watchdog: Some(watchdog),
+      ..self
+    }
+  }
+
+  /// Apply `shutdown_policy` to resting orders when shutting down
+  pub fn with_shutdown_policy(self, shutdown_policy: ShutdownPolicy) -> Self {
+    Self {
+      shutdown_policy,
+      ..self
+    }
+  }
+
+  /// Load the runtime configuration from a JSON file, and again from the same file whenever a reload is requested
+  pub fn with_config<P: Into<PathBuf>>(mut self, path: P) -> Result<Self, failure::Error> {
+    let path = path.into();
+    self.engine.reload_config(load_config(&path)?)?;
+    self.config = Some(path);
+    Ok(self)
+  }
+
+  #[cfg(test)]
+  pub fn engine(&self) -> &MatchEngine {
+    &self.engine
+  }
+
+  /// Handle the next network event or run the next tick, whichever is first
+  ///
+  /// Everything the step journaled is handed to the journal before any reply is sent, and with
+  /// `Durability::EveryCommand` synced, so a client never sees the result of a command that would be lost in a crash.
+  pub fn step<N: Network>(&mut self, network: &mut N) {
+    if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+      self.reload_config();
+      if let Some(notifier) = self.notifier.as_mut() {
+        notifier.reload();
+      }
+    }
+
+    if let Some(event) = network.poll(self.next_tick) {
+      if let NetEvent::Connected(session) = event {
+        self.connections.insert(session, network.connection(session));
+      }
+      self.handle(event);
+    }
+    self.halt_stalled();
+
+    let is_tick = self.clock.now() >= self.next_tick;
+    if is_tick {
+      self.tick();
+    }
+    if self.end_of_day.as_ref().is_some_and(|job| job.is_due(self.clock.now())) {
+      self.close_day();
+    }
+    self.flush(network, is_tick);
+  }
+
+  /// Step until a shutdown is requested, then shut down
+  ///
+  /// A step that panics is logged with the state the engine was left in and skipped, so one bad command does not take
+  /// down every session.
+  pub fn run<N: Network>(&mut self, network: &mut N) {
+    while !SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+      if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| self.step(network))) {
+        threads::caught_panic("matching", panic.as_ref());
+        eprintln!(
+          "engine state {:016x} after journal entry {:?}",
+          self.engine.state_hash(),
+          self.last_journaled
+        );
+      }
+    }
+    self.shutdown(network);
+  }
+
+  /// Apply the shutdown policy, writing and sending everything it did like any other step
+  pub fn shutdown<N: Network>(&mut self, network: &mut N) {
+    if self.shutdown_policy == ShutdownPolicy::CancelAll {
+      let cancelled = self.engine.cancel_all();
+      eprintln!("cancelled {} resting orders before shutting down", cancelled);
+    }
+    self.flush(network, false);
+    self.save_accounts();
+  }
+
+  /// Write out and send everything the engine produced since the last flush
+  fn flush<N: Network>(&mut self, network: &mut N, is_tick: bool) {
+    let (audit_trail, journal) = (self.engine.drain_audit_trail(), self.engine.drain_journal());
+    if let Some(exporter) = self.audit_exporter.as_mut() {
+      if let Err(e) = exporter.export(&audit_trail) {
+        eprintln!("failed to export audit trail: {}", e);
+      }
+    }
+    if let Some(writer) = self.journal.as_mut() {
+      writer.append(&journal);
+    }
+    if let Some(entry) = journal.last() {
+      self.last_journaled = Some(entry.sequence);
+    }
+    if let Some(watchdog) = self.watchdog.as_ref() {
+      watchdog.record(&audit_trail);
+    }
+    if is_tick && self.ticks.is_multiple_of(JOURNAL_SNAPSHOT_TICKS) {
+      self.snapshot_journal();
+    }
+    if let Some(replicator) = self.replicator.as_mut() {
+      replicator.replicate(&journal, &self.engine);
+    }
+    if let Some(notifier) = self.notifier.as_mut() {
+      notifier.notify(&audit_trail);
+    }
+    if let Some(publisher) = self.publisher.as_mut() {
+      publisher.publish_audit_trail(&audit_trail);
+      if is_tick && self.ticks.is_multiple_of(BOOK_SNAPSHOT_TICKS) {
+        publisher.publish_books(&self.engine, self.clock.now());
+      }
+      if is_tick && self.ticks.is_multiple_of(BOOK_STATS_TICKS) {
+        publisher.publish_stats(&self.engine);
+      }
+    }
+    if is_tick {
+      self.publish_health();
+    }
+    if journal.iter().any(|entry| entry.event.changes_accounts())
+      || (is_tick && self.ticks.is_multiple_of(CHECKPOINT_TICKS))
+    {
+      self.save_accounts();
+    }
+    self.push_executions(&audit_trail);
+    let market_data = self.engine.drain_market_data();
+    if let Some(tick_store) = self.tick_store.as_mut() {
+      if let Err(e) = tick_store.record(&self.engine, &market_data, self.clock.now(), !journal.is_empty()) {
+        eprintln!("failed to store ticks: {}", e);
+      }
+    }
+    let pushes = self.market_data.publish(&self.engine, &market_data, self.clock.now(), !journal.is_empty());
+    for (session, push) in pushes {
+      queue(&mut self.reply_bytes, &mut self.replies, session, &push);
+    }
+
+    let mut traces = self.traces.drain(..).peekable();
+    for (index, (session, range)) in self.replies.drain(..).enumerate() {
+      network.send(session, &self.reply_bytes[range]);
+      if let Some((_, mut trace)) = traces.next_if(|&(reply, _)| reply == index) {
+        trace.sent_at = self.clock.now();
+        self.latency.record(&trace);
+        if self.trace_latency {
+          let mut bytes = serde_json::to_vec(&Push::Latency(trace)).expect("messages always serialize");
+          bytes.push(b'\n');
+          network.send(session, &bytes);
+        }
+      }
+    }
+    self.reply_bytes.clear();
+  }
+
+  fn handle(&mut self, event: NetEvent) {
+    match event {
+      NetEvent::Connected(session) => {
+        self.sessions.insert(session, vec![]);
+      }
+      NetEvent::Received(session, bytes, received_at) => {
+        let mut buffer = match self.sessions.remove(&session) {
+          Some(buffer) => buffer,
+          None => return,
+        };
+        buffer.extend(bytes);
+
+        let (engine, reply_bytes, replies) = (&mut self.engine, &mut self.reply_bytes, &mut self.replies);
+        let (execution_subscribers, market_data) = (&mut self.execution_subscribers, &mut self.market_data);
+        let ack_modes = &mut self.ack_modes;
+        let (tick_store, dead_letters) = (self.tick_store.as_ref(), &self.dead_letters);
+        let (book_dumper, watchdog) = (self.book_dumper.as_ref(), self.watchdog.as_ref());
+        let connection = self.connections.get(&session).copied().unwrap_or_default();
+        let account_sources = &self.account_sources;
+        let (clock, traces) = (self.clock.as_ref(), &mut self.traces);
+        let now = self.clock.now();
+        let dequeued_at = now;
+        let mut commands = vec![];
+        let malformed = drain_commands(&mut buffer, |command| commands.push(command));
+        if let Some(watchdog) = watchdog {
+          // a command on an order placed earlier in the same input is not counted, its symbol not yet known
+          watchdog.queue(commands.iter().filter_map(|command| engine.book_symbol(&command.kind)));
+        }
+        let mut process = |command: Command| {
+          let _watch = watchdog.and_then(|watchdog| Some(watchdog.watch_queued(engine.book_symbol(&command.kind)?)));
+          if let Err(e) = listeners::permit(engine, connection, account_sources, &command) {
+            if let Error::SourceNotAllowed { id, source } = e {
+              engine.deny(session, id, source);
+            }
+            queue(reply_bytes, replies, session, &Err::<Success, _>(e));
+            trace(traces, replies, received_at, dequeued_at, clock);
+            return;
+          }
+          let ack_mode = ack_modes.get(&session).copied().unwrap_or_default();
+          let result = match command.kind {
+            CommandKind::SubscribeExecutions(_)
+            | CommandKind::Subscribe(..)
+            | CommandKind::Unsubscribe(..)
+            | CommandKind::Conflate(_)
+            | CommandKind::SetAckMode(_)
+            | CommandKind::SetEntitlement(..)
+            | CommandKind::GetTradesHistory(..)
+            | CommandKind::GetQuotesHistory(..)
+            | CommandKind::GetDeadLetters
+            | CommandKind::GetWatchdog
+            | CommandKind::DumpBook(..) => engine.try_process_from(session, command),
+            CommandKind::PlaceOrder(..) if ack_mode == AckMode::Fast => engine.try_process_from(session, command),
+            _ => {
+              let start = reply_bytes.len();
+              engine.write_result(session, command, &mut *reply_bytes).expect("results always serialize");
+              reply_bytes.push(b'\n');
+              replies.push((session, start..reply_bytes.len()));
+              trace(traces, replies, received_at, dequeued_at, clock);
+              return;
+            }
+          };
+          let result = match (result, tick_store, command.kind) {
+            (Ok(_), Some(tick_store), CommandKind::GetTradesHistory(symbol, from, to)) => {
+              history(tick_store.trades(symbol, from, to)).map(Success::GetTradesHistory)
+            }
+            (Ok(_), Some(tick_store), CommandKind::GetQuotesHistory(symbol, from, to)) => {
+              history(tick_store.quotes(symbol, from, to)).map(Success::GetQuotesHistory)
+            }
+            (Ok(_), _, CommandKind::GetDeadLetters) => {
+              let mut letters: Vec<_> = dead_letters.values().cloned().collect();
+              letters.sort_by_key(|letters| usize::from(letters.session));
+              Ok(Success::GetDeadLetters(letters))
+            }
+            (Ok(_), _, CommandKind::GetWatchdog) => {
+              Ok(Success::GetWatchdog(watchdog.map_or(vec![], |watchdog| watchdog.progress(engine))))
+            }
+            (Ok(_), _, CommandKind::DumpBook(symbol, format)) => dump(engine, book_dumper, symbol, format),
+            // only orders of fast acknowledged sessions are placed here
+            (Ok(Success::PlaceOrder(placement)), ..) => Ok(Success::Accepted(placement.id)),
+            (result, ..) => result,
+          };
+          queue(reply_bytes, replies, session, &result);
+          trace(traces, replies, received_at, dequeued_at, clock);
+          if result.is_err() {
+            return;
+          }
+
+          match command.kind {
+            CommandKind::SubscribeExecutions(account) => subscribe_executions(execution_subscribers, account, session),
+            CommandKind::PlaceOrder(..) => subscribe_executions(execution_subscribers, command.account_id, session),
+            CommandKind::SetAckMode(ack_mode) => {
+              ack_modes.insert(session, ack_mode);
+            }
+            CommandKind::Subscribe(symbol, feed) => {
+              if let Some(snapshot) = market_data.subscribe(engine, session, command.account_id, symbol, feed) {
+                queue(reply_bytes, replies, session, &snapshot);
+              }
+            }
+            CommandKind::Unsubscribe(symbol, feed) => market_data.unsubscribe(session, symbol, feed),
+            CommandKind::SetEntitlement(account, entitlement) => market_data.entitle(account, entitlement),
+            CommandKind::Conflate(interval) => {
+              for push in market_data.conflate(session, interval, now) {
+                queue(reply_bytes, replies, session, &push);
+              }
+            }
+            _ => {}
+          }
+        };
+        commands.into_iter().for_each(&mut process);
+        if let Some((input, error)) = malformed {
+          queue(&mut self.reply_bytes, &mut self.replies, session, &Err::<Success, _>(error));
+          self.keep_dead_letter(session, &input);
+        }
+        self.sessions.insert(session, buffer);
+      }
+      // a partially received command is dropped with its connection
+      NetEvent::Disconnected(session) => {
+        self.sessions.remove(&session);
+        self.connections.remove(&session);
+        self.ack_modes.remove(&session);
+        self.dead_letters.remove(&session);
+        for subscribers in self.execution_subscribers.values_mut() {
+          subscribers.retain(|&(subscriber, _)| subscriber != session);
+        }
+        self.market_data.disconnect(session);
+      }
+      NetEvent::Refused(session, source) => {
+        eprintln!("refused a connection from {}", source);
+        self.engine.deny(session, AccountId::default(), source);
+      }
+    }
+  }
+
+  /// Keep input a session sent that could not be decoded, dropping the session's oldest beyond `DEAD_LETTERS_KEPT`
+  fn keep_dead_letter(&mut self, session: SessionId, input: &[u8]) {
+    self.dead_letter_count += 1;
+    let letters = self.dead_letters.entry(session).or_insert_with(|| DeadLetters {
+      session,
+      count: 0,
+      inputs: vec![],
+    });
+    letters.count += 1;
+    if letters.inputs.len() == DEAD_LETTERS_KEPT {
+      letters.inputs.remove(0);
+    }
+    let kept = &input[..input.len().min(DEAD_LETTER_BYTES)];
+    letters.inputs.push(String::from_utf8_lossy(kept).into_owned());
+  }
+
+  /// Queue an execution report for each subscriber to the account of each order event, and a report of each self match
+  /// prevented and each cancel the engine made on its own for the session of the order, after the step's replies
+  fn push_executions(&mut self, audit_trail: &[AuditRecord]) {
+    let is_order_event = |record: &&AuditRecord| record.event != AuditEvent::Receive && record.event != AuditEvent::Deny;
+    for record in audit_trail.iter().filter(is_order_event) {
+      let quantity_precision = record.symbol.map(|symbol| self.engine.quantity_precision(symbol));
+      let subscribers = match self.execution_subscribers.get_mut(&record.account) {
+        Some(subscribers) => subscribers,
+        None => continue,
+      };
+      for (session, sequence) in subscribers.iter_mut() {
+        *sequence += 1;
+        let push = Push::ExecutionReport {
+          sequence: *sequence,
+          record: *record,
+          quantity_precision,
+        };
+        queue(&mut self.reply_bytes, &mut self.replies, *session, &push);
+      }
+    }
+    // the session that placed an order hears of its self matches whether or not it subscribed to executions
+    let self_matches = audit_trail.iter().filter(|record| record.event == AuditEvent::SelfMatchPrevented);
+    for record in self_matches {
+      if !self.sessions.contains_key(&record.session) {
+        continue;
+      }
+      queue(&mut self.reply_bytes, &mut self.replies, record.session, &Push::SelfMatchPrevented(*record));
+    }
+    // and of its orders the engine cancelled, which no reply told it of
+    let is_unsolicited = |record: &&AuditRecord| record.cancel_reason.is_some_and(CancelReason::is_unsolicited);
+    for record in audit_trail.iter().filter(is_unsolicited) {
+      if !self.sessions.contains_key(&record.session) {
+        continue;
+      }
+      queue(&mut self.reply_bytes, &mut self.replies, record.session, &Push::Cancelled(*record));
+    }
+  }
+
+  fn save_accounts(&self) {
+    let path = match self.account_store.as_ref() {
+      Some(path) => path,
+      None => return,
+    };
+    if let Err(e) = save_accounts(path, &self.engine.account_store()) {
+      eprintln!("failed to save accounts to {}: {}", path.display(), e);
+    }
+  }
+
+  /// Snapshot the engine into the journal directory, if anything was journaled since the last snapshot
+  fn snapshot_journal(&mut self) {
+    let (compactor, last) = match (self.compactor.as_ref(), self.last_journaled) {
+      (Some(compactor), Some(last)) if self.last_snapshot != Some(last) => (compactor, last),
+      _ => return,
+    };
+    match compactor.snapshot(&self.engine, last) {
+      Ok(compaction) => {
+        self.last_snapshot = Some(last);
+        if compaction.segments > 0 {
+          eprintln!("snapshotted the journal, removing {} segments", compaction.segments);
+        }
+      }
+      Err(e) => eprintln!("failed to snapshot the journal: {}", e),
+    }
+  }
+
+  fn publish_health(&self) {
+    let health = match self.health.as_ref() {
+      Some(health) => health,
+      None => return,
+    };
+    let symbols = self
+      .engine
+      .symbols()
+      .into_iter()
+      .filter_map(|symbol| {
+        let trading_mode = self.engine.trading_mode(symbol).ok()?;
+        Some(SymbolHealth { symbol, trading_mode })
+      })
+      .collect();
+
+    *threads::lock(health) = Health {
+      heartbeat: self.clock.now(),
+      journal_lag: self.journal.as_ref().map_or(0, DurableJournal::lag),
+      journal_durability: self.journal.as_ref().map(DurableJournal::durability),
+      journal_pending: self.journal.as_ref().map_or(0, DurableJournal::pending),
+      sessions: self.sessions.len(),
+      buffered: self.sessions.values().map(Vec::len).sum(),
+      dead_letters: self.dead_letter_count,
+      watchdog: self.watchdog.as_ref().map_or(vec![], |watchdog| watchdog.progress(&self.engine)),
+      panics: threads::panics(),
+      standbys: self.replicator.as_ref().map(Replicator::standbys),
+      queues: self.engine.queue_depths(),
+      symbols,
+      latency: self.latency,
+    };
+  }
+
+  /// Reload the runtime configuration between commands, keeping the current one if the file is invalid
+  fn reload_config(&mut self) {
+    let path = match self.config.as_ref() {
+      Some(path) => path,
+      None => return,
+    };
+    match load_config(path) {
+      Ok(config) => match self.engine.reload_config(config) {
+        Ok(()) => eprintln!("reloaded configuration from {}", path.display()),
+        Err(e) => eprintln!("rejected configuration from {}: {}", path.display(), e),
+      },
+      Err(e) => eprintln!("failed to read configuration from {}: {}", path.display(), e),
+    }
+  }
+
+  /// Close the engine's trading day, writing its snapshot and publishing what it closed with
+  fn close_day(&mut self) {
+    let report = self.engine.end_of_day();
+    if let Some(job) = self.end_of_day.as_mut() {
+      match job.finish(&self.engine, &report) {
+        Ok(path) => eprintln!("closed the day and wrote its snapshot to {}", path.display()),
+        Err(e) => eprintln!("closed the day, but failed to write its snapshot: {}", e),
+      }
+    }
+    if let Some(publisher) = self.publisher.as_mut() {
+      publisher.publish_end_of_day(&report);
+    }
+  }
+
+  /// Halt order entry on each symbol the watchdog found stalled since the last step
+  fn halt_stalled(&mut self) {
+    let watchdog = match self.watchdog.as_ref() {
+      Some(watchdog) => watchdog,
+      None => return,
+    };
+    for symbol in watchdog.take_halts() {
+      if self.engine.is_halted(symbol) {
+        continue;
+      }
+      match self.engine.halt_symbol(symbol) {
+        Ok(()) => eprintln!("halted order entry on stalled symbol {}", symbol),
+        Err(e) => eprintln!("failed to halt stalled symbol {}: {}", symbol, e),
+      }
+    }
+  }
+
+  /// Drive scheduled auctions, even when no commands are arriving
+  fn tick(&mut self) {
+    self.ticks += 1;
+    let watchdog = self.watchdog.as_ref();
+    self.engine.tick_watched(|symbol| watchdog.map(|watchdog| watchdog.watch(symbol)));
+    if self.ticks.is_multiple_of(CHECKPOINT_TICKS) {
+      self.engine.checkpoint();
+    }
+    self.next_tick = self.clock.now() + TICK_INTERVAL;
+  }
+}
+
+/// Answer a history query with what was read of the tick store, or with no history if it could not be read
+fn history<T>(read: io::Result<Vec<T>>) -> Result<Vec<T>, Error> {
+  Ok(read.unwrap_or_else(|e| {
+    eprintln!("failed to read ticks: {}", e);
+    vec![]
+  }))
+}
+
+/// Write a dump of a symbol's book, answering with the file written
+fn dump(
+  engine: &MatchEngine,
+  book_dumper: Option<&BookDumper>,
+  symbol: Symbol,
+  format: DumpFormat,
+) -> Result<Success, Error> {
+  let book_dumper = book_dumper.ok_or(Error::DumpFailed { symbol })?;
+  match book_dumper.write(&engine.dump_book(symbol)?, format) {
+    Ok(path) => Ok(Success::DumpBook(path.display().to_string())),
+    Err(e) => {
+      eprintln!("failed to dump the book of {}: {}", symbol, e);
+      Err(Error::DumpFailed { symbol })
+    }
+  }
+}
+
+/// Queue a message to send to a session once the journal has been written, on its own line
+fn queue<T: serde::Serialize>(
+  reply_bytes: &mut Vec<u8>,
+  replies: &mut Vec<(SessionId, Range<usize>)>,
+  session: SessionId,
+  message: &T,
+) {
+  let start = reply_bytes.len();
+  serde_json::to_writer(&mut *reply_bytes, message).expect("messages always serialize");
+  reply_bytes.push(b'\n');
+  replies.push((session, start..reply_bytes.len()));
+}
+
+/// Push a session each of an account's order events from now on, if it is not already subscribed
+fn subscribe_executions(
+  execution_subscribers: &mut HashMap<AccountId, Vec<(SessionId, u64)>>,
+  account: AccountId,
+  session: SessionId,
+) {
+  let subscribers = execution_subscribers.entry(account).or_default();
+  if subscribers.iter().all(|&(subscriber, _)| subscriber != session) {
+    subscribers.push((session, 0));
+  }
+}
+
+/// Trace the reply just queued, to a command received and dequeued at the given times that was processed by now
+fn trace(
+  traces: &mut Vec<(usize, LatencyTrace)>,
+  replies: &[(SessionId, Range<usize>)],
+  received_at: Timestamp,
+  dequeued_at: Timestamp,
+  clock: &dyn Clock,
+) {
+  traces.push((replies.len() - 1, LatencyTrace {
+    received_at,
+    dequeued_at,
+    matched_at: clock.now(),
+    sent_at: Timestamp::default(),
+  }));
+}
+
+/// Take every whole command from the front of the bytes a session has sent, leaving any command still arriving
+///
+/// There is no telling where the next command starts after malformed input, so everything received so far is dropped.
+///
+/// # Returns
+/// the malformed input dropped, from the start of the first command that could not be decoded, and the error to reply
+/// with
+pub fn drain_commands<F: FnMut(Command)>(buffer: &mut Vec<u8>, mut handle: F) -> Option<(Vec<u8>, Error)> {
+  let mut commands = Deserializer::from_slice(buffer).into_iter::<Command>();
+  let mut malformed_from = None;
+  let consumed = loop {
+    let start = commands.byte_offset();
+    match commands.next() {
+      Some(Ok(command)) => handle(command),
+      // the rest of the command has not arrived yet
+      Some(Err(e)) if e.is_eof() => break commands.byte_offset(),
+      Some(Err(_)) => {
+        malformed_from = Some(start);
+        break buffer.len();
+      }
+      None => break buffer.len(),
+    }
+  };
+
+  let malformed = malformed_from.map(|start| {
+    let input = buffer[start..].trim_ascii_start().to_vec();
+    // decoded again on its own, so the error's position is counted from the start of the input
+    let error = match Deserializer::from_slice(&input).into_iter::<Command>().next() {
+      Some(Err(e)) => Error::MalformedCommand {
+        line: e.line(),
+        column: e.column(),
+      },
+      _ => Error::MalformedCommand { line: 1, column: 1 },
+    };
+    (input, error)
+  });
+  buffer.drain(..consumed);
+  malformed
+}
+
+/// Append a result as the line a client is sent
+pub fn write_reply(bytes: &mut Vec<u8>, result: &Result<Success, Error>) {
+  serde_json::to_writer(&mut *bytes, result).expect("results always serialize");
+  bytes.push(b'\n');
+}
+
+/// The line a connection beyond the limit is sent before it is closed
+pub fn rejection(limit: usize) -> Vec<u8> {
+  let mut bytes = vec![];
+  write_reply(&mut bytes, &Err(Error::TooManyConnections { limit }));
+  bytes
+}
+
+fn load_config(path: &Path) -> Result<RuntimeConfig, failure::Error> {
+  Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Read the accounts kept in a file by `Server::with_account_store`
+pub fn load_accounts(path: &Path) -> Result<AccountStore, failure::Error> {
+  Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Replace the accounts kept in a file, writing beside it first so a crash mid-write leaves the last store whole
+fn save_accounts(path: &Path, store: &AccountStore) -> Result<(), failure::Error> {
+  let partial = path.with_extension("partial");
+  fs::write(&partial, serde_json::to_vec(store)?)?;
+  fs::rename(&partial, path)?;
+  Ok(())
+}
+
+/// How threads with nothing to do wait for more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+  /// Block in the kernel until woken
+  #[default]
+  Park,
+  /// Keep checking for work, trading a core for lower and steadier wake-up latency, and park after `limit` empty
+  /// checks in a row if there is one
+  ///
+  /// Spinning threads that share cores preempt each other, which is far slower than parking.
+  Spin { limit: Option<u32> },
+}
+
+impl WaitStrategy {
+  /// Returns true if a thread that has found nothing `idle` times in a row should check again rather than park
+  pub fn spins(&self, idle: u32) -> bool {
+    match *self {
+      WaitStrategy::Park => false,
+      WaitStrategy::Spin { limit } => limit.is_none_or(|limit| idle < limit),
+    }
+  }
+}
+
+/// How a `TcpNetwork` shares out its connections
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+  /// Threads reading connections, each waiting on its share of them at once
+  pub workers: usize,
+  /// Connections beyond this are sent `Error::TooManyConnections` and closed
+  pub max_connections: usize,
+  /// How the event loop and the workers wait when there is nothing to read
+  pub wait: WaitStrategy,
+  /// Cores to pin the workers to, shared out in turn, or none to leave them unpinned
+  pub io_cores: Vec<usize>,
+}
+
+impl Default for TcpConfig {
+  fn default() -> Self {
+    Self {
+      workers: 4,
+      max_connections: 1024,
+      wait: WaitStrategy::Park,
+      io_cores: vec![],
+    }
+  }
+}
+
+/// How long a worker waits on its connections before checking for new ones
+const WORKER_POLL_MS: libc::c_int = 10;
+
+/// Clients connected over TCP, read by a fixed pool of worker threads
+pub struct TcpNetwork {
+  clock: Arc<dyn Clock>,
+  events: Receiver<NetEvent>,
+  sender: TcpSender,
+  wait: WaitStrategy,
+}
+
+/// Sends to the clients of a `TcpNetwork`, from any thread
+#[derive(Clone)]
+pub struct TcpSender {
+  streams: Arc<Mutex<HashMap<SessionId, TcpStream>>>,
+  /// Where each connected session was accepted
+  connections: Arc<Mutex<HashMap<SessionId, Connection>>>,
+}
+
+impl TcpSender {
+  /// Send bytes to a session, dropping them if it has disconnected
+  pub fn send(&self, session: SessionId, bytes: &[u8]) {
+    let mut streams = threads::lock(&self.streams);
+    let failed = match streams.get_mut(&session) {
+      Some(stream) => stream.write_all(bytes).is_err(),
+      None => false,
+    };
+    if failed {
+      streams.remove(&session);
+    }
+  }
+}
+
+impl TcpNetwork {
+  /// Listen for clients on every address `address` resolves to, measuring poll deadlines by `clock`
+  pub fn bind<A: ToSocketAddrs>(address: A, clock: Arc<dyn Clock>, config: TcpConfig) -> io::Result<Self> {
+    let bound = listeners::bind(address)?.into_iter().map(|listener| (listener, EndpointRole::All, vec![]));
+    Self::listen(bound.collect(), clock, config)
+  }
+
+  /// Listen for clients on each of `listeners`, measuring poll deadlines by `clock`
+  pub fn bind_listeners(listeners: &[Listener], clock: Arc<dyn Clock>, config: TcpConfig) -> io::Result<Self> {
+    let mut bound = vec![];
+    for listener in listeners {
+      for socket in listeners::bind(listener.address.as_str())? {
+        bound.push((socket, listener.role, listener.allow.clone()));
+      }
+    }
+    Self::listen(bound, clock, config)
+  }
+
+  fn listen(
+    listeners: Vec<(TcpListener, EndpointRole, Vec<Subnet>)>,
+    clock: Arc<dyn Clock>,
+    config: TcpConfig,
+  ) -> io::Result<Self> {
+    let (sender, events) = mpsc::channel();
+    let streams = Arc::new(Mutex::new(HashMap::new()));
+    let connections = Arc::new(Mutex::new(HashMap::new()));
+
+    let workers = (0..config.workers.max(1))
+      .map(|worker| {
+        let (connections, incoming) = mpsc::channel();
+        let core = match config.io_cores.as_slice() {
+          [] => None,
+          cores => Some(cores[worker % cores.len()]),
+        };
+        let read = io_worker(incoming, sender.clone(), clock.clone(), config.wait);
+        threads::supervise(&format!("io-{}", worker), core, read)?;
+        Ok(connections)
+      })
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let acceptor = Acceptor {
+      max_connections: config.max_connections,
+      next_session: Arc::new(AtomicUsize::new(0)),
+      writers: streams.clone(),
+      connections: connections.clone(),
+      events: sender,
+      workers,
+    };
+    for (index, (listener, role, allow)) in listeners.into_iter().enumerate() {
+      let acceptor = acceptor.clone();
+      let name = if index == 0 { "accept".to_string() } else { format!("accept-{}", index) };
+      threads::supervise(&name, None, move || acceptor.accept(&listener, role, &allow))?;
+    }
+
+    Ok(Self {
+      clock,
+      events,
+      sender: TcpSender { streams, connections },
+      wait: config.wait,
+    })
+  }
+
+  pub fn sender(&self) -> TcpSender {
+    self.sender.clone()
+  }
+}
+
+/// Accepts the connections of a `TcpNetwork`'s listeners, handing each to a worker
+#[derive(Clone)]
+struct Acceptor {
+  max_connections: usize,
+  /// Shared by every listener, so sessions are numbered apart across them
+  next_session: Arc<AtomicUsize>,
+  writers: Arc<Mutex<HashMap<SessionId, TcpStream>>>,
+  connections: Arc<Mutex<HashMap<SessionId, Connection>>>,
+  events: mpsc::Sender<NetEvent>,
+  workers: Vec<mpsc::Sender<(SessionId, TcpStream)>>,
+}
+
+impl Acceptor {
+  /// Accept connections on `listener` from the subnets in `allow`, or from anywhere if it is empty, until the server
+  /// stops listening to it
+  fn accept(&self, listener: &TcpListener, role: EndpointRole, allow: &[Subnet]) {
+    for stream in listener.incoming() {
+      let session = SessionId::from(self.next_session.fetch_add(1, Ordering::Relaxed));
+      let mut stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+          eprintln!("failed to accept connection: {}", e);
+          continue;
+        }
+      };
+      let peer = stream.peer_addr().ok().map(|address| address.ip());
+      if let Some(peer) = peer.filter(|&peer| !listeners::allows(allow, peer)) {
+        if self.events.send(NetEvent::Refused(session, peer)).is_err() {
+          return;
+        }
+        continue;
+      }
+
+      let mut writers = threads::lock(&self.writers);
+      if writers.len() >= self.max_connections {
+        drop(writers);
+        let _ = stream.write_all(&rejection(self.max_connections));
+        continue;
+      }
+      let reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(e) => {
+          eprintln!("failed to accept connection: {}", e);
+          continue;
+        }
+      };
+      writers.insert(session, stream);
+      drop(writers);
+      threads::lock(&self.connections).insert(session, Connection { role, peer });
+
+      if self.events.send(NetEvent::Connected(session)).is_err() {
+        return;
+      }
+      let worker = &self.workers[usize::from(session) % self.workers.len()];
+      if worker.send((session, reader)).is_err() {
+        return;
+      }
+    }
+  }
+}
+
+/// Get a worker reading the connections handed to it, to be supervised
+///
+/// The worker's connections outlive a run that panics, and each is closed and reported disconnected before the next
+/// run starts, since a read may have been cut off part way through a message.
+fn io_worker(
+  incoming: Receiver<(SessionId, TcpStream)>,
+  events: mpsc::Sender<NetEvent>,
+  clock: Arc<dyn Clock>,
+  wait: WaitStrategy,
+) -> impl FnMut() + Send + 'static {
+  let mut connections: Vec<(SessionId, TcpStream)> = vec![];
+  move || {
+    for (session, stream) in connections.drain(..) {
+      let _ = stream.shutdown(Shutdown::Both);
+      if events.send(NetEvent::Disconnected(session)).is_err() {
+        return;
+      }
+    }
+    read_connections(&mut connections, &incoming, &events, &*clock, wait)
+  }
+}
+
+/// Read the connections handed to a worker until the server stops listening to it
+fn read_connections(
+  connections: &mut Vec<(SessionId, TcpStream)>,
+  incoming: &Receiver<(SessionId, TcpStream)>,
+  events: &mpsc::Sender<NetEvent>,
+  clock: &dyn Clock,
+  wait: WaitStrategy,
+) {
+  let mut buffer = [0; 4096];
+  let mut idle = 0;
+  loop {
+    if connections.is_empty() {
+      match incoming.recv() {
+        Ok(connection) => connections.push(connection),
+        Err(_) => return,
+      }
+    }
+    connections.extend(incoming.try_iter());
+
+    let mut fds: Vec<_> = connections
+      .iter()
+      .map(|(_, stream)| libc::pollfd {
+        fd: stream.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+      })
+      .collect();
+    // SAFETY: `fds` is a valid array of `fds.len()` pollfds, and every fd stays open for the call
+    let timeout = if wait.spins(idle) { 0 } else { WORKER_POLL_MS };
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if ready <= 0 {
+      idle = idle.saturating_add(1);
+      continue;
+    }
+    idle = 0;
+
+    let mut index = 0;
+    connections.retain_mut(|(session, stream)| {
+      let revents = fds[index].revents;
+      index += 1;
+      if revents == 0 {
+        return true;
+      }
+
+      let event = match stream.read(&mut buffer) {
+        Ok(0) | Err(_) => NetEvent::Disconnected(*session),
+        Ok(read) => NetEvent::Received(*session, buffer[..read].to_vec(), clock.now()),
+      };
+      let is_open = !matches!(event, NetEvent::Disconnected(_));
+      events.send(event).is_ok() && is_open
+    });
+  }
+}
+
+impl TcpNetwork {
+  /// Forget the stream of a session the workers found disconnected
+  fn received(&mut self, event: NetEvent) -> NetEvent {
+    if let NetEvent::Disconnected(session) = event {
+      threads::lock(&self.sender.streams).remove(&session);
+      threads::lock(&self.sender.connections).remove(&session);
+    }
+    event
+  }
+}
+
+impl Network for TcpNetwork {
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent> {
+    let mut idle = 0;
+    while self.wait.spins(idle) {
+      match self.events.try_recv() {
+        Ok(event) => return Some(self.received(event)),
+        Err(TryRecvError::Empty) if self.clock.now() >= deadline => return None,
+        Err(TryRecvError::Empty) => {
+          idle += 1;
+          hint::spin_loop();
+        }
+        Err(TryRecvError::Disconnected) => break,
+      }
+    }
+
+    let timeout = u64::from(deadline).saturating_sub(self.clock.now().into());
+    match self.events.recv_timeout(Duration::from_nanos(timeout)) {
+      Ok(event) => Some(self.received(event)),
+      Err(RecvTimeoutError::Timeout) => None,
+      Err(RecvTimeoutError::Disconnected) => {
+        // the listener has stopped, so there is nothing left but ticks
+        thread::sleep(Duration::from_nanos(timeout));
+        None
+      }
+    }
+  }
+
+  fn send(&mut self, session: SessionId, bytes: &[u8]) {
+    self.sender.send(session, bytes);
+  }
+
+  fn connection(&self, session: SessionId) -> Connection {
+    threads::lock(&self.sender.connections).get(&session).copied().unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  /// Panics the first time it is read
+  struct FaultyClock(AtomicBool);
+
+  impl Clock for FaultyClock {
+    fn now(&self) -> Timestamp {
+      if !self.0.swap(true, Ordering::SeqCst) {
+        panic!("the clock failed");
+      }
+      Timestamp::default()
+    }
+  }
+
+  #[test]
+  fn sessions_of_a_restarted_worker_are_reported_disconnected() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let (connections, incoming) = mpsc::channel();
+    let (events, received) = mpsc::channel();
+    let clock = Arc::new(FaultyClock(AtomicBool::new(false)));
+    let worker = threads::supervise("io-test", None, io_worker(incoming, events, clock, WaitStrategy::Park)).unwrap();
+
+    let session = SessionId::from(3);
+    connections.send((session, stream)).unwrap();
+    client.write_all(b"{}").unwrap();
+    assert_eq!(received.recv_timeout(Duration::from_secs(5)), Ok(NetEvent::Disconnected(session)));
+    assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+
+    drop(connections);
+    worker.join().unwrap();
+  }
+}
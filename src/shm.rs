@@ -0,0 +1,443 @@
+//! Shared memory transport for clients on the same host
+//!
+//! The server maps a file split into a fixed number of slots, and a client connects by claiming a free one. Each slot
+//! holds a ring of bytes in each direction with a single writer and a single reader, so neither side ever takes a lock
+//! or makes a system call to pass a message. Clients send and are sent the same bytes as over TCP.
+//!
+//! A client that stops reading while the server has replies for it is dropped, since the replies cannot be held back
+//! without stalling every other client, and a client whose process has exited is dropped on the next tick.
+
+use crate::server::{NetEvent, Network, WaitStrategy};
+use engine::*;
+use std::collections::{HashMap, VecDeque};
+use std::cell::UnsafeCell;
+use std::fs::{self, OpenOptions};
+use std::hint;
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Clients that can be connected at once
+pub const SLOTS: usize = 64;
+/// Bytes each ring holds before its writer has to wait for its reader
+const RING_CAPACITY: usize = 1 << 16;
+/// How long a parked `ShmNetwork` sleeps between checks, there being nothing to wake it
+const PARK_INTERVAL: Duration = Duration::from_micros(50);
+/// Marks a mapping as set up by a server
+const MAGIC: u64 = 0x6d61_7463_6862_6f6f;
+
+const FREE: u32 = 0;
+/// Taken by a client the server has not seen yet
+const CLAIMED: u32 = 1;
+const OPEN: u32 = 2;
+/// Let go by the client, for the server to free
+const CLOSED: u32 = 3;
+/// Dropped by the server, waiting on the client to let go
+const EVICTED: u32 = 4;
+
+/// A ring of bytes with one writer and one reader
+#[repr(C, align(64))]
+struct Ring {
+  /// Bytes written so far, advanced only by the writer
+  written: AtomicU64,
+  _written_line: [u8; 56],
+  /// Bytes read so far, advanced only by the reader
+  read: AtomicU64,
+  _read_line: [u8; 56],
+  data: UnsafeCell<[u8; RING_CAPACITY]>,
+}
+
+impl Ring {
+  /// Write as much of `bytes` as there is room for
+  ///
+  /// # Safety
+  /// nothing else may write to the ring at the same time, in this process or any other
+  ///
+  /// # Returns
+  /// how many bytes were written
+  unsafe fn push(&self, bytes: &[u8]) -> usize {
+    let written = self.written.load(Ordering::Relaxed);
+    let len = bytes.len().min(self.free());
+    let start = written as usize % RING_CAPACITY;
+    let first = len.min(RING_CAPACITY - start);
+    let data = self.data.get() as *mut u8;
+    ptr::copy_nonoverlapping(bytes.as_ptr(), data.add(start), first);
+    ptr::copy_nonoverlapping(bytes.as_ptr().add(first), data, len - first);
+    self.written.store(written + len as u64, Ordering::Release);
+    len
+  }
+
+  /// Get how many bytes can be written before the reader catches up
+  fn free(&self) -> usize {
+    RING_CAPACITY - (self.written.load(Ordering::Relaxed) - self.read.load(Ordering::Acquire)) as usize
+  }
+
+  /// Append everything written since the last read to `bytes`
+  ///
+  /// # Safety
+  /// nothing else may read from the ring at the same time, in this process or any other
+  ///
+  /// # Returns
+  /// how many bytes were read
+  unsafe fn pop(&self, bytes: &mut Vec<u8>) -> usize {
+    let read = self.read.load(Ordering::Relaxed);
+    let len = (self.written.load(Ordering::Acquire) - read) as usize;
+    let start = read as usize % RING_CAPACITY;
+    let first = len.min(RING_CAPACITY - start);
+    let data = self.data.get() as *const u8;
+    bytes.extend_from_slice(slice::from_raw_parts(data.add(start), first));
+    bytes.extend_from_slice(slice::from_raw_parts(data, len - first));
+    self.read.store(read + len as u64, Ordering::Release);
+    len
+  }
+
+  /// Empty the ring, while neither side is using it
+  fn reset(&self) {
+    self.written.store(0, Ordering::Relaxed);
+    self.read.store(0, Ordering::Relaxed);
+  }
+}
+
+/// One client's connection
+#[repr(C)]
+struct Slot {
+  state: AtomicU32,
+  /// The process of the client holding the slot
+  pid: AtomicU32,
+  /// Written by the client and read by the server
+  requests: Ring,
+  /// Written by the server and read by the client
+  replies: Ring,
+}
+
+#[repr(C)]
+struct Region {
+  magic: AtomicU64,
+  slots: [Slot; SLOTS],
+}
+
+/// A region mapped into this process
+struct Mapping {
+  region: *const Region,
+}
+
+// SAFETY: the region is only accessed through atomics and rings, which are shared between processes anyway
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+  /// Map the region in the file at `path`, replacing it with a fresh one if `create`
+  fn open(path: &Path, create: bool) -> io::Result<Self> {
+    let size = mem::size_of::<Region>();
+    if create {
+      // a fresh file rather than a truncated one, as clients of an old server still have the old one mapped
+      match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+      }
+    }
+    let file = OpenOptions::new().read(true).write(true).create_new(create).open(path)?;
+    if create {
+      file.set_len(size as u64)?;
+    } else if file.metadata()?.len() < size as u64 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not a matchbook shared memory file"));
+    }
+
+    // SAFETY: the file is at least `size` bytes, and stays mapped after it is closed
+    let region = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        file.as_raw_fd(),
+        0,
+      )
+    };
+    if region == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(Self {
+      region: region as *const Region,
+    })
+  }
+
+  fn region(&self) -> &Region {
+    // SAFETY: the region stays mapped until the mapping is dropped, and all-zero bytes are a valid one
+    unsafe { &*self.region }
+  }
+}
+
+impl Drop for Mapping {
+  fn drop(&mut self) {
+    // SAFETY: the region was mapped with this size, and nothing borrowed from it outlives the mapping
+    unsafe { libc::munmap(self.region as *mut libc::c_void, mem::size_of::<Region>()) };
+  }
+}
+
+/// Clients connected over shared memory
+pub struct ShmNetwork {
+  mapping: Mapping,
+  clock: Arc<dyn Clock>,
+  wait: WaitStrategy,
+  /// The session in each slot, if it is open
+  sessions: Vec<Option<SessionId>>,
+  slots: HashMap<SessionId, usize>,
+  next_session: usize,
+  pending: VecDeque<NetEvent>,
+}
+
+impl ShmNetwork {
+  /// Serve clients through a fresh file at `path`, measuring poll deadlines by `clock`
+  pub fn bind<P: AsRef<Path>>(path: P, clock: Arc<dyn Clock>, wait: WaitStrategy) -> io::Result<Self> {
+    let mapping = Mapping::open(path.as_ref(), true)?;
+    mapping.region().magic.store(MAGIC, Ordering::Release);
+    Ok(Self {
+      mapping,
+      clock,
+      wait,
+      sessions: vec![None; SLOTS],
+      slots: HashMap::new(),
+      next_session: 0,
+      pending: VecDeque::new(),
+    })
+  }
+
+  /// Queue up what has happened in every slot since the last scan
+  fn scan(&mut self) {
+    let now = self.clock.now();
+    let region = self.mapping.region();
+    for (index, slot) in region.slots.iter().enumerate() {
+      match slot.state.load(Ordering::Acquire) {
+        CLAIMED if slot.state.compare_exchange(CLAIMED, OPEN, Ordering::AcqRel, Ordering::Acquire).is_ok() => {
+          let session = SessionId::from(self.next_session);
+          self.next_session += 1;
+          self.sessions[index] = Some(session);
+          self.slots.insert(session, index);
+          self.pending.push_back(NetEvent::Connected(session));
+        }
+        OPEN => {
+          if let Some(session) = self.sessions[index] {
+            let mut bytes = vec![];
+            // SAFETY: the server is the only reader of requests
+            if unsafe { slot.requests.pop(&mut bytes) } > 0 {
+              self.pending.push_back(NetEvent::Received(session, bytes, now));
+            }
+          }
+        }
+        CLOSED => {
+          // like TCP, what was sent before closing still arrives
+          if let Some(session) = self.sessions[index].take() {
+            let mut bytes = vec![];
+            // SAFETY: the server is the only reader of requests
+            if unsafe { slot.requests.pop(&mut bytes) } > 0 {
+              self.pending.push_back(NetEvent::Received(session, bytes, now));
+            }
+            self.slots.remove(&session);
+            self.pending.push_back(NetEvent::Disconnected(session));
+          }
+          slot.requests.reset();
+          slot.replies.reset();
+          slot.state.store(FREE, Ordering::Release);
+        }
+        _ => {}
+      }
+    }
+  }
+
+  /// Close the slots of clients whose processes have exited without letting go
+  fn reap(&self) {
+    for slot in self.mapping.region().slots.iter() {
+      let state = slot.state.load(Ordering::Acquire);
+      if state != CLAIMED && state != OPEN && state != EVICTED {
+        continue;
+      }
+      let pid = slot.pid.load(Ordering::Relaxed);
+      // SAFETY: signal 0 only checks that the process exists
+      let is_gone = pid != 0 && unsafe { libc::kill(pid as libc::pid_t, 0) } != 0;
+      if is_gone && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH) {
+        let _ = slot.state.compare_exchange(state, CLOSED, Ordering::AcqRel, Ordering::Acquire);
+      }
+    }
+  }
+}
+
+impl Network for ShmNetwork {
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent> {
+    let mut idle = 0;
+    loop {
+      if let Some(event) = self.pending.pop_front() {
+        return Some(event);
+      }
+      self.scan();
+      if !self.pending.is_empty() {
+        continue;
+      }
+
+      if self.clock.now() >= deadline {
+        self.reap();
+        return None;
+      }
+      if self.wait.spins(idle) {
+        hint::spin_loop();
+      } else {
+        thread::sleep(PARK_INTERVAL);
+      }
+      idle = idle.saturating_add(1);
+    }
+  }
+
+  fn send(&mut self, session: SessionId, bytes: &[u8]) {
+    let index = match self.slots.get(&session) {
+      Some(&index) => index,
+      None => return,
+    };
+    let slot = &self.mapping.region().slots[index];
+    // a reply is never sent in part, so the client does not mistake the start of one for the whole
+    if slot.replies.free() >= bytes.len() {
+      // SAFETY: the server is the only writer of replies
+      unsafe { slot.replies.push(bytes) };
+      return;
+    }
+
+    let _ = slot.state.compare_exchange(OPEN, EVICTED, Ordering::AcqRel, Ordering::Acquire);
+    self.sessions[index] = None;
+    self.slots.remove(&session);
+    self.pending.push_back(NetEvent::Disconnected(session));
+  }
+}
+
+/// A client connected to a server over shared memory
+pub struct ShmClient {
+  mapping: Mapping,
+  slot: usize,
+}
+
+impl ShmClient {
+  /// Connect to the server serving through the file at `path`
+  pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let mapping = Mapping::open(path.as_ref(), false)?;
+    if mapping.region().magic.load(Ordering::Acquire) != MAGIC {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not a matchbook shared memory file"));
+    }
+
+    let slot = mapping
+      .region()
+      .slots
+      .iter()
+      .position(|slot| slot.state.compare_exchange(FREE, CLAIMED, Ordering::AcqRel, Ordering::Relaxed).is_ok());
+    let slot = match slot {
+      Some(slot) => slot,
+      None => return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "every slot is taken")),
+    };
+    mapping.region().slots[slot].pid.store(process::id(), Ordering::Relaxed);
+    Ok(Self { mapping, slot })
+  }
+
+  fn slot(&self) -> &Slot {
+    &self.mapping.region().slots[self.slot]
+  }
+
+  fn check_open(&self) -> io::Result<()> {
+    match self.slot().state.load(Ordering::Acquire) {
+      EVICTED => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "dropped by the server")),
+      _ => Ok(()),
+    }
+  }
+
+  /// Send bytes to the server, waiting for room in the ring
+  pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+    let mut sent = 0;
+    while sent < bytes.len() {
+      self.check_open()?;
+      // SAFETY: `&mut self` makes this the only writer of the slot's requests
+      sent += unsafe { self.slot().requests.push(&bytes[sent..]) };
+      hint::spin_loop();
+    }
+    Ok(())
+  }
+
+  /// Append everything the server has sent since the last call to `bytes`, without waiting
+  ///
+  /// # Returns
+  /// how many bytes were appended
+  pub fn recv(&mut self, bytes: &mut Vec<u8>) -> io::Result<usize> {
+    // SAFETY: `&mut self` makes this the only reader of the slot's replies
+    match unsafe { self.slot().replies.pop(bytes) } {
+      0 => self.check_open().map(|()| 0),
+      read => Ok(read),
+    }
+  }
+}
+
+impl Drop for ShmClient {
+  fn drop(&mut self) {
+    self.slot().state.store(CLOSED, Ordering::Release);
+  }
+}
+
+/// Send `command` `count` times, waiting for each reply before sending the next
+///
+/// # Returns
+/// the time from sending each command to its whole reply arriving, in order
+pub fn round_trips(client: &mut ShmClient, command: &Command, count: usize) -> io::Result<Vec<Duration>> {
+  let mut line = serde_json::to_vec(command)?;
+  line.push(b'\n');
+  let mut reply = vec![];
+  (0..count)
+    .map(|_| {
+      let start = Instant::now();
+      client.send(&line)?;
+      reply.clear();
+      while reply.last() != Some(&b'\n') {
+        client.recv(&mut reply)?;
+        hint::spin_loop();
+      }
+      Ok(start.elapsed())
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn clients_are_served_like_tcp_connections() {
+    let path = std::env::temp_dir().join(format!("matchbook-shm-test-{}", process::id()));
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let mut network = ShmNetwork::bind(&path, clock.clone(), WaitStrategy::Park).unwrap();
+    let deadline = || clock.now() + Duration::from_secs(5);
+
+    let mut client = ShmClient::connect(&path).unwrap();
+    client.send(b"{}").unwrap();
+    let session = match network.poll(deadline()) {
+      Some(NetEvent::Connected(session)) => session,
+      event => panic!("expected a connection, got {:?}", event),
+    };
+    assert!(matches!(network.poll(deadline()), Some(NetEvent::Received(s, bytes, _)) if s == session && bytes == b"{}"));
+
+    network.send(session, b"reply\n");
+    let mut reply = vec![];
+    assert_eq!(client.recv(&mut reply).unwrap(), 6);
+    assert_eq!(reply, b"reply\n");
+
+    network.send(session, &vec![0; RING_CAPACITY + 1]);
+    assert_eq!(network.poll(deadline()), Some(NetEvent::Disconnected(session)));
+    assert_eq!(client.recv(&mut reply).unwrap_err().kind(), io::ErrorKind::ConnectionAborted);
+
+    drop(client);
+    let client = ShmClient::connect(&path).unwrap();
+    assert!(matches!(network.poll(deadline()), Some(NetEvent::Connected(_))));
+    drop(client);
+    assert!(matches!(network.poll(deadline()), Some(NetEvent::Disconnected(_))));
+    fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,776 @@
+//! Deterministic simulation of the server
+//!
+//! A `SimNetwork` delivers client bytes in randomly sized fragments after random delays, interleaving sessions as
+//! threads reading real sockets would, and moves a `ManualClock` to each delivery, so a whole client/server scenario
+//! is reproduced exactly by its seed.
+//!
+//! For backtests, the delay each way between a strategy and the engine can be drawn from a `Latency`, and a
+//! `FillModel` decides how much of a resting order volume traded at its price would have filled, given its place in
+//! the queue, rather than assuming every fill is instant and first in line.
+
+use crate::bench::XorShift;
+use crate::durability::{DurableJournal, SyncWrite};
+use crate::listeners::Connection;
+use crate::server::{NetEvent, Network, Server, ShutdownPolicy};
+use engine::*;
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The largest fragment client bytes are split into
+const MAX_FRAGMENT: u64 = 16;
+
+/// How long a message takes to cross the network, drawn afresh for each one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latency {
+  /// Always the same delay
+  Fixed(Duration),
+  /// Any delay from a nanosecond up to `max`, all equally likely
+  Uniform { max: Duration },
+  /// Usually `base`, but one message in every `one_in` on average is held up by a further `spike`
+  Spiky { base: Duration, spike: Duration, one_in: u64 },
+}
+
+impl Latency {
+  /// Draw the delay of one message
+  pub fn sample(&self, rng: &mut XorShift) -> Duration {
+    match *self {
+      Latency::Fixed(delay) => delay,
+      Latency::Uniform { max } => Duration::from_nanos(1 + rng.below(max.as_nanos() as u64)),
+      Latency::Spiky { base, spike, one_in } => {
+        if rng.below(one_in.max(1)) == 0 {
+          base + spike
+        } else {
+          base
+        }
+      }
+    }
+  }
+}
+
+/// How much of a resting order is filled by quantity traded at its price, e.g. in replayed market data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillModel {
+  /// Filled before anything else at its price, as if it were at the front of the queue
+  Optimistic,
+  /// Filled only by what trades once everything ahead of it has been filled
+  Pessimistic,
+  /// Like `Pessimistic`, but up to `cancelled_percent` of the quantity ahead may have cancelled instead, drawn from
+  /// the seed
+  Probabilistic { cancelled_percent: u64 },
+}
+
+impl FillModel {
+  /// Get how much of an order at `position` in its queue, with `remaining` left to fill, is filled by `traded`
+  pub fn fill(&self, position: &QueuePosition, remaining: Quantity, traded: Quantity, rng: &mut XorShift) -> Quantity {
+    let (ahead, traded) = (u64::from(position.ahead), u64::from(traded));
+    let ahead = match *self {
+      FillModel::Optimistic => 0,
+      FillModel::Pessimistic => ahead,
+      FillModel::Probabilistic { cancelled_percent } => {
+        ahead - rng.below(ahead * cancelled_percent.min(100) / 100 + 1)
+      }
+    };
+    traded.saturating_sub(ahead).min(remaining.into()).into()
+  }
+}
+
+/// A network of simulated clients
+pub struct SimNetwork {
+  clock: Arc<ManualClock>,
+  rng: XorShift,
+  /// How long client bytes take to reach the server
+  inbound: Latency,
+  /// How long server bytes take to reach a client
+  outbound: Latency,
+  /// Events in delivery order, with a sequence number to order events delivered at the same time
+  scheduled: BTreeMap<(Timestamp, u64), NetEvent>,
+  sequence: u64,
+  /// When each session's last event is delivered, so a session's events arrive in order as they would over TCP
+  last_delivery: HashMap<SessionId, Timestamp>,
+  /// Bytes sent to clients that have not yet reached them, in delivery order
+  in_flight: BTreeMap<(Timestamp, u64), (SessionId, Vec<u8>)>,
+  /// When each session's last bytes from the server reach it
+  last_reply: HashMap<SessionId, Timestamp>,
+  connected: HashSet<SessionId>,
+  /// Where each session connects to and from, if not the default
+  connections: HashMap<SessionId, Connection>,
+  received: HashMap<SessionId, Vec<u8>>,
+}
+
+impl SimNetwork {
+  pub fn new(clock: Arc<ManualClock>, seed: u64) -> Self {
+    Self {
+      clock,
+      rng: XorShift::new(seed),
+      inbound: Latency::Uniform {
+        max: Duration::from_millis(5),
+      },
+      outbound: Latency::Fixed(Duration::from_nanos(0)),
+      scheduled: BTreeMap::new(),
+      sequence: 0,
+      last_delivery: HashMap::new(),
+      in_flight: BTreeMap::new(),
+      last_reply: HashMap::new(),
+      connected: HashSet::new(),
+      connections: HashMap::new(),
+      received: HashMap::new(),
+    }
+  }
+
+  /// Delay client bytes by `inbound` on their way to the server, and server bytes by `outbound` on their way back
+  pub fn with_latency(self, inbound: Latency, outbound: Latency) -> Self {
+    Self {
+      inbound,
+      outbound,
+      ..self
+    }
+  }
+
+  pub fn connect(&mut self, session: SessionId) {
+    self.schedule(session, NetEvent::Connected(session));
+  }
+
+  /// Connect a client to an endpoint of `role`
+  pub fn connect_as(&mut self, session: SessionId, role: EndpointRole) {
+    self.connect_from(session, Connection { role, peer: None });
+  }
+
+  /// Connect a client as `connection` says
+  pub fn connect_from(&mut self, session: SessionId, connection: Connection) {
+    self.connections.insert(session, connection);
+    self.connect(session);
+  }
+
+  /// Refuse a client's connection for its address
+  pub fn refuse(&mut self, session: SessionId, source: IpAddr) {
+    self.schedule(session, NetEvent::Refused(session, source));
+  }
+
+  /// Send bytes from a client, in fragments
+  pub fn send_from(&mut self, session: SessionId, bytes: &[u8]) {
+    let mut remaining = bytes;
+    while !remaining.is_empty() {
+      let length = 1 + self.rng.below(MAX_FRAGMENT.min(remaining.len() as u64)) as usize;
+      let (fragment, rest) = remaining.split_at(length);
+      // stamped once it arrives
+      self.schedule(session, NetEvent::Received(session, fragment.to_vec(), Timestamp::default()));
+      remaining = rest;
+    }
+  }
+
+  /// Send a command from a client
+  pub fn send_command(&mut self, session: SessionId, command: Command) {
+    self.send_from(session, &serde_json::to_vec(&command).unwrap());
+  }
+
+  /// Disconnect a client once everything it sent has arrived
+  pub fn disconnect(&mut self, session: SessionId) {
+    self.schedule(session, NetEvent::Disconnected(session));
+  }
+
+  /// Returns true once every scheduled event has been delivered, to the server and back
+  pub fn is_idle(&self) -> bool {
+    self.scheduled.is_empty() && self.in_flight.is_empty()
+  }
+
+  /// Get the results the server replied to a session with, in order
+  pub fn replies(&self, session: SessionId) -> Vec<Result<Success, Error>> {
+    self.messages(session)
+  }
+
+  /// Get the messages the server pushed to a session unprompted, in order
+  pub fn pushes(&self, session: SessionId) -> Vec<Push> {
+    self.messages(session)
+  }
+
+  /// Get the messages a session received that are of one kind, in order
+  fn messages<T: DeserializeOwned>(&self, session: SessionId) -> Vec<T> {
+    let received = self.received.get(&session).map(Vec::as_slice).unwrap_or_default();
+    serde_json::Deserializer::from_slice(received)
+      .into_iter::<serde_json::Value>()
+      .filter_map(|message| serde_json::from_value(message.unwrap()).ok())
+      .collect()
+  }
+
+  fn schedule(&mut self, session: SessionId, event: NetEvent) {
+    let delay = self.inbound.sample(&mut self.rng);
+    let earliest = self.last_delivery.get(&session).map(|&at| at + Duration::from_nanos(1));
+    let at = (self.clock.now() + delay).max(earliest.unwrap_or_default());
+    self.last_delivery.insert(session, at);
+
+    self.scheduled.insert((at, self.sequence), event);
+    self.sequence += 1;
+  }
+
+  /// Hand clients the bytes that have reached them by now
+  fn deliver_replies(&mut self) {
+    let now = self.clock.now();
+    while let Some(entry) = self.in_flight.first_entry() {
+      if entry.key().0 > now {
+        break;
+      }
+      let (session, bytes) = entry.remove();
+      if self.connected.contains(&session) {
+        self.received.entry(session).or_default().extend_from_slice(&bytes);
+      }
+    }
+  }
+}
+
+impl Network for SimNetwork {
+  fn poll(&mut self, deadline: Timestamp) -> Option<NetEvent> {
+    let key = match self.scheduled.keys().next() {
+      Some(&key) if key.0 <= deadline => key,
+      _ => {
+        self.clock.set(deadline.max(self.clock.now()));
+        self.deliver_replies();
+        return None;
+      }
+    };
+
+    let event = match self.scheduled.remove(&key).unwrap() {
+      // read off the network when it arrived, however late the server polls
+      NetEvent::Received(session, bytes, _) => NetEvent::Received(session, bytes, key.0),
+      event => event,
+    };
+    self.clock.set(key.0.max(self.clock.now()));
+    self.deliver_replies();
+    match event {
+      NetEvent::Connected(session) => {
+        self.connected.insert(session);
+      }
+      NetEvent::Disconnected(session) => {
+        self.connected.remove(&session);
+      }
+      NetEvent::Received(..) | NetEvent::Refused(..) => (),
+    }
+
+    Some(event)
+  }
+
+  fn send(&mut self, session: SessionId, bytes: &[u8]) {
+    let delay = self.outbound.sample(&mut self.rng);
+    let earliest = self.last_reply.get(&session).copied().unwrap_or_default();
+    let at = (self.clock.now() + delay).max(earliest);
+    self.last_reply.insert(session, at);
+
+    self.in_flight.insert((at, self.sequence), (session, bytes.to_vec()));
+    self.sequence += 1;
+    self.deliver_replies();
+  }
+
+  fn connection(&self, session: SessionId) -> Connection {
+    self.connections.get(&session).copied().unwrap_or_default()
+  }
+}
+
+/// A journal file that outlives the server writing it
+#[derive(Debug, Clone, Default)]
+pub struct Disk(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Disk {
+  fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    self.0.borrow_mut().write(bytes)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl SyncWrite for Disk {
+  fn sync(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Restart a server journaling to `disk`, recovering the state already journaled there
+pub fn recover(clock: Arc<ManualClock>, disk: &Disk) -> Result<Server, failure::Error> {
+  let now = clock.now();
+  let journal = disk.0.borrow().clone();
+  let entries = read_journal(journal.as_slice()).collect::<Result<Vec<_>, _>>()?;
+  let mut engine = MatchEngine::replay(clock.clone(), entries)?;
+  clock.set(now);
+
+  // everything replayed is already on disk or was already exported
+  engine.drain_journal();
+  engine.drain_audit_trail();
+  engine.drain_market_data();
+
+  Ok(Server::new(engine, clock).with_journal(DurableJournal::every_command(Box::new(disk.clone()))))
+}
+
+/// Step a server until every scheduled event has been delivered
+pub fn run_until_idle(server: &mut Server, network: &mut SimNetwork) {
+  while !network.is_idle() {
+    server.step(network);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::health::Health;
+  use std::sync::Mutex;
+
+  const ADBE: [char; 4] = ['A', 'D', 'B', 'E'];
+
+  fn place(account: usize, side: Side, price: u32, quantity: u64) -> Command {
+    Command {
+      account_id: account.into(),
+      kind: CommandKind::PlaceOrder(side, ADBE.into(), Order::new(price.into(), quantity.into())),
+    }
+  }
+
+  /// Start a server with one symbol and an account per client, with every client connected
+  fn start(seed: u64, clients: usize) -> (Server, SimNetwork, Disk) {
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1)));
+    let disk = Disk::default();
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    engine.insert_new_symbol(ADBE.into()).unwrap();
+    let mut network = SimNetwork::new(clock.clone(), seed);
+    for session in 0..clients {
+      engine.create_account();
+      network.connect(session.into());
+    }
+
+    let server = Server::new(engine, clock).with_journal(DurableJournal::every_command(Box::new(disk.clone())));
+    (server, network, disk)
+  }
+
+  /// Two clients trading against each other
+  fn trade(seed: u64) -> (Server, SimNetwork, Disk) {
+    let (mut server, mut network, disk) = start(seed, 2);
+    for i in 0..10 {
+      network.send_command(0.into(), place(0, Side::Ask, 100 + i % 3, 5));
+      network.send_command(1.into(), place(1, Side::Bid, 101, 4));
+    }
+    run_until_idle(&mut server, &mut network);
+    (server, network, disk)
+  }
+
+  #[test]
+  fn same_seed_replays_the_same_run() {
+    let (first, first_network, _) = trade(7);
+    let (second, second_network, _) = trade(7);
+
+    assert_eq!(first.engine().state_hash(), second.engine().state_hash());
+    for session in 0..2 {
+      assert_eq!(first_network.replies(session.into()), second_network.replies(session.into()));
+      assert_eq!(first_network.replies(session.into()).len(), 10);
+    }
+  }
+
+  #[test]
+  fn fragmenting_one_session_does_not_change_the_result() {
+    let hashes: Vec<_> = (1..6)
+      .map(|seed| {
+        let (mut server, mut network, _) = start(seed, 1);
+        for i in 0..10 {
+          let side = if i % 2 == 0 { Side::Ask } else { Side::Bid };
+          network.send_command(0.into(), place(0, side, 100 + i % 3, 5));
+        }
+        run_until_idle(&mut server, &mut network);
+        server.engine().state_hash()
+      })
+      .collect();
+
+    assert!(hashes.windows(2).all(|pair| pair[0] == pair[1]));
+  }
+
+  #[test]
+  fn disconnecting_mid_command_drops_it() {
+    let (mut server, mut network, _) = start(3, 1);
+    let command = serde_json::to_vec(&place(0, Side::Ask, 100, 5)).unwrap();
+    network.send_from(0.into(), &command[..command.len() / 2]);
+    network.disconnect(0.into());
+    network.connect(1.into());
+    network.send_command(1.into(), place(0, Side::Ask, 100, 5));
+    run_until_idle(&mut server, &mut network);
+
+    assert_eq!(network.replies(0.into()), vec![]);
+    let placement = Placement {
+      id: 0.into(),
+      fills: vec![],
+      remaining: 5.into(),
+      state: OrderState::New,
+      quantity_precision: 0,
+    };
+    assert_eq!(network.replies(1.into()), vec![Ok(Success::PlaceOrder(placement))]);
+  }
+
+  #[test]
+  fn malformed_input_is_answered_and_kept_as_a_dead_letter() {
+    let clock = Arc::new(ManualClock::new(Timestamp::from(1)));
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    engine.insert_new_symbol(ADBE.into()).unwrap();
+    let admin = Account {
+      is_admin: true,
+      ..Account::default()
+    };
+    engine.load_accounts(AccountStore {
+      accounts: vec![(0.into(), Account::default()), (1.into(), admin)],
+      ..AccountStore::default()
+    });
+    let mut network = SimNetwork::new(clock.clone(), 5);
+    network.connect(0.into());
+    network.connect(1.into());
+    let mut server = Server::new(engine, clock);
+
+    network.send_command(0.into(), place(0, Side::Ask, 100, 5));
+    network.send_from(0.into(), b"{\"account_id\":0,\"kind\":nope}\n");
+    run_until_idle(&mut server, &mut network);
+    let replies = network.replies(0.into());
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[1], Err(Error::MalformedCommand { line: 1, column: 24 }));
+
+    network.send_command(1.into(), Command {
+      account_id: 1.into(),
+      kind: CommandKind::GetDeadLetters,
+    });
+    run_until_idle(&mut server, &mut network);
+    assert_eq!(network.replies(1.into()), vec![Ok(Success::GetDeadLetters(vec![DeadLetters {
+      session: 0.into(),
+      count: 1,
+      inputs: vec!["{\"account_id\":0,\"kind\":nope}".to_string()],
+    }]))]);
+  }
+
+  #[test]
+  fn replies_wait_out_the_outbound_latency() {
+    let (mut server, network, _) = start(5, 1);
+    let latency = |millis| Latency::Fixed(Duration::from_millis(millis));
+    let mut network = network.with_latency(latency(1), latency(3));
+    network.send_command(0.into(), place(0, Side::Ask, 100, 5));
+    while !network.scheduled.is_empty() {
+      server.step(&mut network);
+    }
+    assert_eq!(network.replies(0.into()), vec![]);
+
+    let sent = network.clock.now();
+    run_until_idle(&mut server, &mut network);
+    assert_eq!(network.replies(0.into()).len(), 1);
+    assert!(network.clock.now() >= sent + Duration::from_millis(3));
+  }
+
+  #[test]
+  fn spiky_latency_holds_up_some_messages() {
+    let spiky = Latency::Spiky {
+      base: Duration::from_micros(50),
+      spike: Duration::from_millis(2),
+      one_in: 4,
+    };
+    let mut rng = XorShift::new(9);
+    let delays: Vec<_> = (0..100).map(|_| spiky.sample(&mut rng)).collect();
+    let spikes = delays.iter().filter(|&&delay| delay > Duration::from_micros(50)).count();
+    assert!(spikes > 0 && spikes < 100);
+  }
+
+  #[test]
+  fn fill_models_disagree_on_how_much_of_the_queue_trades_first() {
+    let position = QueuePosition {
+      price: 100.into(),
+      position: 2,
+      ahead: 10.into(),
+      orders: 3,
+      quantity: 15.into(),
+    };
+    let mut rng = XorShift::new(3);
+    let fill = |model: FillModel, traded: u64, rng: &mut XorShift| model.fill(&position, 5.into(), traded.into(), rng);
+
+    assert_eq!(fill(FillModel::Optimistic, 8, &mut rng), 5.into());
+    assert_eq!(fill(FillModel::Pessimistic, 8, &mut rng), 0.into());
+    assert_eq!(fill(FillModel::Pessimistic, 12, &mut rng), 2.into());
+    let probabilistic = FillModel::Probabilistic { cancelled_percent: 50 };
+    for _ in 0..20 {
+      let filled = fill(probabilistic, 12, &mut rng);
+      assert!(filled >= 2.into() && filled <= 5.into());
+    }
+  }
+
+  #[test]
+  fn endpoints_only_serve_the_commands_of_their_role() {
+    let (mut server, mut network, _) = start(13, 1);
+    network.connect_as(1.into(), EndpointRole::MarketData);
+    network.connect_as(2.into(), EndpointRole::Admin);
+    network.send_command(1.into(), place(0, Side::Ask, 100, 5));
+    network.send_command(1.into(), Command {
+      account_id: 0.into(),
+      kind: CommandKind::GetBookStats(ADBE.into()),
+    });
+    network.send_command(2.into(), Command {
+      account_id: 0.into(),
+      kind: CommandKind::GetBookStats(ADBE.into()),
+    });
+    run_until_idle(&mut server, &mut network);
+
+    let replies = network.replies(1.into());
+    assert_eq!(replies[0], Err(Error::NotServedOnEndpoint {
+      role: EndpointRole::MarketData
+    }));
+    assert!(matches!(replies[1], Ok(Success::GetBookStats(_))));
+    assert_eq!(network.replies(2.into()), vec![Err(Error::PermissionDenied { id: 0.into() })]);
+  }
+
+  #[test]
+  fn refusals_for_the_source_address_are_audited() {
+    let (server, mut network, _) = start(19, 1);
+    let audit_log = Disk::default();
+    let mut sources = HashMap::new();
+    sources.insert(AccountId::from(0), vec!["10.1.0.0/16".parse().unwrap()]);
+    let mut server = server
+      .with_audit_exporter(AuditExporter::new(Box::new(audit_log.clone()) as Box<dyn Write>).unwrap())
+      .with_account_sources(sources);
+    let connection = |peer: &str| Connection {
+      role: EndpointRole::All,
+      peer: Some(peer.parse().unwrap()),
+    };
+    network.connect_from(1.into(), connection("10.1.4.2"));
+    network.connect_from(2.into(), connection("10.2.4.2"));
+    network.send_command(1.into(), place(0, Side::Ask, 100, 5));
+    network.send_command(2.into(), place(0, Side::Ask, 100, 5));
+    network.refuse(3.into(), "192.0.2.9".parse().unwrap());
+    run_until_idle(&mut server, &mut network);
+
+    assert!(matches!(network.replies(1.into())[..], [Ok(Success::PlaceOrder(_))]));
+    assert_eq!(network.replies(2.into()), vec![Err(Error::SourceNotAllowed {
+      id: 0.into(),
+      source: "10.2.4.2".parse().unwrap(),
+    })]);
+    let audit_log = String::from_utf8(audit_log.0.borrow().clone()).unwrap();
+    let denials: Vec<_> = audit_log.lines().filter(|line| line.contains("|DENY|")).collect();
+    assert_eq!(denials.len(), 2);
+    assert!(denials.iter().any(|line| line.ends_with("|10.2.4.2||||")));
+    assert!(denials.iter().any(|line| line.ends_with("|192.0.2.9||||")));
+  }
+
+  #[test]
+  fn latency_traces_split_each_reply_between_its_stages() {
+    let (server, network, _) = start(20, 1);
+    let health = Arc::new(Mutex::new(Health::default()));
+    let mut server = server.with_latency_traces().with_health(health.clone());
+    let mut network = network.with_latency(Latency::Fixed(Duration::from_micros(50)), Latency::Fixed(Duration::ZERO));
+    network.send_command(0.into(), place(0, Side::Ask, 100, 5));
+    network.send_command(0.into(), place(0, Side::Bid, 100, 5));
+    run_until_idle(&mut server, &mut network);
+
+    let traces: Vec<_> = network
+      .pushes(0.into())
+      .into_iter()
+      .filter_map(|push| match push {
+        Push::Latency(trace) => Some(trace),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(traces.len(), 2);
+    for trace in &traces {
+      assert!(trace.received_at <= trace.dequeued_at);
+      assert!(trace.dequeued_at <= trace.matched_at);
+      assert!(trace.matched_at <= trace.sent_at);
+    }
+
+    // published on the next tick
+    server.step(&mut network);
+    let budget = health.lock().unwrap().latency;
+    assert_eq!(budget.queued.count, 2);
+    assert_eq!(budget.sending.count, 2);
+  }
+
+  #[test]
+  fn restart_recovers_from_the_journal() {
+    let (crashed, mut network, disk) = trade(11);
+    let state = crashed.engine().state_hash();
+    drop(crashed);
+
+    let mut server = recover(network.clock.clone(), &disk).unwrap();
+    assert_eq!(server.engine().state_hash(), state);
+
+    network.connect(2.into());
+    network.send_command(2.into(), place(1, Side::Bid, 105, 1));
+    run_until_idle(&mut server, &mut network);
+    let placement = Placement {
+      id: 20.into(),
+      fills: vec![Fill {
+        execution: 94.into(),
+        trade: 14.into(),
+        liquidity: Liquidity::Taker,
+        price: 102.into(),
+        quantity: 1.into(),
+      }],
+      remaining: 0.into(),
+      state: OrderState::Filled,
+      quantity_precision: 0,
+    };
+    assert_eq!(network.replies(2.into()), vec![Ok(Success::PlaceOrder(placement))]);
+
+    let recovered = recover(network.clock.clone(), &disk).unwrap();
+    assert_eq!(recovered.engine().state_hash(), server.engine().state_hash());
+  }
+
+  #[test]
+  fn shutting_down_can_cancel_every_resting_order() {
+    let (server, mut network, disk) = trade(13);
+    let mut server = server.with_shutdown_policy(ShutdownPolicy::CancelAll);
+    assert_ne!(server.engine().depth(ADBE.into(), Side::Ask).unwrap(), vec![]);
+    server.shutdown(&mut network);
+    // the session that placed each cancelled order is told why, unasked
+    let reasons: Vec<_> = network
+      .pushes(0.into())
+      .into_iter()
+      .filter_map(|push| match push {
+        Push::Cancelled(record) => record.cancel_reason,
+        _ => None,
+      })
+      .collect();
+    assert!(!reasons.is_empty());
+    assert!(reasons.iter().all(|&reason| reason == CancelReason::KillSwitch));
+
+    let restarted = recover(network.clock.clone(), &disk).unwrap();
+    for side in [Side::Bid, Side::Ask] {
+      assert_eq!(restarted.engine().depth(ADBE.into(), side).unwrap(), vec![]);
+    }
+    assert_eq!(restarted.engine().state_hash(), server.engine().state_hash());
+  }
+
+  #[test]
+  fn subscribers_are_pushed_their_accounts_order_events_in_order() {
+    let (mut server, mut network, _) = start(5, 2);
+    let subscribe = |account: usize, to: usize| Command {
+      account_id: account.into(),
+      kind: CommandKind::SubscribeExecutions(to.into()),
+    };
+    network.send_command(0.into(), subscribe(0, 0));
+    network.send_command(1.into(), subscribe(1, 0));
+    run_until_idle(&mut server, &mut network);
+    network.send_command(0.into(), place(0, Side::Ask, 100, 5));
+    run_until_idle(&mut server, &mut network);
+    network.send_command(1.into(), place(1, Side::Bid, 100, 3));
+    run_until_idle(&mut server, &mut network);
+
+    assert_eq!(network.replies(0.into())[0], Ok(Success::SubscribeExecutions));
+    assert!(matches!(network.replies(1.into())[0], Err(Error::PermissionDenied { .. })));
+    let pushes: Vec<_> = network
+      .pushes(0.into())
+      .into_iter()
+      .filter_map(|push| match push {
+        Push::ExecutionReport { sequence, record, .. } => Some((sequence, record.event, record.order)),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(pushes, vec![(1, AuditEvent::Accept, Some(0.into())), (2, AuditEvent::Execute, Some(0.into()))]);
+    assert_eq!(network.pushes(1.into()), vec![]);
+  }
+
+  #[test]
+  fn fast_acknowledged_orders_stream_their_fills_after_the_ack() {
+    let (mut server, mut network, _) = start(21, 2);
+    network.send_command(0.into(), Command {
+      account_id: 0.into(),
+      kind: CommandKind::SetAckMode(AckMode::Fast),
+    });
+    run_until_idle(&mut server, &mut network);
+    network.send_command(1.into(), place(1, Side::Ask, 100, 5));
+    run_until_idle(&mut server, &mut network);
+    network.send_command(0.into(), place(0, Side::Bid, 100, 3));
+    run_until_idle(&mut server, &mut network);
+
+    assert_eq!(network.replies(0.into()), vec![Ok(Success::SetAckMode), Ok(Success::Accepted(1.into()))]);
+    assert!(matches!(network.replies(1.into())[..], [Ok(Success::PlaceOrder(_))]));
+    let pushes: Vec<_> = network
+      .pushes(0.into())
+      .into_iter()
+      .filter_map(|push| match push {
+        Push::ExecutionReport { record, .. } => Some((record.event, record.order, record.filled)),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(pushes, vec![
+      (AuditEvent::Accept, Some(1.into()), None),
+      (AuditEvent::Execute, Some(1.into()), Some(3.into())),
+    ]);
+  }
+
+  #[test]
+  fn sessions_are_pushed_only_the_feeds_they_subscribed_to() {
+    let (mut server, mut network, _) = start(9, 2);
+    let subscribe = |feed| Command {
+      account_id: 0.into(),
+      kind: CommandKind::Subscribe(ADBE.into(), feed),
+    };
+    network.send_command(0.into(), subscribe(Feed::Bbo));
+    network.send_command(1.into(), subscribe(Feed::Trades));
+    run_until_idle(&mut server, &mut network);
+    network.send_command(0.into(), place(0, Side::Ask, 100, 5));
+    run_until_idle(&mut server, &mut network);
+    network.send_command(1.into(), place(1, Side::Bid, 100, 3));
+    run_until_idle(&mut server, &mut network);
+
+    let bbo = |ask: Option<(u32, u64)>| Push::Bbo {
+      symbol: ADBE.into(),
+      bid: None,
+      ask: ask.map(|(price, quantity)| (price.into(), quantity.into())),
+      quantity_precision: 0,
+    };
+    assert_eq!(network.pushes(0.into()), vec![bbo(None), bbo(Some((100, 5))), bbo(Some((100, 2)))]);
+    assert_eq!(network.pushes(1.into()), vec![Push::Trade {
+      symbol: ADBE.into(),
+      price: 100.into(),
+      quantity: 3.into(),
+      trade: 0.into(),
+      quantity_precision: 0,
+    }]);
+  }
+
+  #[test]
+  fn conflated_sessions_are_pushed_only_the_latest_update_of_each_interval() {
+    let (mut server, mut network, _) = start(13, 1);
+    let command = |kind| Command {
+      account_id: 0.into(),
+      kind,
+    };
+    network.send_command(0.into(), command(CommandKind::Conflate(Some(Duration::from_millis(500)))));
+    network.send_command(0.into(), command(CommandKind::Subscribe(ADBE.into(), Feed::Bbo)));
+    for &(price, quantity) in &[(100, 5), (99, 5), (100, 1)] {
+      network.send_command(0.into(), place(0, Side::Ask, price, quantity));
+    }
+    run_until_idle(&mut server, &mut network);
+    assert_eq!(network.pushes(0.into()).len(), 1);
+
+    for _ in 0..6 {
+      server.step(&mut network);
+    }
+    assert_eq!(network.pushes(0.into())[1..], [Push::Bbo {
+      symbol: ADBE.into(),
+      bid: None,
+      ask: Some((99.into(), 5.into())),
+      quantity_precision: 0,
+    }]);
+  }
+
+  #[test]
+  fn depth_subscribers_are_pushed_only_changes_within_their_levels() {
+    let (mut server, mut network, _) = start(17, 1);
+    network.send_command(0.into(), Command {
+      account_id: 0.into(),
+      kind: CommandKind::Subscribe(ADBE.into(), Feed::Depth(Some(1))),
+    });
+    for &price in &[100, 101, 100] {
+      network.send_command(0.into(), place(0, Side::Ask, price, 5));
+      run_until_idle(&mut server, &mut network);
+    }
+
+    let asks = |asks: &[(u32, u64)]| Push::Depth {
+      symbol: ADBE.into(),
+      bids: vec![],
+      asks: asks.iter().map(|&(price, quantity)| (price.into(), quantity.into())).collect(),
+      quantity_precision: 0,
+    };
+    assert_eq!(network.pushes(0.into()), vec![asks(&[]), asks(&[(100, 5)]), asks(&[(100, 10)])]);
+  }
+}
@@ -0,0 +1,84 @@
+//! Soak test
+//!
+//! Runs the bench workload for a long time, checking the engine's invariants and the process's resident memory every
+//! so often, and fails as soon as an invariant breaks or memory grows past a limit.
+
+use crate::bench::{self, Generator, Workload};
+use engine::*;
+use failure::Fail;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Bytes in a page of memory, as reported by `/proc/self/statm`
+const PAGE_SIZE: u64 = 4096;
+
+/// How long to soak for and what counts as failure
+#[derive(Debug, Clone, Copy)]
+pub struct Soak {
+  pub workload: Workload,
+  pub duration: Duration,
+  /// Commands between checks
+  pub check_interval: usize,
+  /// The most resident memory may grow after the first check
+  pub max_rss_growth: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+pub enum Failure {
+  #[fail(display = "after {} commands, invariants broken: {:?}", commands, violations)]
+  Violations {
+    commands: usize,
+    violations: Vec<(Symbol, Violation)>,
+  },
+  #[fail(display = "after {} commands, resident memory grew by {} bytes", commands, growth)]
+  RssGrowth { commands: usize, growth: u64 },
+}
+
+/// Run a soak test, printing progress at each check
+///
+/// # Returns
+/// the number of commands processed, or the first failure
+pub fn run(soak: Soak) -> Result<usize, Failure> {
+  let mut engine = MatchEngine::default();
+  let mut generator = Generator::new(&mut engine, soak.workload);
+  let mut baseline = None;
+
+  let started = Instant::now();
+  let mut commands = 0;
+  while started.elapsed() < soak.duration {
+    for _ in 0..soak.check_interval {
+      if let Ok(Success::PlaceOrder(Placement { id, .. })) = engine.try_process(generator.command()) {
+        generator.placed(id);
+      }
+    }
+    commands += soak.check_interval;
+    bench::drain(&mut engine);
+
+    let violations = engine.violations();
+    if !violations.is_empty() {
+      return Err(Failure::Violations { commands, violations });
+    }
+
+    let rss = resident_memory();
+    let mib = rss.map_or_else(|| "?".to_string(), |rss| (rss >> 20).to_string());
+    println!("{:>8}s {:>12} commands  rss {} MiB", started.elapsed().as_secs(), commands, mib);
+    if let Some(rss) = rss {
+      let growth = rss.saturating_sub(*baseline.get_or_insert(rss));
+      if growth > soak.max_rss_growth {
+        return Err(Failure::RssGrowth { commands, growth });
+      }
+    }
+  }
+
+  Ok(commands)
+}
+
+/// Get the process's resident memory in bytes
+///
+/// # Returns
+/// `None` on platforms without `/proc`
+fn resident_memory() -> Option<u64> {
+  let statm = fs::read_to_string("/proc/self/statm").ok()?;
+  let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+  Some(pages * PAGE_SIZE)
+}
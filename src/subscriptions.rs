@@ -0,0 +1,294 @@
+//! Market data subscriptions
+//!
+//! Sessions subscribe to one feed of one symbol at a time, and are pushed only the feeds they subscribed to. Book feeds
+//! are pushed whenever they differ from what was last pushed, and a new subscriber is pushed the current book at once.
+//!
+//! A session that asked for its market data conflated is instead sent it once every interval of its choosing, with
+//! only the latest update of each feed of each symbol in the interval. Candles are never coalesced, since each
+//! summarizes an interval of its own.
+//!
+//! Depth is cut to the levels the subscriber asked for, and to those the subscribing account is entitled to, and a
+//! subscriber is only pushed depth when the levels it is sent change. Subscriptions the account is no longer entitled
+//! to are dropped as soon as its entitlement changes.
+
+use engine::*;
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+/// How long each candle summarizes
+pub const CANDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The bids and asks of a book, best first
+type Levels = (Vec<(Price, Quantity)>, Vec<(Price, Quantity)>);
+
+/// The market data of a conflated session waiting for the end of its interval
+struct Conflated {
+  interval: Duration,
+  next: Timestamp,
+  /// The latest update of each feed, in the order each feed first updated
+  pending: Vec<((Symbol, Feed), Push)>,
+}
+
+/// Depth subscriptions of any number of levels are kept under this feed, with the levels in each `Subscriber`
+const DEPTH: Feed = Feed::Depth(None);
+
+/// A session subscribed to a feed, on behalf of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Subscriber {
+  session: SessionId,
+  account: AccountId,
+  /// The most price levels of each side of a book the session asked for, or `None` for all of them
+  requested: Option<usize>,
+  /// The most price levels of each side of a book to send, the fewer of those asked for and those entitled to
+  levels: Option<usize>,
+}
+
+impl Subscriber {
+  fn entitle(&mut self, entitlement: Entitlement) {
+    self.levels = match (self.requested, entitlement.depth()) {
+      (Some(requested), Some(entitled)) => Some(requested.min(entitled)),
+      (requested, entitled) => requested.or(entitled),
+    };
+  }
+}
+
+/// The feeds each session subscribed to, and what they were last pushed
+#[derive(Default)]
+pub struct Subscriptions {
+  sessions: HashMap<(Symbol, Feed), Vec<Subscriber>>,
+  conflated: HashMap<SessionId, Conflated>,
+  /// The levels last pushed of each symbol with book subscribers
+  books: HashMap<Symbol, Levels>,
+  /// The candle of each symbol's current interval, if it has traded in it
+  candles: HashMap<Symbol, Candle>,
+}
+
+impl Subscriptions {
+  /// Subscribe a session to a feed of a symbol on behalf of an account entitled to it, both of which must exist
+  ///
+  /// # Returns
+  /// the current state of a book feed, to push to the session before any update
+  pub fn subscribe(
+    &mut self,
+    engine: &MatchEngine,
+    session: SessionId,
+    account: AccountId,
+    symbol: Symbol,
+    feed: Feed,
+  ) -> Option<Push> {
+    let (feed, requested) = match feed {
+      Feed::Depth(levels) => (DEPTH, levels.map(|levels| levels as usize)),
+      feed => (feed, None),
+    };
+    let mut subscriber = Subscriber {
+      session,
+      account,
+      requested,
+      levels: None,
+    };
+    subscriber.entitle(engine.account(account).ok()?.entitlement);
+    let subscribers = self.sessions.entry((symbol, feed)).or_default();
+    subscribers.retain(|subscriber| subscriber.session != session);
+    subscribers.push(subscriber);
+
+    let snapshot = match feed {
+      Feed::Bbo => bbo(engine, symbol, self.books.entry(symbol).or_insert_with(|| levels(engine, symbol))),
+      Feed::Depth(_) => depth(engine, symbol, self.books.entry(symbol).or_insert_with(|| levels(engine, symbol))),
+      Feed::Trades | Feed::Candles => return None,
+    };
+    Some(limit(snapshot, subscriber.levels))
+  }
+
+  /// Unsubscribe a session from a feed of a symbol, or from its depth whatever the number of levels
+  pub fn unsubscribe(&mut self, session: SessionId, symbol: Symbol, feed: Feed) {
+    let feed = if let Feed::Depth(_) = feed { DEPTH } else { feed };
+    if let Some(subscribers) = self.sessions.get_mut(&(symbol, feed)) {
+      subscribers.retain(|subscriber| subscriber.session != session);
+    }
+  }
+
+  /// Apply an account's new entitlement to the subscriptions made on its behalf
+  pub fn entitle(&mut self, account: AccountId, entitlement: Entitlement) {
+    for (&(_, feed), subscribers) in self.sessions.iter_mut() {
+      subscribers.retain(|subscriber| subscriber.account != account || entitlement.allows(feed));
+      for subscriber in subscribers.iter_mut().filter(|subscriber| subscriber.account == account) {
+        subscriber.entitle(entitlement);
+      }
+    }
+  }
+
+  /// Send a session its market data once every `interval` from `now`, or as it happens if `None`
+  ///
+  /// # Returns
+  /// whatever was held for the session, if it stops being conflated
+  pub fn conflate(&mut self, session: SessionId, interval: Option<Duration>, now: Timestamp) -> Vec<Push> {
+    match interval {
+      Some(interval) => {
+        let conflated = self.conflated.entry(session).or_insert_with(|| Conflated {
+          interval,
+          next: now + interval,
+          pending: vec![],
+        });
+        conflated.interval = interval;
+        conflated.next = conflated.next.min(now + interval);
+        vec![]
+      }
+      None => {
+        let conflated = self.conflated.remove(&session);
+        conflated.into_iter().flat_map(|conflated| conflated.pending).map(|(_, push)| push).collect()
+      }
+    }
+  }
+
+  /// Unsubscribe a session from everything
+  pub fn disconnect(&mut self, session: SessionId) {
+    for subscribers in self.sessions.values_mut() {
+      subscribers.retain(|subscriber| subscriber.session != session);
+    }
+    self.conflated.remove(&session);
+  }
+
+  /// Get what to push to subscribers after a step that published `market_data` at `now`
+  ///
+  /// Books are only compared with what was last pushed if `is_changed`, i.e. the step changed the engine.
+  pub fn publish(
+    &mut self,
+    engine: &MatchEngine,
+    market_data: &[MarketData],
+    now: Timestamp,
+    is_changed: bool,
+  ) -> Vec<(SessionId, Push)> {
+    self.sessions.retain(|_, subscribers| !subscribers.is_empty());
+    let mut pushes = vec![];
+    let (sessions, conflated) = (&self.sessions, &mut self.conflated);
+    // a subscriber is skipped if what it was last sent is the same as `message`, cut to its levels
+    let mut push = |symbol, feed, message: Push, last: Option<&Push>| {
+      for subscriber in sessions.get(&(symbol, feed)).map(Vec::as_slice).unwrap_or_default() {
+        let message = limit(message.clone(), subscriber.levels);
+        if last.map(|last| limit(last.clone(), subscriber.levels)).as_ref() == Some(&message) {
+          continue;
+        }
+        let pending = match conflated.get_mut(&subscriber.session) {
+          Some(conflated) => &mut conflated.pending,
+          None => {
+            pushes.push((subscriber.session, message));
+            continue;
+          }
+        };
+        match pending.iter_mut().find(|(key, _)| *key == (symbol, feed) && feed != Feed::Candles) {
+          Some((_, latest)) => *latest = message,
+          None => pending.push(((symbol, feed), message)),
+        }
+      }
+    };
+
+    let ended: Vec<Symbol> = self
+      .candles
+      .iter()
+      .filter(|(_, candle)| candle.start + CANDLE_INTERVAL <= now)
+      .map(|(&symbol, _)| symbol)
+      .collect();
+    for symbol in ended {
+      let candle = self.candles.remove(&symbol).unwrap();
+      push(symbol, Feed::Candles, Push::Candle(candle), None);
+    }
+
+    for data in market_data {
+      if let MarketData::Trade {
+        symbol,
+        price,
+        quantity,
+        trade,
+      } = *data
+      {
+        let push_trade = Push::Trade {
+          symbol,
+          price,
+          quantity,
+          trade,
+          quantity_precision: engine.quantity_precision(symbol),
+        };
+        push(symbol, Feed::Trades, push_trade, None);
+        if sessions.contains_key(&(symbol, Feed::Candles)) {
+          add_trade(&mut self.candles, symbol, price, quantity, now);
+        }
+      }
+    }
+
+    self.books.retain(|&symbol, _| {
+      sessions.contains_key(&(symbol, Feed::Bbo)) || sessions.contains_key(&(symbol, DEPTH))
+    });
+    if is_changed {
+      for (&symbol, last) in self.books.iter_mut() {
+        let current = levels(engine, symbol);
+        if current == *last {
+          continue;
+        }
+        push(symbol, Feed::Bbo, bbo(engine, symbol, &current), Some(&bbo(engine, symbol, &*last)));
+        push(symbol, DEPTH, depth(engine, symbol, &current), Some(&depth(engine, symbol, &*last)));
+        *last = current;
+      }
+    }
+
+    for (&session, conflated) in self.conflated.iter_mut().filter(|(_, conflated)| conflated.next <= now) {
+      conflated.next = now + conflated.interval;
+      pushes.extend(mem::take(&mut conflated.pending).into_iter().map(|(_, push)| (session, push)));
+    }
+    pushes
+  }
+}
+
+fn levels(engine: &MatchEngine, symbol: Symbol) -> Levels {
+  // subscriptions are only made to symbols that exist, and symbols are never removed
+  let side = |side| engine.depth(symbol, side).unwrap_or_default();
+  (side(Side::Bid), side(Side::Ask))
+}
+
+fn bbo(engine: &MatchEngine, symbol: Symbol, (bids, asks): &Levels) -> Push {
+  Push::Bbo {
+    symbol,
+    bid: bids.first().cloned(),
+    ask: asks.first().cloned(),
+    quantity_precision: engine.quantity_precision(symbol),
+  }
+}
+
+fn depth(engine: &MatchEngine, symbol: Symbol, (bids, asks): &Levels) -> Push {
+  Push::Depth {
+    symbol,
+    bids: bids.clone(),
+    asks: asks.clone(),
+    quantity_precision: engine.quantity_precision(symbol),
+  }
+}
+
+/// Cut a depth update to at most `levels` price levels of each side
+fn limit(push: Push, levels: Option<usize>) -> Push {
+  match (push, levels) {
+    (Push::Depth { symbol, mut bids, mut asks, quantity_precision }, Some(levels)) => {
+      bids.truncate(levels);
+      asks.truncate(levels);
+      Push::Depth { symbol, bids, asks, quantity_precision }
+    }
+    (push, _) => push,
+  }
+}
+
+/// Add a trade at `now` to its symbol's current candle, starting a candle if there is none
+fn add_trade(candles: &mut HashMap<Symbol, Candle>, symbol: Symbol, price: Price, quantity: Quantity, now: Timestamp) {
+  let interval = CANDLE_INTERVAL.as_nanos() as u64;
+  let candle = candles.entry(symbol).or_insert_with(|| Candle {
+    symbol,
+    start: Timestamp::from(u64::from(now) / interval * interval),
+    open: price,
+    high: price,
+    low: price,
+    close: price,
+    volume: Quantity::default(),
+  });
+  candle.high = candle.high.max(price);
+  candle.low = candle.low.min(price);
+  candle.close = price;
+  candle.volume += quantity;
+}
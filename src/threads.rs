@@ -0,0 +1,149 @@
+//! Thread naming and core pinning
+//!
+//! Every thread the server starts is named, optionally pinned to a core, and recorded in a process-wide layout so
+//! operators can check where the hot path runs. The journal is written by the matching thread, so it runs wherever
+//! that thread is pinned.
+//!
+//! A worker thread started with `supervise` is restarted when it panics rather than taking the server down with it,
+//! and every panic caught is logged and counted for the health endpoints. Locks shared between threads are taken with
+//! `lock`, so a thread that panicked while holding one does not poison it for every other thread.
+
+use serde_derive::Serialize;
+use std::ffi::CString;
+use std::io;
+use std::any::Any;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a supervised thread waits before it is restarted after a panic
+pub const RESTART_DELAY: Duration = Duration::from_millis(100);
+
+/// A running thread
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ThreadInfo {
+  pub name: String,
+  /// The core the thread is pinned to, if any
+  pub core: Option<usize>,
+}
+
+static LAYOUT: Mutex<Vec<ThreadInfo>> = Mutex::new(vec![]);
+
+static PANICS: AtomicUsize = AtomicUsize::new(0);
+
+/// Get every thread started so far, in the order they started
+pub fn layout() -> Vec<ThreadInfo> {
+  lock(&LAYOUT).clone()
+}
+
+/// Pin the calling thread to a core
+pub fn pin(core: usize) -> io::Result<()> {
+  if core >= libc::CPU_SETSIZE as usize {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no such core {}", core)));
+  }
+
+  // SAFETY: an all-zero cpu_set_t is an empty set, and `core` is within it
+  let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+  unsafe { libc::CPU_SET(core, &mut set) };
+  // SAFETY: `set` is a valid cpu_set_t of the size passed, and pid 0 is the calling thread
+  if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// Name the calling thread, pin it to `core` if given, and record it in the layout
+///
+/// Failing to pin is reported rather than fatal, and the thread is recorded as unpinned.
+pub fn register(name: &str, core: Option<usize>) {
+  if let Ok(c_name) = CString::new(name) {
+    // SAFETY: `c_name` is a nul-terminated string, which the kernel truncates to 15 bytes
+    unsafe { libc::pthread_setname_np(libc::pthread_self(), c_name.as_ptr()) };
+  }
+
+  let core = core.filter(|&core| match pin(core) {
+    Ok(()) => true,
+    Err(e) => {
+      eprintln!("failed to pin {} to core {}: {}", name, core, e);
+      false
+    }
+  });
+  lock(&LAYOUT).push(ThreadInfo {
+    name: name.to_string(),
+    core,
+  });
+}
+
+/// Spawn a thread, named and pinned as by `register`
+pub fn spawn<F: FnOnce() + Send + 'static>(name: &str, core: Option<usize>, f: F) -> io::Result<JoinHandle<()>> {
+  let thread_name = name.to_string();
+  thread::Builder::new().name(name.to_string()).spawn(move || {
+    register(&thread_name, core);
+    f()
+  })
+}
+
+/// Spawn a thread, named and pinned as by `register`, that runs `f` again whenever it panics
+///
+/// The thread stops once `f` returns.
+pub fn supervise<F: FnMut() + Send + 'static>(name: &str, core: Option<usize>, mut f: F) -> io::Result<JoinHandle<()>> {
+  let thread_name = name.to_string();
+  spawn(name, core, move || {
+    while let Err(panic) = panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+      caught_panic(&thread_name, panic.as_ref());
+      thread::sleep(RESTART_DELAY);
+      eprintln!("restarting {}", thread_name);
+    }
+  })
+}
+
+/// Log and count a panic caught in the thread `name`
+pub fn caught_panic(name: &str, panic: &(dyn Any + Send)) {
+  let message = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+    (Some(message), _) => message,
+    (_, Some(message)) => message.as_str(),
+    _ => "an unknown panic",
+  };
+  PANICS.fetch_add(1, Ordering::Relaxed);
+  eprintln!("caught a panic in {}: {}", name, message);
+}
+
+/// Get how many panics have been caught in any thread since the process started
+pub fn panics() -> usize {
+  PANICS.load(Ordering::Relaxed)
+}
+
+/// Lock a mutex shared between threads, taking it even if a thread panicked while holding it
+///
+/// Everything shared this way is a table or a writer that a panic cannot leave half-updated, so it is safe to go on
+/// using.
+pub fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::mpsc;
+
+  #[test]
+  fn supervised_threads_are_restarted_after_a_panic() {
+    let (sender, receiver) = mpsc::channel();
+    let mut runs = 0;
+    let thread = supervise("test-supervised", None, move || {
+      runs += 1;
+      sender.send(runs).unwrap();
+      if runs < 3 {
+        panic!("run {} failed", runs);
+      }
+    })
+    .unwrap();
+
+    thread.join().unwrap();
+    assert_eq!(receiver.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert!(panics() >= 2);
+  }
+}
@@ -0,0 +1,242 @@
+//! On-disk history of trades and quotes
+//!
+//! Each symbol's trades and changes of best bid and ask are appended to files of their own in the store's directory,
+//! as fixed-size little-endian records in the order they happened, so the start of a time range is found by binary
+//! search without an index. A side without orders is stored as a zero price and quantity, which no level can have.
+
+use engine::*;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+
+const TRADE_SIZE: usize = 20;
+const QUOTE_SIZE: usize = 32;
+
+/// Which of a symbol's histories a file holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Series {
+  Trades,
+  Quotes,
+}
+
+impl Series {
+  fn extension(self) -> &'static str {
+    match self {
+      Series::Trades => "trades",
+      Series::Quotes => "quotes",
+    }
+  }
+
+  fn record_size(self) -> usize {
+    match self {
+      Series::Trades => TRADE_SIZE,
+      Series::Quotes => QUOTE_SIZE,
+    }
+  }
+}
+
+/// The trade and quote history of every symbol, in a directory
+pub struct TickStore {
+  directory: PathBuf,
+  writers: HashMap<(Symbol, Series), BufWriter<File>>,
+  /// The best bid and ask last stored of each symbol, so only changes are stored
+  quotes: HashMap<Symbol, TopOfBook>,
+}
+
+impl TickStore {
+  /// Store history in `directory`, creating it if needed and appending to any history already there
+  pub fn open<P: Into<PathBuf>>(directory: P) -> io::Result<Self> {
+    let directory = directory.into();
+    fs::create_dir_all(&directory)?;
+    Ok(Self {
+      directory,
+      writers: HashMap::new(),
+      quotes: HashMap::new(),
+    })
+  }
+
+  /// Store the trades in `market_data` and, if the engine changed, every change of best bid and ask, as of `now`
+  pub fn record(
+    &mut self,
+    engine: &MatchEngine,
+    market_data: &[MarketData],
+    now: Timestamp,
+    is_changed: bool,
+  ) -> io::Result<()> {
+    for data in market_data {
+      if let MarketData::Trade { symbol, price, quantity, .. } = *data {
+        let mut record = Vec::with_capacity(TRADE_SIZE);
+        record.extend_from_slice(&u64::from(now).to_le_bytes());
+        encode_level(&mut record, Some((price, quantity)));
+        self.writer(symbol, Series::Trades)?.write_all(&record)?;
+      }
+    }
+
+    if is_changed {
+      for symbol in engine.symbols() {
+        let quote = engine.bbo(symbol).unwrap_or_default();
+        if self.quotes.get(&symbol) == Some(&quote) {
+          continue;
+        }
+        let mut record = Vec::with_capacity(QUOTE_SIZE);
+        record.extend_from_slice(&u64::from(now).to_le_bytes());
+        encode_level(&mut record, quote.0);
+        encode_level(&mut record, quote.1);
+        self.writer(symbol, Series::Quotes)?.write_all(&record)?;
+        self.quotes.insert(symbol, quote);
+      }
+    }
+
+    for writer in self.writers.values_mut() {
+      writer.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Get up to `MAX_PAGE_SIZE` of a symbol's trades from `from` until before `to`, oldest first
+  pub fn trades(&self, symbol: Symbol, from: Timestamp, to: Timestamp) -> io::Result<Vec<TradeTick>> {
+    let records = self.read(symbol, Series::Trades, from, to)?;
+    Ok(
+      records
+        .chunks(TRADE_SIZE)
+        .map(|record| {
+          let (price, quantity) = decode_level(&record[8..20]).unwrap_or_default();
+          TradeTick {
+            timestamp: decode_timestamp(record),
+            price,
+            quantity,
+          }
+        })
+        .collect(),
+    )
+  }
+
+  /// Get up to `MAX_PAGE_SIZE` of a symbol's changes of best bid and ask from `from` until before `to`, oldest first
+  pub fn quotes(&self, symbol: Symbol, from: Timestamp, to: Timestamp) -> io::Result<Vec<QuoteTick>> {
+    let records = self.read(symbol, Series::Quotes, from, to)?;
+    Ok(
+      records
+        .chunks(QUOTE_SIZE)
+        .map(|record| QuoteTick {
+          timestamp: decode_timestamp(record),
+          bid: decode_level(&record[8..20]),
+          ask: decode_level(&record[20..32]),
+        })
+        .collect(),
+    )
+  }
+
+  fn path(&self, symbol: Symbol, series: Series) -> PathBuf {
+    // symbols may hold any character, so anything but letters and digits is escaped
+    let name: String = symbol
+      .to_string()
+      .chars()
+      .map(|c| if c.is_ascii_alphanumeric() { c.to_string() } else { format!("%{:x}", c as u32) })
+      .collect();
+    self.directory.join(format!("{}.{}", name, series.extension()))
+  }
+
+  fn writer(&mut self, symbol: Symbol, series: Series) -> io::Result<&mut BufWriter<File>> {
+    if !self.writers.contains_key(&(symbol, series)) {
+      let file = OpenOptions::new().create(true).append(true).open(self.path(symbol, series))?;
+      self.writers.insert((symbol, series), BufWriter::new(file));
+    }
+    Ok(self.writers.get_mut(&(symbol, series)).unwrap())
+  }
+
+  /// Read the records of a series from the first at or after `from`, up to `MAX_PAGE_SIZE` of them before `to`
+  fn read(&self, symbol: Symbol, series: Series, from: Timestamp, to: Timestamp) -> io::Result<Vec<u8>> {
+    let file = match File::open(self.path(symbol, series)) {
+      Ok(file) => file,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+      Err(e) => return Err(e),
+    };
+    let size = series.record_size();
+    let count = file.metadata()?.len() as usize / size;
+    let timestamp_at = |index: usize| -> io::Result<Timestamp> {
+      let mut timestamp = [0; 8];
+      file.read_exact_at(&mut timestamp, (index * size) as u64)?;
+      Ok(decode_timestamp(&timestamp))
+    };
+
+    let (mut low, mut high) = (0, count);
+    while low < high {
+      let middle = low + (high - low) / 2;
+      if timestamp_at(middle)? < from {
+        low = middle + 1;
+      } else {
+        high = middle;
+      }
+    }
+
+    let mut records = vec![0; (count - low).min(MAX_PAGE_SIZE) * size];
+    file.read_exact_at(&mut records, (low * size) as u64)?;
+    let before_to = records.chunks(size).take_while(|record| decode_timestamp(record) < to).count();
+    records.truncate(before_to * size);
+    Ok(records)
+  }
+}
+
+fn encode_level(record: &mut Vec<u8>, level: Option<(Price, Quantity)>) {
+  let (price, quantity) = level.unwrap_or_default();
+  record.extend_from_slice(&u32::from(price).to_le_bytes());
+  record.extend_from_slice(&u64::from(quantity).to_le_bytes());
+}
+
+fn decode_level(bytes: &[u8]) -> Option<(Price, Quantity)> {
+  let price = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+  let mut quantity = [0; 8];
+  quantity.copy_from_slice(&bytes[4..12]);
+  let quantity = u64::from_le_bytes(quantity);
+  if quantity == 0 {
+    return None;
+  }
+  Some((price.into(), quantity.into()))
+}
+
+fn decode_timestamp(record: &[u8]) -> Timestamp {
+  let mut timestamp = [0; 8];
+  timestamp.copy_from_slice(&record[..8]);
+  Timestamp::from(u64::from_le_bytes(timestamp))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn history_is_read_back_by_time_range() {
+    let directory = std::env::temp_dir().join(format!("matchbook-ticks-test-{}", std::process::id()));
+    let mut store = TickStore::open(&directory).unwrap();
+    let symbol = Symbol::from(['A', 'D', 'B', 'E']);
+    let mut engine = MatchEngine::default();
+    engine.insert_new_symbol(symbol).unwrap();
+    let account_id = engine.create_account();
+
+    for (time, price) in (1..=5).map(|time| (Timestamp::from(time * 10), time as u32)) {
+      let trade = MarketData::Trade {
+        symbol,
+        price: price.into(),
+        quantity: 1.into(),
+        trade: u64::from(price).into(),
+      };
+      let kind = CommandKind::PlaceOrder(Side::Bid, symbol, Order::new(price.into(), 1.into()));
+      engine.try_process(Command { account_id, kind }).unwrap();
+      store.record(&engine, &[trade], time, true).unwrap();
+    }
+    store.record(&engine, &[], Timestamp::from(60), true).unwrap();
+
+    let trades = store.trades(symbol, Timestamp::from(20), Timestamp::from(40)).unwrap();
+    assert_eq!(trades.iter().map(|trade| u32::from(trade.price)).collect::<Vec<_>>(), vec![2, 3]);
+    let quotes = store.quotes(symbol, Timestamp::from(45), Timestamp::from(100)).unwrap();
+    assert_eq!(quotes, vec![QuoteTick {
+      timestamp: Timestamp::from(50),
+      bid: Some((5.into(), 1.into())),
+      ask: None,
+    }]);
+
+    fs::remove_dir_all(&directory).unwrap();
+  }
+}
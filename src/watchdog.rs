@@ -0,0 +1,275 @@
+//! Matching watchdog
+//!
+//! Every symbol is matched on the one matching thread, so a command that takes too long on one symbol's book holds up
+//! every other. The matching thread records in a `Watchdog` when it starts and finishes each command that changes a
+//! book, and each symbol's auctions and quote-life cancels when it ticks, and a watchdog thread checks every
+//! `WATCHDOG_INTERVAL` for a symbol whose work has been processing for longer than `stall_after`, raising an alert for
+//! it even while the matching thread is stuck. Work that finishes having taken that long stalls its symbol too. The
+//! commands received together are counted by symbol before they are processed, so a stall shows how many are waiting
+//! behind it.
+//!
+//! A watchdog that halts stalled symbols has the matching thread halt order entry on each as soon as it is free to,
+//! journaled so a replay halts it too, until an admin resumes it with `CommandKind::HaltSymbol`. Each symbol's
+//! progress is reported by the health endpoints and answered to `CommandKind::GetWatchdog`.
+
+use crate::threads;
+use engine::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the watchdog thread checks for stalled symbols
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When a symbol counts as stalled, and what is done about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+  /// How long one command may take before its symbol is stalled
+  pub stall_after: Duration,
+  /// Whether order entry is halted on a stalled symbol
+  pub halts_stalled: bool,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+  symbols: HashMap<Symbol, SymbolProgress>,
+  /// Stalled symbols waiting for the matching thread to halt them
+  halting: Vec<Symbol>,
+}
+
+/// The progress of every symbol's matching, shared between the matching thread and the watchdog thread
+#[derive(Clone)]
+pub struct Watchdog {
+  config: WatchdogConfig,
+  clock: Arc<dyn Clock>,
+  progress: Arc<Mutex<Progress>>,
+}
+
+impl Watchdog {
+  pub fn new(config: WatchdogConfig, clock: Arc<dyn Clock>) -> Self {
+    Self {
+      config,
+      clock,
+      progress: Arc::default(),
+    }
+  }
+
+  /// Start the watchdog thread, which checks for stalled symbols until the process exits
+  pub fn spawn(&self) -> io::Result<JoinHandle<()>> {
+    let watchdog = self.clone();
+    threads::supervise("watchdog", None, move || loop {
+      thread::sleep(WATCHDOG_INTERVAL);
+      watchdog.check();
+    })
+  }
+
+  /// Count the commands waiting to be processed on each symbol, one for each symbol given, in place of the last count
+  pub fn queue<I: IntoIterator<Item = Symbol>>(&self, symbols: I) {
+    let mut progress = threads::lock(&self.progress);
+    for symbol_progress in progress.symbols.values_mut() {
+      symbol_progress.pending = 0;
+    }
+    for symbol in symbols {
+      progress.symbols.entry(symbol).or_insert_with(|| new_progress(symbol)).pending += 1;
+    }
+  }
+
+  /// Start watching a command counted by `queue`, taking it off its symbol's waiting commands
+  pub fn watch_queued(&self, symbol: Symbol) -> Watch<'_> {
+    if let Some(symbol_progress) = threads::lock(&self.progress).symbols.get_mut(&symbol) {
+      symbol_progress.pending = symbol_progress.pending.saturating_sub(1);
+    }
+    self.watch(symbol)
+  }
+
+  /// Start watching work on a symbol, until the returned watch is dropped
+  pub fn watch(&self, symbol: Symbol) -> Watch<'_> {
+    let now = self.clock.now();
+    let mut progress = threads::lock(&self.progress);
+    let symbol_progress = progress.symbols.entry(symbol).or_insert_with(|| new_progress(symbol));
+    symbol_progress.busy_since = Some(now);
+    Watch { watchdog: self, symbol }
+  }
+
+  /// Raise an alert for each symbol whose command has been processing for longer than a stall is allowed to
+  ///
+  /// # Returns
+  /// the symbols newly found stalled
+  pub fn check(&self) -> Vec<Symbol> {
+    let now = self.clock.now();
+    let mut progress = threads::lock(&self.progress);
+    let mut stalled = vec![];
+    for symbol_progress in progress.symbols.values_mut() {
+      let busy = match symbol_progress.busy_since {
+        Some(since) if !symbol_progress.is_stalled => elapsed(since, now),
+        _ => continue,
+      };
+      if busy > self.config.stall_after {
+        eprintln!("symbol {} has been processing one command for {}ms", symbol_progress.symbol, busy.as_millis());
+        symbol_progress.stalls += 1;
+        symbol_progress.is_stalled = true;
+        stalled.push(symbol_progress.symbol);
+      }
+    }
+    if self.config.halts_stalled {
+      progress.halting.extend(stalled.iter().copied());
+    }
+    stalled
+  }
+
+  /// Record the last event of each symbol the audit trail names
+  pub fn record(&self, audit_trail: &[AuditRecord]) {
+    let mut progress = threads::lock(&self.progress);
+    for record in audit_trail {
+      if let Some(symbol_progress) = record.symbol.and_then(|symbol| progress.symbols.get_mut(&symbol)) {
+        symbol_progress.sequence = Some(record.sequence);
+      }
+    }
+  }
+
+  /// Take the stalled symbols the matching thread has yet to halt
+  pub fn take_halts(&self) -> Vec<Symbol> {
+    std::mem::take(&mut threads::lock(&self.progress).halting)
+  }
+
+  /// Get the progress of every symbol a command was watched on, in order of symbol, with whether `engine` halted it
+  pub fn progress(&self, engine: &MatchEngine) -> Vec<SymbolProgress> {
+    let mut symbols: Vec<_> = threads::lock(&self.progress)
+      .symbols
+      .values()
+      .map(|&progress| SymbolProgress {
+        is_halted: engine.is_halted(progress.symbol),
+        ..progress
+      })
+      .collect();
+    symbols.sort_by_key(|progress| progress.symbol.to_string());
+    symbols
+  }
+
+  fn finish(&self, symbol: Symbol) {
+    let now = self.clock.now();
+    let mut progress = threads::lock(&self.progress);
+    let symbol_progress = match progress.symbols.get_mut(&symbol) {
+      Some(symbol_progress) => symbol_progress,
+      None => return,
+    };
+    let since = match symbol_progress.busy_since.take() {
+      Some(since) => since,
+      None => return,
+    };
+    let latency = elapsed(since, now);
+    symbol_progress.processed += 1;
+    symbol_progress.processed_at = now;
+    symbol_progress.max_latency = symbol_progress.max_latency.max(latency.as_nanos() as u64);
+
+    let is_stalled = latency > self.config.stall_after;
+    // a stall the watchdog thread found while the command was processing was counted and queued for halting then
+    let is_new_stall = is_stalled && !symbol_progress.is_stalled;
+    if is_new_stall {
+      eprintln!("symbol {} took {}ms to process one command", symbol, latency.as_millis());
+      symbol_progress.stalls += 1;
+    }
+    symbol_progress.is_stalled = is_stalled;
+    if is_new_stall && self.config.halts_stalled {
+      progress.halting.push(symbol);
+    }
+  }
+}
+
+/// Work on a symbol being watched, finished when dropped
+#[must_use = "a command stops being watched when its watch is dropped"]
+pub struct Watch<'a> {
+  watchdog: &'a Watchdog,
+  symbol: Symbol,
+}
+
+impl Drop for Watch<'_> {
+  fn drop(&mut self) {
+    self.watchdog.finish(self.symbol);
+  }
+}
+
+fn new_progress(symbol: Symbol) -> SymbolProgress {
+  SymbolProgress {
+    symbol,
+    sequence: None,
+    processed_at: Timestamp::default(),
+    busy_since: None,
+    processed: 0,
+    pending: 0,
+    max_latency: 0,
+    stalls: 0,
+    is_stalled: false,
+    is_halted: false,
+  }
+}
+
+fn elapsed(from: Timestamp, to: Timestamp) -> Duration {
+  Duration::from_nanos(u64::from(to).saturating_sub(from.into()))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn stalled_symbols_are_found_while_busy_and_halted_once() {
+    let clock = Arc::new(ManualClock::default());
+    let config = WatchdogConfig {
+      stall_after: Duration::from_millis(50),
+      halts_stalled: true,
+    };
+    let watchdog = Watchdog::new(config, clock.clone());
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+
+    drop(watchdog.watch(msft));
+    let watch = watchdog.watch(adbe);
+    clock.advance(Duration::from_millis(40));
+    assert!(watchdog.check().is_empty());
+    clock.advance(Duration::from_millis(20));
+    assert_eq!(watchdog.check(), vec![adbe]);
+    assert!(watchdog.check().is_empty());
+    drop(watch);
+    assert_eq!(watchdog.take_halts(), vec![adbe]);
+
+    let progress = watchdog.progress(&MatchEngine::default());
+    assert_eq!(progress.iter().map(|progress| progress.symbol).collect::<Vec<_>>(), vec![adbe, msft]);
+    assert_eq!((progress[0].stalls, progress[0].is_stalled, progress[0].busy_since), (1, true, None));
+    assert_eq!(progress[0].max_latency, Duration::from_millis(60).as_nanos() as u64);
+    assert_eq!((progress[1].processed, progress[1].stalls), (1, 0));
+
+    // a quick command clears the stall without counting another
+    drop(watchdog.watch(adbe));
+    assert!(watchdog.take_halts().is_empty());
+    let progress = watchdog.progress(&MatchEngine::default());
+    assert_eq!((progress[0].processed, progress[0].stalls, progress[0].is_stalled), (2, 1, false));
+  }
+
+  #[test]
+  fn queued_commands_are_counted_until_watched() {
+    let watchdog = Watchdog::new(
+      WatchdogConfig {
+        stall_after: Duration::from_millis(50),
+        halts_stalled: false,
+      },
+      Arc::new(ManualClock::default()),
+    );
+    let (adbe, msft) = (['A', 'D', 'B', 'E'].into(), ['M', 'S', 'F', 'T'].into());
+    let pending = || -> Vec<usize> {
+      watchdog.progress(&MatchEngine::default()).iter().map(|progress| progress.pending).collect()
+    };
+
+    watchdog.queue(vec![adbe, msft, adbe]);
+    let watch = watchdog.watch_queued(adbe);
+    assert_eq!(pending(), vec![1, 1]);
+    drop(watch);
+    drop(watchdog.watch_queued(msft));
+    assert_eq!(pending(), vec![1, 0]);
+
+    // the next input's count replaces what was left of the last, e.g. by a step that panicked
+    watchdog.queue(vec![msft]);
+    assert_eq!(pending(), vec![0, 1]);
+  }
+}
@@ -0,0 +1,268 @@
+//! Webhook notifications
+//!
+//! Accounts registered in a webhooks file are sent their execution reports and rejection notices, each an HTTP POST
+//! of the event's `AuditRecord` as JSON. Deliveries are made on a thread of their own and failed ones are retried with
+//! exponential backoff, so a slow or failing endpoint never holds up the event loop.
+//!
+//! The server has no TLS client, so an `https://` endpoint is reached through a TLS terminating proxy given as a plain
+//! `http://` URL.
+
+use crate::threads;
+use engine::*;
+use failure::format_err;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// Attempts at delivering a notification before it is given up on
+const MAX_ATTEMPTS: u32 = 5;
+/// How long to wait before the first retry, doubling for each one after
+const FIRST_RETRY: Duration = Duration::from_millis(100);
+/// How long connecting to, writing to or reading from an endpoint may take before the attempt fails
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An endpoint registered for an account, as read from a webhooks file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Webhook {
+  pub account: AccountId,
+  pub url: String,
+}
+
+/// Where notifications are posted, as parsed from a webhook's URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+  host: String,
+  port: u16,
+  path: String,
+}
+
+impl Endpoint {
+  fn parse(url: &str) -> Result<Self, failure::Error> {
+    let rest = match url.strip_prefix("http://") {
+      Some(rest) => rest,
+      None if url.starts_with("https://") => {
+        return Err(format_err!("{} must be reached through a TLS terminating proxy at an http:// URL", url))
+      }
+      None => return Err(format_err!("{} is not an http:// URL", url)),
+    };
+    let (authority, path) = match rest.find('/') {
+      Some(index) => rest.split_at(index),
+      None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+      Some((host, port)) => (host, port.parse()?),
+      None => (authority, 80),
+    };
+    if host.is_empty() {
+      return Err(format_err!("{} has no host", url));
+    }
+
+    Ok(Self {
+      host: host.to_string(),
+      port,
+      path: path.to_string(),
+    })
+  }
+}
+
+/// A notification on its way to an endpoint
+struct Delivery {
+  endpoint: Endpoint,
+  body: Vec<u8>,
+  attempts: u32,
+  due: Instant,
+}
+
+/// Posts accounts' fills and rejections to their webhooks
+pub struct Notifier {
+  /// The webhooks file, reloaded on request
+  path: PathBuf,
+  endpoints: HashMap<AccountId, Endpoint>,
+  deliveries: Sender<Delivery>,
+}
+
+impl Notifier {
+  /// Notify the accounts registered in the JSON webhooks file at `path`
+  pub fn spawn<P: Into<PathBuf>>(path: P) -> Result<Self, failure::Error> {
+    let path = path.into();
+    let endpoints = load(&path)?;
+    let (deliveries, queued) = mpsc::channel();
+    threads::spawn("webhooks", None, move || deliver(queued))?;
+    Ok(Self {
+      path,
+      endpoints,
+      deliveries,
+    })
+  }
+
+  /// Read the webhooks file again, keeping the current webhooks if it is invalid
+  pub fn reload(&mut self) {
+    match load(&self.path) {
+      Ok(endpoints) => {
+        self.endpoints = endpoints;
+        eprintln!("reloaded webhooks from {}", self.path.display());
+      }
+      Err(e) => eprintln!("failed to read webhooks from {}: {}", self.path.display(), e),
+    }
+  }
+
+  /// Queue the executions and rejections of registered accounts to be posted
+  pub fn notify(&mut self, records: &[AuditRecord]) {
+    for record in records {
+      if record.event != AuditEvent::Execute && record.event != AuditEvent::Reject {
+        continue;
+      }
+      let endpoint = match self.endpoints.get(&record.account) {
+        Some(endpoint) => endpoint,
+        None => continue,
+      };
+
+      let delivery = Delivery {
+        endpoint: endpoint.clone(),
+        body: serde_json::to_vec(record).expect("audit records always serialize"),
+        attempts: 0,
+        due: Instant::now(),
+      };
+      let _ = self.deliveries.send(delivery);
+    }
+  }
+}
+
+fn load(path: &Path) -> Result<HashMap<AccountId, Endpoint>, failure::Error> {
+  let webhooks: Vec<Webhook> = serde_json::from_slice(&fs::read(path)?)?;
+  webhooks.into_iter().map(|webhook| Ok((webhook.account, Endpoint::parse(&webhook.url)?))).collect()
+}
+
+/// Make deliveries as they are queued and retries as they fall due, until the notifier is dropped
+fn deliver(queued: Receiver<Delivery>) {
+  let mut retries: Vec<Delivery> = vec![];
+  loop {
+    let received = match retries.iter().map(|delivery| delivery.due).min() {
+      Some(due) => match queued.recv_timeout(due.saturating_duration_since(Instant::now())) {
+        Ok(delivery) => Some(delivery),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => return,
+      },
+      None => match queued.recv() {
+        Ok(delivery) => Some(delivery),
+        Err(_) => return,
+      },
+    };
+
+    let now = Instant::now();
+    let (due, waiting): (Vec<_>, Vec<_>) = retries.drain(..).partition(|delivery| delivery.due <= now);
+    retries = waiting;
+    for mut delivery in received.into_iter().chain(due) {
+      delivery.attempts += 1;
+      match post(&delivery.endpoint, &delivery.body) {
+        Ok(()) => {}
+        Err(e) if delivery.attempts >= MAX_ATTEMPTS => eprintln!(
+          "gave up on a webhook to {} after {} attempts: {}",
+          delivery.endpoint.host, delivery.attempts, e
+        ),
+        Err(_) => {
+          delivery.due = Instant::now() + FIRST_RETRY * 2u32.pow(delivery.attempts - 1);
+          retries.push(delivery);
+        }
+      }
+    }
+  }
+}
+
+/// Post a JSON body to an endpoint
+///
+/// # Returns
+/// an error unless the endpoint answered with a 2xx status
+fn post(endpoint: &Endpoint, body: &[u8]) -> io::Result<()> {
+  let address = (endpoint.host.as_str(), endpoint.port)
+    .to_socket_addrs()?
+    .next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", endpoint.host)))?;
+  let mut stream = TcpStream::connect_timeout(&address, DELIVERY_TIMEOUT)?;
+  stream.set_write_timeout(Some(DELIVERY_TIMEOUT))?;
+  stream.set_read_timeout(Some(DELIVERY_TIMEOUT))?;
+
+  let mut request = format!(
+    "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+    endpoint.path,
+    endpoint.host,
+    body.len()
+  )
+  .into_bytes();
+  request.extend_from_slice(body);
+  stream.write_all(&request)?;
+
+  let mut status = String::new();
+  BufReader::new(stream).read_line(&mut status)?;
+  match status.split_whitespace().nth(1) {
+    Some(code) if code.starts_with('2') => Ok(()),
+    _ => Err(io::Error::other(format!("endpoint answered {}", status.trim()))),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::io::Read;
+  use std::net::TcpListener;
+  use std::thread;
+
+  #[test]
+  fn failed_deliveries_are_retried() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let path = std::env::temp_dir().join(format!("matchbook-webhooks-test-{}", std::process::id()));
+    let url = format!("http://{}/fills", listener.local_addr().unwrap());
+    fs::write(&path, serde_json::json!([{ "account": 1, "url": url }]).to_string()).unwrap();
+    let mut notifier = Notifier::spawn(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let endpoint = thread::spawn(move || {
+      ["500 Internal Server Error", "200 OK"]
+        .iter()
+        .map(|status| {
+          let (mut stream, _) = listener.accept().unwrap();
+          let (mut request, mut buffer) = (vec![], [0; 4096]);
+          while request.last() != Some(&b'}') {
+            let read = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..read]);
+          }
+          write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+          String::from_utf8(request).unwrap()
+        })
+        .collect::<Vec<_>>()
+    });
+
+    let record = |account: usize, event| AuditRecord {
+      sequence: EventId::default(),
+      timestamp: Timestamp::default(),
+      session: SessionId::default(),
+      account: account.into(),
+      event,
+      order: None,
+      symbol: None,
+      side: None,
+      price: None,
+      quantity: None,
+      trade: None,
+      liquidity: None,
+      filled: None,
+      average_price: None,
+      source: None,
+      tag: None,
+      check: None,
+      reason: None,
+      cancel_reason: None,
+    };
+    notifier.notify(&[record(1, AuditEvent::Accept), record(2, AuditEvent::Reject), record(1, AuditEvent::Reject)]);
+
+    let requests = endpoint.join().unwrap();
+    assert_eq!(requests[0], requests[1]);
+    assert!(requests[1].starts_with("POST /fills HTTP/1.1\r\n"));
+    assert!(requests[1].ends_with(&serde_json::to_string(&record(1, AuditEvent::Reject)).unwrap()));
+  }
+}
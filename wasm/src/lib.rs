@@ -0,0 +1,130 @@
+//! JavaScript bindings for an in-browser book
+//!
+//! Build with `wasm-pack build wasm --target web` and drive a `Book` from JS:
+//!
+//! ```js
+//! const book = new Book("ADBE");
+//! const ask = book.place(false, 101, 10);
+//! book.place(true, 101, 4);
+//! book.depth(false); // Uint32Array [101, 6]
+//! ```
+//!
+//! `wasm32-unknown-unknown` has no system clock, so the page sets the engine's time with `set_time`.
+
+use engine::*;
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+/// A match engine trading one symbol for one account
+#[wasm_bindgen]
+pub struct Book {
+  engine: MatchEngine,
+  clock: Arc<ManualClock>,
+  account_id: AccountId,
+  symbol: Symbol,
+}
+
+#[wasm_bindgen]
+impl Book {
+  /// Create a book for a four character symbol
+  #[wasm_bindgen(constructor)]
+  pub fn new(symbol: &str) -> Result<Book, JsValue> {
+    let mut chars = ['\0'; 4];
+    if symbol.chars().count() != chars.len() {
+      return Err(JsValue::from_str("symbols are four characters"));
+    }
+    for (slot, c) in chars.iter_mut().zip(symbol.chars()) {
+      *slot = c;
+    }
+
+    let clock = Arc::new(ManualClock::default());
+    let mut engine = MatchEngine::with_clock(clock.clone());
+    let account_id = engine.create_account();
+    let symbol = chars.into();
+    engine.insert_new_symbol(symbol).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    Ok(Book {
+      engine,
+      clock,
+      account_id,
+      symbol,
+    })
+  }
+
+  /// Set the engine's time in nanoseconds since the unix epoch, e.g. from `performance.timeOrigin`
+  pub fn set_time(&mut self, nanos: u64) {
+    self.clock.set(nanos.into());
+    self.engine.tick();
+  }
+
+  /// Place an order
+  ///
+  /// # Returns
+  /// the order's id
+  pub fn place(&mut self, is_bid: bool, price: u32, quantity: u64) -> Result<usize, JsValue> {
+    let side = if is_bid { Side::Bid } else { Side::Ask };
+    let order = Order::new(price.into(), quantity.into());
+    match self.process(CommandKind::PlaceOrder(side, self.symbol, order))? {
+      Success::PlaceOrder(Placement { id, .. }) => Ok(id.into()),
+      _ => unreachable!(),
+    }
+  }
+
+  /// Cancel an order
+  ///
+  /// # Returns
+  /// true if the order was resting and is now cancelled, rather than left to be cancelled when its quote life ends
+  pub fn cancel(&mut self, id: usize) -> Result<bool, JsValue> {
+    match self.process(CommandKind::CancelOrder(id.into()))? {
+      Success::CancelOrder(is_cancelled) => Ok(is_cancelled),
+      Success::CancelPending(_) => Ok(false),
+      _ => unreachable!(),
+    }
+  }
+
+  /// Execute a resting order against the book
+  ///
+  /// # Returns
+  /// true if the order is now filled
+  pub fn execute(&mut self, id: usize) -> Result<bool, JsValue> {
+    match self.process(CommandKind::ExecuteOrder(id.into()))? {
+      Success::ExecuteOrder(is_filled, _) => Ok(is_filled),
+      _ => unreachable!(),
+    }
+  }
+
+  /// Get a side's levels, best first, flattened to `[price, quantity, price, quantity, ...]`
+  pub fn depth(&self, is_bid: bool) -> Vec<u64> {
+    let side = if is_bid { Side::Bid } else { Side::Ask };
+    // the symbol was inserted when the book was created
+    let levels = self.engine.depth(self.symbol, side).unwrap();
+    levels
+      .into_iter()
+      .flat_map(|(price, quantity)| vec![u32::from(price).into(), quantity.into()])
+      .collect()
+  }
+
+  /// Take the trades since the last call, flattened to `[price, quantity, price, quantity, ...]`
+  pub fn drain_trades(&mut self) -> Vec<u64> {
+    self
+      .engine
+      .drain_market_data()
+      .into_iter()
+      .filter_map(|data| match data {
+        MarketData::Trade { price, quantity, .. } => Some(vec![u32::from(price).into(), quantity.into()]),
+        _ => None,
+      })
+      .flatten()
+      .collect()
+  }
+
+  fn process(&mut self, kind: CommandKind) -> Result<Success, JsValue> {
+    self
+      .engine
+      .try_process(Command {
+        account_id: self.account_id,
+        kind,
+      })
+      .map_err(|e| JsValue::from_str(&e.to_string()))
+  }
+}